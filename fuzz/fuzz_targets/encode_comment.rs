@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|com: &str| {
+    for html in [blu::encode_comment(com), blu::encode_markup(com)] {
+        // the input is escaped, the only tags are the ones the encoders make
+        assert!(!html.contains("<script"), "{html:?}");
+        assert!(!html.contains("\"<") && !html.contains("<\""), "{html:?}");
+    }
+});
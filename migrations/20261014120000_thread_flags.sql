@@ -0,0 +1,2 @@
+ALTER TABLE comments ADD COLUMN is_sticky BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE comments ADD COLUMN is_locked BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,2 @@
+ALTER TABLE comments ADD COLUMN edit_token TEXT;
+ALTER TABLE comments ADD COLUMN edited_at INTEGER;
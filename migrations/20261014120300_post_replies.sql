@@ -0,0 +1,7 @@
+CREATE TABLE post_replies (
+    post_id INTEGER NOT NULL,
+    reply_id INTEGER NOT NULL,
+    PRIMARY KEY (post_id, reply_id),
+    FOREIGN KEY (post_id) REFERENCES comments (id) ON DELETE CASCADE,
+    FOREIGN KEY (reply_id) REFERENCES comments (id) ON DELETE CASCADE
+);
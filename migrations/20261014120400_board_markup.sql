@@ -0,0 +1 @@
+ALTER TABLE boards ADD COLUMN markup BOOLEAN NOT NULL DEFAULT FALSE;
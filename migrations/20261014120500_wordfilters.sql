@@ -0,0 +1,9 @@
+CREATE TABLE wordfilters (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    board TEXT NOT NULL,
+    pattern TEXT NOT NULL,
+    replacement TEXT NOT NULL,
+    is_regex BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    FOREIGN KEY (board) REFERENCES boards (code)
+);
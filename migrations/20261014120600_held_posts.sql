@@ -0,0 +1 @@
+ALTER TABLE comments ADD COLUMN is_held BOOLEAN NOT NULL DEFAULT FALSE;
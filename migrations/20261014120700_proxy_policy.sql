@@ -0,0 +1,5 @@
+ALTER TABLE boards ADD COLUMN proxy_policy TEXT NOT NULL DEFAULT 'allow';
+CREATE TABLE proxy_whitelist (
+    ip TEXT PRIMARY KEY NOT NULL,
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+);
@@ -0,0 +1,12 @@
+CREATE TABLE settings (
+    name TEXT PRIMARY KEY NOT NULL,
+    value TEXT NOT NULL
+);
+ALTER TABLE comments ADD COLUMN ip_hash TEXT;
+CREATE INDEX comments_ip_hash ON comments (ip_hash);
+-- raw ips can't be hashed from sql, whitelisted ips have to be added again
+DROP TABLE proxy_whitelist;
+CREATE TABLE proxy_whitelist (
+    ip_hash TEXT PRIMARY KEY NOT NULL,
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+);
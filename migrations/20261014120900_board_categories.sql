@@ -0,0 +1,2 @@
+ALTER TABLE boards ADD COLUMN category TEXT;
+ALTER TABLE boards ADD COLUMN position INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1 @@
+ALTER TABLE boards ADD COLUMN on_overboard BOOLEAN NOT NULL DEFAULT TRUE;
@@ -0,0 +1,8 @@
+CREATE TABLE announcements (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    text TEXT NOT NULL,
+    board TEXT,
+    expires_at INTEGER,
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    FOREIGN KEY (board) REFERENCES boards (code)
+);
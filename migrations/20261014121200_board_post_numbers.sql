@@ -0,0 +1,16 @@
+ALTER TABLE boards ADD COLUMN post_count INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE comments ADD COLUMN board_post_no INTEGER NOT NULL DEFAULT 0;
+UPDATE comments SET board_post_no = (
+    SELECT n.no FROM (
+        SELECT c.id AS id, ROW_NUMBER() OVER (PARTITION BY COALESCE(t.board, c.board) ORDER BY c.id) AS no
+        FROM comments c
+        LEFT JOIN comments t ON t.id = c.op
+    ) n
+    WHERE n.id = comments.id
+);
+UPDATE boards SET post_count = (
+    SELECT COUNT(*) FROM comments c
+    LEFT JOIN comments t ON t.id = c.op
+    WHERE COALESCE(t.board, c.board) = boards.code
+);
+CREATE INDEX comments_board_post_no ON comments (board, board_post_no);
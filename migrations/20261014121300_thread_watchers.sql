@@ -0,0 +1,8 @@
+CREATE TABLE thread_watchers (
+    token TEXT NOT NULL,
+    thread_id INTEGER NOT NULL,
+    last_seen INTEGER NOT NULL,
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    PRIMARY KEY (token, thread_id),
+    FOREIGN KEY (thread_id) REFERENCES comments (id) ON DELETE CASCADE
+);
@@ -0,0 +1,4 @@
+ALTER TABLE comments ADD COLUMN media_w INTEGER;
+ALTER TABLE comments ADD COLUMN media_h INTEGER;
+ALTER TABLE comments ADD COLUMN thumb_w INTEGER;
+ALTER TABLE comments ADD COLUMN thumb_h INTEGER;
@@ -0,0 +1,7 @@
+ALTER TABLE boards ADD COLUMN thumb_dimension INTEGER;
+ALTER TABLE boards ADD COLUMN catalog_thumb_dimension INTEGER;
+ALTER TABLE boards ADD COLUMN thumb_quality INTEGER;
+ALTER TABLE boards ADD COLUMN thumb_format TEXT;
+ALTER TABLE comments ADD COLUMN catalog_thumb_name TEXT;
+ALTER TABLE comments ADD COLUMN catalog_thumb_w INTEGER;
+ALTER TABLE comments ADD COLUMN catalog_thumb_h INTEGER;
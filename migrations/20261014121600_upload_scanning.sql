@@ -0,0 +1 @@
+ALTER TABLE boards ADD COLUMN scan_uploads BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,14 @@
+CREATE TABLE link_embeds (
+    url TEXT PRIMARY KEY,
+    title TEXT,
+    description TEXT,
+    image TEXT,
+    provider TEXT,
+    fetched_at INTEGER NOT NULL
+);
+
+CREATE TABLE post_links (
+    post_id INTEGER NOT NULL REFERENCES comments(id) ON DELETE CASCADE,
+    url TEXT NOT NULL,
+    PRIMARY KEY (post_id, url)
+);
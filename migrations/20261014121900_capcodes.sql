@@ -0,0 +1 @@
+ALTER TABLE comments ADD COLUMN capcode TEXT;
@@ -0,0 +1,2 @@
+ALTER TABLE boards ADD COLUMN forced_anon BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE boards ADD COLUMN default_name TEXT NOT NULL DEFAULT 'Anonymous';
@@ -0,0 +1,8 @@
+CREATE TABLE thread_redirects (
+    board TEXT NOT NULL,
+    thread_id INTEGER NOT NULL,
+    target_id INTEGER NOT NULL,
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    PRIMARY KEY (board, thread_id),
+    FOREIGN KEY (target_id) REFERENCES comments (id) ON DELETE CASCADE
+);
@@ -0,0 +1,8 @@
+CREATE TABLE bans (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    ip_hash TEXT NOT NULL,
+    reason TEXT,
+    expires_at INTEGER,
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+);
+CREATE INDEX bans_ip_hash ON bans (ip_hash);
@@ -0,0 +1,2 @@
+ALTER TABLE bans ADD COLUMN board TEXT;
+ALTER TABLE bans ADD COLUMN is_public BOOLEAN NOT NULL DEFAULT TRUE;
@@ -0,0 +1 @@
+ALTER TABLE boards ADD COLUMN hold_new_posters BOOLEAN NOT NULL DEFAULT FALSE;
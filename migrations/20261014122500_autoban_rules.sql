@@ -0,0 +1,14 @@
+CREATE TABLE autoban_rules (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    board TEXT,
+    pattern TEXT NOT NULL,
+    target TEXT NOT NULL,
+    action TEXT NOT NULL,
+    reason TEXT,
+    ban_duration INTEGER,
+    is_enabled BOOLEAN NOT NULL DEFAULT FALSE,
+    hits INTEGER NOT NULL DEFAULT 0,
+    last_hit_at INTEGER,
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    FOREIGN KEY (board) REFERENCES boards (code) ON DELETE CASCADE
+);
@@ -0,0 +1,24 @@
+CREATE TABLE takedowns (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    claimant TEXT NOT NULL,
+    contact TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending',
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    resolved_at INTEGER
+);
+CREATE TABLE takedown_posts (
+    takedown_id INTEGER NOT NULL,
+    post_id INTEGER NOT NULL,
+    PRIMARY KEY (takedown_id, post_id),
+    FOREIGN KEY (takedown_id) REFERENCES takedowns (id) ON DELETE CASCADE
+);
+CREATE TABLE takedown_media (
+    takedown_id INTEGER NOT NULL,
+    post_id INTEGER NOT NULL,
+    file_name TEXT NOT NULL,
+    expires_at INTEGER NOT NULL,
+    FOREIGN KEY (takedown_id) REFERENCES takedowns (id) ON DELETE CASCADE
+);
+CREATE INDEX takedown_media_expires_at ON takedown_media (expires_at);
+ALTER TABLE comments ADD COLUMN media_removed TEXT;
@@ -0,0 +1,5 @@
+CREATE TABLE media_blocklist (
+    hash TEXT PRIMARY KEY NOT NULL,
+    post_id INTEGER,
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+);
@@ -0,0 +1,8 @@
+CREATE TABLE daily_stats (
+    day TEXT NOT NULL,
+    board TEXT NOT NULL,
+    posts INTEGER NOT NULL,
+    threads INTEGER NOT NULL,
+    posters INTEGER NOT NULL,
+    PRIMARY KEY (day, board)
+);
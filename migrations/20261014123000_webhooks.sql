@@ -0,0 +1,20 @@
+CREATE TABLE webhooks (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    url TEXT NOT NULL,
+    secret TEXT NOT NULL,
+    events TEXT NOT NULL,
+    board TEXT,
+    is_enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    FOREIGN KEY (board) REFERENCES boards (code) ON DELETE CASCADE
+);
+CREATE TABLE reports (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    post_id INTEGER NOT NULL,
+    reason TEXT NOT NULL,
+    ip_hash TEXT,
+    dismissed_at INTEGER,
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    FOREIGN KEY (post_id) REFERENCES comments (id) ON DELETE CASCADE
+);
+CREATE INDEX reports_post_id ON reports (post_id);
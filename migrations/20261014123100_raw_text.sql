@@ -0,0 +1,3 @@
+-- the text as it was posted, the rendered html in sub and com can be made again from it
+ALTER TABLE comments ADD COLUMN sub_raw TEXT;
+ALTER TABLE comments ADD COLUMN com_raw TEXT;
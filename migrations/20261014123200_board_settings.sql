@@ -0,0 +1,8 @@
+CREATE TABLE board_settings (
+    board TEXT PRIMARY KEY NOT NULL,
+    theme TEXT,
+    banners TEXT NOT NULL DEFAULT '[]',
+    custom_css TEXT,
+    updated_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    FOREIGN KEY (board) REFERENCES boards (code) ON DELETE CASCADE
+);
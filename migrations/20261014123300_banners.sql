@@ -0,0 +1,12 @@
+CREATE TABLE banners (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    board TEXT NOT NULL,
+    media_name TEXT NOT NULL,
+    media_ext TEXT NOT NULL,
+    media_w INTEGER,
+    media_h INTEGER,
+    is_active BOOLEAN NOT NULL DEFAULT TRUE,
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    FOREIGN KEY (board) REFERENCES boards (code) ON DELETE CASCADE
+);
+CREATE INDEX banners_board ON banners (board);
@@ -0,0 +1 @@
+ALTER TABLE comments ADD COLUMN thumb_failed BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,2 @@
+ALTER TABLE boards ADD COLUMN allow_documents BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE comments ADD COLUMN media_pages INTEGER;
@@ -0,0 +1,3 @@
+ALTER TABLE boards ADD COLUMN allow_audio BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE comments ADD COLUMN media_duration REAL;
+ALTER TABLE comments ADD COLUMN media_bitrate INTEGER;
@@ -0,0 +1,2 @@
+ALTER TABLE boards ADD COLUMN reencode_images BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE comments ADD COLUMN media_orig_size INTEGER;
@@ -0,0 +1,8 @@
+ALTER TABLE comments ADD COLUMN media_type TEXT;
+ALTER TABLE comments ADD COLUMN thumb_type TEXT;
+ALTER TABLE banners ADD COLUMN media_type TEXT;
+
+CREATE INDEX comments_media_name ON comments (media_name) WHERE media_name IS NOT NULL;
+CREATE INDEX comments_thumb_name ON comments (thumb_name) WHERE thumb_name IS NOT NULL;
+CREATE INDEX comments_catalog_thumb_name ON comments (catalog_thumb_name)
+WHERE catalog_thumb_name IS NOT NULL;
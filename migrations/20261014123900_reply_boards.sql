@@ -0,0 +1,2 @@
+UPDATE comments SET board = (SELECT t.board FROM comments t WHERE t.id = comments.op)
+WHERE op IS NOT NULL;
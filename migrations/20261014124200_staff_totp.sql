@@ -0,0 +1,8 @@
+CREATE TABLE staff_totp (
+    subject TEXT PRIMARY KEY NOT NULL,
+    secret TEXT NOT NULL,
+    recovery_codes TEXT NOT NULL DEFAULT '[]',
+    last_counter INTEGER NOT NULL DEFAULT 0,
+    enabled_at INTEGER,
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+);
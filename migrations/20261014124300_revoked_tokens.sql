@@ -0,0 +1,4 @@
+CREATE TABLE revoked_tokens (
+    token_hash TEXT PRIMARY KEY NOT NULL,
+    revoked_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+);
@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS quarantined_posts (
+    post_id INTEGER PRIMARY KEY NOT NULL,
+    board TEXT,
+    board_post_no INTEGER NOT NULL,
+    op INTEGER,
+    posts INTEGER NOT NULL,
+    media_hash TEXT,
+    data BLOB NOT NULL,
+    deleted_by TEXT NOT NULL,
+    deleted_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    expires_at INTEGER NOT NULL
+);
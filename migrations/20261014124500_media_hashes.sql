@@ -0,0 +1,2 @@
+ALTER TABLE comments ADD COLUMN media_hash TEXT;
+CREATE INDEX comments_media_hash ON comments (media_hash) WHERE media_hash IS NOT NULL;
@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS staged_uploads (
+    id TEXT PRIMARY KEY NOT NULL,
+    length INTEGER NOT NULL,
+    offset INTEGER NOT NULL DEFAULT 0,
+    file_name TEXT,
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    expires_at INTEGER NOT NULL
+);
@@ -0,0 +1,7 @@
+ALTER TABLE boards ADD COLUMN pow_difficulty INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE boards ADD COLUMN pow_step INTEGER NOT NULL DEFAULT 1;
+
+CREATE TABLE IF NOT EXISTS pow_solutions (
+    challenge TEXT PRIMARY KEY NOT NULL,
+    expires_at INTEGER NOT NULL
+);
@@ -0,0 +1,7 @@
+CREATE TABLE board_rules (
+    board TEXT PRIMARY KEY NOT NULL,
+    rules TEXT NOT NULL DEFAULT '[]',
+    require_acceptance BOOLEAN NOT NULL DEFAULT FALSE,
+    updated_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    FOREIGN KEY (board) REFERENCES boards (code) ON DELETE CASCADE
+);
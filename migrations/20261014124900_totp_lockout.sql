@@ -0,0 +1,2 @@
+ALTER TABLE staff_totp ADD COLUMN failed_attempts INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE staff_totp ADD COLUMN locked_until INTEGER;
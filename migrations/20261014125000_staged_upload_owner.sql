@@ -0,0 +1,2 @@
+ALTER TABLE staged_uploads ADD COLUMN ip_hash TEXT;
+CREATE INDEX IF NOT EXISTS idx_staged_uploads_ip_hash ON staged_uploads (ip_hash);
@@ -0,0 +1,233 @@
+//! Optional accounts, enabled with ACCOUNTS=true. Posting stays anonymous, an account only
+//! signs the posts sent with its session and keeps the watchlist, the hidden threads and the
+//! settings of its owner across devices
+
+use axum::http::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::SqlitePool;
+use sqlx::prelude::FromRow;
+use sqlx::types::Json;
+use uuid::Uuid;
+
+use crate::privacy::hash_token;
+use crate::{Res, StatusError, hash_password, verify_password};
+
+/// Sessions unused for this long are logged out
+const SESSION_IDLE: i64 = 30 * 24 * 60 * 60;
+const MAX_SETTINGS_SIZE: usize = 16 * 1024;
+
+/// An account as shown to its owner, the watch token is used with the `/watch` routes
+#[derive(Serialize, Deserialize, FromRow)]
+pub struct Account {
+    pub id: i64,
+    pub username: String,
+    pub watch_token: String,
+    pub settings: Json<Value>,
+    pub created_at: i64,
+    #[sqlx(skip)]
+    pub hidden_threads: Vec<i64>,
+}
+
+/// A session as listed to its account, the id is the hash of its token
+#[derive(Serialize, Deserialize, FromRow)]
+pub struct AccountSession {
+    pub id: String,
+    pub created_at: i64,
+    pub last_used_at: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Session {
+    pub account: Account,
+    pub session: String,
+}
+
+/// Usernames are 3 to 20 letters, digits or underscores, compared without case
+pub fn check_username(username: &str) -> Result<(), &'static str> {
+    let valid = (3..=20).contains(&username.len())
+        && username
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        return Err("usernames are 3 to 20 letters, digits or underscores");
+    }
+    Ok(())
+}
+
+pub async fn register(pool: &SqlitePool, username: &str, password: &str) -> Res<Session> {
+    check_username(username).map_err(|e| StatusError(StatusCode::BAD_REQUEST, e.to_string()))?;
+    let password_hash = hash_password(password)?;
+    let inserted: Option<i64> = sqlx::query_scalar(
+        r#"
+        INSERT INTO accounts (username, password_hash, watch_token) VALUES (?, ?, ?)
+        ON CONFLICT (username) DO NOTHING
+        RETURNING id
+        "#,
+    )
+    .bind(username)
+    .bind(password_hash)
+    .bind(Uuid::new_v4().simple().to_string())
+    .fetch_optional(pool)
+    .await?;
+    let Some(id) = inserted else {
+        let message = format!("username {username} is taken");
+        return Err(StatusError(StatusCode::CONFLICT, message).into());
+    };
+    start_session(pool, id).await
+}
+
+pub async fn login(pool: &SqlitePool, username: &str, password: &str) -> Res<Session> {
+    let account: Option<(i64, String)> =
+        sqlx::query_as(r#"SELECT id, password_hash FROM accounts WHERE username = ?"#)
+            .bind(username)
+            .fetch_optional(pool)
+            .await?;
+    match account {
+        Some((id, hash)) if verify_password(password, &hash) => start_session(pool, id).await,
+        _ => Err(StatusError(
+            StatusCode::UNAUTHORIZED,
+            "invalid username or password".to_string(),
+        )
+        .into()),
+    }
+}
+
+async fn start_session(pool: &SqlitePool, account_id: i64) -> Res<Session> {
+    let session = Uuid::new_v4().simple().to_string();
+    sqlx::query(r#"INSERT INTO account_sessions (token_hash, account_id) VALUES (?, ?)"#)
+        .bind(hash_token(&session))
+        .bind(account_id)
+        .execute(pool)
+        .await?;
+    let account = fetch(pool, account_id).await?;
+    Ok(Session { account, session })
+}
+
+pub async fn logout(pool: &SqlitePool, session: &str) -> Res<()> {
+    sqlx::query(r#"DELETE FROM account_sessions WHERE token_hash = ?"#)
+        .bind(hash_token(session))
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn sessions(pool: &SqlitePool, account_id: i64) -> Res<Vec<AccountSession>> {
+    sqlx::query_as(
+        r#"
+        SELECT token_hash AS id, created_at, last_used_at FROM account_sessions
+        WHERE account_id = ? AND last_used_at > strftime('%s', 'now') - ?
+        ORDER BY last_used_at DESC
+        "#,
+    )
+    .bind(account_id)
+    .bind(SESSION_IDLE)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| e.into())
+}
+
+/// Logs out a session of the account by its id, or every session but the current one
+pub async fn revoke_sessions(
+    pool: &SqlitePool,
+    account_id: i64,
+    id: Option<&str>,
+    current: &str,
+) -> Res<u64> {
+    let revoked = sqlx::query(
+        r#"
+        DELETE FROM account_sessions
+        WHERE account_id = ? AND (token_hash = ? OR (? IS NULL AND token_hash != ?))
+        "#,
+    )
+    .bind(account_id)
+    .bind(id)
+    .bind(id)
+    .bind(hash_token(current))
+    .execute(pool)
+    .await?;
+    Ok(revoked.rows_affected())
+}
+
+/// The account of a session that is still active, keeping it active
+pub async fn authenticate(pool: &SqlitePool, session: &str) -> Res<Option<i64>> {
+    sqlx::query_scalar(
+        r#"
+        UPDATE account_sessions SET last_used_at = strftime('%s', 'now')
+        WHERE token_hash = ? AND last_used_at > strftime('%s', 'now') - ?
+        RETURNING account_id
+        "#,
+    )
+    .bind(hash_token(session))
+    .bind(SESSION_IDLE)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| e.into())
+}
+
+pub async fn fetch(pool: &SqlitePool, account_id: i64) -> Res<Account> {
+    let mut account: Account = sqlx::query_as(
+        r#"SELECT id, username, watch_token, settings, created_at FROM accounts WHERE id = ?"#,
+    )
+    .bind(account_id)
+    .fetch_one(pool)
+    .await?;
+    account.hidden_threads = sqlx::query_scalar(
+        r#"SELECT thread_id FROM hidden_threads WHERE account_id = ? ORDER BY created_at, thread_id"#,
+    )
+    .bind(account_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(account)
+}
+
+/// Replaces the settings, an object the frontends keep as they like
+pub async fn save_settings(pool: &SqlitePool, account_id: i64, settings: &Value) -> Res<()> {
+    if !settings.is_object() {
+        return Err("settings must be an object".into());
+    }
+    if settings.to_string().len() > MAX_SETTINGS_SIZE {
+        return Err(format!("settings can't be larger than {MAX_SETTINGS_SIZE} bytes").into());
+    }
+    sqlx::query(r#"UPDATE accounts SET settings = ? WHERE id = ?"#)
+        .bind(Json(settings))
+        .bind(account_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn hide_thread(pool: &SqlitePool, account_id: i64, thread_id: i64) -> Res<()> {
+    let exists: bool =
+        sqlx::query_scalar(r#"SELECT EXISTS(SELECT 1 FROM comments WHERE id = ? AND op IS NULL)"#)
+            .bind(thread_id)
+            .fetch_one(pool)
+            .await?;
+    if !exists {
+        return Err(StatusError(StatusCode::NOT_FOUND, "thread not found".to_string()).into());
+    }
+    sqlx::query(r#"INSERT OR IGNORE INTO hidden_threads (account_id, thread_id) VALUES (?, ?)"#)
+        .bind(account_id)
+        .bind(thread_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn unhide_thread(pool: &SqlitePool, account_id: i64, thread_id: i64) -> Res<()> {
+    sqlx::query(r#"DELETE FROM hidden_threads WHERE account_id = ? AND thread_id = ?"#)
+        .bind(account_id)
+        .bind(thread_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+#[test]
+fn test_check_username() {
+    assert!(check_username("anon_42").is_ok());
+    assert!(check_username("ab").is_err());
+    assert!(check_username("a".repeat(21).as_str()).is_err());
+    assert!(check_username("moot!").is_err());
+    assert!(check_username("名無し").is_err());
+}
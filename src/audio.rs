@@ -0,0 +1,244 @@
+//! Mp3, ogg and flac uploads, on the boards with `allow_audio`. The duration and bitrate are
+//! read from the headers of the file, and with the `ffmpeg` feature its waveform is drawn by
+//! ffmpeg as the thumbnail, otherwise audio gets a generic icon
+use std::path::Path;
+
+use crate::Res;
+use crate::thumbs::{Thumb, ThumbSettings};
+
+pub fn is_audio(mime: &str) -> bool {
+    matches!(
+        mime,
+        "audio/mpeg" | "audio/ogg" | "audio/opus" | "audio/x-flac"
+    )
+}
+
+#[derive(Default, Debug, PartialEq)]
+pub struct AudioInfo {
+    /// In seconds
+    pub duration: Option<f64>,
+    /// In kilobits per second
+    pub bitrate: Option<i64>,
+}
+
+pub fn probe(data: &[u8], mime: &str) -> AudioInfo {
+    let (duration, bitrate) = match mime {
+        "audio/mpeg" => probe_mp3(data),
+        "audio/ogg" | "audio/opus" => probe_ogg(data),
+        "audio/x-flac" => (probe_flac(data), None),
+        _ => (None, None),
+    };
+    let duration = duration.filter(|d| d.is_finite() && *d > 0.0);
+    // without a nominal bitrate, the average over the whole file
+    let bitrate =
+        bitrate.or_else(|| duration.map(|d| (data.len() as f64 * 8.0 / d / 1000.0) as i64));
+    AudioInfo { duration, bitrate }
+}
+
+/// The waveform of the audio, or the audio icon when it can't be drawn
+#[cfg_attr(not(feature = "ffmpeg"), allow(unused_variables))]
+pub async fn thumbnail(path: &Path, settings: ThumbSettings) -> Res<(Thumb, Option<Thumb>)> {
+    #[cfg(feature = "ffmpeg")]
+    match render_waveform(path, settings).await {
+        Ok(thumbs) => return Ok(thumbs),
+        Err(e) => tracing::warn!("failed to draw the waveform of {}: {e}", path.display()),
+    }
+    settings.audio_icon()
+}
+
+#[cfg(feature = "ffmpeg")]
+async fn render_waveform(path: &Path, settings: ThumbSettings) -> Res<(Thumb, Option<Thumb>)> {
+    use crate::media::Upload;
+
+    let waveform = Upload {
+        path: Upload::temp_path().with_extension("part.png"),
+        size: 0,
+        name: None,
+    };
+    let size = format!("{}x{}", settings.size, settings.size / 2);
+    let status = tokio::process::Command::new("ffmpeg")
+        .args(["-nostdin", "-loglevel", "error", "-i"])
+        .arg(path)
+        .args(["-filter_complex"])
+        .arg(format!(
+            "showwavespic=s={size}:split_channels=0:colors=#5a7dbf"
+        ))
+        .args(["-frames:v", "1", "-y"])
+        .arg(&waveform.path)
+        .status()
+        .await?;
+    if !status.success() {
+        return Err(format!("ffmpeg exited with {status}").into());
+    }
+    settings
+        .create_blocking(waveform.path.clone(), "image/png".to_string())
+        .await
+}
+
+fn probe_mp3(data: &[u8]) -> (Option<f64>, Option<i64>) {
+    const BITRATES_V1: [i64; 15] = [
+        0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+    ];
+    const BITRATES_V2: [i64; 15] = [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160];
+    let mut start = 0;
+    if data.starts_with(b"ID3") && data.len() >= 10 {
+        let size = data[6..10]
+            .iter()
+            .fold(0, |n, b| (n << 7) | (*b as usize & 0x7f));
+        let footer = if data[5] & 0x10 != 0 { 10 } else { 0 };
+        start = 10 + size + footer;
+    }
+    let Some(offset) = data.get(start..).and_then(|d| {
+        d.windows(2)
+            .position(|w| w[0] == 0xff && w[1] & 0xe0 == 0xe0)
+    }) else {
+        return (None, None);
+    };
+    let frame = &data[start + offset..];
+    let Some(header) = frame.get(..4) else {
+        return (None, None);
+    };
+    let version = (header[1] >> 3) & 3;
+    let layer = (header[1] >> 1) & 3;
+    let bitrate_index = (header[2] >> 4) as usize;
+    let rate_index = ((header[2] >> 2) & 3) as usize;
+    let mono = header[3] >> 6 == 3;
+    // only mpeg 1, 2 and 2.5 layer III, the other layers aren't mp3
+    if version == 1 || layer != 1 || bitrate_index == 15 || rate_index == 3 {
+        return (None, None);
+    }
+    let mpeg1 = version == 3;
+    let sample_rate = [44100.0, 48000.0, 32000.0][rate_index]
+        / match version {
+            3 => 1.0,
+            2 => 2.0,
+            _ => 4.0,
+        };
+    let samples = if mpeg1 { 1152.0 } else { 576.0 };
+    let side_info = match (mpeg1, mono) {
+        (true, false) => 32,
+        (true, true) | (false, false) => 17,
+        (false, true) => 9,
+    };
+    let be32 = |at: usize| {
+        frame
+            .get(at..at + 4)
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    };
+    let xing = 4 + side_info;
+    let frames = match frame.get(xing..xing + 4) {
+        Some(b"Xing" | b"Info") => be32(xing + 4)
+            .filter(|flags| flags & 1 != 0)
+            .and_then(|_| be32(xing + 8)),
+        _ => match frame.get(36..40) {
+            Some(b"VBRI") => be32(36 + 14),
+            _ => None,
+        },
+    };
+    match frames {
+        Some(frames) => (Some(frames as f64 * samples / sample_rate), None),
+        None => {
+            let bitrate = match mpeg1 {
+                true => BITRATES_V1[bitrate_index],
+                false => BITRATES_V2[bitrate_index],
+            };
+            let duration =
+                (bitrate > 0).then(|| frame.len() as f64 * 8.0 / (bitrate as f64 * 1000.0));
+            (duration, Some(bitrate).filter(|b| *b > 0))
+        }
+    }
+}
+
+fn probe_ogg(data: &[u8]) -> (Option<f64>, Option<i64>) {
+    if !data.starts_with(b"OggS") || data.len() < 28 {
+        return (None, None);
+    }
+    let segments = data[26] as usize;
+    let Some(packet) = data.get(27 + segments..) else {
+        return (None, None);
+    };
+    let le32 = |b: &[u8], at: usize| {
+        b.get(at..at + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    };
+    let (sample_rate, pre_skip, bitrate) = if packet.starts_with(b"\x01vorbis") {
+        let nominal = le32(packet, 20).map(|b| b as i32).filter(|b| *b > 0);
+        (
+            le32(packet, 12).map(f64::from),
+            0,
+            nominal.map(|b| b as i64 / 1000),
+        )
+    } else if packet.starts_with(b"OpusHead") && packet.len() >= 12 {
+        // opus positions always count 48kHz samples
+        let pre_skip = u16::from_le_bytes([packet[10], packet[11]]);
+        (Some(48000.0), pre_skip as u64, None)
+    } else {
+        return (None, None);
+    };
+    let last_page = data
+        .windows(4)
+        .rposition(|w| w == b"OggS")
+        .and_then(|at| data.get(at + 6..at + 14));
+    let granule = last_page.map(|g| u64::from_le_bytes(g.try_into().unwrap()));
+    let duration = granule
+        .zip(sample_rate.filter(|r| *r > 0.0))
+        .map(|(g, rate)| g.saturating_sub(pre_skip) as f64 / rate);
+    (duration, bitrate)
+}
+
+fn probe_flac(data: &[u8]) -> Option<f64> {
+    // the stream info is always the first metadata block
+    if !data.starts_with(b"fLaC") || data.get(4)? & 0x7f != 0 {
+        return None;
+    }
+    let info = u64::from_be_bytes(data.get(18..26)?.try_into().ok()?);
+    let sample_rate = info >> 44;
+    let samples = info & ((1 << 36) - 1);
+    (sample_rate > 0 && samples > 0).then(|| samples as f64 / sample_rate as f64)
+}
+
+#[test]
+fn test_probe() {
+    // a 128kbps mpeg 1 layer III frame header, repeated for a second of audio
+    let mut mp3 = b"ID3\x03\x00\x00\x00\x00\x00\x02ab".to_vec();
+    mp3.extend([0xff, 0xfb, 0x90, 0x00].iter().cycle().take(16000));
+    assert_eq!(
+        probe(&mp3, "audio/mpeg"),
+        AudioInfo {
+            duration: Some(1.0),
+            bitrate: Some(128)
+        }
+    );
+
+    let mut flac = b"fLaC\x00\x00\x00\x22".to_vec();
+    flac.extend([0; 10]);
+    // 44100Hz, stereo, 16 bits, 88200 samples
+    let info: u64 = (44100 << 44) | (1 << 41) | (15 << 36) | 88200;
+    flac.extend(info.to_be_bytes());
+    flac.resize(11025, 0);
+    assert_eq!(
+        probe(&flac, "audio/x-flac"),
+        AudioInfo {
+            duration: Some(2.0),
+            bitrate: Some(44)
+        }
+    );
+
+    let mut ogg = b"OggS\x00\x02".to_vec();
+    ogg.extend([0; 20]);
+    ogg.extend([1, 30]);
+    ogg.extend(b"\x01vorbis\x00\x00\x00\x00\x02");
+    ogg.extend(48000u32.to_le_bytes());
+    ogg.extend(0u32.to_le_bytes());
+    ogg.extend(96000u32.to_le_bytes());
+    ogg.extend(b"OggS\x00\x04");
+    ogg.extend(144000u64.to_le_bytes());
+    assert_eq!(
+        probe(&ogg, "audio/ogg"),
+        AudioInfo {
+            duration: Some(3.0),
+            bitrate: Some(96)
+        }
+    );
+    assert_eq!(probe(b"nothing", "audio/mpeg"), AudioInfo::default());
+}
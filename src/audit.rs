@@ -0,0 +1,36 @@
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use sqlx::prelude::FromRow;
+
+use crate::Res;
+
+/// A sensitive staff action, such as looking up what an ip posted
+#[derive(Serialize, Deserialize, FromRow)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub actor: String,
+    pub action: String,
+    pub target: String,
+    pub created_at: i64,
+}
+
+pub async fn record(pool: &SqlitePool, actor: &str, action: &str, target: &str) -> Res<()> {
+    sqlx::query(r#"INSERT INTO audit_log (actor, action, target) VALUES (?, ?, ?)"#)
+        .bind(actor)
+        .bind(action)
+        .bind(target)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn fetch(pool: &SqlitePool, before: Option<i64>, limit: i64) -> Res<Vec<AuditEntry>> {
+    sqlx::query_as(
+        r#"SELECT * FROM audit_log WHERE ?1 IS NULL OR id < ?1 ORDER BY id DESC LIMIT ?2"#,
+    )
+    .bind(before)
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| e.into())
+}
@@ -0,0 +1,223 @@
+use std::sync::LazyLock;
+
+use axum::http::StatusCode;
+use html_escape::decode_html_entities;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use sqlx::prelude::FromRow;
+
+use crate::bans::PublicBan;
+use crate::events::{EventBus, EventKind};
+use crate::{Res, StatusError, bans, text};
+
+static RE_TAGS: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<[^>]*>").unwrap());
+
+#[derive(Serialize, Deserialize, sqlx::Type, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum RuleTarget {
+    Comment,
+    FileName,
+    Url,
+}
+
+#[derive(Serialize, Deserialize, sqlx::Type, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum RuleAction {
+    Reject,
+    Ban,
+}
+
+/// A pattern moderators match against new posts, rules without a board apply to every board
+#[derive(Serialize, Deserialize, FromRow)]
+pub struct AutobanRule {
+    pub id: i64,
+    pub board: Option<String>,
+    pub pattern: String,
+    pub target: RuleTarget,
+    pub action: RuleAction,
+    pub reason: Option<String>,
+    pub ban_duration: Option<i64>,
+    pub is_enabled: bool,
+    pub hits: i64,
+    pub last_hit_at: Option<i64>,
+    pub created_at: i64,
+}
+
+/// The parts of a new post the rules look at, the text is the raw subject and comment
+pub struct Submission<'a> {
+    pub text: &'a str,
+    pub file_name: Option<&'a str>,
+}
+
+#[derive(Serialize, FromRow)]
+pub struct RuleMatch {
+    pub id: i64,
+    pub board: Option<String>,
+    pub board_post_no: i64,
+}
+
+#[derive(FromRow)]
+struct RecentPost {
+    #[sqlx(flatten)]
+    post: RuleMatch,
+    sub: Option<String>,
+    com: Option<String>,
+    sub_raw: Option<String>,
+    com_raw: Option<String>,
+    file_name: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct DryRun {
+    pub checked: usize,
+    pub matches: Vec<RuleMatch>,
+}
+
+impl AutobanRule {
+    fn matches(&self, re: &Regex, post: &Submission) -> bool {
+        match self.target {
+            RuleTarget::Comment => re.is_match(post.text),
+            RuleTarget::FileName => post.file_name.is_some_and(|f| re.is_match(f)),
+            RuleTarget::Url => text::find_urls(post.text)
+                .iter()
+                .any(|l| re.is_match(l.text)),
+        }
+    }
+}
+
+/// Applies the enabled rules of the board to the post, the first matching rule rejects it or
+/// bans the poster, and counts the hit
+pub async fn enforce(
+    pool: &SqlitePool,
+    events: &EventBus,
+    board: &str,
+    ip_hash: &str,
+    post: Submission<'_>,
+) -> Res<()> {
+    let rules: Vec<AutobanRule> = sqlx::query_as(
+        r#"SELECT * FROM autoban_rules WHERE is_enabled AND (board IS NULL OR board = ?) ORDER BY id"#,
+    )
+    .bind(board)
+    .fetch_all(pool)
+    .await?;
+    for rule in rules {
+        let re = Regex::new(&rule.pattern)?;
+        if !rule.matches(&re, &post) {
+            continue;
+        }
+        let mut tx = pool.begin().await?;
+        sqlx::query(
+            r#"UPDATE autoban_rules SET hits = hits + 1, last_hit_at = strftime('%s', 'now') WHERE id = ?"#,
+        )
+        .bind(rule.id)
+        .execute(&mut *tx)
+        .await?;
+        let reason = rule.reason.as_deref().unwrap_or("blacklisted content");
+        let mut ban = None;
+        let err = match rule.action {
+            RuleAction::Reject => StatusError(StatusCode::BAD_REQUEST, reason.to_string()),
+            RuleAction::Ban => {
+                ban = Some(
+                    bans::insert(
+                        &mut tx,
+                        ip_hash,
+                        Some(board),
+                        Some(reason),
+                        rule.ban_duration,
+                        true,
+                    )
+                    .await?,
+                );
+                StatusError(StatusCode::FORBIDDEN, format!("you are banned: {reason}"))
+            }
+        };
+        tx.commit().await?;
+        if let Some(ban) = &ban {
+            let ban = PublicBan::from(ban);
+            events.publish(EventKind::BanIssued, Some(board), &ban);
+        }
+        return Err(err.into());
+    }
+    Ok(())
+}
+
+/// Lists the posts of the last `window` seconds the rule would have matched, the posts made
+/// before the raw text was kept are approximated from their html
+pub async fn dry_run(pool: &SqlitePool, rule: &AutobanRule, window: i64) -> Res<DryRun> {
+    let re = Regex::new(&rule.pattern)?;
+    let posts: Vec<RecentPost> = sqlx::query_as(
+        r#"
+        SELECT c.id, COALESCE(t.board, c.board) AS board, c.board_post_no, c.sub, c.com, c.sub_raw, c.com_raw, c.file_name
+        FROM comments c
+        LEFT JOIN comments t ON t.id = c.op
+        WHERE c.created_at >= strftime('%s', 'now') - ?
+        AND (? IS NULL OR COALESCE(t.board, c.board) = ?)
+        ORDER BY c.id DESC
+        "#,
+    )
+    .bind(window)
+    .bind(&rule.board)
+    .bind(&rule.board)
+    .fetch_all(pool)
+    .await?;
+    let checked = posts.len();
+    let matches = posts
+        .into_iter()
+        .filter(|p| {
+            let text = [(&p.sub_raw, &p.sub), (&p.com_raw, &p.com)]
+                .into_iter()
+                .filter_map(|(raw, html)| raw.clone().or_else(|| html.as_deref().map(plain_text)))
+                .collect::<Vec<_>>()
+                .join("\n");
+            let post = Submission {
+                text: &text,
+                file_name: p.file_name.as_deref(),
+            };
+            rule.matches(&re, &post)
+        })
+        .map(|p| p.post)
+        .collect();
+    Ok(DryRun { checked, matches })
+}
+
+fn plain_text(html: &str) -> String {
+    let text = html.replace("<br>", "\n");
+    decode_html_entities(&RE_TAGS.replace_all(&text, "")).into_owned()
+}
+
+#[test]
+fn test_matches() {
+    let rule = |target| AutobanRule {
+        id: 1,
+        board: None,
+        pattern: r"(?i)casino".to_string(),
+        target,
+        action: RuleAction::Reject,
+        reason: None,
+        ban_duration: None,
+        is_enabled: true,
+        hits: 0,
+        last_hit_at: None,
+        created_at: 0,
+    };
+    let re = Regex::new(r"(?i)casino").unwrap();
+    let post = Submission {
+        text: "visit https://casino.example now",
+        file_name: Some("cat.png"),
+    };
+    assert!(rule(RuleTarget::Comment).matches(&re, &post));
+    assert!(rule(RuleTarget::Url).matches(&re, &post));
+    assert!(!rule(RuleTarget::FileName).matches(&re, &post));
+    let post = Submission {
+        text: "the casino is closed",
+        file_name: None,
+    };
+    assert!(!rule(RuleTarget::Url).matches(&re, &post));
+    assert_eq!(
+        plain_text("<span>&gt;a</span><br><b>b</b>"),
+        ">a\nb".to_string()
+    );
+}
@@ -0,0 +1,57 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+use crate::Res;
+use crate::media::fetch_media_names;
+
+/// Writes a consistent copy of the database to `out`, or with `with_media` a gzipped tarball
+/// holding the copy as `db.sqlite` and the media the posts refer to under `media/`
+pub async fn create_backup(pool: &SqlitePool, out: &Path, with_media: bool) -> Res<()> {
+    if !with_media {
+        return vacuum_into(pool, out).await;
+    }
+    let db_path = temp_path();
+    vacuum_into(pool, &db_path).await?;
+    let media = fetch_media_names(pool).await?;
+    let tarball = tokio::task::spawn_blocking({
+        let out = out.to_owned();
+        let db_path = db_path.clone();
+        move || write_tarball(&out, &db_path, &media)
+    })
+    .await;
+    tokio::fs::remove_file(&db_path).await?;
+    Ok(tarball??)
+}
+
+pub fn temp_path() -> PathBuf {
+    std::env::temp_dir().join(format!("blu-{}", Uuid::new_v4().simple()))
+}
+
+async fn vacuum_into(pool: &SqlitePool, out: &Path) -> Res<()> {
+    sqlx::query(r#"VACUUM INTO ?"#)
+        .bind(out.to_str().ok_or("invalid backup path")?)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+fn write_tarball(out: &Path, db_path: &Path, media: &HashSet<String>) -> std::io::Result<()> {
+    let mut tar = tar::Builder::new(GzEncoder::new(File::create(out)?, Compression::default()));
+    tar.append_path_with_name(db_path, "db.sqlite")?;
+    for name in media {
+        match File::open(format!("media/{name}")) {
+            Ok(mut file) => tar.append_file(format!("media/{name}"), &mut file)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                tracing::warn!("media {name} is missing, skipping it")
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    tar.into_inner()?.finish()?;
+    Ok(())
+}
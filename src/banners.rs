@@ -0,0 +1,78 @@
+use axum::http::StatusCode;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use sqlx::prelude::FromRow;
+use uuid::Uuid;
+
+use crate::media::{Upload, inspect_media, remove_media};
+use crate::{Res, StatusError};
+
+/// An image shown at the top of a board, a random active one is served for each request
+#[derive(Serialize, Deserialize, FromRow)]
+pub struct Banner {
+    pub id: i64,
+    pub board: String,
+    pub media_name: String,
+    pub media_ext: String,
+    pub media_w: Option<i64>,
+    pub media_h: Option<i64>,
+    pub is_active: bool,
+    pub created_at: i64,
+}
+
+/// Moves the image to the media directory as a banner of the board, banners have no thumbnails
+pub async fn save(pool: &SqlitePool, board: &str, upload: Upload, is_active: bool) -> Res<Banner> {
+    let (kind, media_w, media_h) = inspect_media(&upload.path).await?;
+    if kind.matcher_type() != infer::MatcherType::Image {
+        return Err(StatusError(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "banners have to be images".to_string(),
+        )
+        .into());
+    }
+    let media_name = Uuid::new_v4().to_string();
+    tokio::fs::rename(&upload.path, format!("media/{media_name}")).await?;
+    let banner = sqlx::query_as(
+        r#"
+        INSERT INTO banners (board, media_name, media_ext, media_type, media_w, media_h, is_active)
+        VALUES (?, ?, ?, ?, ?, ?, ?)
+        RETURNING *
+        "#,
+    )
+    .bind(board)
+    .bind(&media_name)
+    .bind(kind.extension())
+    .bind(kind.mime_type())
+    .bind(media_w)
+    .bind(media_h)
+    .bind(is_active)
+    .fetch_one(pool)
+    .await;
+    if banner.is_err() {
+        remove_media(&media_name).await?;
+    }
+    Ok(banner?)
+}
+
+pub async fn random(pool: &SqlitePool, board: &str) -> Res<Option<Banner>> {
+    let banner = sqlx::query_as(
+        r#"SELECT * FROM banners WHERE board = ? AND is_active ORDER BY random() LIMIT 1"#,
+    )
+    .bind(board)
+    .fetch_optional(pool)
+    .await?;
+    Ok(banner)
+}
+
+pub async fn delete(pool: &SqlitePool, board: &str, id: i64) -> Res<Option<Banner>> {
+    let banner: Option<Banner> =
+        sqlx::query_as(r#"DELETE FROM banners WHERE board = ? AND id = ? RETURNING *"#)
+            .bind(board)
+            .bind(id)
+            .fetch_optional(pool)
+            .await?;
+    if let Some(banner) = &banner {
+        remove_media(&banner.media_name).await?;
+    }
+    Ok(banner)
+}
@@ -0,0 +1,174 @@
+use axum::http::StatusCode;
+use serde::{Deserialize, Serialize};
+use sqlx::prelude::FromRow;
+use sqlx::{SqliteConnection, SqlitePool};
+
+use crate::media::remove_media;
+use crate::{Res, StatusError};
+
+#[derive(Serialize, Deserialize, FromRow)]
+pub struct Ban {
+    pub id: i64,
+    pub ip_hash: String,
+    pub board: Option<String>,
+    pub reason: Option<String>,
+    pub is_public: bool,
+    pub expires_at: Option<i64>,
+    pub created_at: i64,
+}
+
+/// A ban as shown on the public ban list, without anything that identifies the poster
+#[derive(Serialize, Deserialize, FromRow)]
+pub struct PublicBan {
+    pub board: Option<String>,
+    pub reason: Option<String>,
+    pub duration: Option<i64>,
+    pub expires_at: Option<i64>,
+    pub created_at: i64,
+}
+
+impl From<&Ban> for PublicBan {
+    fn from(ban: &Ban) -> Self {
+        Self {
+            board: ban.board.clone(),
+            reason: ban.reason.clone(),
+            duration: ban.expires_at.map(|e| e - ban.created_at),
+            expires_at: ban.expires_at,
+            created_at: ban.created_at,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct BanResult {
+    pub ban: Ban,
+    pub deleted: Vec<i64>,
+    pub boards: Vec<String>,
+}
+
+/// Rejects the posts of banned ips, until the ban expires
+pub async fn check(pool: &SqlitePool, ip_hash: &str) -> Res<()> {
+    let reason: Option<Option<String>> = sqlx::query_scalar(
+        r#"
+        SELECT reason FROM bans
+        WHERE ip_hash = ? AND (expires_at IS NULL OR expires_at > strftime('%s', 'now'))
+        ORDER BY expires_at IS NULL DESC, expires_at DESC
+        "#,
+    )
+    .bind(ip_hash)
+    .fetch_optional(pool)
+    .await?;
+    let Some(reason) = reason else {
+        return Ok(());
+    };
+    let message = match reason {
+        Some(reason) => format!("you are banned: {reason}"),
+        None => "you are banned".to_string(),
+    };
+    Err(StatusError(StatusCode::FORBIDDEN, message).into())
+}
+
+/// Bans the ip hash for `duration` seconds, or forever
+pub async fn insert(
+    conn: &mut SqliteConnection,
+    ip_hash: &str,
+    board: Option<&str>,
+    reason: Option<&str>,
+    duration: Option<i64>,
+    is_public: bool,
+) -> Res<Ban> {
+    Ok(sqlx::query_as(
+        r#"
+        INSERT INTO bans (ip_hash, board, reason, is_public, expires_at)
+        VALUES (?, ?, ?, ?, strftime('%s', 'now') + ?)
+        RETURNING *
+        "#,
+    )
+    .bind(ip_hash)
+    .bind(board)
+    .bind(reason)
+    .bind(is_public)
+    .bind(duration)
+    .fetch_one(conn)
+    .await?)
+}
+
+/// Bans the ip the post was made from and deletes the posts it made in the last `window`
+/// seconds on every board, threads with their replies, in a single transaction. The media is
+/// removed once the posts are gone
+pub async fn ban_and_purge(
+    pool: &SqlitePool,
+    post_id: i64,
+    reason: Option<&str>,
+    duration: Option<i64>,
+    is_public: bool,
+    window: i64,
+) -> Res<BanResult> {
+    let mut tx = pool.begin().await?;
+    let (ip_hash, board): (Option<String>, Option<String>) = sqlx::query_as(
+        r#"
+        SELECT c.ip_hash, COALESCE(t.board, c.board) FROM comments c
+        LEFT JOIN comments t ON t.id = c.op
+        WHERE c.id = ?
+        "#,
+    )
+    .bind(post_id)
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| StatusError(StatusCode::NOT_FOUND, "post not found".to_string()))?;
+    let ip_hash = ip_hash.ok_or("the ip of the post is no longer known")?;
+
+    let ban = insert(
+        &mut tx,
+        &ip_hash,
+        board.as_deref(),
+        reason,
+        duration,
+        is_public,
+    )
+    .await?;
+
+    let posts: Vec<(i64, String)> = sqlx::query_as(
+        r#"
+        SELECT c.id, COALESCE(t.board, c.board) FROM comments c
+        LEFT JOIN comments t ON t.id = c.op
+        WHERE c.ip_hash = ? AND c.created_at >= strftime('%s', 'now') - ?
+        ORDER BY c.id
+        "#,
+    )
+    .bind(&ip_hash)
+    .bind(window)
+    .fetch_all(&mut *tx)
+    .await?;
+    let ids = serde_json::to_string(&posts.iter().map(|p| p.0).collect::<Vec<_>>())?;
+    let media: Vec<(Option<String>, Option<String>, Option<String>)> = sqlx::query_as(
+        r#"
+        SELECT media_name, thumb_name, catalog_thumb_name FROM comments
+        WHERE id IN (SELECT value FROM json_each(?1)) OR op IN (SELECT value FROM json_each(?1))
+        "#,
+    )
+    .bind(&ids)
+    .fetch_all(&mut *tx)
+    .await?;
+    sqlx::query(r#"DELETE FROM comments WHERE op IN (SELECT value FROM json_each(?))"#)
+        .bind(&ids)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query(r#"DELETE FROM comments WHERE id IN (SELECT value FROM json_each(?))"#)
+        .bind(&ids)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+
+    for name in media.into_iter().flat_map(|(m, t, c)| [m, t, c]).flatten() {
+        remove_media(&name).await?;
+    }
+    let mut boards: Vec<String> = posts.iter().map(|p| p.1.clone()).collect();
+    boards.sort();
+    boards.dedup();
+    Ok(BanResult {
+        ban,
+        deleted: posts.into_iter().map(|p| p.0).collect(),
+        boards,
+    })
+}
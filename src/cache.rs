@@ -0,0 +1,115 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use axum::body::Bytes;
+use moka::future::Cache;
+use serde::Serialize;
+
+use crate::Res;
+
+/// The pages kept in the cache, threads are keyed by board
+#[derive(Clone, Hash, PartialEq, Eq)]
+pub enum CacheKey {
+    Boards,
+    Threads(String),
+    Overboard,
+    /// The stats of a board, or of the whole site
+    Stats(Option<String>),
+}
+
+/// Short lived cache of the serialized read pages, disabled with CACHE_TTL=0
+pub struct ResponseCache {
+    pages: Option<Cache<CacheKey, Bytes>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Serialize)]
+pub struct CacheStats {
+    hits: u64,
+    misses: u64,
+    entries: u64,
+}
+
+impl ResponseCache {
+    pub fn from_env() -> Self {
+        let ttl = std::env::var("CACHE_TTL")
+            .ok()
+            .and_then(|t| t.parse().ok())
+            .unwrap_or(5);
+        let pages = (ttl > 0).then(|| {
+            Cache::builder()
+                .max_capacity(10_000)
+                .time_to_live(Duration::from_secs(ttl))
+                .build()
+        });
+        Self {
+            pages,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the cached page, or loads and serializes it as the `Ok` of an api response
+    pub async fn get_or_load<T: Serialize>(
+        &self,
+        key: CacheKey,
+        load: impl Future<Output = Res<T>>,
+    ) -> Res<Bytes> {
+        let load = async {
+            let page = load.await.map_err(|e| e.to_string())?;
+            serde_json::to_vec(&Ok::<_, String>(page))
+                .map(Bytes::from)
+                .map_err(|e| e.to_string())
+        };
+        let Some(pages) = &self.pages else {
+            return Ok(load.await?);
+        };
+        let entry = pages
+            .entry(key)
+            .or_try_insert_with(load)
+            .await
+            .map_err(|e| e.to_string())?;
+        let counter = match entry.is_fresh() {
+            true => &self.misses,
+            false => &self.hits,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        Ok(entry.into_value())
+    }
+
+    /// Drops the pages a write to the board may have changed
+    pub async fn invalidate_board(&self, board: &str) {
+        if let Some(pages) = &self.pages {
+            pages
+                .invalidate(&CacheKey::Threads(board.to_string()))
+                .await;
+            pages.invalidate(&CacheKey::Boards).await;
+            pages.invalidate(&CacheKey::Overboard).await;
+            pages
+                .invalidate(&CacheKey::Stats(Some(board.to_string())))
+                .await;
+            pages.invalidate(&CacheKey::Stats(None)).await;
+        }
+    }
+
+    pub fn invalidate_all(&self) {
+        if let Some(pages) = &self.pages {
+            pages.invalidate_all();
+        }
+    }
+
+    pub async fn stats(&self) -> CacheStats {
+        let mut entries = 0;
+        if let Some(pages) = &self.pages {
+            // the count is only updated by the pending maintenance
+            pages.run_pending_tasks().await;
+            entries = pages.entry_count();
+        }
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries,
+        }
+    }
+}
@@ -0,0 +1,220 @@
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use clap::{Parser, Subcommand};
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+use crate::media::fetch_media_names;
+use crate::{Board, PostFormat, Res};
+
+/// Files younger than this are never collected, they may belong to a post being created
+const GC_GRACE_PERIOD: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Parser)]
+#[command(version, about = "A toy imageboard engine")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Default)]
+pub enum Command {
+    /// Run the migrations and serve the api on PORT (default)
+    #[default]
+    Serve,
+    /// Run the migrations and exit
+    Migrate,
+    /// Generate a new admin token, used when ADMIN_TOKEN is not set
+    CreateAdmin,
+    /// Remove the media files no post refers to
+    GcMedia {
+        /// Only list the files that would be removed
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Print post and media statistics
+    Stats,
+    /// Render the posts again from their raw text, after the formatting or the wordfilters changed
+    Rerender {
+        #[arg(long)]
+        board: Option<String>,
+    },
+    /// Write a consistent copy of the database to OUT
+    Backup {
+        out: PathBuf,
+        /// Write a gzipped tarball with the database and the media instead
+        #[arg(long)]
+        media: bool,
+    },
+    /// Export boards with their threads as JSON
+    Export {
+        /// The boards to export, all of them if not given
+        #[arg(long)]
+        board: Vec<String>,
+        /// Embed the media as base64
+        #[arg(long)]
+        media: bool,
+        /// Write to a file instead of stdout
+        #[arg(long, short)]
+        out: Option<PathBuf>,
+    },
+    /// Decrypt the copy of the media kept when a takedown was actioned, with TAKEDOWN_KEY
+    DecryptTakedown {
+        /// The copy in legal/
+        file: PathBuf,
+        out: PathBuf,
+    },
+    /// Import boards exported with export, the boards must not exist yet
+    Import { file: PathBuf },
+    /// Import a board from a vichan or Tinyboard instance through its JSON api
+    ImportVichan {
+        /// The url of the instance, such as https://example.org
+        url: String,
+        /// The board to import
+        #[arg(long)]
+        board: String,
+        /// The code of the new board, the same as the imported one if not given
+        #[arg(long)]
+        code: Option<String>,
+        /// Skip downloading the media
+        #[arg(long)]
+        no_media: bool,
+    },
+}
+
+pub async fn create_admin(pool: &SqlitePool) -> Res<()> {
+    let token = Uuid::new_v4().simple().to_string();
+    sqlx::query(
+        r#"
+        INSERT INTO settings (name, value) VALUES ('admin_token', ?)
+        ON CONFLICT (name) DO UPDATE SET value = excluded.value
+        "#,
+    )
+    .bind(&token)
+    .execute(pool)
+    .await?;
+    if std::env::var("ADMIN_TOKEN").is_ok_and(|t| !t.is_empty()) {
+        eprintln!("warning: ADMIN_TOKEN is set and takes precedence over the generated token");
+    }
+    println!("{token}");
+    Ok(())
+}
+
+pub async fn gc_media(pool: &SqlitePool, dry_run: bool) -> Res<()> {
+    let (files, bytes) = collect_media(pool, dry_run).await?;
+    if dry_run {
+        for name in &files {
+            println!("{name}");
+        }
+    }
+    let verb = if dry_run { "would remove" } else { "removed" };
+    println!("{verb} {} files, {bytes} bytes", files.len());
+    Ok(())
+}
+
+/// Removes the media files no post refers to, or only lists them. Returns their names and size
+pub async fn collect_media(pool: &SqlitePool, dry_run: bool) -> Res<(Vec<String>, u64)> {
+    let referenced = fetch_media_names(pool).await?;
+
+    let (mut files, mut bytes) = (Vec::new(), 0);
+    let mut entries = tokio::fs::read_dir("media").await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let meta = entry.metadata().await?;
+        let age = SystemTime::now()
+            .duration_since(meta.modified()?)
+            .unwrap_or_default();
+        if !meta.is_file() || referenced.contains(&name) || age < GC_GRACE_PERIOD {
+            continue;
+        }
+        if !dry_run {
+            tokio::fs::remove_file(entry.path()).await?;
+        }
+        files.push(name);
+        bytes += meta.len();
+    }
+    Ok((files, bytes))
+}
+
+/// Posts made before the raw text was kept only have their html and are left as they are
+pub async fn rerender(pool: &SqlitePool, board: Option<&str>) -> Res<()> {
+    let boards: Vec<Board> = sqlx::query_as(r#"SELECT * FROM boards WHERE ? IS NULL OR code = ?"#)
+        .bind(board)
+        .bind(board)
+        .fetch_all(pool)
+        .await?;
+    if let (Some(code), true) = (board, boards.is_empty()) {
+        return Err(format!("board {code} not found").into());
+    }
+    let mut count = 0;
+    for board in &boards {
+        let format = PostFormat::load(pool, Some(board)).await?;
+        let posts: Vec<(i64, bool, Option<String>, Option<String>)> = sqlx::query_as(
+            r#"
+            SELECT c.id, c.op IS NULL, c.sub_raw, c.com_raw
+            FROM comments c
+            LEFT JOIN comments t ON t.id = c.op
+            WHERE COALESCE(t.board, c.board) = ? AND (c.sub_raw IS NOT NULL OR c.com_raw IS NOT NULL)
+            "#,
+        )
+        .bind(&board.code)
+        .fetch_all(pool)
+        .await?;
+        let mut tx = pool.begin().await?;
+        for (id, is_op, sub_raw, com_raw) in &posts {
+            let sub = sub_raw.as_deref().filter(|_| *is_op);
+            sqlx::query(r#"UPDATE comments SET sub = COALESCE(?, sub), com = COALESCE(?, com) WHERE id = ?"#)
+                .bind(sub.map(|sub| format.encode_subject(sub)))
+                .bind(com_raw.as_deref().map(|com| format.encode_comment(com)))
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        count += posts.len();
+    }
+    println!("rendered {count} posts");
+    Ok(())
+}
+
+pub async fn stats(pool: &SqlitePool) -> Res<()> {
+    let boards: Vec<(String, i64, i64, i64, i64)> = sqlx::query_as(
+        r#"
+        SELECT b.code,
+        COUNT(DISTINCT t.id),
+        COUNT(p.id),
+        COUNT(p.media_name),
+        COALESCE(SUM(p.media_size), 0) + COALESCE(SUM(p.thumb_size), 0)
+        FROM boards b
+        LEFT JOIN comments t ON t.board = b.code AND t.op IS NULL
+        LEFT JOIN comments p ON p.id = t.id OR p.op = t.id
+        GROUP BY b.code
+        ORDER BY b.code
+        "#,
+    )
+    .fetch_all(pool)
+    .await?;
+    let held: i64 = sqlx::query_scalar(r#"SELECT COUNT(*) FROM comments WHERE is_held"#)
+        .fetch_one(pool)
+        .await?;
+
+    println!(
+        "{:<8}{:>10}{:>10}{:>10}{:>14}",
+        "board", "threads", "posts", "media", "bytes"
+    );
+    for (code, threads, posts, media, bytes) in &boards {
+        println!("{code:<8}{threads:>10}{posts:>10}{media:>10}{bytes:>14}");
+    }
+    let total = |f: fn(&(String, i64, i64, i64, i64)) -> i64| boards.iter().map(f).sum::<i64>();
+    println!(
+        "{:<8}{:>10}{:>10}{:>10}{:>14}",
+        "total",
+        total(|b| b.1),
+        total(|b| b.2),
+        total(|b| b.3),
+        total(|b| b.4)
+    );
+    println!("{held} posts held for approval");
+    Ok(())
+}
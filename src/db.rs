@@ -0,0 +1,79 @@
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use sqlx::SqlitePool;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions, SqliteSynchronous};
+
+use crate::Res;
+
+/// The read only connections used by the handlers that never write, in WAL mode they read
+/// alongside the writer instead of queueing behind it
+#[derive(Clone)]
+pub struct ReadPool(pub Arc<SqlitePool>);
+
+/// Opens the writer, a single connection so writes are serialized by the pool rather than
+/// failing with SQLITE_BUSY, with the pragmas from DB_JOURNAL_MODE (default wal),
+/// DB_SYNCHRONOUS (default normal) and DB_BUSY_TIMEOUT in milliseconds (default 5000)
+pub async fn connect(database_url: &str) -> Res<SqlitePool> {
+    let journal_mode = match std::env::var("DB_JOURNAL_MODE") {
+        Ok(mode) => SqliteJournalMode::from_str(&mode)?,
+        Err(_) => SqliteJournalMode::Wal,
+    };
+    let synchronous = match std::env::var("DB_SYNCHRONOUS") {
+        Ok(mode) => SqliteSynchronous::from_str(&mode)?,
+        Err(_) => SqliteSynchronous::Normal,
+    };
+    let options = SqliteConnectOptions::from_str(database_url)?
+        .journal_mode(journal_mode)
+        .synchronous(synchronous)
+        .busy_timeout(busy_timeout()?);
+    Ok(SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect_with(options)
+        .await?)
+}
+
+/// Opens the writer, retrying DB_CONNECT_RETRIES times (default 10) with a backoff doubling
+/// from a quarter of a second up to 5 seconds, for the volumes mounted after the server starts
+pub async fn connect_with_retry(database_url: &str) -> Res<SqlitePool> {
+    let retries: u32 = match std::env::var("DB_CONNECT_RETRIES") {
+        Ok(n) => n.parse()?,
+        Err(_) => 10,
+    };
+    let mut backoff = Duration::from_millis(250);
+    for attempt in 1.. {
+        match connect(database_url).await {
+            Err(e) if attempt <= retries => {
+                tracing::warn!("failed to open the database, retrying in {backoff:?}: {e}");
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(Duration::from_secs(5));
+            }
+            res => return res,
+        }
+    }
+    unreachable!()
+}
+
+/// Opens DB_MAX_CONNECTIONS (default 10) read only connections, the database must exist
+pub async fn connect_readers(database_url: &str) -> Res<ReadPool> {
+    let max_connections = match std::env::var("DB_MAX_CONNECTIONS") {
+        Ok(n) => n.parse()?,
+        Err(_) => 10,
+    };
+    let options = SqliteConnectOptions::from_str(database_url)?
+        .read_only(true)
+        .busy_timeout(busy_timeout()?);
+    let pool = SqlitePoolOptions::new()
+        .max_connections(max_connections)
+        .connect_with(options)
+        .await?;
+    Ok(ReadPool(Arc::new(pool)))
+}
+
+fn busy_timeout() -> Res<Duration> {
+    match std::env::var("DB_BUSY_TIMEOUT") {
+        Ok(ms) => Ok(Duration::from_millis(ms.parse()?)),
+        Err(_) => Ok(Duration::from_secs(5)),
+    }
+}
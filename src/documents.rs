@@ -0,0 +1,97 @@
+//! Pdf and epub uploads, on the boards with `allow_documents`. With the `poppler` feature the
+//! first page of a pdf is rendered by `pdftoppm` as its thumbnail and its pages are counted by
+//! `pdfinfo`, otherwise documents get a generic icon and the pages are counted from the file
+use std::path::Path;
+use std::sync::LazyLock;
+
+use regex::bytes::Regex;
+
+use crate::Res;
+use crate::thumbs::{Thumb, ThumbSettings};
+
+static RE_PAGE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"/Type\s*/Page(?:[^s]|$)").unwrap());
+
+pub const PDF: &str = "application/pdf";
+pub const EPUB: &str = "application/epub+zip";
+
+pub fn is_document(mime: &str) -> bool {
+    matches!(mime, PDF | EPUB)
+}
+
+/// The cover of a pdf, or the document icon when it can't be rendered
+#[cfg_attr(not(feature = "poppler"), allow(unused_variables))]
+pub async fn thumbnail(
+    path: &Path,
+    mime: &str,
+    settings: ThumbSettings,
+) -> Res<(Thumb, Option<Thumb>)> {
+    #[cfg(feature = "poppler")]
+    if mime == PDF {
+        match render_cover(path, settings).await {
+            Ok(thumbs) => return Ok(thumbs),
+            Err(e) => tracing::warn!("failed to render the cover of {}: {e}", path.display()),
+        }
+    }
+    settings.document_icon()
+}
+
+/// The number of pages of a pdf, epubs have no fixed pages
+pub async fn page_count(path: &Path, mime: &str) -> Res<Option<i64>> {
+    if mime != PDF {
+        return Ok(None);
+    }
+    #[cfg(feature = "poppler")]
+    {
+        let out = tokio::process::Command::new("pdfinfo")
+            .arg(path)
+            .output()
+            .await?;
+        let pages = String::from_utf8_lossy(&out.stdout)
+            .lines()
+            .find_map(|l| l.strip_prefix("Pages:")?.trim().parse().ok());
+        if pages.is_some() {
+            return Ok(pages);
+        }
+    }
+    Ok(count_pages(&tokio::fs::read(path).await?))
+}
+
+/// Counts the page objects, which misses the pages kept in compressed object streams
+fn count_pages(pdf: &[u8]) -> Option<i64> {
+    Some(RE_PAGE.find_iter(pdf).count() as i64).filter(|&n| n > 0)
+}
+
+#[cfg(feature = "poppler")]
+async fn render_cover(path: &Path, settings: ThumbSettings) -> Res<(Thumb, Option<Thumb>)> {
+    use crate::media::Upload;
+
+    let prefix = Upload::temp_path();
+    // pdftoppm appends the extension to the prefix
+    let cover = Upload {
+        path: prefix.with_extension("part.png"),
+        size: 0,
+        name: None,
+    };
+    let status = tokio::process::Command::new("pdftoppm")
+        .args(["-png", "-singlefile", "-f", "1", "-l", "1", "-scale-to"])
+        .arg(settings.size.to_string())
+        .arg(path)
+        .arg(&prefix)
+        .status()
+        .await?;
+    if !status.success() {
+        return Err(format!("pdftoppm exited with {status}").into());
+    }
+    settings
+        .create_blocking(cover.path.clone(), "image/png".to_string())
+        .await
+}
+
+#[test]
+fn test_count_pages() {
+    let pdf = b"1 0 obj << /Type /Pages /Kids [2 0 R 3 0 R] /Count 2 >> endobj
+        2 0 obj << /Type /Page /Parent 1 0 R >> endobj
+        3 0 obj <</Type/Page/Parent 1 0 R>> endobj";
+    assert_eq!(count_pages(pdf), Some(2));
+    assert_eq!(count_pages(b"%PDF-1.7 nothing here"), None);
+}
@@ -0,0 +1,386 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+
+use html_escape::decode_html_entities;
+use regex::Regex;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use sqlx::prelude::FromRow;
+
+use crate::{Comment, Res, text};
+
+const MAX_LINKS: usize = 3;
+const MAX_PAGE_SIZE: usize = 1024 * 1024;
+const FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+static RE_META: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"(?is)<meta\s[^>]*>"#).unwrap());
+static RE_ATTR: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(?is)([a-z:]+)\s*=\s*("[^"]*"|'[^']*')"#).unwrap());
+
+#[derive(Serialize, Deserialize, FromRow, Clone, PartialEq, Debug, Default)]
+pub struct Embed {
+    pub url: String,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub image: Option<String>,
+    pub provider: Option<String>,
+}
+
+#[derive(FromRow)]
+struct PostEmbed {
+    post_id: i64,
+    #[sqlx(flatten)]
+    embed: Embed,
+}
+
+/// Link previews for the hosts in EMBED_HOSTS, a comma separated list that also matches
+/// subdomains, fetched in the background when a post is made and kept for EMBED_TTL seconds
+pub struct Embeds {
+    hosts: Vec<String>,
+    ttl: i64,
+    client: reqwest::Client,
+}
+
+#[derive(Deserialize)]
+struct OEmbed {
+    title: Option<String>,
+    author_name: Option<String>,
+    provider_name: Option<String>,
+    thumbnail_url: Option<String>,
+}
+
+impl Embeds {
+    pub fn from_env() -> Res<Option<Arc<Self>>> {
+        let Some(hosts) = std::env::var("EMBED_HOSTS").ok().filter(|h| !h.is_empty()) else {
+            return Ok(None);
+        };
+        let hosts: Vec<String> = hosts.split(',').map(|h| h.trim().to_lowercase()).collect();
+        let redirect_hosts = hosts.clone();
+        let client = reqwest::Client::builder()
+            .timeout(FETCH_TIMEOUT)
+            .user_agent(concat!("blu/", env!("CARGO_PKG_VERSION")))
+            .dns_resolver(Arc::new(PublicResolver))
+            .redirect(reqwest::redirect::Policy::custom(move |attempt| {
+                let allowed = attempt
+                    .url()
+                    .host_str()
+                    .is_some_and(|h| host_allowed(&redirect_hosts, h))
+                    && check_literal(attempt.url()).is_ok();
+                match allowed && attempt.previous().len() < 3 {
+                    true => attempt.follow(),
+                    false => attempt.stop(),
+                }
+            }))
+            .build()?;
+        Ok(Some(Arc::new(Self {
+            hosts,
+            ttl: std::env::var("EMBED_TTL")
+                .ok()
+                .and_then(|t| t.parse().ok())
+                .unwrap_or(7 * 24 * 60 * 60),
+            client,
+        })))
+    }
+
+    /// The links of the text to allowed hosts, at most a few per post
+    pub fn links(&self, text: &str) -> Vec<String> {
+        let mut links: Vec<String> = Vec::new();
+        for link in text::find_urls(text) {
+            let url = link.url.as_str();
+            let allowed = link
+                .url
+                .host_str()
+                .is_some_and(|h| host_allowed(&self.hosts, h));
+            if allowed && !links.iter().any(|l| l == url) && links.len() < MAX_LINKS {
+                links.push(url.to_string());
+            }
+        }
+        links
+    }
+
+    /// Replaces the links of the post, the ones not fetched recently are fetched in the
+    /// background
+    pub async fn save_links(
+        self: &Arc<Self>,
+        pool: &SqlitePool,
+        post_id: i64,
+        text: &str,
+    ) -> Res<()> {
+        let links = self.links(text);
+        let mut tx = pool.begin().await?;
+        sqlx::query(r#"DELETE FROM post_links WHERE post_id = ?"#)
+            .bind(post_id)
+            .execute(&mut *tx)
+            .await?;
+        for url in &links {
+            sqlx::query(r#"INSERT OR IGNORE INTO post_links (post_id, url) VALUES (?, ?)"#)
+                .bind(post_id)
+                .bind(url)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+
+        let mut stale = Vec::new();
+        for url in links {
+            let fresh: bool = sqlx::query_scalar(
+                r#"SELECT EXISTS(SELECT 1 FROM link_embeds WHERE url = ? AND fetched_at >= strftime('%s', 'now') - ?)"#,
+            )
+            .bind(&url)
+            .bind(self.ttl)
+            .fetch_one(pool)
+            .await?;
+            if !fresh {
+                stale.push(url);
+            }
+        }
+        if !stale.is_empty() {
+            let embeds = self.clone();
+            let pool = pool.clone();
+            tokio::spawn(async move {
+                for url in stale {
+                    embeds.refresh(&pool, &url).await;
+                }
+            });
+        }
+        Ok(())
+    }
+
+    /// Failures are stored as empty previews, so broken links aren't fetched again until they
+    /// expire
+    async fn refresh(&self, pool: &SqlitePool, url: &str) {
+        let embed = match self.fetch(url).await {
+            Ok(embed) => embed,
+            Err(e) => {
+                tracing::warn!("failed to fetch the preview of {url}: {e}");
+                Embed {
+                    url: url.to_string(),
+                    ..Embed::default()
+                }
+            }
+        };
+        let saved = sqlx::query(
+            r#"
+            INSERT INTO link_embeds (url, title, description, image, provider, fetched_at)
+            VALUES (?, ?, ?, ?, ?, strftime('%s', 'now'))
+            ON CONFLICT (url) DO UPDATE SET
+                title = excluded.title,
+                description = excluded.description,
+                image = excluded.image,
+                provider = excluded.provider,
+                fetched_at = excluded.fetched_at
+            "#,
+        )
+        .bind(&embed.url)
+        .bind(&embed.title)
+        .bind(&embed.description)
+        .bind(&embed.image)
+        .bind(&embed.provider)
+        .execute(pool)
+        .await;
+        if let Err(e) = saved {
+            tracing::warn!("failed to save the preview of {url}: {e}");
+        }
+    }
+
+    async fn fetch(&self, url: &str) -> Result<Embed, Box<dyn std::error::Error + Send + Sync>> {
+        let parsed = reqwest::Url::parse(url)?;
+        let host = parsed.host_str().ok_or("url without host")?.to_lowercase();
+        check_literal(&parsed)?;
+
+        let oembed = match host.trim_start_matches("www.") {
+            "youtube.com" | "m.youtube.com" | "youtu.be" => Some("https://www.youtube.com/oembed"),
+            "twitter.com" | "x.com" => Some("https://publish.twitter.com/oembed"),
+            _ => None,
+        };
+        if let Some(endpoint) = oembed {
+            let text = self
+                .client
+                .get(endpoint)
+                .query(&[("url", url), ("format", "json")])
+                .send()
+                .await?
+                .error_for_status()?
+                .text()
+                .await?;
+            let oembed: OEmbed = serde_json::from_str(&text)?;
+            return Ok(Embed {
+                url: url.to_string(),
+                title: oembed.title,
+                description: oembed.author_name,
+                image: oembed.thumbnail_url,
+                provider: oembed.provider_name,
+            });
+        }
+
+        let mut res = self.client.get(url).send().await?.error_for_status()?;
+        let mut page = Vec::new();
+        while let Some(chunk) = res.chunk().await? {
+            page.extend_from_slice(&chunk);
+            if page.len() >= MAX_PAGE_SIZE {
+                break;
+            }
+        }
+        let mut embed = parse_opengraph(&String::from_utf8_lossy(&page));
+        embed.url = url.to_string();
+        Ok(embed)
+    }
+}
+
+/// Attaches the previews fetched for the links of the comments
+pub async fn attach(pool: &SqlitePool, comments: &mut [Comment]) -> Res<()> {
+    let ids = serde_json::to_string(&comments.iter().map(|c| c.id).collect::<Vec<_>>())?;
+    let embeds: Vec<PostEmbed> = sqlx::query_as(
+        r#"
+        SELECT l.post_id, e.url, e.title, e.description, e.image, e.provider
+        FROM post_links l
+        JOIN link_embeds e ON e.url = l.url
+        WHERE l.post_id IN (SELECT value FROM json_each(?))
+        AND (e.title IS NOT NULL OR e.image IS NOT NULL)
+        ORDER BY l.rowid
+        "#,
+    )
+    .bind(ids)
+    .fetch_all(pool)
+    .await?;
+    for comment in comments {
+        comment.embeds = embeds
+            .iter()
+            .filter(|e| e.post_id == comment.id)
+            .map(|e| e.embed.clone())
+            .collect();
+    }
+    Ok(())
+}
+
+fn host_allowed(hosts: &[String], host: &str) -> bool {
+    let host = host.to_lowercase();
+    hosts
+        .iter()
+        .any(|h| host == *h || host.ends_with(&format!(".{h}")))
+}
+
+/// Resolves the hosts of the previews and refuses the ones with private addresses, so links
+/// can't reach the internal network. The checked addresses are the ones connected to, which a
+/// host changing its records between a check and the request can't get around
+struct PublicResolver;
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> =
+                tokio::net::lookup_host((name.as_str(), 0)).await?.collect();
+            if addrs.iter().any(|addr| !is_public(addr.ip())) {
+                return Err(format!("{} resolves to a private address", name.as_str()).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Refuses urls to private ip addresses, which are connected to without being resolved
+fn check_literal(url: &reqwest::Url) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let host = url.host_str().unwrap_or_default();
+    let Ok(ip) = host.trim_matches(['[', ']']).parse::<IpAddr>() else {
+        return Ok(());
+    };
+    match is_public(ip) {
+        true => Ok(()),
+        false => Err(format!("{ip} is a private address").into()),
+    }
+}
+
+/// Whether the address is on the internet, the ipv4 addresses mapped in ipv6 are checked as
+/// ipv4
+fn is_public(ip: IpAddr) -> bool {
+    match ip.to_canonical() {
+        IpAddr::V4(ip) => {
+            let shared = ip.octets()[0] == 100 && ip.octets()[1] & 0xc0 == 64;
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || shared)
+        }
+        IpAddr::V6(ip) => {
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_unique_local()
+                || ip.is_unicast_link_local())
+        }
+    }
+}
+
+fn parse_opengraph(page: &str) -> Embed {
+    let mut embed = Embed::default();
+    for meta in RE_META.find_iter(page) {
+        let mut property = None;
+        let mut content = None;
+        for attr in RE_ATTR.captures_iter(meta.as_str()) {
+            let value = attr[2][1..attr[2].len() - 1].to_string();
+            match attr[1].to_lowercase().as_str() {
+                "property" | "name" => property = Some(value.to_lowercase()),
+                "content" => content = Some(decode_html_entities(&value).into_owned()),
+                _ => {}
+            }
+        }
+        let (Some(property), Some(content)) = (property, content) else {
+            continue;
+        };
+        let field = match property.as_str() {
+            "og:title" => &mut embed.title,
+            "og:description" => &mut embed.description,
+            "og:image" => &mut embed.image,
+            "og:site_name" => &mut embed.provider,
+            _ => continue,
+        };
+        field.get_or_insert(content);
+    }
+    embed
+}
+
+#[test]
+fn test_parse_opengraph() {
+    let page = r#"
+        <meta property="og:title" content="A &amp; B">
+        <meta content='https://example.org/a.png' property='og:image' />
+        <meta name="og:site_name" content="Example">
+        <meta charset="utf-8">
+    "#;
+    assert_eq!(
+        parse_opengraph(page),
+        Embed {
+            url: String::new(),
+            title: Some("A & B".to_string()),
+            description: None,
+            image: Some("https://example.org/a.png".to_string()),
+            provider: Some("Example".to_string()),
+        }
+    );
+}
+
+#[test]
+fn test_is_public() {
+    let public = |ip: &str| is_public(ip.parse().unwrap());
+    assert!(public("93.184.216.34"));
+    assert!(public("2606:2800:220:1::"));
+    assert!(!public("10.1.2.3"));
+    assert!(!public("100.64.0.1"));
+    assert!(!public("100.127.255.254"));
+    assert!(public("100.128.0.1"));
+    assert!(!public("::ffff:127.0.0.1"));
+    assert!(!public("::ffff:a9fe:a9fe"));
+    assert!(!public("fd00::1"));
+    let url = |url: &str| check_literal(&reqwest::Url::parse(url).unwrap()).is_ok();
+    assert!(!url("http://[::ffff:192.168.0.1]/"));
+    assert!(!url("http://127.0.0.1:8080/"));
+    assert!(url("https://example.org/"));
+}
+
+#[tokio::test]
+async fn test_resolver() {
+    let name = "localhost".parse().unwrap();
+    assert!(PublicResolver.resolve(name).await.is_err());
+}
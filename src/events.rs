@@ -0,0 +1,138 @@
+//! In-process fan-out of what happens on the boards, the handlers publish events and the
+//! subscribers, such as the webhooks or a chat bridge, each get them from their own task
+
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+
+/// The events a slow subscriber can fall behind by before it misses some
+const CAPACITY: usize = 1024;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    NewThread,
+    NewReply,
+    ReportFiled,
+    BanIssued,
+}
+
+impl EventKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            EventKind::NewThread => "new_thread",
+            EventKind::NewReply => "new_reply",
+            EventKind::ReportFiled => "report_filed",
+            EventKind::BanIssued => "ban_issued",
+        }
+    }
+}
+
+/// The data is the JSON the api answers with for the post, report or public ban
+#[derive(Serialize, Clone, Debug)]
+pub struct Event {
+    #[serde(rename = "event")]
+    pub kind: EventKind,
+    pub board: Option<String>,
+    pub created_at: i64,
+    pub data: Arc<serde_json::Value>,
+}
+
+pub trait Subscriber: Send + Sync + 'static {
+    fn on_event(&self, event: Event) -> impl Future<Output = ()> + Send;
+}
+
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<Event>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self {
+            sender: broadcast::Sender::new(CAPACITY),
+        }
+    }
+}
+
+impl EventBus {
+    /// Events published without subscribers are dropped
+    pub fn publish<T: Serialize>(&self, kind: EventKind, board: Option<&str>, data: &T) {
+        let data = match serde_json::to_value(data) {
+            Ok(data) => Arc::new(data),
+            Err(e) => {
+                tracing::warn!("failed to serialize the {} event: {e}", kind.name());
+                return;
+            }
+        };
+        let _ = self.sender.send(Event {
+            kind,
+            board: board.map(str::to_string),
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs() as i64),
+            data,
+        });
+    }
+
+    /// The events published from now on, for the embedders that want to drive the receiver
+    pub fn receiver(&self) -> broadcast::Receiver<Event> {
+        self.sender.subscribe()
+    }
+
+    /// Calls the subscriber with each event in order from a task of its own, until the bus is
+    /// dropped
+    pub fn subscribe(&self, subscriber: impl Subscriber) {
+        let mut receiver = self.receiver();
+        tokio::spawn(async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => subscriber.on_event(event).await,
+                    Err(RecvError::Lagged(missed)) => {
+                        tracing::warn!("a subscriber fell behind and missed {missed} events")
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+}
+
+#[tokio::test]
+async fn test_subscribe() {
+    use std::sync::Mutex;
+
+    struct Collect(Arc<Mutex<Vec<EventKind>>>, tokio::sync::mpsc::Sender<()>);
+    impl Subscriber for Collect {
+        async fn on_event(&self, event: Event) {
+            self.0.lock().unwrap().push(event.kind);
+            let _ = self.1.send(()).await;
+        }
+    }
+
+    let bus = EventBus::default();
+    bus.publish(EventKind::NewThread, Some("g"), &"dropped");
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let (done, mut wait) = tokio::sync::mpsc::channel(2);
+    bus.subscribe(Collect(seen.clone(), done));
+    let mut receiver = bus.receiver();
+
+    bus.publish(
+        EventKind::NewReply,
+        Some("g"),
+        &serde_json::json!({"id": 2}),
+    );
+    bus.publish(EventKind::BanIssued, None, &"spam");
+    wait.recv().await;
+    wait.recv().await;
+    assert_eq!(
+        *seen.lock().unwrap(),
+        [EventKind::NewReply, EventKind::BanIssued]
+    );
+    let event = receiver.recv().await.unwrap();
+    assert_eq!(event.board.as_deref(), Some("g"));
+    assert_eq!(event.data["id"], 2);
+}
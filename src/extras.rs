@@ -0,0 +1,98 @@
+use std::sync::LazyLock;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+const MAX_ROLLS: usize = 5;
+const MAX_DICE: u32 = 20;
+const MAX_SIDES: u32 = 10_000;
+
+static RE_DICE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)\[dice\s+(\d{1,3})d(\d{1,6})\]").unwrap());
+static RE_FORTUNE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)\[fortune\]").unwrap());
+
+const FORTUNES: &[&str] = &[
+    "Excellent Luck",
+    "Good Luck",
+    "Average Luck",
+    "Bad Luck",
+    "Very Bad Luck",
+    "Better not tell you now",
+    "Outlook good",
+    "Godly Luck",
+    "Reply hazy, try again",
+];
+
+/// What the server rolled for the `[dice XdY]` and `[fortune]` commands of a post, computed
+/// once when the post is made so editing the post doesn't change them
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+pub struct PostExtras {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dice: Vec<DiceRoll>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fortune: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct DiceRoll {
+    pub count: u32,
+    pub sides: u32,
+    pub rolls: Vec<u32>,
+    pub total: u32,
+}
+
+impl PostExtras {
+    /// Rolls the commands found in the comment, none if there are no commands
+    pub fn roll(com: &str) -> Option<Self> {
+        let dice = RE_DICE
+            .captures_iter(com)
+            .filter_map(|c| Some((c[1].parse().ok()?, c[2].parse().ok()?)))
+            .filter(|&(count, sides)| {
+                (1..=MAX_DICE).contains(&count) && (2..=MAX_SIDES).contains(&sides)
+            })
+            .take(MAX_ROLLS)
+            .map(|(count, sides)| DiceRoll::roll(count, sides))
+            .collect();
+        let fortune = RE_FORTUNE
+            .is_match(com)
+            .then(|| FORTUNES[random(FORTUNES.len() as u32) as usize].to_string());
+        let extras = Self { dice, fortune };
+        (extras != Self::default()).then_some(extras)
+    }
+}
+
+impl DiceRoll {
+    fn roll(count: u32, sides: u32) -> Self {
+        let rolls: Vec<u32> = (0..count).map(|_| random(sides) + 1).collect();
+        Self {
+            count,
+            sides,
+            total: rolls.iter().sum(),
+            rolls,
+        }
+    }
+}
+
+/// A number below n, from the random bits of a v4 uuid, the bias is negligible for such n
+fn random(n: u32) -> u32 {
+    (Uuid::new_v4().as_u128() % n as u128) as u32
+}
+
+#[test]
+fn test_roll() {
+    assert_eq!(PostExtras::roll("no commands here"), None);
+    assert_eq!(PostExtras::roll("[dice 0d6] [dice 1d1]"), None);
+
+    let extras = PostExtras::roll("[dice 2d6] and [DICE 1d20] [fortune]").unwrap();
+    assert_eq!(extras.dice.len(), 2);
+    let roll = &extras.dice[0];
+    assert_eq!((roll.count, roll.sides, roll.rolls.len()), (2, 6, 2));
+    assert!(roll.rolls.iter().all(|r| (1..=6).contains(r)));
+    assert_eq!(roll.total, roll.rolls.iter().sum::<u32>());
+    assert!(
+        extras
+            .fortune
+            .is_some_and(|f| FORTUNES.contains(&f.as_str()))
+    );
+}
@@ -0,0 +1,181 @@
+//! Flood control, the time an ip has to wait between its threads, replies and images. Set in
+//! seconds with THREAD_COOLDOWN, REPLY_COOLDOWN and IMAGE_COOLDOWN, which are off by default, or
+//! by admins with `/admin/flood` which takes effect immediately and is kept across restarts
+
+use std::error::Error;
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+use crate::Res;
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct FloodControl {
+    pub thread: i64,
+    pub reply: i64,
+    pub image: i64,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CooldownReason {
+    #[serde(rename = "thread_cooldown")]
+    Thread,
+    #[serde(rename = "reply_cooldown")]
+    Reply,
+    #[serde(rename = "image_cooldown")]
+    Image,
+}
+impl CooldownReason {
+    fn as_str(self) -> &'static str {
+        match self {
+            CooldownReason::Thread => "thread_cooldown",
+            CooldownReason::Reply => "reply_cooldown",
+            CooldownReason::Image => "image_cooldown",
+        }
+    }
+}
+
+/// The rejection of a post made too soon, answered with the reason and the seconds left so
+/// clients can count down
+#[derive(Serialize, Debug)]
+pub struct Cooldown {
+    pub reason: CooldownReason,
+    pub retry_after: i64,
+}
+impl Display for Cooldown {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let post = match self.reason {
+            CooldownReason::Thread => "thread",
+            CooldownReason::Reply => "reply",
+            CooldownReason::Image => "image",
+        };
+        write!(
+            f,
+            "wait {} seconds before posting another {post}",
+            self.retry_after
+        )
+    }
+}
+impl Error for Cooldown {}
+
+impl FloodControl {
+    pub fn from_env() -> Self {
+        let cooldown = |name| {
+            std::env::var(name)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(0)
+        };
+        Self {
+            thread: cooldown("THREAD_COOLDOWN"),
+            reply: cooldown("REPLY_COOLDOWN"),
+            image: cooldown("IMAGE_COOLDOWN"),
+        }
+    }
+
+    /// The cooldowns set by admins, or the ones of the environment
+    pub async fn load(pool: &SqlitePool) -> Res<Self> {
+        let saved: Option<String> =
+            sqlx::query_scalar(r#"SELECT value FROM settings WHERE name = 'flood'"#)
+                .fetch_optional(pool)
+                .await?;
+        match saved {
+            Some(saved) => Ok(serde_json::from_str(&saved)?),
+            None => Ok(Self::from_env()),
+        }
+    }
+
+    pub async fn save(&self, pool: &SqlitePool) -> Res<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO settings (name, value) VALUES ('flood', ?)
+            ON CONFLICT (name) DO UPDATE SET value = excluded.value
+            "#,
+        )
+        .bind(serde_json::to_string(self)?)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Rejects the post with the longest cooldown it's still under
+    pub async fn check(
+        &self,
+        pool: &SqlitePool,
+        ip_hash: &str,
+        is_thread: bool,
+        has_image: bool,
+    ) -> Res<()> {
+        let (reason, post_cooldown) = match is_thread {
+            true => (CooldownReason::Thread, self.thread),
+            false => (CooldownReason::Reply, self.reply),
+        };
+        let checks = [
+            (reason, post_cooldown, true),
+            (CooldownReason::Image, self.image, has_image),
+        ];
+        let mut longest: Option<Cooldown> = None;
+        for (reason, cooldown, applies) in checks {
+            if !applies || cooldown <= 0 {
+                continue;
+            }
+            let left: Option<i64> = sqlx::query_scalar(
+                r#"
+                SELECT MAX(created_at) + ?2 - strftime('%s', 'now') FROM comments
+                WHERE ip_hash = ?1 AND CASE ?3
+                    WHEN 'thread_cooldown' THEN op IS NULL
+                    WHEN 'reply_cooldown' THEN op IS NOT NULL
+                    ELSE media_name IS NOT NULL
+                END
+                "#,
+            )
+            .bind(ip_hash)
+            .bind(cooldown)
+            .bind(reason.as_str())
+            .fetch_one(pool)
+            .await?;
+            match left {
+                Some(left) if left > 0 && longest.as_ref().is_none_or(|l| left > l.retry_after) => {
+                    longest = Some(Cooldown {
+                        reason,
+                        retry_after: left,
+                    });
+                }
+                _ => {}
+            }
+        }
+        match longest {
+            Some(cooldown) => Err(cooldown.into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Rejects a resumable upload started before the image cooldown passed, counted from the
+    /// last image or upload of the ip
+    pub async fn check_upload(&self, pool: &SqlitePool, ip_hash: &str) -> Res<()> {
+        if self.image <= 0 {
+            return Ok(());
+        }
+        let left: Option<i64> = sqlx::query_scalar(
+            r#"
+            SELECT MAX(created_at) + ?2 - strftime('%s', 'now') FROM (
+                SELECT created_at FROM comments WHERE ip_hash = ?1 AND media_name IS NOT NULL
+                UNION ALL SELECT created_at FROM staged_uploads WHERE ip_hash = ?1
+            )
+            "#,
+        )
+        .bind(ip_hash)
+        .bind(self.image)
+        .fetch_one(pool)
+        .await?;
+        match left {
+            Some(left) if left > 0 => Err(Cooldown {
+                reason: CooldownReason::Image,
+                retry_after: left,
+            }
+            .into()),
+            _ => Ok(()),
+        }
+    }
+}
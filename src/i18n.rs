@@ -0,0 +1,333 @@
+//! Message catalogs for the errors returned to users. Every known error has a code, added to the
+//! error responses next to `Err`, and MESSAGES_DIR can hold a `{lang}.json` per language mapping
+//! the codes to translations. The language is picked from Accept-Language, the `{names}` of the
+//! english message, like the reason of a ban, are put in the translation
+
+use std::collections::HashMap;
+
+use axum::body::{Body, HttpBody, to_bytes};
+use axum::extract::Request;
+use axum::http::{HeaderValue, header};
+use axum::middleware::Next;
+use axum::response::Response;
+use regex::Regex;
+use serde_json::Value;
+
+use crate::{Config, Res};
+
+const MAX_ERROR_LEN: usize = 64 * 1024;
+
+/// The errors with a code, in english
+const MESSAGES: &[(&str, &str)] = &[
+    ("unauthorized", "unauthorized"),
+    ("not_logged_in", "not logged in"),
+    ("board_not_found", "board not found"),
+    ("board_exists", "board /{board}/ already exists"),
+    ("board_code_reserved", "this board code is reserved"),
+    ("thread_not_found", "thread not found"),
+    ("thread_locked", "thread is locked"),
+    ("post_not_found", "post not found"),
+    ("media_not_found", "media not found"),
+    ("media_required", "media is required"),
+    ("media_too_large", "media is too large"),
+    (
+        "media_removed",
+        "this file was removed and can't be posted again",
+    ),
+    ("comment_required", "comment or image is required"),
+    ("duplicate_post", "duplicate post"),
+    ("blacklisted_content", "blacklisted content"),
+    ("too_many_links", "too many links"),
+    ("too_many_capitals", "too many capital letters"),
+    ("banned", "you are banned"),
+    ("banned_with_reason", "you are banned: {reason}"),
+    ("already_reported", "you already reported this post"),
+    (
+        "thread_cooldown",
+        "wait {retry_after} seconds before posting another thread",
+    ),
+    (
+        "reply_cooldown",
+        "wait {retry_after} seconds before posting another reply",
+    ),
+    (
+        "image_cooldown",
+        "wait {retry_after} seconds before posting another image",
+    ),
+    (
+        "rules_not_accepted",
+        "accept the rules in /{board}/rules first, with accepted_rules",
+    ),
+    ("challenge_expired", "the challenge expired"),
+    ("challenge_used", "the challenge was already used"),
+    (
+        "challenge_too_easy",
+        "the challenge is too easy now, get a new one",
+    ),
+    (
+        "invalid_field",
+        "{field}: Validation error: {rule} {params}",
+    ),
+];
+
+pub struct Catalog {
+    /// The english messages, with the pattern matching them when they have parameters
+    messages: Vec<(&'static str, &'static str, Option<Regex>)>,
+    /// The translations of each language, by code
+    locales: HashMap<String, HashMap<String, String>>,
+}
+
+impl Catalog {
+    pub fn from_env() -> Res<Self> {
+        let mut locales = HashMap::new();
+        if let Ok(dir) = std::env::var("MESSAGES_DIR") {
+            for file in std::fs::read_dir(&dir)? {
+                let path = file?.path();
+                if path.extension().is_none_or(|e| e != "json") {
+                    continue;
+                }
+                let Some(lang) = path.file_stem().and_then(|s| s.to_str()) else {
+                    continue;
+                };
+                let messages: HashMap<String, String> =
+                    serde_json::from_str(&std::fs::read_to_string(&path)?)
+                        .map_err(|e| format!("invalid messages in {}: {e}", path.display()))?;
+                locales.insert(lang.to_lowercase(), messages);
+            }
+        }
+        Ok(Self::new(locales))
+    }
+
+    fn new(locales: HashMap<String, HashMap<String, String>>) -> Self {
+        let messages = MESSAGES
+            .iter()
+            .map(|&(code, message)| (code, message, pattern(message)))
+            .collect();
+        Self { messages, locales }
+    }
+
+    /// The code of the message, with its parameters
+    fn code<'a>(&self, message: &'a str) -> Option<(&'static str, Vec<(&str, &'a str)>)> {
+        self.messages
+            .iter()
+            .find_map(|(code, english, pattern)| match pattern {
+                None => (*english == message).then(|| (*code, Vec::new())),
+                Some(pattern) => {
+                    let captures = pattern.captures(message)?;
+                    let params = pattern
+                        .capture_names()
+                        .flatten()
+                        .filter_map(|name| Some((name, captures.name(name)?.as_str())))
+                        .collect();
+                    Some((*code, params))
+                }
+            })
+    }
+
+    /// The language of the catalogs the client prefers, from the ranges of its Accept-Language
+    fn language(&self, accept_language: &str) -> Option<&str> {
+        let mut ranges: Vec<(&str, f32)> = accept_language
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';');
+                let tag = parts.next()?.trim();
+                let q = parts
+                    .find_map(|p| p.trim().strip_prefix("q="))
+                    .map_or(Some(1.0), |q| q.parse().ok())?;
+                (!tag.is_empty() && q > 0.0).then_some((tag, q))
+            })
+            .collect();
+        // the sort is stable, so ranges of the same weight keep their order
+        ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+        ranges.into_iter().find_map(|(tag, _)| {
+            let tag = tag.to_lowercase();
+            let primary = tag.split('-').next().unwrap_or_default();
+            [tag.as_str(), primary]
+                .into_iter()
+                .find_map(|lang| self.locales.get_key_value(lang))
+                .map(|(lang, _)| lang.as_str())
+        })
+    }
+
+    /// The message in the language, with the parameters put back in
+    fn translate(&self, lang: &str, code: &str, params: &[(&str, &str)]) -> Option<String> {
+        let template = self.locales.get(lang)?.get(code)?;
+        Some(
+            params
+                .iter()
+                .fold(template.clone(), |message, (name, value)| {
+                    message.replace(&format!("{{{name}}}"), value)
+                }),
+        )
+    }
+}
+
+/// Matches the messages with `{names}`, none for messages without parameters
+fn pattern(message: &str) -> Option<Regex> {
+    let params = Regex::new(r"\{(\w+)\}").unwrap();
+    if !params.is_match(message) {
+        return None;
+    }
+    let mut pattern = String::from("(?s)^");
+    let mut last = 0;
+    for c in params.captures_iter(message) {
+        let name = c.get(0).unwrap();
+        pattern.push_str(&regex::escape(&message[last..name.start()]));
+        pattern.push_str(&format!("(?P<{}>.+?)", &c[1]));
+        last = name.end();
+    }
+    pattern.push_str(&regex::escape(&message[last..]));
+    pattern.push('$');
+    Some(Regex::new(&pattern).unwrap())
+}
+
+/// Adds the code to the json errors and translates them to the language of the client
+pub async fn localize(req: Request, next: Next) -> Response {
+    let config = req.extensions().get::<std::sync::Arc<Config>>().cloned();
+    let accept_language = req
+        .headers()
+        .get(header::ACCEPT_LANGUAGE)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    let res = next.run(req).await;
+    let Some(config) = config else {
+        return res;
+    };
+    let catalog = &config.messages;
+    let is_json = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|v| v.as_bytes().starts_with(b"application/json"));
+    // the errors are short, a body too large for one is returned as it was
+    let is_short = res
+        .body()
+        .size_hint()
+        .exact()
+        .is_some_and(|len| len <= MAX_ERROR_LEN as u64);
+    if !(res.status().is_client_error() || res.status().is_server_error()) || !is_json || !is_short
+    {
+        return res;
+    }
+    let (mut parts, body) = res.into_parts();
+    let bytes = match to_bytes(body, MAX_ERROR_LEN).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            // the body is gone, the error is answered with why it couldn't be read
+            tracing::warn!("failed to read an error response: {e}");
+            parts.headers.remove(header::CONTENT_LENGTH);
+            let error = serde_json::json!({ "Err": e.to_string() });
+            return Response::from_parts(parts, Body::from(error.to_string()));
+        }
+    };
+    let mut error: Value = match serde_json::from_slice(&bytes) {
+        Ok(Value::Object(error)) => Value::Object(error),
+        _ => return Response::from_parts(parts, Body::from(bytes)),
+    };
+    let Some((code, params)) = error["Err"].as_str().and_then(|m| catalog.code(m)) else {
+        return Response::from_parts(parts, Body::from(bytes));
+    };
+    let lang = accept_language.as_deref().and_then(|l| catalog.language(l));
+    let translated = lang.and_then(|lang| Some((lang, catalog.translate(lang, code, &params)?)));
+    if let Some((lang, message)) = translated {
+        error["Err"] = message.into();
+        if let Ok(lang) = HeaderValue::from_str(lang) {
+            parts.headers.insert(header::CONTENT_LANGUAGE, lang);
+        }
+    }
+    error["code"] = code.into();
+    if !catalog.locales.is_empty() {
+        parts
+            .headers
+            .append(header::VARY, HeaderValue::from_static("accept-language"));
+    }
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, Body::from(error.to_string()))
+}
+
+#[test]
+fn test_translate() {
+    let fr = HashMap::from([
+        (
+            "banned_with_reason".to_string(),
+            "vous êtes banni : {reason}".to_string(),
+        ),
+        (
+            "board_not_found".to_string(),
+            "planche introuvable".to_string(),
+        ),
+    ]);
+    let catalog = Catalog::new(HashMap::from([("fr".to_string(), fr)]));
+    let (code, params) = catalog.code("you are banned: spam: again").unwrap();
+    assert_eq!(code, "banned_with_reason");
+    let lang = catalog
+        .language("de;q=0.9, fr-CH;q=0.95, en;q=0.8")
+        .unwrap();
+    assert_eq!(
+        catalog.translate(lang, code, &params).as_deref(),
+        Some("vous êtes banni : spam: again")
+    );
+    assert_eq!(catalog.code("you are banned").unwrap().0, "banned");
+    assert_eq!(catalog.language("de, *;q=0.5"), None);
+    assert_eq!(catalog.code("something else"), None);
+}
+
+/// The messages are found by their text, so every one has to be a message the server sends
+#[test]
+fn test_messages_exist() {
+    use crate::flood::{Cooldown, CooldownReason};
+    use validator::Validate;
+
+    #[derive(Validate)]
+    struct Form {
+        #[validate(length(max = 1))]
+        com: String,
+    }
+    let catalog = Catalog::new(HashMap::new());
+    let mut sent: Vec<String> = [
+        CooldownReason::Thread,
+        CooldownReason::Reply,
+        CooldownReason::Image,
+    ]
+    .into_iter()
+    .map(|reason| {
+        let cooldown = Cooldown {
+            reason,
+            retry_after: 30,
+        };
+        cooldown.to_string()
+    })
+    .collect();
+    let form = Form {
+        com: "too long".to_string(),
+    };
+    sent.push(form.validate().unwrap_err().to_string());
+    let mut built: Vec<&str> = sent
+        .iter()
+        .filter_map(|m| Some(catalog.code(m)?.0))
+        .collect();
+    assert_eq!(built.len(), sent.len(), "{sent:?}");
+
+    // the rest are written in the sources, with their parameters put in by format!
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/src");
+    let mut sources = String::new();
+    for file in std::fs::read_dir(dir).unwrap() {
+        let path = file.unwrap().path();
+        if path.file_name().is_some_and(|n| n != "i18n.rs") {
+            sources.push_str(&std::fs::read_to_string(path).unwrap());
+        }
+    }
+    let params = Regex::new(r"\{\w+\}").unwrap();
+    for (code, message) in MESSAGES {
+        if built.contains(code) {
+            continue;
+        }
+        let literal: Vec<String> = params.split(message).map(regex::escape).collect();
+        let written = Regex::new(&format!("\"{}\"", literal.join(r#"\{[^{}"]*\}"#))).unwrap();
+        assert!(
+            written.is_match(&sources),
+            "{code}: {message} is never sent"
+        );
+        built.push(code);
+    }
+    assert_eq!(built.len(), MESSAGES.len());
+}
@@ -0,0 +1,6998 @@
+mod accounts;
+mod audio;
+mod audit;
+mod autoban;
+mod backup;
+mod banners;
+mod bans;
+mod cache;
+mod cli;
+pub mod db;
+mod documents;
+mod embeds;
+pub mod events;
+mod extras;
+mod flood;
+mod i18n;
+mod listen;
+pub mod media;
+mod oidc;
+mod origin;
+mod pow;
+mod privacy;
+mod proxy;
+mod quarantine;
+mod reencode;
+mod relink;
+mod rules;
+pub mod scan;
+mod scheduler;
+mod spam;
+mod takedowns;
+mod text;
+mod theme;
+pub mod thumbs;
+mod tls;
+mod totp;
+mod transfer;
+mod tus;
+mod vault;
+mod vichan;
+mod webhooks;
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs::DirBuilder;
+use std::net::IpAddr;
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+
+use accounts::{Account, AccountSession, Session};
+use argon2::Argon2;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+
+use audit::AuditEntry;
+use autoban::{AutobanRule, DryRun, RuleAction, RuleTarget, Submission};
+use axum::body::{Body, Bytes};
+use axum::error_handling::HandleErrorLayer;
+use axum::extract::multipart::MultipartError;
+use axum::extract::{DefaultBodyLimit, FromRequest, Multipart, OriginalUri, Path, Query};
+use axum::handler::Handler;
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Method, Request, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get, head, options, patch, post, put};
+use axum::{BoxError, Extension, Json, Router};
+use banners::Banner;
+use bans::{Ban, BanResult, PublicBan};
+use cache::{CacheKey, CacheStats, ResponseCache};
+use clap::Parser;
+use cli::{Cli, Command};
+use db::ReadPool;
+use embeds::{Embed, Embeds};
+use events::{EventBus, EventKind};
+use extras::PostExtras;
+use flood::{Cooldown, FloodControl};
+use html_escape::{encode_double_quoted_attribute, encode_text};
+use i18n::Catalog;
+use listen::{Listen, PeerIp, TrustedProxies};
+use media::{Accepts, Upload, regenerate_thumbs, remove_media, save_media, shred_media};
+use oidc::{Oidc, StaffLogin, StaffRole, StaffSession};
+use origin::AllowedOrigins;
+use pow::PowPolicy;
+use proxy::{ProxyCheck, ProxyPolicy};
+use quarantine::{Quarantine, QuarantinedContent, QuarantinedPost};
+use reencode::ReencodeSettings;
+use regex::{NoExpand, Regex};
+use rules::BoardRules;
+use scan::{Clamd, ScanStats};
+use scheduler::{JobStatus, Scheduler};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use spam::SpamRules;
+use sqlx::migrate::Migrator;
+use sqlx::prelude::FromRow;
+use sqlx::{SqliteConnection, SqlitePool};
+use takedowns::{Takedown, TakedownStatus, TakedownVault};
+use theme::BoardSettings;
+use thumbs::{ThumbFormat, ThumbSettings, WorkerStats};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::signal::unix::{SignalKind, signal};
+use tokio_util::io::ReaderStream;
+use totp::Enrollment;
+use tower::ServiceBuilder;
+use tower::limit::GlobalConcurrencyLimitLayer;
+use tower::load_shed::error::Overloaded;
+use tower::timeout::error::Elapsed;
+use tower_http::compression::CompressionLayer;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
+use tower_http::services::{ServeDir, ServeFile};
+use tower_http::trace::TraceLayer;
+use tus::{StagedUpload, UploadQuota};
+use uuid::Uuid;
+use validator::{Validate, ValidationError};
+use webhooks::{Webhook, WebhookSubscriber};
+
+pub type Res<T> = Result<T, Box<dyn Error>>;
+
+const OVERBOARD_THREADS: i64 = 100;
+
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+static RE_QUOTES: LazyLock<Regex> = LazyLock::new(|| Regex::new(r">>(\d+)").unwrap());
+static RE_QUOTE_LINKS: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r##"<a href="#p\d+">&gt;&gt;(\d+)</a>"##).unwrap());
+static RE_SPOILER: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?s)\*\*(.+?)\*\*|\[spoiler\](.+?)\[/spoiler\]").unwrap());
+static RE_BOLD: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"'''(.+?)'''").unwrap());
+static RE_ITALIC: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"''(.+?)''").unwrap());
+static RE_HEADING: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?m)^==(.+?)==\r?$").unwrap());
+
+// markup is marked with private use characters before escaping and turned into html at the end,
+// so the tags can't be mangled by the escaping, linking and greentext passes
+const MARKUP: [(char, char, &str, &str); 4] = [
+    (
+        '\u{E000}',
+        '\u{E001}',
+        "<span class=\"spoiler\">",
+        "</span>",
+    ),
+    ('\u{E002}', '\u{E003}', "<b>", "</b>"),
+    ('\u{E004}', '\u{E005}', "<i>", "</i>"),
+    (
+        '\u{E006}',
+        '\u{E007}',
+        "<span class=\"heading\">",
+        "</span>",
+    ),
+];
+static RE_REPLIES: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"&gt;&gt;(\d+)").unwrap());
+/// MAX_QUOTE_LINKS, how many posts a post can quote, the quotes past it are left as text
+static MAX_QUOTE_LINKS: LazyLock<usize> = LazyLock::new(|| {
+    std::env::var("MAX_QUOTE_LINKS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(50)
+});
+/// The attributes of the links in posts, LINK_TARGET=_blank opens them in a new tab
+static LINK_ATTRS: LazyLock<String> =
+    LazyLock::new(|| match std::env::var("LINK_TARGET").as_deref() {
+        Ok(target @ ("_blank" | "_self" | "_parent" | "_top")) => {
+            format!(r#" rel="noreferrer nofollow noopener" target="{target}""#)
+        }
+        _ => r#" rel="noreferrer nofollow""#.to_string(),
+    });
+
+/// Runs the command line, the entry point of the `blu` binary
+pub async fn run() -> Res<()> {
+    let command = Cli::parse().command.unwrap_or_default();
+    let database_url = std::env::var("DATABASE_URL").expect("[error] DATABASE_URL is not set");
+    if let Command::Serve = command {
+        let logger = tracing_subscriber::fmt().with_max_level(tracing::Level::DEBUG);
+        match std::env::var("LOG_FORMAT").as_deref() {
+            Ok("json") => logger.json().init(),
+            _ => logger.init(),
+        }
+    }
+    check_writable("media")?;
+
+    let pool = Arc::new(db::connect_with_retry(&database_url).await?);
+    MIGRATOR.run(&*pool).await?;
+    match command {
+        Command::Serve => serve(pool, db::connect_readers(&database_url).await?).await,
+        Command::Migrate => Ok(()),
+        Command::CreateAdmin => cli::create_admin(&pool).await,
+        Command::GcMedia { dry_run } => cli::gc_media(&pool, dry_run).await,
+        Command::Stats => cli::stats(&pool).await,
+        Command::Rerender { board } => cli::rerender(&pool, board.as_deref()).await,
+        Command::Backup { out, media } => backup::create_backup(&pool, &out, media).await,
+        Command::Export { board, media, out } => {
+            let archive = transfer::export(&pool, &board, media).await?;
+            match out {
+                Some(path) => serde_json::to_writer(std::fs::File::create(path)?, &archive)?,
+                None => serde_json::to_writer(std::io::stdout(), &archive)?,
+            }
+            Ok(())
+        }
+        Command::ImportVichan {
+            url,
+            board,
+            code,
+            no_media,
+        } => {
+            let code = code.unwrap_or_else(|| board.clone());
+            let config = Config::load(&pool).await?;
+            vichan::import(&pool, &config, &url, &board, &code, !no_media).await
+        }
+        Command::DecryptTakedown { file, out } => {
+            let vault = TakedownVault::from_env()?.ok_or("TAKEDOWN_KEY is not set")?;
+            std::fs::write(out, vault.open(&std::fs::read(file)?)?)?;
+            Ok(())
+        }
+        Command::Import { file } => {
+            let archive =
+                serde_json::from_reader(std::io::BufReader::new(std::fs::File::open(file)?))?;
+            transfer::import(&pool, &Config::load(&pool).await?, archive).await
+        }
+    }
+}
+/// Creates the directory if missing and checks files can be written in it, so a volume mounted
+/// read only fails the startup instead of the uploads
+fn check_writable(dir: &str) -> Res<()> {
+    DirBuilder::new()
+        .recursive(true)
+        .create(dir)
+        .map_err(|e| format!("failed to create {dir}/: {e}"))?;
+    let probe = std::path::Path::new(dir).join(".write-check");
+    std::fs::write(&probe, b"")
+        .and_then(|_| std::fs::remove_file(&probe))
+        .map_err(|e| format!("{dir}/ isn't writable: {e}"))?;
+    Ok(())
+}
+
+/// Reloads the configuration that can change without a restart on each SIGHUP
+async fn reload_on_hangup(pool: Arc<SqlitePool>, config: Arc<Config>) {
+    let Ok(mut hangups) = signal(SignalKind::hangup()) else {
+        return tracing::warn!("failed to listen for SIGHUP");
+    };
+    while hangups.recv().await.is_some() {
+        match config.reload(&pool).await {
+            Ok(()) => tracing::info!("reloaded the spam rules and the cooldowns"),
+            Err(e) => tracing::warn!("failed to reload the configuration: {e}"),
+        }
+    }
+}
+
+async fn serve(pool: Arc<SqlitePool>, readers: ReadPool) -> Res<()> {
+    let listen = Listen::from_env()?;
+    let services = Services::load(pool, readers).await?;
+    seed_boards(&services.pool, &services.config).await?;
+    services.spawn_jobs();
+    tokio::spawn(reload_on_hangup(
+        services.pool.clone(),
+        services.config.clone(),
+    ));
+    let app = app(&services)?;
+
+    if let (Ok(cert), Ok(key)) = (std::env::var("TLS_CERT"), std::env::var("TLS_KEY")) {
+        let Listen::Tcp(port) = listen else {
+            return Err("TLS_CERT and TLS_KEY only apply to the tcp listener".into());
+        };
+        if let Some(http_port) = std::env::var("HTTP_REDIRECT_PORT")
+            .ok()
+            .and_then(|p| p.parse().ok())
+        {
+            tokio::spawn(async move {
+                if let Err(e) = tls::redirect_http(http_port, port).await {
+                    tracing::warn!("failed to serve the https redirect: {e}");
+                }
+            });
+        }
+        return tls::serve_tls(app, port, &cert, &key).await;
+    }
+    listen::serve(app, listen).await
+}
+
+/// The state shared by the handlers and the background jobs
+pub struct Services {
+    pub pool: Arc<SqlitePool>,
+    pub readers: ReadPool,
+    pub config: Arc<Config>,
+    proxy: Arc<ProxyCheck>,
+    cache: Arc<ResponseCache>,
+    scheduler: Arc<Scheduler>,
+}
+impl Services {
+    /// Loads the configuration from the environment, the pool must already be migrated
+    pub async fn load(pool: Arc<SqlitePool>, readers: ReadPool) -> Res<Self> {
+        Ok(Self {
+            config: Arc::new(Config::load(&pool).await?),
+            proxy: Arc::new(ProxyCheck::from_env()),
+            cache: Arc::new(ResponseCache::from_env()),
+            scheduler: Arc::new(Scheduler::from_env()?),
+            pool,
+            readers,
+        })
+    }
+    /// Starts the scheduled jobs, the webhook deliveries, the refresh of the tor exit list and
+    /// the hashing of the media uploaded before the hashes were kept
+    pub fn spawn_jobs(&self) {
+        self.config.events.subscribe(WebhookSubscriber {
+            pool: self.pool.clone(),
+        });
+        tokio::spawn(self.scheduler.clone().run(
+            self.pool.clone(),
+            self.config.clone(),
+            self.cache.clone(),
+        ));
+        tokio::spawn({
+            let proxy = self.proxy.clone();
+            async move { proxy.refresh_tor_exits().await }
+        });
+        tokio::spawn({
+            let pool = self.pool.clone();
+            async move {
+                match media::backfill_hashes(&pool).await {
+                    Ok(0) => {}
+                    Ok(hashed) => tracing::info!("hashed {hashed} media files"),
+                    Err(e) => tracing::warn!("failed to hash the media files: {e}"),
+                }
+            }
+        });
+    }
+}
+
+/// The imageboard api and media routes, to be served or nested in a larger router
+pub fn app(services: &Services) -> Res<Router> {
+    let mut api = Router::new()
+        .merge(board_routes(&services.config.body_limits))
+        .merge(post_routes(&services.config.body_limits))
+        .nest("/watch", watch_routes())
+        .nest("/mod", mod_routes())
+        .nest("/admin", admin_routes());
+    if services.config.accounts {
+        api = api.nest("/account", account_routes());
+    }
+    if services.config.oidc.is_some() {
+        api = api.nest("/staff", staff_routes());
+    }
+    api = api.layer(axum::middleware::from_fn(i18n::localize));
+    if std::env::var("COMPRESSION").as_deref() != Ok("false") {
+        api = api.layer(CompressionLayer::new());
+    }
+    let mut media = Router::new()
+        .route("/media/exists", post(media_exists))
+        .route("/media/{file_name}", get(get_media));
+    if let Some(cors) = cors_from_env()? {
+        api = api.layer(cors.clone());
+        if std::env::var("CORS_MEDIA").as_deref() != Ok("false") {
+            media = media.layer(cors);
+        }
+    }
+    let frontend = std::env::var("FRONTEND_DIR").ok().filter(|d| !d.is_empty());
+    if frontend.is_some() {
+        // unknown api paths stay errors instead of loading the frontend
+        api = api.fallback(|| async { StatusCode::NOT_FOUND });
+    }
+    let mut routes = Router::new().nest("/api/v1", api.clone()).merge(media);
+    if std::env::var("LEGACY_ROUTES").as_deref() != Ok("false") {
+        routes = routes.merge(api.layer(axum::middleware::map_response(deprecated)));
+    }
+    if let Some(dir) = frontend {
+        let index = ServeFile::new(std::path::Path::new(&dir).join("index.html"));
+        routes = routes.fallback_service(ServeDir::new(dir).fallback(index));
+    }
+
+    let timeout = std::env::var("REQUEST_TIMEOUT")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(60);
+    if timeout > 0 {
+        routes = routes.layer(
+            ServiceBuilder::new()
+                .layer(HandleErrorLayer::new(limit_error))
+                .timeout(Duration::from_secs(timeout)),
+        );
+    }
+    let max_requests = std::env::var("MAX_CONCURRENT_REQUESTS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(512);
+    if max_requests > 0 {
+        // the limit is shared by every route
+        routes = routes.layer(
+            ServiceBuilder::new()
+                .layer(HandleErrorLayer::new(limit_error))
+                .load_shed()
+                .layer(GlobalConcurrencyLimitLayer::new(max_requests)),
+        );
+    }
+
+    let app = routes
+        .layer(DefaultBodyLimit::max(
+            services.config.body_limits.json as usize,
+        ))
+        .layer(Extension(services.pool.clone()))
+        .layer(Extension(services.readers.clone()))
+        .layer(Extension(services.config.clone()))
+        .layer(Extension(services.proxy.clone()))
+        .layer(Extension(services.cache.clone()))
+        .layer(Extension(services.scheduler.clone()))
+        .layer({
+            let config = services.config.clone();
+            TraceLayer::new_for_http().make_span_with(move |req: &Request<Body>| {
+                let peer = listen::peer_addr(req.extensions());
+                let client = config.trusted_proxies.client_ip(peer, req.headers());
+                let request_id = req
+                    .headers()
+                    .get("x-request-id")
+                    .and_then(|id| id.to_str().ok())
+                    .unwrap_or_default();
+                tracing::debug_span!(
+                    "request",
+                    method = %req.method(),
+                    uri = %req.uri(),
+                    client = client.map(tracing::field::display),
+                    request_id,
+                )
+            })
+        })
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid));
+    Ok(app)
+}
+
+fn board_routes(limits: &BodyLimits) -> Router {
+    Router::new()
+        .route("/boards", get(get_boards))
+        .route("/overboard", get(get_overboard))
+        .route("/stats", get(get_stats))
+        .route("/stats/daily", get(get_daily_stats))
+        .route(
+            "/boards/{board_id}",
+            patch(update_board).delete(delete_board),
+        )
+        .route("/boards/{board_id}/settings", put(put_board_settings))
+        .route("/boards/{board_id}/rules", put(put_board_rules))
+        .route(
+            "/boards/{board_id}/banners",
+            get(get_banners).post(create_banner.layer(limits.uploads())),
+        )
+        .route(
+            "/boards/{board_id}/banners/{banner_id}",
+            patch(update_banner).delete(delete_banner),
+        )
+        .route("/{board_id}", get(get_threads))
+        .route("/{board_id}/thread/{thread_id}", get(get_comments))
+        .route(
+            "/{board_id}/thread/{thread_id}/last/{n}",
+            get(get_last_comments),
+        )
+        .route("/{board_id}/post/{post_no}", get(get_post))
+        .route("/{board_id}/stats", get(get_board_stats))
+        .route("/{board_id}/banner", get(get_banner))
+        .route("/{board_id}/rules", get(get_board_rules))
+        .route("/create_board", post(create_board))
+        .route("/bans/public", get(get_public_bans))
+        .route("/pow", get(get_pow))
+        .route("/takedowns", post(create_takedown))
+}
+/// The routes that create or change posts, which are checked against POST_ORIGINS
+fn post_routes(limits: &BodyLimits) -> Router {
+    let posting = Router::new()
+        .route(
+            "/create_thread",
+            post(create_thread).layer(limits.uploads()),
+        )
+        .route(
+            "/create_comment",
+            post(create_comment).layer(limits.uploads()),
+        )
+        .route("/uploads", options(get_tus_options).post(create_upload))
+        .route(
+            "/uploads/{upload_id}",
+            head(get_upload).patch(patch_upload).delete(delete_upload),
+        )
+        .route("/post/{post_id}", patch(edit_post).delete(delete_post))
+        .route("/post/{post_id}/report", post(report_post));
+    match AllowedOrigins::from_env() {
+        Some(origins) => posting.route_layer(axum::middleware::from_fn_with_state(
+            origins,
+            origin::verify_origin,
+        )),
+        None => posting,
+    }
+}
+fn watch_routes() -> Router {
+    Router::new().route("/{token}", get(get_watched)).route(
+        "/{token}/{thread_id}",
+        put(watch_thread).delete(unwatch_thread),
+    )
+}
+fn account_routes() -> Router {
+    Router::new()
+        .route("/", get(get_account))
+        .route("/register", post(register_account))
+        .route("/login", post(login_account))
+        .route("/logout", post(logout_account))
+        .route("/settings", put(put_account_settings))
+        .route("/sessions", get(get_account_sessions))
+        .route("/sessions/{session_id}", delete(revoke_account_session))
+        .route("/logout_all", post(logout_all_accounts))
+        .route(
+            "/hidden/{thread_id}",
+            put(hide_thread).delete(unhide_thread),
+        )
+}
+fn staff_routes() -> Router {
+    Router::new()
+        .route("/login", get(staff_login))
+        .route("/callback", get(staff_callback))
+        .route("/logout", post(staff_logout))
+        .route("/totp/enroll", post(enroll_totp))
+        .route("/totp/verify", post(verify_totp))
+}
+fn mod_routes() -> Router {
+    Router::new()
+        .route("/sticky/{thread_id}", patch(set_sticky))
+        .route("/lock/{thread_id}", patch(set_locked))
+        .route("/merge", post(merge_threads))
+        .route("/move", post(move_thread))
+        .route("/reports", get(get_reports))
+        .route("/reports/{report_id}/dismiss", post(dismiss_report))
+        .route("/held", get(get_held))
+        .route("/approve/{post_id}", post(approve_post))
+        .route("/reject/{post_id}", post(reject_post))
+        .route("/ban", post(ban_ip))
+        .route("/bans", get(get_bans))
+        .route("/bans/{ban_id}", patch(set_ban_public).delete(delete_ban))
+        .route("/takedowns", get(get_takedowns))
+        .route("/takedowns/{takedown_id}/action", post(action_takedown))
+        .route("/takedowns/{takedown_id}/dismiss", post(dismiss_takedown))
+        .route("/quarantine", get(get_quarantine))
+        .route("/quarantine/{post_id}", get(get_quarantined_post))
+        .route("/restore/{post_id}", post(restore_post))
+        .route("/bulk/delete", post(bulk_delete))
+        .route("/bulk/approve", post(bulk_approve))
+        .route("/bulk/dismiss", post(bulk_dismiss))
+        .route("/whitelist", get(get_whitelist).post(create_whitelist))
+        .route("/whitelist/{ip}", delete(delete_whitelist))
+        .route("/autoban", get(get_autoban_rules).post(create_autoban_rule))
+        .route(
+            "/autoban/{rule_id}",
+            patch(update_autoban_rule).delete(delete_autoban_rule),
+        )
+        .route("/autoban/{rule_id}/dry_run", get(dry_run_autoban_rule))
+        .route("/wordfilters", get(get_wordfilters).post(create_wordfilter))
+        .route(
+            "/wordfilters/{wordfilter_id}",
+            patch(update_wordfilter).delete(delete_wordfilter),
+        )
+}
+fn admin_routes() -> Router {
+    Router::new()
+        .route(
+            "/announcements",
+            get(get_announcements).post(create_announcement),
+        )
+        .route(
+            "/announcements/{announcement_id}",
+            delete(delete_announcement),
+        )
+        .route("/rethumb", post(rethumb))
+        .route("/reencoded", get(get_reencoded))
+        .route("/backup", get(get_backup))
+        .route("/metrics", get(get_metrics))
+        .route("/jobs", get(get_jobs))
+        .route("/webhooks", get(get_webhooks).post(create_webhook))
+        .route(
+            "/webhooks/{webhook_id}",
+            patch(update_webhook).delete(delete_webhook),
+        )
+        .route("/audit", get(get_audit_log))
+        .route("/staff/{subject}/totp", delete(reset_totp))
+        .route("/sessions", get(get_staff_sessions))
+        .route("/sessions/{session_id}", delete(revoke_staff_session))
+        .route("/sessions/logout_all", post(logout_all_staff))
+        .route("/revoke", post(revoke_token))
+        .route("/flood", get(get_flood).put(put_flood))
+        .route("/reload", post(reload_config))
+        .route("/ip/{ip_hash}/posts", get(get_ip_posts))
+}
+/// The requests past REQUEST_TIMEOUT or over MAX_CONCURRENT_REQUESTS
+async fn limit_error(e: BoxError) -> (StatusCode, Json<Result<(), String>>) {
+    let (status, message) = if e.is::<Elapsed>() {
+        (StatusCode::REQUEST_TIMEOUT, "request timed out")
+    } else if e.is::<Overloaded>() {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "server overloaded, try again later",
+        )
+    } else {
+        (StatusCode::INTERNAL_SERVER_ERROR, "internal server error")
+    };
+    (status, Json(Err(message.to_string())))
+}
+
+/// Marks the responses of the routes served at the root before the api moved under /api/v1
+async fn deprecated(mut res: Response) -> Response {
+    res.headers_mut()
+        .insert("deprecation", HeaderValue::from_static("true"));
+    res
+}
+
+/// Allows cross origin requests from CORS_ORIGINS, a comma separated list of origins or *
+fn cors_from_env() -> Res<Option<CorsLayer>> {
+    let origins = match std::env::var("CORS_ORIGINS") {
+        Ok(origins) if !origins.trim().is_empty() => origins,
+        _ => return Ok(None),
+    };
+    let origins = match origins.trim() {
+        "*" => AllowOrigin::any(),
+        origins => AllowOrigin::list(
+            origins
+                .split(',')
+                .map(|origin| origin.trim().parse())
+                .collect::<Result<Vec<HeaderValue>, _>>()?,
+        ),
+    };
+    let methods = std::env::var("CORS_METHODS")
+        .unwrap_or("GET,POST,PUT,PATCH,DELETE".to_string())
+        .split(',')
+        .map(|method| method.trim().parse())
+        .collect::<Result<Vec<Method>, _>>()?;
+    let max_age = std::env::var("CORS_MAX_AGE")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(60 * 60);
+    Ok(Some(
+        CorsLayer::new()
+            .allow_origin(origins)
+            .allow_methods(methods)
+            .allow_headers([header::AUTHORIZATION, header::CONTENT_TYPE])
+            .expose_headers([HeaderName::from_static("x-request-id")])
+            .max_age(Duration::from_secs(max_age)),
+    ))
+}
+
+/// The largest bodies accepted, JSON_BODY_LIMIT (default 64 KiB) for every route and
+/// MAX_UPLOAD_SIZE (default 5 MiB) for the media of the routes that take uploads, which also
+/// caps the max_file_size of the boards
+struct BodyLimits {
+    json: u64,
+    upload: u64,
+}
+impl BodyLimits {
+    fn from_env() -> Self {
+        let limit = |name, default| {
+            std::env::var(name)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default)
+        };
+        Self {
+            json: limit("JSON_BODY_LIMIT", 64 * 1024),
+            upload: limit("MAX_UPLOAD_SIZE", 5 * 1024 * 1024),
+        }
+    }
+    /// The limit of the routes with uploads, the media and the fields sent along
+    fn uploads(&self) -> DefaultBodyLimit {
+        DefaultBodyLimit::max((self.upload + self.json) as usize)
+    }
+    fn check_file_size(&self, max_file_size: Option<i64>) -> Res<()> {
+        match max_file_size {
+            Some(size) if size as u64 > self.upload => Err(format!(
+                "max_file_size can't be over the MAX_UPLOAD_SIZE of {} bytes",
+                self.upload
+            )
+            .into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The settings read at startup from the environment and the settings table
+pub struct Config {
+    mod_token: Option<String>,
+    admin_token: Option<String>,
+    edit_window: i64,
+    duplicate_window: i64,
+    /// Reloaded with `/admin/reload` or on SIGHUP, like the cooldowns
+    spam_rules: std::sync::RwLock<Arc<SpamRules>>,
+    ip_salt: String,
+    ip_retention: i64,
+    thumbs: ThumbSettings,
+    clamd: Option<Clamd>,
+    reencode: Option<ReencodeSettings>,
+    embeds: Option<Arc<Embeds>>,
+    ban_window: i64,
+    held_expiry: i64,
+    upload_expiry: i64,
+    upload_quota: UploadQuota,
+    takedowns: Option<TakedownVault>,
+    quarantine: Option<Quarantine>,
+    accounts: bool,
+    oidc: Option<Oidc>,
+    trusted_proxies: TrustedProxies,
+    body_limits: BodyLimits,
+    flood: std::sync::RwLock<FloodControl>,
+    messages: Catalog,
+    /// The hashes of the tokens revoked with `/admin/revoke`
+    revoked: std::sync::RwLock<HashSet<String>>,
+    events: EventBus,
+}
+impl Config {
+    pub async fn load(pool: &SqlitePool) -> Res<Self> {
+        let spam_rules = SpamRules::from_env()?;
+        let admin_token = match std::env::var("ADMIN_TOKEN").ok().filter(|t| !t.is_empty()) {
+            Some(token) => Some(token),
+            None => {
+                sqlx::query_scalar(r#"SELECT value FROM settings WHERE name = 'admin_token'"#)
+                    .fetch_optional(pool)
+                    .await?
+            }
+        };
+        Ok(Self {
+            mod_token: std::env::var("MOD_TOKEN").ok().filter(|t| !t.is_empty()),
+            admin_token,
+            edit_window: std::env::var("EDIT_WINDOW")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(5 * 60),
+            duplicate_window: std::env::var("DUPLICATE_WINDOW")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(60),
+            spam_rules: std::sync::RwLock::new(Arc::new(spam_rules)),
+            ip_salt: privacy::load_ip_salt(pool).await?,
+            ip_retention: std::env::var("IP_RETENTION")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(30 * 24 * 60 * 60),
+            thumbs: ThumbSettings::from_env(),
+            clamd: Clamd::from_env(),
+            reencode: ReencodeSettings::from_env(),
+            embeds: Embeds::from_env()?,
+            ban_window: std::env::var("BAN_DELETE_WINDOW")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(24 * 60 * 60),
+            held_expiry: std::env::var("HELD_EXPIRY")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(7 * 24 * 60 * 60),
+            upload_expiry: std::env::var("UPLOAD_EXPIRY")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(24 * 60 * 60),
+            upload_quota: UploadQuota::from_env(),
+            takedowns: TakedownVault::from_env()?,
+            quarantine: Quarantine::from_env()?,
+            accounts: std::env::var("ACCOUNTS").as_deref() == Ok("true"),
+            oidc: Oidc::from_env()?,
+            trusted_proxies: TrustedProxies::from_env()?,
+            body_limits: BodyLimits::from_env(),
+            flood: std::sync::RwLock::new(FloodControl::load(pool).await?),
+            messages: Catalog::from_env()?,
+            revoked: std::sync::RwLock::new(
+                sqlx::query_scalar(r#"SELECT token_hash FROM revoked_tokens"#)
+                    .fetch_all(pool)
+                    .await?
+                    .into_iter()
+                    .collect(),
+            ),
+            events: EventBus::default(),
+        })
+    }
+    /// Loads the spam rules and the cooldowns again, so their changes apply without a restart.
+    /// The rules in use are kept if the new ones are invalid
+    pub async fn reload(&self, pool: &SqlitePool) -> Res<()> {
+        let spam_rules = SpamRules::from_env()?;
+        let flood = FloodControl::load(pool).await?;
+        *self.spam_rules.write().unwrap() = Arc::new(spam_rules);
+        *self.flood.write().unwrap() = flood;
+        Ok(())
+    }
+    fn spam_rules(&self) -> Arc<SpamRules> {
+        self.spam_rules.read().unwrap().clone()
+    }
+    fn flood(&self) -> FloodControl {
+        *self.flood.read().unwrap()
+    }
+    /// The bus the board events are published on
+    pub fn events(&self) -> &EventBus {
+        &self.events
+    }
+    fn thumbs_for(&self, board: &Board) -> ThumbSettings {
+        self.thumbs.with_overrides(
+            board.thumb_dimension,
+            board.catalog_thumb_dimension,
+            board.thumb_quality,
+            board.thumb_format,
+        )
+    }
+    /// Uploads are only scanned on the boards that enable it and when CLAMD is set
+    fn scanner_for(&self, board: &Board) -> Option<&Clamd> {
+        self.clamd.as_ref().filter(|_| board.scan_uploads)
+    }
+    /// Images are only re-encoded on the boards that enable it and when REENCODE_MAX_DIMENSION
+    /// is set
+    fn reencode_for(&self, board: &Board) -> Option<ReencodeSettings> {
+        self.reencode.filter(|_| board.reencode_images)
+    }
+    fn hash_ip(&self, ip: IpAddr) -> String {
+        privacy::hash_ip(&self.ip_salt, ip)
+    }
+    /// The poster token of the request, or a new one for posters without it, with the cookie
+    /// that keeps it until the poster hashes are forgotten
+    fn poster_token(&self, headers: &HeaderMap) -> (String, String) {
+        let token =
+            privacy::poster_token(headers).unwrap_or_else(|| Uuid::new_v4().simple().to_string());
+        let cookie = privacy::poster_cookie(&token, self.ip_retention);
+        (token, cookie)
+    }
+    /// The username posts are signed with, when the request has the session of an account
+    async fn post_account(&self, pool: &SqlitePool, headers: &HeaderMap) -> Res<Option<String>> {
+        let Some(session) = bearer_token(headers).filter(|_| self.accounts) else {
+            return Ok(None);
+        };
+        let Some(account_id) = accounts::authenticate(pool, session).await? else {
+            return Ok(None);
+        };
+        Ok(Some(accounts::fetch(pool, account_id).await?.username))
+    }
+    /// Flags the posts made with the poster token of the request
+    fn mark_yours(&self, headers: &HeaderMap, comments: &mut [Comment]) {
+        let Some(token) = privacy::poster_token(headers) else {
+            return;
+        };
+        let hash = privacy::hash_poster_token(&self.ip_salt, &token);
+        for comment in comments {
+            comment.yours = comment.poster_hash.as_ref() == Some(&hash);
+        }
+    }
+    fn is_mod(&self, headers: &HeaderMap) -> bool {
+        let token = self.live_token(headers);
+        self.is_admin(headers)
+            || (token.is_some() && token == self.mod_token.as_deref())
+            || self.staff_role(headers) == Some(StaffRole::Mod)
+    }
+    fn is_admin(&self, headers: &HeaderMap) -> bool {
+        let token = self.live_token(headers);
+        (token.is_some() && token == self.admin_token.as_deref())
+            || self.staff_role(headers) == Some(StaffRole::Admin)
+    }
+    /// The bearer token of the request, unless it was revoked
+    fn live_token<'a>(&self, headers: &'a HeaderMap) -> Option<&'a str> {
+        let revoked = self.revoked.read().unwrap();
+        bearer_token(headers).filter(|t| !revoked.contains(&privacy::hash_token(t)))
+    }
+    /// The role of the staff session of the request, for staff logged in with OIDC
+    fn staff_role(&self, headers: &HeaderMap) -> Option<StaffRole> {
+        let session = bearer_token(headers)?;
+        let staff = self.oidc.as_ref()?.session(session)?;
+        staff.verified.then_some(staff.role)
+    }
+    /// Who the audit log records as doing the action, the name of the staff logged in with
+    /// OIDC or the token used, admin or mod
+    fn staff_name(&self, headers: &HeaderMap) -> String {
+        if let Some(staff) = bearer_token(headers).and_then(|s| self.oidc.as_ref()?.session(s)) {
+            return staff.name;
+        }
+        match self.is_admin(headers) {
+            true => "admin".to_string(),
+            false => "mod".to_string(),
+        }
+    }
+    /// Splits the capcode off the alias, only staff authenticated with the matching role can
+    /// sign their posts with it
+    fn take_capcode(
+        &self,
+        headers: &HeaderMap,
+        alias: Option<String>,
+    ) -> Res<(Option<String>, Option<Capcode>)> {
+        let Some((alias, capcode)) = alias.as_deref().map(split_capcode) else {
+            return Ok((None, None));
+        };
+        let allowed = match capcode {
+            Some(Capcode::Admin) => self.is_admin(headers),
+            Some(Capcode::Mod) => self.is_mod(headers),
+            None => true,
+        };
+        if !allowed {
+            return Err(
+                StatusError(StatusCode::FORBIDDEN, "capcode not allowed".to_string()).into(),
+            );
+        }
+        Ok((alias, capcode))
+    }
+}
+
+#[derive(Serialize, Deserialize, sqlx::Type, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+enum Capcode {
+    Mod,
+    Admin,
+}
+
+#[derive(Serialize, Deserialize, FromRow)]
+struct Board {
+    code: String,
+    name: String,
+    desc: String,
+    max_threads: i64,
+    max_replies: i64,
+    max_img_replies: i64,
+    max_sub_len: i64,
+    max_com_len: i64,
+    max_file_size: i64,
+    is_nsfw: bool,
+    markup: bool,
+    proxy_policy: ProxyPolicy,
+    category: Option<String>,
+    position: i64,
+    on_overboard: bool,
+    thumb_dimension: Option<i64>,
+    catalog_thumb_dimension: Option<i64>,
+    thumb_quality: Option<i64>,
+    thumb_format: Option<ThumbFormat>,
+    #[serde(default)]
+    scan_uploads: bool,
+    #[serde(default)]
+    reencode_images: bool,
+    #[serde(default)]
+    forced_anon: bool,
+    #[serde(default = "default_name")]
+    default_name: String,
+    #[serde(default)]
+    hold_new_posters: bool,
+    #[serde(default)]
+    allow_documents: bool,
+    #[serde(default)]
+    allow_audio: bool,
+    #[serde(default)]
+    pow_difficulty: i64,
+    #[serde(default = "default_pow_step")]
+    pow_step: i64,
+    created_at: i64,
+}
+impl Board {
+    /// Applies the length limits of the board, counted in graphemes so an emoji or an accented
+    /// letter count as one character
+    fn check_lengths(&self, sub: Option<&str>, com: Option<&str>) -> Res<()> {
+        let checks = [
+            ("subject", sub, self.max_sub_len),
+            ("comment", com, self.max_com_len),
+        ];
+        for (field, text, max) in checks {
+            if text.is_some_and(|t| text::graphemes(t) as i64 > max) {
+                return Err(StatusError(
+                    StatusCode::BAD_REQUEST,
+                    format!("the {field} is longer than {max} characters"),
+                )
+                .into());
+            }
+        }
+        Ok(())
+    }
+    fn pow(&self) -> PowPolicy {
+        PowPolicy {
+            difficulty: self.pow_difficulty,
+            step: self.pow_step,
+        }
+    }
+    fn accepts(&self) -> Accepts {
+        Accepts {
+            documents: self.allow_documents,
+            audio: self.allow_audio,
+        }
+    }
+    /// The name a post is made with, the alias is ignored on forced anonymous boards
+    fn poster_name(&self, alias: Option<String>) -> String {
+        alias
+            .filter(|_| !self.forced_anon)
+            .unwrap_or_else(|| self.default_name.clone())
+    }
+}
+#[derive(Serialize, Deserialize, FromRow)]
+struct BoardListing {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    board: Board,
+    total_posts: i64,
+    posts_per_hour: i64,
+    active_threads: i64,
+    settings: Option<sqlx::types::Json<BoardSettings>>,
+}
+/// Held posts are not counted as posts, the media of every post is counted as it is stored and
+/// the posters are only told apart for as long as the ip hashes are retained
+#[derive(Serialize, Deserialize, FromRow)]
+struct Stats {
+    total_posts: i64,
+    posts_last_hour: i64,
+    posts_last_day: i64,
+    unique_posters: i64,
+    media_bytes: i64,
+    threads: i64,
+}
+#[derive(Serialize, Deserialize, FromRow)]
+struct DailyStats {
+    day: String,
+    board: String,
+    posts: i64,
+    threads: i64,
+    posters: i64,
+}
+#[derive(Serialize, Deserialize)]
+struct DailyStatsQuery {
+    board: Option<String>,
+    days: Option<i64>,
+}
+#[derive(Serialize, Deserialize)]
+struct BoardsPage {
+    boards: Vec<BoardListing>,
+    announcements: Vec<Announcement>,
+}
+#[derive(Serialize, Deserialize, FromRow)]
+struct Thread {
+    id: i64,
+    board_post_no: i64,
+    file_name: Option<String>,
+    media_name: Option<String>,
+    media_hash: Option<String>,
+    media_size: Option<i64>,
+    media_ext: Option<String>,
+    media_type: Option<String>,
+    media_desc: Option<String>,
+    media_removed: Option<String>,
+    thumb_name: Option<String>,
+    thumb_size: Option<i64>,
+    media_w: Option<i64>,
+    media_h: Option<i64>,
+    media_pages: Option<i64>,
+    media_duration: Option<f64>,
+    media_bitrate: Option<i64>,
+    media_orig_size: Option<i64>,
+    thumb_w: Option<i64>,
+    thumb_h: Option<i64>,
+    catalog_thumb_name: Option<String>,
+    catalog_thumb_w: Option<i64>,
+    catalog_thumb_h: Option<i64>,
+    thumb_failed: bool,
+    sub: Option<String>,
+    com: Option<String>,
+    sub_raw: Option<String>,
+    com_raw: Option<String>,
+    op: Option<i64>,
+    board: Option<String>,
+    is_sticky: bool,
+    is_locked: bool,
+    created_at: i64,
+    replies: i64,
+    images: i64,
+}
+#[derive(Serialize, Deserialize)]
+struct ThreadsPage {
+    threads: Vec<Thread>,
+    announcements: Vec<Announcement>,
+}
+#[derive(Serialize, Deserialize, FromRow)]
+struct OverboardThread {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    thread: Thread,
+    board_name: String,
+    is_nsfw: bool,
+    bumped_at: i64,
+}
+#[derive(Serialize, Deserialize, FromRow)]
+struct Comment {
+    id: i64,
+    board_post_no: i64,
+    alias: Option<String>,
+    file_name: Option<String>,
+    media_name: Option<String>,
+    media_hash: Option<String>,
+    media_size: Option<i64>,
+    media_ext: Option<String>,
+    media_type: Option<String>,
+    media_desc: Option<String>,
+    media_removed: Option<String>,
+    thumb_name: Option<String>,
+    thumb_size: Option<i64>,
+    media_w: Option<i64>,
+    media_h: Option<i64>,
+    media_pages: Option<i64>,
+    media_duration: Option<f64>,
+    media_bitrate: Option<i64>,
+    media_orig_size: Option<i64>,
+    thumb_w: Option<i64>,
+    thumb_h: Option<i64>,
+    catalog_thumb_name: Option<String>,
+    catalog_thumb_w: Option<i64>,
+    catalog_thumb_h: Option<i64>,
+    thumb_failed: bool,
+    sub: Option<String>,
+    com: Option<String>,
+    sub_raw: Option<String>,
+    com_raw: Option<String>,
+    op: Option<i64>,
+    board: Option<String>,
+    is_sticky: bool,
+    is_locked: bool,
+    created_at: i64,
+    edited_at: Option<i64>,
+    post_extras: Option<sqlx::types::Json<PostExtras>>,
+    capcode: Option<Capcode>,
+    /// The account the post was signed with
+    account: Option<String>,
+    #[serde(skip)]
+    poster_hash: Option<String>,
+    /// Whether the post was made with the poster token of the request
+    #[sqlx(skip)]
+    #[serde(default)]
+    yours: bool,
+    #[sqlx(skip)]
+    replying_to: Vec<i64>,
+    #[sqlx(skip)]
+    replied_by: Vec<i64>,
+    #[sqlx(skip)]
+    embeds: Vec<Embed>,
+}
+/// The op of a thread with its last replies, and how many replies and images were left out
+#[derive(Serialize, Deserialize)]
+struct ThreadTail {
+    posts: Vec<Comment>,
+    omitted_posts: i64,
+    omitted_images: i64,
+}
+/// A post of the tree view of a thread, with the replies that quote it
+#[derive(Serialize, Deserialize)]
+struct CommentNode {
+    #[serde(flatten)]
+    comment: Comment,
+    children: Vec<CommentNode>,
+}
+#[derive(Serialize, Deserialize, FromRow)]
+struct IpPost {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    post: Comment,
+    thread_board: String,
+    is_held: bool,
+}
+#[derive(Serialize, Deserialize, FromRow)]
+struct Report {
+    id: i64,
+    post_id: i64,
+    board: String,
+    board_post_no: i64,
+    reason: String,
+    dismissed_at: Option<i64>,
+    created_at: i64,
+}
+#[derive(Serialize, Deserialize, FromRow)]
+struct Wordfilter {
+    id: i64,
+    board: String,
+    pattern: String,
+    replacement: String,
+    is_regex: bool,
+    created_at: i64,
+}
+#[derive(Serialize, Deserialize, FromRow)]
+struct Announcement {
+    id: i64,
+    text: String,
+    board: Option<String>,
+    expires_at: Option<i64>,
+    created_at: i64,
+}
+#[derive(Serialize, Deserialize, FromRow)]
+struct WhitelistedIp {
+    ip_hash: String,
+    created_at: i64,
+}
+#[derive(Serialize, Deserialize, FromRow)]
+struct ThreadWatch {
+    thread_id: i64,
+    last_seen: i64,
+    created_at: i64,
+}
+#[derive(Serialize, Deserialize, FromRow)]
+struct WatchedThread {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    watch: ThreadWatch,
+    board: String,
+    board_post_no: i64,
+    sub: Option<String>,
+    new_replies: i64,
+    last_post_id: i64,
+}
+#[derive(Serialize, Deserialize)]
+struct RethumbReport {
+    updated: i64,
+    failed: Vec<i64>,
+}
+/// The space saved by re-encoding the images of a board
+#[derive(Serialize, Deserialize, FromRow)]
+struct ReencodeReport {
+    board: Option<String>,
+    images: i64,
+    original_size: i64,
+    size: i64,
+    saved: i64,
+}
+#[derive(Serialize, Deserialize)]
+struct CreatedPost {
+    #[serde(flatten)]
+    comment: Comment,
+    edit_token: String,
+}
+
+#[derive(Serialize, Deserialize, Validate)]
+struct CreateBoard {
+    #[validate(length(min = 1, max = 5), custom(function = "is_board_code"))]
+    code: String,
+
+    #[validate(length(min = 1, max = 255), custom(function = "is_whitespace_empty"))]
+    name: String,
+
+    #[validate(length(min = 1, max = 255), custom(function = "is_whitespace_empty"))]
+    desc: String,
+
+    #[validate(range(min = 0))]
+    max_threads: i64,
+
+    #[validate(range(min = 0))]
+    max_replies: i64,
+
+    #[validate(range(min = 0))]
+    max_img_replies: i64,
+
+    #[validate(range(min = 0))]
+    max_sub_len: i64,
+
+    #[validate(range(min = 0))]
+    max_com_len: i64,
+
+    #[validate(range(min = 0))]
+    max_file_size: i64,
+
+    is_nsfw: bool,
+
+    #[serde(default)]
+    markup: bool,
+
+    #[serde(default)]
+    proxy_policy: ProxyPolicy,
+
+    #[validate(length(min = 1, max = 255), custom(function = "is_whitespace_empty"))]
+    category: Option<String>,
+
+    #[serde(default)]
+    position: i64,
+
+    #[serde(default = "default_true")]
+    on_overboard: bool,
+
+    #[validate(range(min = 16, max = 1024))]
+    thumb_dimension: Option<i64>,
+
+    #[validate(range(min = 16, max = 1024))]
+    catalog_thumb_dimension: Option<i64>,
+
+    #[validate(range(min = 1, max = 100))]
+    thumb_quality: Option<i64>,
+
+    thumb_format: Option<ThumbFormat>,
+
+    #[serde(default)]
+    scan_uploads: bool,
+
+    #[serde(default)]
+    reencode_images: bool,
+
+    #[serde(default)]
+    forced_anon: bool,
+
+    #[validate(length(min = 1, max = 255), custom(function = "is_whitespace_empty"))]
+    #[serde(default = "default_name")]
+    default_name: String,
+
+    #[serde(default)]
+    hold_new_posters: bool,
+
+    #[serde(default)]
+    allow_documents: bool,
+
+    #[serde(default)]
+    allow_audio: bool,
+
+    /// The leading zero bits of the proof of work asked of the posters, 0 disables it
+    #[serde(default)]
+    #[validate(range(min = 0, max = 32))]
+    pow_difficulty: i64,
+
+    /// The bits added to the proof of work for each post of the poster in the last 10 minutes
+    #[serde(default = "default_pow_step")]
+    #[validate(range(min = 0, max = 8))]
+    pow_step: i64,
+}
+
+#[derive(Serialize, Deserialize, Validate)]
+struct UpdateBoard {
+    /// Only accepted unchanged, the code of a board can't change once it is created
+    code: Option<String>,
+
+    #[validate(length(min = 1, max = 255), custom(function = "is_whitespace_empty"))]
+    name: Option<String>,
+
+    #[validate(length(min = 1, max = 255), custom(function = "is_whitespace_empty"))]
+    desc: Option<String>,
+
+    #[validate(range(min = 0))]
+    max_threads: Option<i64>,
+
+    #[validate(range(min = 0))]
+    max_replies: Option<i64>,
+
+    #[validate(range(min = 0))]
+    max_img_replies: Option<i64>,
+
+    #[validate(range(min = 0))]
+    max_sub_len: Option<i64>,
+
+    #[validate(range(min = 0))]
+    max_com_len: Option<i64>,
+
+    #[validate(range(min = 0))]
+    max_file_size: Option<i64>,
+
+    is_nsfw: Option<bool>,
+    markup: Option<bool>,
+    proxy_policy: Option<ProxyPolicy>,
+
+    #[validate(length(min = 1, max = 255), custom(function = "is_whitespace_empty"))]
+    category: Option<String>,
+
+    position: Option<i64>,
+    on_overboard: Option<bool>,
+
+    #[validate(range(min = 16, max = 1024))]
+    thumb_dimension: Option<i64>,
+
+    #[validate(range(min = 16, max = 1024))]
+    catalog_thumb_dimension: Option<i64>,
+
+    #[validate(range(min = 1, max = 100))]
+    thumb_quality: Option<i64>,
+
+    thumb_format: Option<ThumbFormat>,
+    scan_uploads: Option<bool>,
+    reencode_images: Option<bool>,
+    forced_anon: Option<bool>,
+
+    #[validate(length(min = 1, max = 255), custom(function = "is_whitespace_empty"))]
+    default_name: Option<String>,
+
+    hold_new_posters: Option<bool>,
+    allow_documents: Option<bool>,
+    allow_audio: Option<bool>,
+
+    #[validate(range(min = 0, max = 32))]
+    pow_difficulty: Option<i64>,
+
+    #[validate(range(min = 0, max = 8))]
+    pow_step: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Validate)]
+struct CreateThread {
+    #[validate(
+        length(min = 1, max = 255),
+        custom(function = "is_whitespace_empty"),
+        custom(function = "no_hidden_controls")
+    )]
+    alias: Option<String>,
+
+    #[validate(custom(function = "no_hidden_controls"))]
+    sub: Option<String>,
+    #[validate(custom(function = "no_hidden_controls"))]
+    com: Option<String>,
+
+    #[validate(
+        length(min = 1, max = 255),
+        custom(function = "is_whitespace_empty"),
+        custom(function = "no_hidden_controls")
+    )]
+    media_desc: Option<String>,
+
+    #[validate(
+        length(min = 1, max = 255),
+        custom(function = "is_whitespace_empty"),
+        custom(function = "no_hidden_controls")
+    )]
+    file_name: Option<String>,
+
+    #[validate(length(min = 1, max = 5), custom(function = "is_whitespace_empty"))]
+    board: String,
+
+    #[validate(length(min = 1, max = 255))]
+    password: Option<String>,
+
+    /// A complete upload staged with `/uploads`, sent instead of the media
+    upload_id: Option<String>,
+
+    /// The `{hash}.{ext}` of a file already stored, found with `/media/exists`, sent instead
+    /// of the media
+    media_ref: Option<String>,
+
+    /// The challenge from `/pow` and its solution, on the boards with `pow_difficulty`
+    pow_challenge: Option<String>,
+    pow_nonce: Option<String>,
+
+    /// Whether the poster accepted the rules, asked of new posters on the boards requiring it
+    #[serde(default)]
+    accepted_rules: bool,
+}
+
+#[derive(Serialize, Deserialize, Validate)]
+struct CreateComment {
+    #[validate(
+        length(min = 1, max = 255),
+        custom(function = "is_whitespace_empty"),
+        custom(function = "no_hidden_controls")
+    )]
+    alias: Option<String>,
+
+    #[validate(
+        length(min = 1),
+        custom(function = "is_whitespace_empty"),
+        custom(function = "no_hidden_controls")
+    )]
+    com: Option<String>,
+
+    #[validate(
+        length(min = 1, max = 255),
+        custom(function = "is_whitespace_empty"),
+        custom(function = "no_hidden_controls")
+    )]
+    media_desc: Option<String>,
+
+    #[validate(
+        length(min = 1, max = 255),
+        custom(function = "is_whitespace_empty"),
+        custom(function = "no_hidden_controls")
+    )]
+    file_name: Option<String>,
+
+    #[validate(range(min = 0))]
+    op: i64,
+
+    /// The board the thread is expected on, the reply is rejected when the thread is elsewhere
+    board: Option<String>,
+
+    #[validate(length(min = 1, max = 255))]
+    password: Option<String>,
+
+    /// A complete upload staged with `/uploads`, sent instead of the media
+    upload_id: Option<String>,
+
+    /// The `{hash}.{ext}` of a file already stored, found with `/media/exists`, sent instead
+    /// of the media
+    media_ref: Option<String>,
+
+    /// The challenge from `/pow` and its solution, on the boards with `pow_difficulty`
+    pow_challenge: Option<String>,
+    pow_nonce: Option<String>,
+
+    /// Whether the poster accepted the rules, asked of new posters on the boards requiring it
+    #[serde(default)]
+    accepted_rules: bool,
+}
+
+#[derive(Serialize, Deserialize, Validate)]
+struct EditPost {
+    edit_token: String,
+
+    #[validate(
+        length(min = 1),
+        custom(function = "is_whitespace_empty"),
+        custom(function = "no_hidden_controls")
+    )]
+    sub: Option<String>,
+
+    #[validate(
+        length(min = 1),
+        custom(function = "is_whitespace_empty"),
+        custom(function = "no_hidden_controls")
+    )]
+    com: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Validate)]
+struct CreateWordfilter {
+    #[validate(length(min = 1, max = 5), custom(function = "is_whitespace_empty"))]
+    board: String,
+
+    #[validate(length(min = 1, max = 255))]
+    pattern: String,
+
+    #[validate(length(max = 255))]
+    replacement: String,
+
+    #[serde(default)]
+    is_regex: bool,
+}
+
+#[derive(Serialize, Deserialize, Validate)]
+struct UpdateWordfilter {
+    #[validate(length(min = 1, max = 255))]
+    pattern: Option<String>,
+
+    #[validate(length(max = 255))]
+    replacement: Option<String>,
+
+    is_regex: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Validate)]
+struct UpdateBoardSettings {
+    #[validate(length(min = 1, max = 64), custom(function = "is_theme_name"))]
+    theme: Option<String>,
+
+    #[serde(default)]
+    #[validate(length(max = 10))]
+    banners: Vec<String>,
+
+    #[validate(length(max = 65536))]
+    custom_css: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Validate)]
+struct UpdateBoardRules {
+    #[validate(length(max = 100))]
+    rules: Vec<String>,
+
+    #[serde(default)]
+    require_acceptance: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CreateBanner {
+    #[serde(default = "default_true")]
+    is_active: bool,
+}
+
+#[derive(Serialize, Deserialize, Validate)]
+struct CreateAutobanRule {
+    #[validate(length(min = 1, max = 5), custom(function = "is_whitespace_empty"))]
+    board: Option<String>,
+
+    #[validate(length(min = 1, max = 255))]
+    pattern: String,
+
+    target: RuleTarget,
+    action: RuleAction,
+
+    #[validate(length(min = 1, max = 255), custom(function = "is_whitespace_empty"))]
+    reason: Option<String>,
+
+    #[validate(range(min = 1))]
+    ban_duration: Option<i64>,
+
+    #[serde(default)]
+    is_enabled: bool,
+}
+
+#[derive(Serialize, Deserialize, Validate)]
+struct UpdateAutobanRule {
+    #[validate(length(min = 1, max = 255))]
+    pattern: Option<String>,
+
+    target: Option<RuleTarget>,
+    action: Option<RuleAction>,
+
+    #[validate(length(min = 1, max = 255), custom(function = "is_whitespace_empty"))]
+    reason: Option<String>,
+
+    #[validate(range(min = 1))]
+    ban_duration: Option<i64>,
+
+    is_enabled: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Validate)]
+struct CreateWebhook {
+    #[validate(url, length(max = 2000))]
+    url: String,
+
+    #[validate(length(min = 16, max = 255))]
+    secret: String,
+
+    #[validate(length(min = 1))]
+    events: Vec<EventKind>,
+
+    #[validate(length(min = 1, max = 5), custom(function = "is_whitespace_empty"))]
+    board: Option<String>,
+
+    #[serde(default = "default_true")]
+    is_enabled: bool,
+}
+
+#[derive(Serialize, Deserialize, Validate)]
+struct UpdateWebhook {
+    #[validate(url, length(max = 2000))]
+    url: Option<String>,
+
+    #[validate(length(min = 16, max = 255))]
+    secret: Option<String>,
+
+    #[validate(length(min = 1))]
+    events: Option<Vec<EventKind>>,
+
+    is_enabled: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Validate)]
+struct CreateReport {
+    #[validate(length(min = 1, max = 255), custom(function = "is_whitespace_empty"))]
+    reason: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct DryRunQuery {
+    window: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RethumbQuery {
+    #[serde(default)]
+    failed: bool,
+}
+
+#[derive(Serialize, Deserialize, Validate)]
+struct CreateAnnouncement {
+    #[validate(length(min = 1, max = 2000), custom(function = "is_whitespace_empty"))]
+    text: String,
+
+    #[validate(length(min = 1, max = 5), custom(function = "is_whitespace_empty"))]
+    board: Option<String>,
+
+    expires_at: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CreateWhitelistedIp {
+    ip: IpAddr,
+}
+
+#[derive(Serialize, Deserialize, Validate)]
+struct CreateBan {
+    post_id: i64,
+
+    #[validate(length(min = 1, max = 255), custom(function = "is_whitespace_empty"))]
+    reason: Option<String>,
+
+    #[validate(range(min = 1))]
+    duration: Option<i64>,
+
+    #[validate(range(min = 0))]
+    delete_window: Option<i64>,
+
+    #[serde(default = "default_true")]
+    is_public: bool,
+}
+
+#[derive(Serialize, Deserialize, Validate)]
+struct CreateTakedown {
+    #[validate(length(min = 1, max = 50))]
+    post_ids: Vec<i64>,
+
+    #[validate(length(min = 1, max = 255), custom(function = "is_whitespace_empty"))]
+    claimant: String,
+
+    #[validate(length(min = 1, max = 255), custom(function = "is_whitespace_empty"))]
+    contact: String,
+
+    #[validate(length(min = 1, max = 5000), custom(function = "is_whitespace_empty"))]
+    reason: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct DeletePost {
+    #[serde(default)]
+    password: String,
+
+    /// Shreds the media and blocks the file from being posted again, moderators only
+    #[serde(default)]
+    purge: bool,
+}
+
+#[derive(Serialize, Deserialize, Validate)]
+struct BulkAction {
+    #[validate(length(min = 1, max = 500))]
+    ids: Vec<i64>,
+
+    /// Shreds the media of the deleted posts and blocks the files
+    #[serde(default)]
+    purge: bool,
+}
+
+/// The outcome of a bulk moderation action, applied in one transaction to the ids that didn't
+/// fail
+#[derive(Serialize, Deserialize)]
+pub struct BulkResult<T> {
+    pub done: Vec<T>,
+    pub failed: Vec<BulkFailure>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BulkFailure {
+    pub id: i64,
+    pub error: String,
+}
+
+/// Keyset pagination from the newest rows, `before` being the last id of the previous page
+#[derive(Serialize, Deserialize)]
+struct PageQuery {
+    before: Option<i64>,
+    limit: Option<i64>,
+}
+impl PageQuery {
+    fn limit(&self) -> i64 {
+        self.limit.unwrap_or(50).clamp(1, 200)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct CommentsQuery {
+    since_id: Option<i64>,
+    #[serde(default)]
+    view: ThreadView,
+}
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+#[serde(rename_all = "snake_case")]
+enum ThreadView {
+    #[default]
+    Flat,
+    Tree,
+}
+
+#[derive(Serialize)]
+struct Metrics {
+    cache: CacheStats,
+    thumbs: WorkerStats,
+    scans: ScanStats,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BackupQuery {
+    #[serde(default)]
+    media: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct OidcCallback {
+    code: String,
+    state: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StaffSessionInfo {
+    id: String,
+    #[serde(flatten)]
+    staff: StaffSession,
+}
+
+#[derive(Serialize, Deserialize)]
+struct LogoutAll {
+    subject: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RevokeToken {
+    token: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TotpCode {
+    code: String,
+}
+
+#[derive(Serialize, Deserialize, Validate)]
+struct Credentials {
+    username: String,
+    #[validate(length(min = 8, max = 128))]
+    password: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct WatchThread {
+    #[serde(default)]
+    last_seen: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SetFlag {
+    value: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct MoveThread {
+    thread_id: i64,
+    dest_board: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct MergeThreads {
+    source_thread: i64,
+    target_thread: i64,
+}
+
+struct PostFormat {
+    markup: bool,
+    wordfilters: Vec<(Regex, String, bool)>,
+}
+impl PostFormat {
+    async fn load(pool: &SqlitePool, board: Option<&Board>) -> Res<Self> {
+        let Some(board) = board else {
+            return Ok(Self {
+                markup: false,
+                wordfilters: Vec::new(),
+            });
+        };
+        let wordfilters: Vec<Wordfilter> =
+            sqlx::query_as(r#"SELECT * FROM wordfilters WHERE board = ? ORDER BY id"#)
+                .bind(&board.code)
+                .fetch_all(pool)
+                .await?;
+        let wordfilters = wordfilters
+            .into_iter()
+            .filter_map(|f| {
+                let pattern = match f.is_regex {
+                    true => f.pattern,
+                    false => regex::escape(&f.pattern),
+                };
+                Some((Regex::new(&pattern).ok()?, f.replacement, f.is_regex))
+            })
+            .collect();
+        Ok(Self {
+            markup: board.markup,
+            wordfilters,
+        })
+    }
+    fn filter(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        for (re, replacement, is_regex) in &self.wordfilters {
+            text = match is_regex {
+                true => re.replace_all(&text, replacement.as_str()),
+                false => re.replace_all(&text, NoExpand(replacement)),
+            }
+            .into_owned();
+        }
+        text
+    }
+    fn encode_comment(&self, com: &str) -> String {
+        let com = self.filter(com);
+        match self.markup {
+            true => encode_markup(com),
+            false => encode_comment(com),
+        }
+    }
+    fn encode_subject(&self, sub: &str) -> String {
+        encode_subject(self.filter(sub))
+    }
+}
+
+struct PostData<T> {
+    form: T,
+    file: Option<Upload>,
+}
+#[derive(FromRow)]
+struct MediaFile {
+    content_type: Option<String>,
+    file_name: Option<String>,
+}
+/// An error answered with its own status instead of the default one of the handler
+#[derive(Debug)]
+struct StatusError(StatusCode, String);
+impl std::fmt::Display for StatusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.1)
+    }
+}
+impl Error for StatusError {}
+fn error_status(e: &(dyn Error + 'static), default: StatusCode) -> StatusCode {
+    e.downcast_ref::<StatusError>().map_or(default, |e| e.0)
+}
+
+#[derive(Deserialize)]
+struct MediaExists {
+    sha256: String,
+}
+/// Finds a stored file by the sha256 of the file a client is about to upload
+async fn media_exists(
+    Extension(ReadPool(pool)): Extension<ReadPool>,
+    Json(form): Json<MediaExists>,
+) -> impl IntoResponse {
+    match media::find_by_hash(&pool, &form.sha256).await {
+        Ok(stored) => (StatusCode::OK, Json(Ok(stored))),
+        Err(e) => (
+            error_status(&*e, StatusCode::BAD_REQUEST),
+            Json(Err(e.to_string())),
+        ),
+    }
+}
+/// The error of a post, the cooldowns of the flood control are answered with the reason and
+/// the seconds left next to the message
+fn post_error(e: &(dyn Error + 'static), default: StatusCode) -> Response {
+    #[derive(Serialize)]
+    struct CooldownError<'a> {
+        #[serde(rename = "Err")]
+        err: String,
+        #[serde(flatten)]
+        cooldown: &'a Cooldown,
+    }
+    if let Some(cooldown) = e.downcast_ref::<Cooldown>() {
+        let retry_after = cooldown.retry_after.to_string();
+        let body = CooldownError {
+            err: e.to_string(),
+            cooldown,
+        };
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, retry_after)],
+            Json(body),
+        )
+            .into_response();
+    }
+    (error_status(e, default), Json(Err::<(), _>(e.to_string()))).into_response()
+}
+
+/// Serves the uploads at their sha256 `/media/{hash}.{ext}`, which never changes so the files
+/// are cached for a day, the names they are stored under redirect there unless the post is
+/// held. Thumbnails and banners are served by their names. The files are sent with the content
+/// type and name stored with them, only the files stored before they were kept are sniffed
+async fn get_media(
+    Path(name): Path<String>,
+    Extension(ReadPool(pool)): Extension<ReadPool>,
+) -> impl IntoResponse {
+    let not_found = || (StatusCode::NOT_FOUND, "file not found").into_response();
+    let get_media_impl = async || -> Res<Response> {
+        let hashed = media::split_hashed_name(&name);
+        let name = match hashed {
+            Some((hash, ext)) => {
+                let stored: Option<String> = sqlx::query_scalar(
+                    r#"
+                    SELECT media_name FROM comments
+                    WHERE media_hash = ? AND media_ext = ? AND NOT is_held LIMIT 1
+                    "#,
+                )
+                .bind(hash)
+                .bind(ext)
+                .fetch_optional(&*pool)
+                .await?;
+                match stored {
+                    Some(stored) => stored,
+                    None => return Ok(not_found()),
+                }
+            }
+            None => {
+                // held media isn't redirected to its hash, it is only reachable by the random
+                // name it is stored under
+                let hashed: Option<(String, String)> = sqlx::query_as(
+                    r#"
+                    SELECT media_hash, media_ext FROM comments
+                    WHERE media_name = ? AND media_hash IS NOT NULL AND NOT is_held LIMIT 1
+                    "#,
+                )
+                .bind(&name)
+                .fetch_optional(&*pool)
+                .await?;
+                if let Some((hash, ext)) = hashed {
+                    let location = format!("/media/{hash}.{ext}");
+                    let res = (
+                        StatusCode::PERMANENT_REDIRECT,
+                        [(header::LOCATION, location)],
+                    );
+                    return Ok(res.into_response());
+                }
+                name.clone()
+            }
+        };
+        let Some(path) = media::media_path(&name).await else {
+            return Ok(not_found());
+        };
+        let Ok(mut file) = File::open(path).await else {
+            return Ok(not_found());
+        };
+        let stored: Option<MediaFile> = sqlx::query_as(
+            r#"
+            SELECT media_type AS content_type,
+            COALESCE(file_name, media_name || '.' || media_ext) AS file_name
+            FROM comments WHERE media_name = ?1
+            UNION ALL
+            SELECT thumb_type, ?1 || CASE thumb_type WHEN 'image/webp' THEN '.webp' ELSE '.jpg' END
+            FROM comments WHERE thumb_name = ?1 OR catalog_thumb_name = ?1
+            UNION ALL
+            SELECT media_type, media_name || '.' || media_ext FROM banners WHERE media_name = ?1
+            LIMIT 1
+            "#,
+        )
+        .bind(&name)
+        .fetch_optional(&*pool)
+        .await?;
+        let (content_type, file_name) = match stored {
+            Some(stored) => (stored.content_type, stored.file_name),
+            None => (None, None),
+        };
+        let content_type = match content_type {
+            Some(content_type) => content_type,
+            None => {
+                let mut head = vec![0; 8192];
+                let read = file.read(&mut head).await?;
+                file.rewind().await?;
+                infer::get(&head[..read])
+                    .map_or("application/octet-stream", |kind| kind.mime_type())
+                    .to_string()
+            }
+        };
+        let len = file.metadata().await?.len();
+        let disposition = match file_name {
+            Some(file_name) => content_disposition(&file_name),
+            None => "inline".to_string(),
+        };
+        let headers = [
+            (header::CONTENT_TYPE, content_type),
+            (header::CONTENT_LENGTH, len.to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ];
+        let body = Body::from_stream(ReaderStream::new(file));
+        let mut res = (StatusCode::OK, headers, body).into_response();
+        if hashed.is_some() {
+            // not immutable, since takedowns, purges and deletions can still remove the file
+            let cache = HeaderValue::from_static("max-age=86400");
+            res.headers_mut().insert(header::CACHE_CONTROL, cache);
+        }
+        Ok(res)
+    };
+    match get_media_impl().await {
+        Ok(res) => res,
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+/// An inline disposition naming the file, with an ascii fallback for the clients that don't
+/// read the utf-8 name
+fn content_disposition(file_name: &str) -> String {
+    let fallback: String = file_name
+        .chars()
+        .map(|c| match c {
+            ' '..='~' if c != '"' && c != '\\' => c,
+            _ => '_',
+        })
+        .collect();
+    let encoded: String = file_name
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect();
+    format!("inline; filename=\"{fallback}\"; filename*=UTF-8''{encoded}")
+}
+async fn get_boards(
+    Extension(ReadPool(pool)): Extension<ReadPool>,
+    Extension(cache): Extension<Arc<ResponseCache>>,
+) -> Response {
+    let get_boards_impl = async || -> Res<BoardsPage> {
+        let boards = sqlx::query_as(
+            r#"
+            SELECT b.*,
+            COUNT(p.id) AS total_posts,
+            COUNT(CASE WHEN p.created_at >= strftime('%s', 'now') - 3600 THEN 1 END) AS posts_per_hour,
+            COUNT(DISTINCT t.id) AS active_threads,
+            CASE WHEN s.board IS NULL THEN NULL
+            ELSE json_object('theme', s.theme, 'banners', json(s.banners), 'custom_css', s.custom_css)
+            END AS settings
+            FROM boards b
+            LEFT JOIN board_settings s ON s.board = b.code
+            LEFT JOIN comments t ON t.board = b.code AND t.op IS NULL AND NOT t.is_held
+            LEFT JOIN comments p ON (p.id = t.id OR p.op = t.id) AND NOT p.is_held
+            GROUP BY b.code
+            ORDER BY b.category IS NULL, b.category, b.position, b.code
+            "#,
+        )
+        .fetch_all(&*pool)
+        .await?;
+        let announcements = fetch_announcements(&pool, None).await?;
+        Ok(BoardsPage {
+            boards,
+            announcements,
+        })
+    };
+    cached_page(cache.get_or_load(CacheKey::Boards, get_boards_impl()).await)
+}
+async fn get_threads(
+    Path(board_id): Path<String>,
+    Extension(ReadPool(pool)): Extension<ReadPool>,
+    Extension(cache): Extension<Arc<ResponseCache>>,
+) -> Response {
+    let get_threads_impl = async || -> Res<ThreadsPage> {
+        let threads = sqlx::query_as(
+            r#"
+            SELECT
+            c.id AS id,
+            c.board_post_no AS board_post_no,
+            c.file_name AS file_name,
+            c.media_name AS media_name,
+            c.media_hash AS media_hash,
+            c.thumb_name AS thumb_name,
+            c.media_size AS media_size,
+            c.media_desc AS media_desc,
+            c.media_removed AS media_removed,
+            c.thumb_size AS thumb_size,
+            c.media_w AS media_w,
+            c.media_h AS media_h,
+            c.media_pages AS media_pages,
+            c.media_duration AS media_duration,
+            c.media_bitrate AS media_bitrate,
+            c.media_orig_size AS media_orig_size,
+            c.thumb_w AS thumb_w,
+            c.thumb_h AS thumb_h,
+            c.catalog_thumb_name AS catalog_thumb_name,
+            c.catalog_thumb_w AS catalog_thumb_w,
+            c.catalog_thumb_h AS catalog_thumb_h,
+            c.thumb_failed AS thumb_failed,
+            c.media_ext AS media_ext,
+            c.media_type AS media_type,
+            c.sub AS sub,
+            c.com AS com,
+            c.sub_raw AS sub_raw,
+            c.com_raw AS com_raw,
+            c.op AS op,
+            c.board AS board,
+            c.is_sticky AS is_sticky,
+            c.is_locked AS is_locked,
+            c.created_at AS created_at,
+            COUNT(r.id) AS replies,
+            COUNT(r.media_name) AS images
+            FROM comments c
+            LEFT JOIN comments r ON r.op = c.id AND NOT r.is_held
+            WHERE c.op IS NULL AND NOT c.is_held AND c.board = ?
+            GROUP BY c.id
+            ORDER BY c.is_sticky DESC, c.id
+            "#,
+        )
+        .bind(&board_id)
+        .fetch_all(&*pool)
+        .await?;
+        let announcements = fetch_announcements(&pool, Some(&board_id)).await?;
+        Ok(ThreadsPage {
+            threads,
+            announcements,
+        })
+    };
+    let key = CacheKey::Threads(board_id.clone());
+    cached_page(cache.get_or_load(key, get_threads_impl()).await)
+}
+async fn get_overboard(
+    Extension(ReadPool(pool)): Extension<ReadPool>,
+    Extension(cache): Extension<Arc<ResponseCache>>,
+) -> Response {
+    let get_overboard_impl = async || -> Res<Vec<OverboardThread>> {
+        sqlx::query_as(
+            r#"
+            SELECT
+            c.id AS id,
+            c.board_post_no AS board_post_no,
+            c.file_name AS file_name,
+            c.media_name AS media_name,
+            c.media_hash AS media_hash,
+            c.thumb_name AS thumb_name,
+            c.media_size AS media_size,
+            c.media_desc AS media_desc,
+            c.media_removed AS media_removed,
+            c.thumb_size AS thumb_size,
+            c.media_w AS media_w,
+            c.media_h AS media_h,
+            c.media_pages AS media_pages,
+            c.media_duration AS media_duration,
+            c.media_bitrate AS media_bitrate,
+            c.media_orig_size AS media_orig_size,
+            c.thumb_w AS thumb_w,
+            c.thumb_h AS thumb_h,
+            c.catalog_thumb_name AS catalog_thumb_name,
+            c.catalog_thumb_w AS catalog_thumb_w,
+            c.catalog_thumb_h AS catalog_thumb_h,
+            c.thumb_failed AS thumb_failed,
+            c.media_ext AS media_ext,
+            c.media_type AS media_type,
+            c.sub AS sub,
+            c.com AS com,
+            c.sub_raw AS sub_raw,
+            c.com_raw AS com_raw,
+            c.op AS op,
+            c.board AS board,
+            c.is_sticky AS is_sticky,
+            c.is_locked AS is_locked,
+            c.created_at AS created_at,
+            COUNT(r.id) AS replies,
+            COUNT(r.media_name) AS images,
+            b.name AS board_name,
+            b.is_nsfw AS is_nsfw,
+            MAX(c.created_at, COALESCE(MAX(r.created_at), 0)) AS bumped_at
+            FROM comments c
+            JOIN boards b ON b.code = c.board AND b.on_overboard
+            LEFT JOIN comments r ON r.op = c.id AND NOT r.is_held
+            WHERE c.op IS NULL AND NOT c.is_held
+            GROUP BY c.id
+            ORDER BY bumped_at DESC
+            LIMIT ?
+            "#,
+        )
+        .bind(OVERBOARD_THREADS)
+        .fetch_all(&*pool)
+        .await
+        .map_err(|e| e.into())
+    };
+    cached_page(
+        cache
+            .get_or_load(CacheKey::Overboard, get_overboard_impl())
+            .await,
+    )
+}
+async fn get_stats(
+    Extension(ReadPool(pool)): Extension<ReadPool>,
+    Extension(cache): Extension<Arc<ResponseCache>>,
+) -> Response {
+    let page = cache
+        .get_or_load(CacheKey::Stats(None), fetch_stats(&pool, None))
+        .await;
+    cached_page(page)
+}
+/// The daily counts of the last `days` days (default 30) as rolled up by the scheduler
+async fn get_daily_stats(
+    Query(query): Query<DailyStatsQuery>,
+    Extension(ReadPool(pool)): Extension<ReadPool>,
+) -> impl IntoResponse {
+    let get_daily_stats_impl = async || -> Res<Vec<DailyStats>> {
+        sqlx::query_as(
+            r#"
+            SELECT * FROM daily_stats
+            WHERE day >= date('now', '-' || ?1 || ' days') AND (?2 IS NULL OR board = ?2)
+            ORDER BY day DESC, board
+            "#,
+        )
+        .bind(query.days.unwrap_or(30).clamp(1, 366))
+        .bind(&query.board)
+        .fetch_all(&*pool)
+        .await
+        .map_err(|e| e.into())
+    };
+    match get_daily_stats_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(Err(e.to_string()))),
+    }
+}
+async fn get_board_stats(
+    Path(board_id): Path<String>,
+    Extension(ReadPool(pool)): Extension<ReadPool>,
+    Extension(cache): Extension<Arc<ResponseCache>>,
+) -> Response {
+    let exists: Result<bool, _> =
+        sqlx::query_scalar(r#"SELECT EXISTS(SELECT 1 FROM boards WHERE code = ?)"#)
+            .bind(&board_id)
+            .fetch_one(&*pool)
+            .await;
+    match exists {
+        Ok(true) => {}
+        Ok(false) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(Err::<(), _>("board not found".to_string())),
+            )
+                .into_response();
+        }
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(Err::<(), _>(e.to_string())),
+            )
+                .into_response();
+        }
+    }
+    let key = CacheKey::Stats(Some(board_id.clone()));
+    cached_page(
+        cache
+            .get_or_load(key, fetch_stats(&pool, Some(&board_id)))
+            .await,
+    )
+}
+/// With `since_id`, only the posts newer than it are returned, or 304 if there are none
+async fn get_comments(
+    Path((board_id, thread_id)): Path<(String, i64)>,
+    Query(query): Query<CommentsQuery>,
+    headers: HeaderMap,
+    Extension(ReadPool(pool)): Extension<ReadPool>,
+    Extension(config): Extension<Arc<Config>>,
+) -> Response {
+    // relative to the thread, so it holds under /api/v1 and the legacy routes alike
+    if let Some(res) = thread_redirect(&pool, &board_id, thread_id, |board, target_id| {
+        format!("../../{board}/thread/{target_id}")
+    })
+    .await
+    {
+        return res;
+    }
+    let get_comments_impl = async || -> Res<Vec<Comment>> {
+        // checked first, so a thread with no new posts isn't confused with a missing one
+        let exists: bool = sqlx::query_scalar(
+            r#"SELECT EXISTS(SELECT 1 FROM comments WHERE id = ? AND board = ? AND op IS NULL AND NOT is_held)"#,
+        )
+        .bind(thread_id)
+        .bind(&board_id)
+        .fetch_one(&*pool)
+        .await?;
+        if !exists {
+            let message = "thread not found".to_string();
+            return Err(StatusError(StatusCode::NOT_FOUND, message).into());
+        }
+        let thread_id = Some(thread_id);
+        let mut comments = sqlx::query_as(
+            r#"SELECT * FROM comments WHERE board = ? AND (id = ? OR op = ?) AND id > ? AND NOT is_held ORDER BY id"#,
+        )
+        .bind(board_id)
+        .bind(thread_id)
+        .bind(thread_id)
+        .bind(query.since_id.unwrap_or(0))
+        .fetch_all(&*pool)
+        .await?;
+        attach_backlinks(&pool, &mut comments).await?;
+        embeds::attach(&pool, &mut comments).await?;
+        config.mark_yours(&headers, &mut comments);
+        Ok(comments)
+    };
+    match get_comments_impl().await {
+        Ok(res) if res.is_empty() && query.since_id.is_some() => {
+            StatusCode::NOT_MODIFIED.into_response()
+        }
+        Ok(res) if query.view == ThreadView::Tree => {
+            (StatusCode::OK, Json(Ok::<_, String>(comment_tree(res)))).into_response()
+        }
+        Ok(res) => (StatusCode::OK, Json(Ok::<_, String>(res))).into_response(),
+        Err(e) => (
+            error_status(&*e, StatusCode::INTERNAL_SERVER_ERROR),
+            Json(Err::<(), _>(e.to_string())),
+        )
+            .into_response(),
+    }
+}
+async fn get_last_comments(
+    Path((board_id, thread_id, n)): Path<(String, i64, u32)>,
+    headers: HeaderMap,
+    Extension(ReadPool(pool)): Extension<ReadPool>,
+    Extension(config): Extension<Arc<Config>>,
+) -> Response {
+    if let Some(res) = thread_redirect(&pool, &board_id, thread_id, |board, target_id| {
+        format!("../../../../{board}/thread/{target_id}/last/{n}")
+    })
+    .await
+    {
+        return res;
+    }
+    let get_last_comments_impl = async || -> Res<Option<ThreadTail>> {
+        let mut posts: Vec<Comment> = sqlx::query_as(
+            r#"
+            SELECT * FROM comments
+            WHERE board = ?1 AND NOT is_held
+            AND ((id = ?2 AND op IS NULL) OR id IN (
+                SELECT id FROM comments WHERE op = ?2 AND NOT is_held ORDER BY id DESC LIMIT ?3
+            ))
+            ORDER BY id
+            "#,
+        )
+        .bind(&board_id)
+        .bind(thread_id)
+        .bind(n)
+        .fetch_all(&*pool)
+        .await?;
+        if posts.first().is_none_or(|p| p.id != thread_id) {
+            return Ok(None);
+        }
+        let (replies, images): (i64, i64) = sqlx::query_as(
+            r#"SELECT COUNT(id), COUNT(media_name) FROM comments WHERE op = ? AND NOT is_held"#,
+        )
+        .bind(thread_id)
+        .fetch_one(&*pool)
+        .await?;
+        let shown = posts.len() as i64 - 1;
+        let shown_images = posts[1..].iter().filter(|p| p.media_name.is_some()).count();
+        attach_backlinks(&pool, &mut posts).await?;
+        embeds::attach(&pool, &mut posts).await?;
+        config.mark_yours(&headers, &mut posts);
+        Ok(Some(ThreadTail {
+            omitted_posts: replies - shown,
+            omitted_images: images - shown_images as i64,
+            posts,
+        }))
+    };
+    match get_last_comments_impl().await {
+        Ok(Some(res)) => (StatusCode::OK, Json(Ok::<_, String>(res))).into_response(),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(Err::<(), _>("thread not found".to_string())),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(Err::<(), _>(e.to_string())),
+        )
+            .into_response(),
+    }
+}
+/// The redirect of a thread that was merged or moved, to the location built from its new board
+/// and id
+async fn thread_redirect(
+    pool: &SqlitePool,
+    board_id: &str,
+    thread_id: i64,
+    location: impl FnOnce(String, i64) -> String,
+) -> Option<Response> {
+    let redirect: Result<Option<(String, i64)>, _> = sqlx::query_as(
+        r#"
+        SELECT t.board, t.id FROM thread_redirects r
+        JOIN comments t ON t.id = r.target_id
+        WHERE r.board = ? AND r.thread_id = ?
+        "#,
+    )
+    .bind(board_id)
+    .bind(thread_id)
+    .fetch_optional(pool)
+    .await;
+    match redirect {
+        Ok(Some((board, target_id))) => Some(
+            (
+                StatusCode::MOVED_PERMANENTLY,
+                [(header::LOCATION, location(board, target_id))],
+                Json(Err::<(), _>("thread was moved".to_string())),
+            )
+                .into_response(),
+        ),
+        Ok(None) => None,
+        Err(e) => Some(
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(Err::<(), _>(e.to_string())),
+            )
+                .into_response(),
+        ),
+    }
+}
+async fn get_post(
+    Path((board_id, post_no)): Path<(String, i64)>,
+    headers: HeaderMap,
+    Extension(ReadPool(pool)): Extension<ReadPool>,
+    Extension(config): Extension<Arc<Config>>,
+) -> impl IntoResponse {
+    let get_post_impl = async || -> Res<Option<Comment>> {
+        let mut comment: Option<Comment> = sqlx::query_as(
+            r#"
+            SELECT c.* FROM comments c
+            LEFT JOIN comments t ON t.id = c.op
+            WHERE COALESCE(t.board, c.board) = ? AND c.board_post_no = ? AND NOT c.is_held
+            "#,
+        )
+        .bind(board_id)
+        .bind(post_no)
+        .fetch_optional(&*pool)
+        .await?;
+        attach_backlinks(&pool, comment.as_mut_slice()).await?;
+        embeds::attach(&pool, comment.as_mut_slice()).await?;
+        config.mark_yours(&headers, comment.as_mut_slice());
+        Ok(comment)
+    };
+    match get_post_impl().await {
+        Ok(Some(res)) => (StatusCode::OK, Json(Ok(res))),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(Err("post not found".to_string())),
+        ),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(Err(e.to_string()))),
+    }
+}
+async fn create_board(
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(cache): Extension<Arc<ResponseCache>>,
+    Json(form): Json<CreateBoard>,
+) -> impl IntoResponse {
+    let create_board_impl = async || -> Res<Board> {
+        form.validate()?;
+        config
+            .body_limits
+            .check_file_size(Some(form.max_file_size))?;
+        let exists: bool =
+            sqlx::query_scalar(r#"SELECT EXISTS(SELECT 1 FROM boards WHERE code = ?)"#)
+                .bind(&form.code)
+                .fetch_one(&*pool)
+                .await?;
+        if exists {
+            return Err(board_exists(&form.code).into());
+        }
+        let board = insert_board(&mut *pool.acquire().await?, &form).await?;
+        cache.invalidate_board(&board.code).await;
+        Ok(board)
+    };
+
+    match create_board_impl().await {
+        Ok(res) => (StatusCode::CREATED, Json(Ok(res))),
+        Err(e) => (
+            error_status(&*e, StatusCode::INTERNAL_SERVER_ERROR),
+            Json(Err(e.to_string())),
+        ),
+    }
+}
+async fn insert_board(conn: &mut SqliteConnection, form: &CreateBoard) -> Res<Board> {
+    // each column reads the field of the same name, so values can't end up in another column
+    sqlx::query_as(
+        r#"
+        INSERT INTO boards (code, name, desc, max_threads, max_replies, max_img_replies, max_sub_len, max_com_len, max_file_size, is_nsfw, markup, proxy_policy, category, position, on_overboard, thumb_dimension, catalog_thumb_dimension, thumb_quality, thumb_format, scan_uploads, reencode_images, forced_anon, default_name, hold_new_posters, allow_documents, allow_audio, pow_difficulty, pow_step)
+        VALUES (
+            ?1 ->> 'code',
+            ?1 ->> 'name',
+            ?1 ->> 'desc',
+            ?1 ->> 'max_threads',
+            ?1 ->> 'max_replies',
+            ?1 ->> 'max_img_replies',
+            ?1 ->> 'max_sub_len',
+            ?1 ->> 'max_com_len',
+            ?1 ->> 'max_file_size',
+            ?1 ->> 'is_nsfw',
+            ?1 ->> 'markup',
+            ?1 ->> 'proxy_policy',
+            ?1 ->> 'category',
+            ?1 ->> 'position',
+            ?1 ->> 'on_overboard',
+            ?1 ->> 'thumb_dimension',
+            ?1 ->> 'catalog_thumb_dimension',
+            ?1 ->> 'thumb_quality',
+            ?1 ->> 'thumb_format',
+            ?1 ->> 'scan_uploads',
+            ?1 ->> 'reencode_images',
+            ?1 ->> 'forced_anon',
+            ?1 ->> 'default_name',
+            ?1 ->> 'hold_new_posters',
+            ?1 ->> 'allow_documents',
+            ?1 ->> 'allow_audio',
+            ?1 ->> 'pow_difficulty',
+            ?1 ->> 'pow_step'
+        )
+        RETURNING *
+        "#,
+    )
+    .bind(sqlx::types::Json(form))
+    .fetch_one(conn)
+    .await
+    .map_err(|e| match e {
+        sqlx::Error::Database(e) if e.is_unique_violation() => board_exists(&form.code).into(),
+        e => Box::<dyn Error>::from(e),
+    })
+}
+/// Creates the boards of the SEED_BOARDS file, a json array of boards as sent to
+/// `/create_board`, when there are no boards yet
+async fn seed_boards(pool: &SqlitePool, config: &Config) -> Res<()> {
+    let Ok(path) = std::env::var("SEED_BOARDS") else {
+        return Ok(());
+    };
+    let mut tx = pool.begin().await?;
+    let empty: bool = sqlx::query_scalar(r#"SELECT NOT EXISTS(SELECT 1 FROM boards)"#)
+        .fetch_one(&mut *tx)
+        .await?;
+    if !empty {
+        return Ok(());
+    }
+    let boards: Vec<CreateBoard> = serde_json::from_str(&std::fs::read_to_string(&path)?)
+        .map_err(|e| format!("invalid boards in {path}: {e}"))?;
+    for board in &boards {
+        board
+            .validate()
+            .map_err(|e| format!("invalid board /{}/ in {path}: {e}", board.code))?;
+        config
+            .body_limits
+            .check_file_size(Some(board.max_file_size))?;
+        insert_board(&mut tx, board).await?;
+    }
+    tx.commit().await?;
+    tracing::info!("created {} boards from {path}", boards.len());
+    Ok(())
+}
+fn board_exists(code: &str) -> StatusError {
+    StatusError(
+        StatusCode::CONFLICT,
+        format!("board /{code}/ already exists"),
+    )
+}
+async fn update_board(
+    Path(board_id): Path<String>,
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(cache): Extension<Arc<ResponseCache>>,
+    Json(form): Json<UpdateBoard>,
+) -> impl IntoResponse {
+    if !config.is_admin(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let update_board_impl = async || -> Res<Board> {
+        form.validate()?;
+        config.body_limits.check_file_size(form.max_file_size)?;
+        if form.code.as_ref().is_some_and(|code| *code != board_id) {
+            return Err("board codes can't be changed".into());
+        }
+        let board: Board = sqlx::query_as(
+            r#"
+            UPDATE boards
+            SET name = COALESCE(?, name),
+                desc = COALESCE(?, desc),
+                max_threads = COALESCE(?, max_threads),
+                max_replies = COALESCE(?, max_replies),
+                max_img_replies = COALESCE(?, max_img_replies),
+                max_sub_len = COALESCE(?, max_sub_len),
+                max_com_len = COALESCE(?, max_com_len),
+                max_file_size = COALESCE(?, max_file_size),
+                is_nsfw = COALESCE(?, is_nsfw),
+                markup = COALESCE(?, markup),
+                proxy_policy = COALESCE(?, proxy_policy),
+                category = COALESCE(?, category),
+                position = COALESCE(?, position),
+                on_overboard = COALESCE(?, on_overboard),
+                thumb_dimension = COALESCE(?, thumb_dimension),
+                catalog_thumb_dimension = COALESCE(?, catalog_thumb_dimension),
+                thumb_quality = COALESCE(?, thumb_quality),
+                thumb_format = COALESCE(?, thumb_format),
+                scan_uploads = COALESCE(?, scan_uploads),
+                reencode_images = COALESCE(?, reencode_images),
+                forced_anon = COALESCE(?, forced_anon),
+                default_name = COALESCE(?, default_name),
+                hold_new_posters = COALESCE(?, hold_new_posters),
+                allow_documents = COALESCE(?, allow_documents),
+                allow_audio = COALESCE(?, allow_audio),
+                pow_difficulty = COALESCE(?, pow_difficulty),
+                pow_step = COALESCE(?, pow_step)
+            WHERE code = ?
+            RETURNING *
+            "#,
+        )
+        .bind(form.name)
+        .bind(form.desc)
+        .bind(form.max_threads)
+        .bind(form.max_replies)
+        .bind(form.max_img_replies)
+        .bind(form.max_sub_len)
+        .bind(form.max_com_len)
+        .bind(form.max_file_size)
+        .bind(form.is_nsfw)
+        .bind(form.markup)
+        .bind(form.proxy_policy)
+        .bind(form.category)
+        .bind(form.position)
+        .bind(form.on_overboard)
+        .bind(form.thumb_dimension)
+        .bind(form.catalog_thumb_dimension)
+        .bind(form.thumb_quality)
+        .bind(form.thumb_format)
+        .bind(form.scan_uploads)
+        .bind(form.reencode_images)
+        .bind(form.forced_anon)
+        .bind(form.default_name)
+        .bind(form.hold_new_posters)
+        .bind(form.allow_documents)
+        .bind(form.allow_audio)
+        .bind(form.pow_difficulty)
+        .bind(form.pow_step)
+        .bind(board_id)
+        .fetch_optional(&*pool)
+        .await?
+        .ok_or("board not found")?;
+        cache.invalidate_board(&board.code).await;
+        Ok(board)
+    };
+    match update_board_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (StatusCode::BAD_REQUEST, Json(Err(e.to_string()))),
+    }
+}
+async fn put_board_settings(
+    Path(board_id): Path<String>,
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(cache): Extension<Arc<ResponseCache>>,
+    Json(form): Json<UpdateBoardSettings>,
+) -> impl IntoResponse {
+    if !config.is_admin(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let put_board_settings_impl = async || -> Res<BoardSettings> {
+        form.validate()?;
+        theme::check_banners(&form.banners)?;
+        let custom_css = form
+            .custom_css
+            .as_deref()
+            .map(theme::sanitize_css)
+            .transpose()?;
+        let (theme, banners, custom_css): (
+            Option<String>,
+            sqlx::types::Json<Vec<String>>,
+            Option<String>,
+        ) = sqlx::query_as(
+            r#"
+                INSERT INTO board_settings (board, theme, banners, custom_css)
+                SELECT code, ?, ?, ? FROM boards WHERE code = ?
+                ON CONFLICT (board) DO UPDATE
+                SET theme = excluded.theme,
+                    banners = excluded.banners,
+                    custom_css = excluded.custom_css,
+                    updated_at = strftime('%s', 'now')
+                RETURNING theme, banners, custom_css
+                "#,
+        )
+        .bind(form.theme)
+        .bind(sqlx::types::Json(form.banners))
+        .bind(custom_css)
+        .bind(&board_id)
+        .fetch_optional(&*pool)
+        .await?
+        .ok_or("board not found")?;
+        cache.invalidate_board(&board_id).await;
+        Ok(BoardSettings {
+            theme,
+            banners: banners.0,
+            custom_css,
+        })
+    };
+    match put_board_settings_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (StatusCode::BAD_REQUEST, Json(Err(e.to_string()))),
+    }
+}
+async fn get_banners(
+    Path(board_id): Path<String>,
+    Extension(ReadPool(pool)): Extension<ReadPool>,
+) -> impl IntoResponse {
+    let get_banners_impl = async || -> Res<Vec<Banner>> {
+        sqlx::query_as(r#"SELECT * FROM banners WHERE board = ? ORDER BY id"#)
+            .bind(board_id)
+            .fetch_all(&*pool)
+            .await
+            .map_err(|e| e.into())
+    };
+    match get_banners_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(Err(e.to_string()))),
+    }
+}
+/// The rules of the board, for frontends to show before the first post
+async fn get_board_rules(
+    Path(board_id): Path<String>,
+    Extension(ReadPool(pool)): Extension<ReadPool>,
+) -> impl IntoResponse {
+    match rules::fetch(&pool, &board_id).await {
+        Ok(Some(rules)) => (StatusCode::OK, Json(Ok(rules))),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(Err("board not found".to_string())),
+        ),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(Err(e.to_string()))),
+    }
+}
+async fn put_board_rules(
+    Path(board_id): Path<String>,
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+    Json(form): Json<UpdateBoardRules>,
+) -> impl IntoResponse {
+    if !config.is_admin(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let put_board_rules_impl = async || -> Res<BoardRules> {
+        form.validate()?;
+        rules::save(&pool, &board_id, form.rules, form.require_acceptance).await
+    };
+    match put_board_rules_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (StatusCode::BAD_REQUEST, Json(Err(e.to_string()))),
+    }
+}
+/// Redirects to a random active banner of the board, so it can be used as the source of an
+/// image as it is
+async fn get_banner(
+    Path(board_id): Path<String>,
+    Extension(ReadPool(pool)): Extension<ReadPool>,
+) -> Response {
+    match banners::random(&pool, &board_id).await {
+        Ok(Some(banner)) => (
+            StatusCode::TEMPORARY_REDIRECT,
+            [
+                (header::LOCATION, format!("/media/{}", banner.media_name)),
+                (header::CACHE_CONTROL, "no-store".to_string()),
+            ],
+        )
+            .into_response(),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(Err::<(), _>("the board has no banners".to_string())),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(Err::<(), _>(e.to_string())),
+        )
+            .into_response(),
+    }
+}
+async fn create_banner(
+    Path(board_id): Path<String>,
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+    body: PostBody,
+) -> impl IntoResponse {
+    if !config.is_admin(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let create_banner_impl = async || -> Res<Banner> {
+        let PostData { form, file } = parse_post_body::<CreateBanner>(body).await?;
+        let exists: bool =
+            sqlx::query_scalar(r#"SELECT EXISTS(SELECT 1 FROM boards WHERE code = ?)"#)
+                .bind(&board_id)
+                .fetch_one(&*pool)
+                .await?;
+        if !exists {
+            return Err("board not found".into());
+        }
+        let file = file.ok_or("media is required")?;
+        banners::save(&pool, &board_id, file, form.is_active).await
+    };
+    match create_banner_impl().await {
+        Ok(res) => (StatusCode::CREATED, Json(Ok(res))),
+        Err(e) => (
+            error_status(&*e, StatusCode::BAD_REQUEST),
+            Json(Err(e.to_string())),
+        ),
+    }
+}
+async fn update_banner(
+    Path((board_id, banner_id)): Path<(String, i64)>,
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+    Json(form): Json<CreateBanner>,
+) -> impl IntoResponse {
+    if !config.is_admin(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let update_banner_impl = async || -> Res<Banner> {
+        sqlx::query_as(r#"UPDATE banners SET is_active = ? WHERE board = ? AND id = ? RETURNING *"#)
+            .bind(form.is_active)
+            .bind(board_id)
+            .bind(banner_id)
+            .fetch_optional(&*pool)
+            .await?
+            .ok_or("banner not found".into())
+    };
+    match update_banner_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (StatusCode::BAD_REQUEST, Json(Err(e.to_string()))),
+    }
+}
+async fn delete_banner(
+    Path((board_id, banner_id)): Path<(String, i64)>,
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+) -> impl IntoResponse {
+    if !config.is_admin(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let delete_banner_impl = async || -> Res<Banner> {
+        banners::delete(&pool, &board_id, banner_id)
+            .await?
+            .ok_or("banner not found".into())
+    };
+    match delete_banner_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (StatusCode::BAD_REQUEST, Json(Err(e.to_string()))),
+    }
+}
+async fn delete_board(
+    Path(board_id): Path<String>,
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(cache): Extension<Arc<ResponseCache>>,
+) -> impl IntoResponse {
+    if !config.is_admin(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let delete_board_impl = async || -> Res<Board> {
+        let media: Vec<(Option<String>, Option<String>, Option<String>)> = sqlx::query_as(
+            r#"
+            SELECT media_name, thumb_name, catalog_thumb_name FROM comments
+            WHERE board = ?1 OR op IN (SELECT id FROM comments WHERE board = ?1)
+            UNION ALL SELECT media_name, NULL, NULL FROM banners WHERE board = ?1
+            "#,
+        )
+        .bind(&board_id)
+        .fetch_all(&*pool)
+        .await?;
+
+        let mut tx = pool.begin().await?;
+        sqlx::query(
+            r#"DELETE FROM comments WHERE op IN (SELECT id FROM comments WHERE board = ?)"#,
+        )
+        .bind(&board_id)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query(r#"DELETE FROM comments WHERE board = ?"#)
+            .bind(&board_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(r#"DELETE FROM wordfilters WHERE board = ?"#)
+            .bind(&board_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(r#"DELETE FROM announcements WHERE board = ?"#)
+            .bind(&board_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(r#"DELETE FROM banners WHERE board = ?"#)
+            .bind(&board_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(r#"DELETE FROM board_settings WHERE board = ?"#)
+            .bind(&board_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(r#"DELETE FROM board_rules WHERE board = ?"#)
+            .bind(&board_id)
+            .execute(&mut *tx)
+            .await?;
+        let board = sqlx::query_as(r#"DELETE FROM boards WHERE code = ? RETURNING *"#)
+            .bind(&board_id)
+            .fetch_optional(&mut *tx)
+            .await?
+            .ok_or("board not found")?;
+        tx.commit().await?;
+
+        for name in media.into_iter().flat_map(|(m, t, c)| [m, t, c]).flatten() {
+            remove_media(&name).await?;
+        }
+        cache.invalidate_board(&board_id).await;
+        Ok(board)
+    };
+    match delete_board_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (StatusCode::BAD_REQUEST, Json(Err(e.to_string()))),
+    }
+}
+async fn create_thread(
+    PeerIp(ip): PeerIp,
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(proxy): Extension<Arc<ProxyCheck>>,
+    Extension(cache): Extension<Arc<ResponseCache>>,
+    body: PostBody,
+) -> impl IntoResponse {
+    let (poster_token, cookie) = config.poster_token(&headers);
+    let create_thread_impl = async || -> Res<CreatedPost> {
+        let PostData { mut form, file } = parse_post_body::<CreateThread>(body).await?;
+        let (upload_id, media_ref) = (form.upload_id.take(), form.media_ref.take());
+        let has_file = file.is_some() || upload_id.is_some() || media_ref.is_some();
+        form.file_name = form.file_name.or_else(|| file.as_ref()?.name.clone());
+        form.validate()?;
+        let (alias, capcode) = config.take_capcode(&headers, form.alias.take())?;
+        let account = config.post_account(&pool, &headers).await?;
+
+        let sub_empty = form.sub.as_deref().is_none_or(text::is_blank);
+        let com_empty = form.com.as_deref().is_none_or(text::is_blank);
+        if sub_empty && com_empty {
+            return Err("both subject and comment can't be empty".into());
+        }
+
+        let board: Board = sqlx::query_as(r#"SELECT * FROM boards WHERE code = ?"#)
+            .bind(&form.board)
+            .fetch_optional(&*pool)
+            .await?
+            .ok_or("board not found")?;
+        board.check_lengths(form.sub.as_deref(), form.com.as_deref())?;
+        let ip_hash = config.hash_ip(ip);
+        bans::check(&pool, &ip_hash).await?;
+        if !config.is_mod(&headers) {
+            config
+                .flood()
+                .check(&pool, &ip_hash, true, has_file)
+                .await?;
+            rules::check(&pool, &board.code, &ip_hash, form.accepted_rules).await?;
+            let solution = (form.pow_challenge.as_deref(), form.pow_nonce.as_deref());
+            pow::verify(
+                &pool,
+                &config.ip_salt,
+                board.pow(),
+                &board.code,
+                &ip_hash,
+                solution,
+            )
+            .await?;
+        }
+        proxy
+            .enforce(&pool, board.proxy_policy, ip, &ip_hash, has_file)
+            .await?;
+        // the staged and stored files are copied once the poster passed the checks
+        let file = tus::attach(&pool, file, upload_id.as_deref(), &ip_hash).await?;
+        let file = media::attach_stored(&pool, file, media_ref.as_deref()).await?;
+        if form.file_name.is_none() {
+            form.file_name = file.as_ref().and_then(|f| f.name.clone());
+            form.validate()?;
+        }
+        check_file_size(file.as_ref(), &board)?;
+
+        let format = PostFormat::load(&pool, Some(&board)).await?;
+        let text = [form.sub.as_deref(), form.com.as_deref()]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+            .join("\n");
+        let submission = Submission {
+            text: &text,
+            file_name: form.file_name.as_deref(),
+        };
+        autoban::enforce(&pool, &config.events, &board.code, &ip_hash, submission).await?;
+        let quotes = form.com.as_deref().map(parse_quotes).unwrap_or_default();
+        let extras = form.com.as_deref().and_then(PostExtras::roll);
+        let (sub_raw, com_raw) = (form.sub.clone(), form.com.clone());
+        form.sub = form.sub.map(|sub| format.encode_subject(&sub));
+        form.com = form.com.map(|com| format.encode_comment(&com));
+        let is_held = config
+            .spam_rules()
+            .screen(&pool, &text, form.com.as_deref())
+            .await?
+            || (board.hold_new_posters && is_new_poster(&pool, &ip_hash).await?);
+
+        let media_data = file.ok_or("media is required")?;
+        media_data.check_blocklist(&pool).await?;
+        let media = save_media(
+            media_data,
+            config.thumbs_for(&board),
+            config.scanner_for(&board),
+            config.reencode_for(&board),
+            board.accepts(),
+        )
+        .await?;
+        let edit_token = Uuid::new_v4().simple().to_string();
+        let password_hash = form.password.as_deref().map(hash_password).transpose()?;
+        let mut tx = pool.begin().await?;
+        let board_post_no = next_post_no(&mut tx, &board.code).await?;
+        let mut comment: Comment = sqlx::query_as(r#"
+                INSERT INTO comments (file_name, media_name, media_hash, thumb_name, media_size, thumb_size, media_ext, media_type, thumb_type, media_w, media_h, media_pages, media_duration, media_bitrate, media_orig_size, thumb_w, thumb_h, catalog_thumb_name, catalog_thumb_w, catalog_thumb_h, thumb_failed, media_desc, alias, sub, com, sub_raw, com_raw, board, op, edit_token, password_hash, is_held, ip_hash, board_post_no, post_extras, capcode, poster_hash, account)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                RETURNING *
+            "#)
+            .bind(form.file_name)
+            .bind(media.media_name)
+            .bind(media.media_hash)
+            .bind(media.thumbs.thumb_name)
+            .bind(media.media_size)
+            .bind(media.thumbs.thumb_size)
+            .bind(media.media_ext)
+            .bind(media.media_type)
+            .bind(media.thumbs.thumb_type)
+            .bind(media.media_w)
+            .bind(media.media_h)
+            .bind(media.media_pages)
+            .bind(media.media_duration)
+            .bind(media.media_bitrate)
+            .bind(media.media_orig_size)
+            .bind(media.thumbs.thumb_w)
+            .bind(media.thumbs.thumb_h)
+            .bind(media.thumbs.catalog_thumb_name)
+            .bind(media.thumbs.catalog_thumb_w)
+            .bind(media.thumbs.catalog_thumb_h)
+            .bind(media.thumbs.thumb_failed)
+            .bind(form.media_desc)
+            .bind(board.poster_name(alias))
+            .bind(form.sub)
+            .bind(form.com)
+            .bind(sub_raw)
+            .bind(com_raw)
+            .bind(form.board)
+            .bind(None::<i64>)
+            .bind(&edit_token)
+            .bind(password_hash)
+            .bind(is_held)
+            .bind(&ip_hash)
+            .bind(board_post_no)
+            .bind(extras.map(sqlx::types::Json))
+            .bind(capcode)
+            .bind(privacy::hash_poster_token(&config.ip_salt, &poster_token))
+            .bind(&account)
+            .fetch_one(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        comment.yours = true;
+        if let Some(upload_id) = &upload_id
+            && let Err(e) = tus::remove(&pool, upload_id).await
+        {
+            tracing::warn!("failed to remove the staged upload {upload_id}: {e}");
+        }
+        save_quotes(&pool, &mut comment, &board.code, &quotes).await?;
+        if let Some(embeds) = &config.embeds {
+            embeds.save_links(&pool, comment.id, &text).await?;
+        }
+        cache.invalidate_board(&board.code).await;
+        if !is_held {
+            let event = EventKind::NewThread;
+            config.events.publish(event, Some(&board.code), &comment);
+        }
+        Ok(CreatedPost {
+            comment,
+            edit_token,
+        })
+    };
+    match create_thread_impl().await {
+        Ok(res) => (
+            StatusCode::CREATED,
+            [(header::SET_COOKIE, cookie)],
+            Json(Ok::<_, String>(res)),
+        )
+            .into_response(),
+        Err(e) => post_error(&*e, StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+async fn create_comment(
+    PeerIp(ip): PeerIp,
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(proxy): Extension<Arc<ProxyCheck>>,
+    Extension(cache): Extension<Arc<ResponseCache>>,
+    body: PostBody,
+) -> impl IntoResponse {
+    let (poster_token, cookie) = config.poster_token(&headers);
+    let create_comment_impl = async || -> Res<CreatedPost> {
+        let PostData { mut form, file } = parse_post_body::<CreateComment>(body).await?;
+        let (upload_id, media_ref) = (form.upload_id.take(), form.media_ref.take());
+        let has_file = file.is_some() || upload_id.is_some() || media_ref.is_some();
+        form.file_name = form.file_name.or_else(|| file.as_ref()?.name.clone());
+        form.validate()?;
+        let (alias, capcode) = config.take_capcode(&headers, form.alias.take())?;
+        let account = config.post_account(&pool, &headers).await?;
+        if form.com.is_none() && !has_file {
+            return Err("comment or image is required".into());
+        }
+        let is_locked =
+            check_parent(&mut *pool.acquire().await?, form.op, form.board.as_deref()).await?;
+        if is_locked {
+            return Err("thread is locked".into());
+        }
+        let board = fetch_post_board(&pool, form.op)
+            .await?
+            .ok_or("thread not found")?;
+        board.check_lengths(None, form.com.as_deref())?;
+        let ip_hash = config.hash_ip(ip);
+        bans::check(&pool, &ip_hash).await?;
+        if !config.is_mod(&headers) {
+            config
+                .flood()
+                .check(&pool, &ip_hash, false, has_file)
+                .await?;
+            rules::check(&pool, &board.code, &ip_hash, form.accepted_rules).await?;
+            let solution = (form.pow_challenge.as_deref(), form.pow_nonce.as_deref());
+            pow::verify(
+                &pool,
+                &config.ip_salt,
+                board.pow(),
+                &board.code,
+                &ip_hash,
+                solution,
+            )
+            .await?;
+        }
+        proxy
+            .enforce(&pool, board.proxy_policy, ip, &ip_hash, has_file)
+            .await?;
+        // the staged and stored files are copied once the poster passed the checks
+        let file = tus::attach(&pool, file, upload_id.as_deref(), &ip_hash).await?;
+        let file = media::attach_stored(&pool, file, media_ref.as_deref()).await?;
+        if form.file_name.is_none() {
+            form.file_name = file.as_ref().and_then(|f| f.name.clone());
+            form.validate()?;
+        }
+        check_file_size(file.as_ref(), &board)?;
+        if let Some(com) = form.com.as_ref().filter(|_| config.duplicate_window > 0)
+            && is_duplicate_post(&pool, form.op, &ip_hash, com, config.duplicate_window, None)
+                .await?
+        {
+            return Err("duplicate post".into());
+        }
+        let format = PostFormat::load(&pool, Some(&board)).await?;
+
+        let text = form.com.clone().unwrap_or_default();
+        let submission = Submission {
+            text: &text,
+            file_name: form.file_name.as_deref(),
+        };
+        autoban::enforce(&pool, &config.events, &board.code, &ip_hash, submission).await?;
+        let quotes = form.com.as_deref().map(parse_quotes).unwrap_or_default();
+        let extras = form.com.as_deref().and_then(PostExtras::roll);
+        let com_raw = form.com.clone();
+        form.com = form.com.map(|com| format.encode_comment(&com));
+        let is_held = config
+            .spam_rules()
+            .screen(&pool, &text, form.com.as_deref())
+            .await?
+            || (board.hold_new_posters && is_new_poster(&pool, &ip_hash).await?);
+
+        let edit_token = Uuid::new_v4().simple().to_string();
+        let password_hash = form.password.as_deref().map(hash_password).transpose()?;
+        if let Some(media_data) = &file {
+            media_data.check_blocklist(&pool).await?;
+        }
+        let media = match file {
+            Some(media_data) => Some(
+                save_media(
+                    media_data,
+                    config.thumbs_for(&board),
+                    config.scanner_for(&board),
+                    config.reencode_for(&board),
+                    board.accepts(),
+                )
+                .await?,
+            ),
+            None => None,
+        };
+        let mut tx = pool.begin().await?;
+        // the thread could have been moved or deleted while the media was saved
+        check_parent(&mut tx, form.op, Some(&board.code)).await?;
+        let board_post_no = next_post_no(&mut tx, &board.code).await?;
+        let mut comment: Comment = sqlx::query_as(r#"
+                INSERT INTO comments (file_name, media_name, media_hash, thumb_name, media_size, thumb_size, media_ext, media_type, thumb_type, media_w, media_h, media_pages, media_duration, media_bitrate, media_orig_size, thumb_w, thumb_h, catalog_thumb_name, catalog_thumb_w, catalog_thumb_h, thumb_failed, media_desc, alias, com, com_raw, board, op, edit_token, password_hash, is_held, ip_hash, board_post_no, post_extras, capcode, poster_hash, account)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                RETURNING *
+                "#
+            )
+            .bind(media.as_ref().and(form.file_name))
+            .bind(media.as_ref().map(|m| &m.media_name))
+            .bind(media.as_ref().map(|m| &m.media_hash))
+            .bind(media.as_ref().map(|m| &m.thumbs.thumb_name))
+            .bind(media.as_ref().map(|m| m.media_size))
+            .bind(media.as_ref().map(|m| m.thumbs.thumb_size))
+            .bind(media.as_ref().map(|m| &m.media_ext))
+            .bind(media.as_ref().map(|m| &m.media_type))
+            .bind(media.as_ref().map(|m| &m.thumbs.thumb_type))
+            .bind(media.as_ref().and_then(|m| m.media_w))
+            .bind(media.as_ref().and_then(|m| m.media_h))
+            .bind(media.as_ref().and_then(|m| m.media_pages))
+            .bind(media.as_ref().and_then(|m| m.media_duration))
+            .bind(media.as_ref().and_then(|m| m.media_bitrate))
+            .bind(media.as_ref().and_then(|m| m.media_orig_size))
+            .bind(media.as_ref().map(|m| m.thumbs.thumb_w))
+            .bind(media.as_ref().map(|m| m.thumbs.thumb_h))
+            .bind(media.as_ref().and_then(|m| m.thumbs.catalog_thumb_name.as_ref()))
+            .bind(media.as_ref().and_then(|m| m.thumbs.catalog_thumb_w))
+            .bind(media.as_ref().and_then(|m| m.thumbs.catalog_thumb_h))
+            .bind(media.as_ref().is_some_and(|m| m.thumbs.thumb_failed))
+            .bind(media.as_ref().and(form.media_desc))
+            .bind(board.poster_name(alias))
+            .bind(form.com)
+            .bind(com_raw)
+            .bind(&board.code)
+            .bind(form.op)
+            .bind(&edit_token)
+            .bind(password_hash)
+            .bind(is_held)
+            .bind(&ip_hash)
+            .bind(board_post_no)
+            .bind(extras.map(sqlx::types::Json))
+            .bind(capcode)
+            .bind(privacy::hash_poster_token(&config.ip_salt, &poster_token))
+            .bind(&account)
+            .fetch_one(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        comment.yours = true;
+        if let Some(upload_id) = &upload_id
+            && let Err(e) = tus::remove(&pool, upload_id).await
+        {
+            tracing::warn!("failed to remove the staged upload {upload_id}: {e}");
+        }
+        save_quotes(&pool, &mut comment, &board.code, &quotes).await?;
+        if let Some(embeds) = &config.embeds {
+            embeds.save_links(&pool, comment.id, &text).await?;
+        }
+        cache.invalidate_board(&board.code).await;
+        if !is_held {
+            let event = EventKind::NewReply;
+            config.events.publish(event, Some(&board.code), &comment);
+        }
+        Ok(CreatedPost {
+            comment,
+            edit_token,
+        })
+    };
+    match create_comment_impl().await {
+        Ok(comment) => (
+            StatusCode::OK,
+            [(header::SET_COOKIE, cookie)],
+            Json(Ok::<_, String>(comment)),
+        )
+            .into_response(),
+        Err(e) => post_error(&*e, StatusCode::BAD_REQUEST),
+    }
+}
+
+#[derive(Deserialize)]
+struct PowQuery {
+    board: String,
+}
+/// A proof of work challenge to post on the board, as hard as the recent posts of the poster
+/// make it
+async fn get_pow(
+    PeerIp(ip): PeerIp,
+    Query(query): Query<PowQuery>,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+) -> impl IntoResponse {
+    let get_pow_impl = async || -> Res<pow::Challenge> {
+        let board: Board = sqlx::query_as(r#"SELECT * FROM boards WHERE code = ?"#)
+            .bind(&query.board)
+            .fetch_optional(&*pool)
+            .await?
+            .ok_or_else(|| StatusError(StatusCode::NOT_FOUND, "board not found".to_string()))?;
+        let ip_hash = config.hash_ip(ip);
+        pow::issue(&pool, &config.ip_salt, board.pow(), &board.code, &ip_hash).await
+    };
+    match get_pow_impl().await {
+        Ok(challenge) => (StatusCode::OK, Json(Ok(challenge))),
+        Err(e) => (
+            error_status(&*e, StatusCode::INTERNAL_SERVER_ERROR),
+            Json(Err(e.to_string())),
+        ),
+    }
+}
+async fn get_tus_options(Extension(config): Extension<Arc<Config>>) -> impl IntoResponse {
+    (
+        StatusCode::NO_CONTENT,
+        [
+            ("tus-resumable", tus::VERSION.to_string()),
+            ("tus-version", tus::VERSION.to_string()),
+            ("tus-extension", tus::EXTENSIONS.to_string()),
+            ("tus-max-size", config.body_limits.upload.to_string()),
+        ],
+    )
+}
+async fn create_upload(
+    PeerIp(ip): PeerIp,
+    OriginalUri(uri): OriginalUri,
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+) -> Response {
+    let create_upload_impl = async || -> Res<StagedUpload> {
+        tus::check_version(&headers)?;
+        let length = tus::header_number(&headers, "upload-length")?;
+        let ip_hash = config.hash_ip(ip);
+        bans::check(&pool, &ip_hash).await?;
+        if !config.is_mod(&headers) {
+            config.flood().check_upload(&pool, &ip_hash).await?;
+        }
+        let file_name = tus::file_name(&headers);
+        let (max_size, expiry) = (config.body_limits.upload, config.upload_expiry);
+        let quota = &config.upload_quota;
+        tus::create(&pool, &ip_hash, length, file_name, max_size, expiry, quota).await
+    };
+    let upload = create_upload_impl().await;
+    let location = upload
+        .as_ref()
+        .ok()
+        .map(|u| format!("{}/{}", uri.path().trim_end_matches('/'), u.id));
+    let mut res = tus_response(upload.map(|u| (StatusCode::CREATED, Some(u))));
+    if let Some(Ok(location)) = location.map(HeaderValue::try_from) {
+        res.headers_mut().insert(header::LOCATION, location);
+    }
+    res
+}
+async fn get_upload(
+    Path(upload_id): Path<String>,
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+) -> Response {
+    let get_upload_impl = async || -> Res<StagedUpload> {
+        tus::check_version(&headers)?;
+        tus::status(&pool, &upload_id).await
+    };
+    tus_response(get_upload_impl().await.map(|u| (StatusCode::OK, Some(u))))
+}
+async fn patch_upload(
+    Path(upload_id): Path<String>,
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    body: Body,
+) -> Response {
+    let patch_upload_impl = async || -> Res<StagedUpload> {
+        tus::check_version(&headers)?;
+        let content_type = headers.get(header::CONTENT_TYPE);
+        if content_type.is_none_or(|t| t != "application/offset+octet-stream") {
+            let message = "expected an application/offset+octet-stream body".to_string();
+            return Err(StatusError(StatusCode::UNSUPPORTED_MEDIA_TYPE, message).into());
+        }
+        let offset = tus::header_number(&headers, "upload-offset")?;
+        tus::append(&pool, &upload_id, offset, body).await
+    };
+    tus_response(
+        patch_upload_impl()
+            .await
+            .map(|u| (StatusCode::NO_CONTENT, Some(u))),
+    )
+}
+async fn delete_upload(
+    Path(upload_id): Path<String>,
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+) -> Response {
+    let delete_upload_impl = async || -> Res<()> {
+        tus::check_version(&headers)?;
+        if !tus::remove(&pool, &upload_id).await? {
+            let message = "upload not found".to_string();
+            return Err(StatusError(StatusCode::NOT_FOUND, message).into());
+        }
+        Ok(())
+    };
+    tus_response(
+        delete_upload_impl()
+            .await
+            .map(|_| (StatusCode::NO_CONTENT, None)),
+    )
+}
+/// Answers a request of the tus protocol with the offset of the upload it's about, the errors
+/// are sent as JSON like the rest of the api, with the cooldowns like the ones of the posts
+fn tus_response(res: Res<(StatusCode, Option<StagedUpload>)>) -> Response {
+    let mut headers = HeaderMap::new();
+    headers.insert("tus-resumable", HeaderValue::from_static(tus::VERSION));
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    match res {
+        Ok((status, upload)) => {
+            if let Some(upload) = upload {
+                headers.insert("upload-offset", upload.offset.into());
+                headers.insert("upload-length", upload.length.into());
+            }
+            (status, headers).into_response()
+        }
+        Err(e) => {
+            let mut res = post_error(&*e, StatusCode::BAD_REQUEST);
+            res.headers_mut().extend(headers);
+            res
+        }
+    }
+}
+async fn edit_post(
+    Path(post_id): Path<i64>,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(cache): Extension<Arc<ResponseCache>>,
+    Json(mut form): Json<EditPost>,
+) -> impl IntoResponse {
+    let edit_post_impl = async || -> Res<Comment> {
+        form.validate()?;
+        if form.sub.is_none() && form.com.is_none() {
+            return Err("nothing to edit".into());
+        }
+        let post: Option<(Option<i64>, Option<String>)> = sqlx::query_as(
+            r#"
+            SELECT op, ip_hash FROM comments
+            WHERE id = ? AND edit_token = ? AND created_at >= strftime('%s', 'now') - ?
+            "#,
+        )
+        .bind(post_id)
+        .bind(&form.edit_token)
+        .bind(config.edit_window)
+        .fetch_optional(&*pool)
+        .await?;
+        let (op, ip_hash) = post.ok_or("post not found or edit window expired")?;
+        let board = fetch_post_board(&pool, post_id).await?;
+        if let Some(board) = &board {
+            board.check_lengths(form.sub.as_deref(), form.com.as_deref())?;
+        }
+        // the edited text is screened like a new post, the ip hashes may already be forgotten
+        let text = [form.sub.as_deref(), form.com.as_deref()]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+            .join("\n");
+        if let Some(ip_hash) = &ip_hash {
+            bans::check(&pool, ip_hash).await?;
+            if let (Some(op), Some(com)) = (op, &form.com)
+                && config.duplicate_window > 0
+                && is_duplicate_post(
+                    &pool,
+                    op,
+                    ip_hash,
+                    com,
+                    config.duplicate_window,
+                    Some(post_id),
+                )
+                .await?
+            {
+                return Err("duplicate post".into());
+            }
+            if let Some(board) = &board {
+                let submission = Submission {
+                    text: &text,
+                    file_name: None,
+                };
+                autoban::enforce(&pool, &config.events, &board.code, ip_hash, submission).await?;
+            }
+        }
+        let format = PostFormat::load(&pool, board.as_ref()).await?;
+
+        let quotes = form.com.as_deref().map(parse_quotes);
+        let (sub_raw, com_raw) = (form.sub.clone(), form.com.clone());
+        form.sub = form.sub.map(|sub| format.encode_subject(&sub));
+        form.com = form.com.map(|com| format.encode_comment(&com));
+        let is_held = config
+            .spam_rules()
+            .screen(&pool, &text, form.com.as_deref())
+            .await?;
+
+        let mut comment = sqlx::query_as(
+            r#"
+            UPDATE comments
+            SET sub = CASE WHEN op IS NULL THEN COALESCE(?1, sub) ELSE sub END,
+                com = COALESCE(?2, com),
+                sub_raw = CASE WHEN op IS NULL AND ?1 IS NOT NULL THEN ?3 ELSE sub_raw END,
+                com_raw = CASE WHEN ?2 IS NOT NULL THEN ?4 ELSE com_raw END,
+                edited_at = strftime('%s', 'now'),
+                is_held = is_held OR ?8
+            WHERE id = ?5 AND edit_token = ?6 AND created_at >= strftime('%s', 'now') - ?7
+            RETURNING *
+            "#,
+        )
+        .bind(form.sub)
+        .bind(form.com)
+        .bind(sub_raw)
+        .bind(&com_raw)
+        .bind(post_id)
+        .bind(form.edit_token)
+        .bind(config.edit_window)
+        .bind(is_held)
+        .fetch_optional(&*pool)
+        .await?
+        .ok_or("post not found or edit window expired")?;
+        if let (Some(quotes), Some(board)) = (quotes, &board) {
+            save_quotes(&pool, &mut comment, &board.code, &quotes).await?;
+        }
+        if let (Some(text), Some(embeds)) = (com_raw, &config.embeds) {
+            embeds.save_links(&pool, comment.id, &text).await?;
+        }
+        if let Some(board) = &board {
+            cache.invalidate_board(&board.code).await;
+        }
+        attach_backlinks(&pool, std::slice::from_mut(&mut comment)).await?;
+        embeds::attach(&pool, std::slice::from_mut(&mut comment)).await?;
+        Ok(comment)
+    };
+    match edit_post_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (
+            error_status(&*e, StatusCode::BAD_REQUEST),
+            Json(Err(e.to_string())),
+        ),
+    }
+}
+/// Deletes the post with its password, moderators can delete any post and purge its media
+async fn delete_post(
+    Path(post_id): Path<i64>,
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(cache): Extension<Arc<ResponseCache>>,
+    Json(form): Json<DeletePost>,
+) -> impl IntoResponse {
+    let delete_post_impl = async || -> Res<Comment> {
+        if config.is_mod(&headers) {
+            let deleted_by = config.staff_name(&headers);
+            let quarantine = config.quarantine.as_ref().filter(|_| !form.purge);
+            let quarantine = quarantine.map(|q| (q, deleted_by.as_str()));
+            return remove_post_cached(&pool, &cache, post_id, form.purge, quarantine).await;
+        }
+        if form.purge {
+            return Err(StatusError(
+                StatusCode::UNAUTHORIZED,
+                "only moderators can purge posts".to_string(),
+            )
+            .into());
+        }
+        let password_hash: Option<String> =
+            sqlx::query_scalar(r#"SELECT password_hash FROM comments WHERE id = ?"#)
+                .bind(post_id)
+                .fetch_optional(&*pool)
+                .await?
+                .flatten();
+        if !password_hash.is_some_and(|hash| verify_password(&form.password, &hash)) {
+            return Err("post not found or wrong password".into());
+        }
+        remove_post_cached(&pool, &cache, post_id, false, None).await
+    };
+    match delete_post_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (
+            error_status(&*e, StatusCode::BAD_REQUEST),
+            Json(Err(e.to_string())),
+        ),
+    }
+}
+async fn staff_login(Extension(config): Extension<Arc<Config>>) -> Response {
+    let Some(oidc) = &config.oidc else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    match oidc.login_url().await {
+        Ok(url) => (StatusCode::FOUND, [(header::LOCATION, url)]).into_response(),
+        Err(e) => (StatusCode::BAD_GATEWAY, Json(Err::<(), _>(e.to_string()))).into_response(),
+    }
+}
+async fn staff_callback(
+    Query(query): Query<OidcCallback>,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+) -> impl IntoResponse {
+    let staff_callback_impl = async || -> Res<StaffLogin> {
+        let oidc = config.oidc.as_ref().ok_or("staff login is disabled")?;
+        let mut login = oidc.callback(&query.code, &query.state).await?;
+        login.totp_enabled = totp::is_enabled(&pool, &login.staff.subject).await?;
+        Ok(login)
+    };
+    match staff_callback_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (
+            error_status(&*e, StatusCode::BAD_GATEWAY),
+            Json(Err(e.to_string())),
+        ),
+    }
+}
+async fn staff_logout(
+    headers: HeaderMap,
+    Extension(config): Extension<Arc<Config>>,
+) -> impl IntoResponse {
+    let session = bearer_token(&headers).unwrap_or_default();
+    match config.oidc.as_ref().is_some_and(|o| o.logout(session)) {
+        true => (StatusCode::OK, Json(Ok(()))),
+        false => (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("not logged in".to_string())),
+        ),
+    }
+}
+/// The staff of the OIDC session the request is made with, verified or not
+fn staff_session(config: &Config, headers: &HeaderMap) -> Res<(String, StaffSession)> {
+    let session = bearer_token(headers).unwrap_or_default();
+    let staff = config.oidc.as_ref().and_then(|o| o.session(session));
+    let staff =
+        staff.ok_or_else(|| StatusError(StatusCode::UNAUTHORIZED, "not logged in".into()))?;
+    Ok((session.to_string(), staff))
+}
+async fn enroll_totp(
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+) -> impl IntoResponse {
+    let enroll_totp_impl = async || -> Res<Enrollment> {
+        let (_, staff) = staff_session(&config, &headers)?;
+        totp::enroll(&pool, &staff.subject, &staff.name).await
+    };
+    match enroll_totp_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (
+            error_status(&*e, StatusCode::INTERNAL_SERVER_ERROR),
+            Json(Err(e.to_string())),
+        ),
+    }
+}
+async fn verify_totp(
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+    Json(form): Json<TotpCode>,
+) -> impl IntoResponse {
+    let verify_totp_impl = async || -> Res<StaffSession> {
+        let (session, staff) = staff_session(&config, &headers)?;
+        let oidc = config.oidc.as_ref().ok_or("staff login is disabled")?;
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?;
+        let verified = totp::verify(&pool, &staff.subject, &form.code, now.as_secs()).await;
+        // a locked out staff has to log in again once the lockout is over
+        if let Err(e) = &verified
+            && error_status(&**e, StatusCode::UNAUTHORIZED) == StatusCode::TOO_MANY_REQUESTS
+        {
+            oidc.revoke(&privacy::hash_token(&session));
+        }
+        verified?;
+        oidc.verify(&session).ok_or_else(|| "not logged in".into())
+    };
+    match verify_totp_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (
+            error_status(&*e, StatusCode::INTERNAL_SERVER_ERROR),
+            Json(Err(e.to_string())),
+        ),
+    }
+}
+async fn reset_totp(
+    Path(subject): Path<String>,
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+) -> impl IntoResponse {
+    if !config.is_admin(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let reset_totp_impl = async || -> Res<()> {
+        if !totp::reset(&pool, &subject).await? {
+            let message = "two factor authentication isn't enrolled".to_string();
+            return Err(StatusError(StatusCode::NOT_FOUND, message).into());
+        }
+        let actor = config.staff_name(&headers);
+        audit::record(&pool, &actor, "reset_totp", &subject).await
+    };
+    match reset_totp_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (
+            error_status(&*e, StatusCode::INTERNAL_SERVER_ERROR),
+            Json(Err(e.to_string())),
+        ),
+    }
+}
+/// The account of the session the request is made with
+async fn session_account(pool: &SqlitePool, headers: &HeaderMap) -> Res<i64> {
+    let session = bearer_token(headers);
+    let account_id = match session {
+        Some(session) => accounts::authenticate(pool, session).await?,
+        None => None,
+    };
+    account_id.ok_or_else(|| StatusError(StatusCode::UNAUTHORIZED, "not logged in".into()).into())
+}
+async fn register_account(
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Json(form): Json<Credentials>,
+) -> impl IntoResponse {
+    let register_account_impl = async || -> Res<Session> {
+        form.validate()?;
+        accounts::register(&pool, &form.username, &form.password).await
+    };
+    match register_account_impl().await {
+        Ok(res) => (StatusCode::CREATED, Json(Ok(res))),
+        Err(e) => (
+            error_status(&*e, StatusCode::BAD_REQUEST),
+            Json(Err(e.to_string())),
+        ),
+    }
+}
+async fn login_account(
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Json(form): Json<Credentials>,
+) -> impl IntoResponse {
+    match accounts::login(&pool, &form.username, &form.password).await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (
+            error_status(&*e, StatusCode::INTERNAL_SERVER_ERROR),
+            Json(Err(e.to_string())),
+        ),
+    }
+}
+async fn logout_account(
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+) -> impl IntoResponse {
+    let logout_account_impl = async || -> Res<()> {
+        session_account(&pool, &headers).await?;
+        accounts::logout(&pool, bearer_token(&headers).unwrap_or_default()).await
+    };
+    match logout_account_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (
+            error_status(&*e, StatusCode::INTERNAL_SERVER_ERROR),
+            Json(Err(e.to_string())),
+        ),
+    }
+}
+async fn get_account(
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+) -> impl IntoResponse {
+    let get_account_impl = async || -> Res<Account> {
+        let account_id = session_account(&pool, &headers).await?;
+        accounts::fetch(&pool, account_id).await
+    };
+    match get_account_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (
+            error_status(&*e, StatusCode::INTERNAL_SERVER_ERROR),
+            Json(Err(e.to_string())),
+        ),
+    }
+}
+async fn put_account_settings(
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Json(settings): Json<serde_json::Value>,
+) -> impl IntoResponse {
+    let put_account_settings_impl = async || -> Res<Account> {
+        let account_id = session_account(&pool, &headers).await?;
+        accounts::save_settings(&pool, account_id, &settings).await?;
+        accounts::fetch(&pool, account_id).await
+    };
+    match put_account_settings_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (
+            error_status(&*e, StatusCode::BAD_REQUEST),
+            Json(Err(e.to_string())),
+        ),
+    }
+}
+async fn hide_thread(
+    Path(thread_id): Path<i64>,
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+) -> impl IntoResponse {
+    let hide_thread_impl = async || -> Res<Account> {
+        let account_id = session_account(&pool, &headers).await?;
+        accounts::hide_thread(&pool, account_id, thread_id).await?;
+        accounts::fetch(&pool, account_id).await
+    };
+    match hide_thread_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (
+            error_status(&*e, StatusCode::INTERNAL_SERVER_ERROR),
+            Json(Err(e.to_string())),
+        ),
+    }
+}
+async fn unhide_thread(
+    Path(thread_id): Path<i64>,
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+) -> impl IntoResponse {
+    let unhide_thread_impl = async || -> Res<Account> {
+        let account_id = session_account(&pool, &headers).await?;
+        accounts::unhide_thread(&pool, account_id, thread_id).await?;
+        accounts::fetch(&pool, account_id).await
+    };
+    match unhide_thread_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (
+            error_status(&*e, StatusCode::INTERNAL_SERVER_ERROR),
+            Json(Err(e.to_string())),
+        ),
+    }
+}
+async fn get_account_sessions(
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+) -> impl IntoResponse {
+    let get_account_sessions_impl = async || -> Res<Vec<AccountSession>> {
+        let account_id = session_account(&pool, &headers).await?;
+        accounts::sessions(&pool, account_id).await
+    };
+    match get_account_sessions_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (
+            error_status(&*e, StatusCode::INTERNAL_SERVER_ERROR),
+            Json(Err(e.to_string())),
+        ),
+    }
+}
+async fn revoke_account_session(
+    Path(session_id): Path<String>,
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+) -> impl IntoResponse {
+    let revoke_account_session_impl = async || -> Res<()> {
+        let account_id = session_account(&pool, &headers).await?;
+        let current = bearer_token(&headers).unwrap_or_default();
+        if accounts::revoke_sessions(&pool, account_id, Some(&session_id), current).await? == 0 {
+            return Err(StatusError(StatusCode::NOT_FOUND, "session not found".into()).into());
+        }
+        Ok(())
+    };
+    match revoke_account_session_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (
+            error_status(&*e, StatusCode::INTERNAL_SERVER_ERROR),
+            Json(Err(e.to_string())),
+        ),
+    }
+}
+/// Logs out the other sessions of the account
+async fn logout_all_accounts(
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+) -> impl IntoResponse {
+    let logout_all_accounts_impl = async || -> Res<u64> {
+        let account_id = session_account(&pool, &headers).await?;
+        let current = bearer_token(&headers).unwrap_or_default();
+        accounts::revoke_sessions(&pool, account_id, None, current).await
+    };
+    match logout_all_accounts_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (
+            error_status(&*e, StatusCode::INTERNAL_SERVER_ERROR),
+            Json(Err(e.to_string())),
+        ),
+    }
+}
+async fn get_staff_sessions(
+    headers: HeaderMap,
+    Extension(config): Extension<Arc<Config>>,
+) -> impl IntoResponse {
+    if !config.is_admin(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let sessions = config
+        .oidc
+        .as_ref()
+        .map(|o| o.sessions())
+        .unwrap_or_default();
+    let sessions = sessions
+        .into_iter()
+        .map(|(id, staff)| StaffSessionInfo { id, staff })
+        .collect::<Vec<_>>();
+    (StatusCode::OK, Json(Ok(sessions)))
+}
+async fn revoke_staff_session(
+    Path(session_id): Path<String>,
+    headers: HeaderMap,
+    Extension(config): Extension<Arc<Config>>,
+) -> impl IntoResponse {
+    if !config.is_admin(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    match config.oidc.as_ref().is_some_and(|o| o.revoke(&session_id)) {
+        true => (StatusCode::OK, Json(Ok(()))),
+        false => (
+            StatusCode::NOT_FOUND,
+            Json(Err("session not found".to_string())),
+        ),
+    }
+}
+async fn logout_all_staff(
+    headers: HeaderMap,
+    Extension(config): Extension<Arc<Config>>,
+    Json(form): Json<LogoutAll>,
+) -> impl IntoResponse {
+    if !config.is_admin(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let oidc = config.oidc.as_ref();
+    let revoked = oidc.map_or(0, |o| o.revoke_all(form.subject.as_deref()));
+    (StatusCode::OK, Json(Ok(revoked)))
+}
+/// Denies the token from now on, for the tokens set in the environment, and logs out the
+/// sessions with it
+async fn revoke_token(
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+    Json(form): Json<RevokeToken>,
+) -> impl IntoResponse {
+    if !config.is_admin(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let revoke_token_impl = async || -> Res<()> {
+        let hash = privacy::hash_token(&form.token);
+        sqlx::query(r#"INSERT OR IGNORE INTO revoked_tokens (token_hash) VALUES (?)"#)
+            .bind(&hash)
+            .execute(&*pool)
+            .await?;
+        sqlx::query(r#"DELETE FROM account_sessions WHERE token_hash = ?"#)
+            .bind(&hash)
+            .execute(&*pool)
+            .await?;
+        if let Some(oidc) = &config.oidc {
+            oidc.revoke(&hash);
+        }
+        config.revoked.write().unwrap().insert(hash.clone());
+        let actor = config.staff_name(&headers);
+        audit::record(&pool, &actor, "revoke_token", &hash).await
+    };
+    match revoke_token_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (
+            error_status(&*e, StatusCode::INTERNAL_SERVER_ERROR),
+            Json(Err(e.to_string())),
+        ),
+    }
+}
+async fn get_flood(
+    headers: HeaderMap,
+    Extension(config): Extension<Arc<Config>>,
+) -> impl IntoResponse {
+    if !config.is_admin(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    (StatusCode::OK, Json(Ok(config.flood())))
+}
+async fn put_flood(
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+    Json(form): Json<FloodControl>,
+) -> impl IntoResponse {
+    if !config.is_admin(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let put_flood_impl = async || -> Res<FloodControl> {
+        if form.thread < 0 || form.reply < 0 || form.image < 0 {
+            return Err("cooldowns can't be negative".into());
+        }
+        form.save(&pool).await?;
+        *config.flood.write().unwrap() = form;
+        let actor = config.staff_name(&headers);
+        audit::record(&pool, &actor, "set_flood", &serde_json::to_string(&form)?).await?;
+        Ok(form)
+    };
+    match put_flood_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (
+            error_status(&*e, StatusCode::BAD_REQUEST),
+            Json(Err(e.to_string())),
+        ),
+    }
+}
+async fn reload_config(
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+) -> impl IntoResponse {
+    if !config.is_admin(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    match config.reload(&pool).await {
+        Ok(()) => (StatusCode::OK, Json(Ok(()))),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(Err(e.to_string()))),
+    }
+}
+async fn get_watched(
+    Path(token): Path<String>,
+    Extension(ReadPool(pool)): Extension<ReadPool>,
+) -> impl IntoResponse {
+    let get_watched_impl = async || -> Res<Vec<WatchedThread>> {
+        check_watch_token(&token)?;
+        sqlx::query_as(
+            r#"
+            SELECT w.*, t.board, t.board_post_no, t.sub,
+            (SELECT COUNT(*) FROM comments r WHERE r.op = t.id AND r.id > w.last_seen AND NOT r.is_held) AS new_replies,
+            (SELECT MAX(r.id) FROM comments r WHERE (r.id = t.id OR r.op = t.id) AND NOT r.is_held) AS last_post_id
+            FROM thread_watchers w
+            JOIN comments t ON t.id = w.thread_id
+            WHERE w.token = ?
+            ORDER BY w.created_at, w.thread_id
+            "#,
+        )
+        .bind(token)
+        .fetch_all(&*pool)
+        .await
+        .map_err(|e| e.into())
+    };
+    match get_watched_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (StatusCode::BAD_REQUEST, Json(Err(e.to_string()))),
+    }
+}
+async fn watch_thread(
+    Path((token, thread_id)): Path<(String, i64)>,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Json(form): Json<WatchThread>,
+) -> impl IntoResponse {
+    let watch_thread_impl = async || -> Res<ThreadWatch> {
+        check_watch_token(&token)?;
+        sqlx::query_as(
+            r#"
+            INSERT INTO thread_watchers (token, thread_id, last_seen)
+            SELECT ?, t.id, COALESCE(?, (SELECT MAX(r.id) FROM comments r WHERE r.id = t.id OR r.op = t.id))
+            FROM comments t
+            WHERE t.id = ? AND t.op IS NULL AND NOT t.is_held
+            ON CONFLICT (token, thread_id) DO UPDATE SET last_seen = excluded.last_seen
+            RETURNING *
+            "#,
+        )
+        .bind(token)
+        .bind(form.last_seen)
+        .bind(thread_id)
+        .fetch_optional(&*pool)
+        .await?
+        .ok_or_else(|| "thread not found".into())
+    };
+    match watch_thread_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (StatusCode::BAD_REQUEST, Json(Err(e.to_string()))),
+    }
+}
+async fn unwatch_thread(
+    Path((token, thread_id)): Path<(String, i64)>,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+) -> impl IntoResponse {
+    let unwatch_thread_impl = async || -> Res<ThreadWatch> {
+        check_watch_token(&token)?;
+        sqlx::query_as(
+            r#"DELETE FROM thread_watchers WHERE token = ? AND thread_id = ? RETURNING *"#,
+        )
+        .bind(token)
+        .bind(thread_id)
+        .fetch_optional(&*pool)
+        .await?
+        .ok_or_else(|| "thread not watched".into())
+    };
+    match unwatch_thread_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (StatusCode::BAD_REQUEST, Json(Err(e.to_string()))),
+    }
+}
+async fn set_sticky(
+    Path(thread_id): Path<i64>,
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(cache): Extension<Arc<ResponseCache>>,
+    Json(form): Json<SetFlag>,
+) -> impl IntoResponse {
+    if !config.is_mod(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    match set_thread_flag(&pool, &cache, "is_sticky", thread_id, form.value).await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (StatusCode::BAD_REQUEST, Json(Err(e.to_string()))),
+    }
+}
+async fn set_locked(
+    Path(thread_id): Path<i64>,
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(cache): Extension<Arc<ResponseCache>>,
+    Json(form): Json<SetFlag>,
+) -> impl IntoResponse {
+    if !config.is_mod(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    match set_thread_flag(&pool, &cache, "is_locked", thread_id, form.value).await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (StatusCode::BAD_REQUEST, Json(Err(e.to_string()))),
+    }
+}
+/// Moves the replies of the source thread into the target, the source op becoming a reply,
+/// and leaves a redirect from the source to the target. Both threads have to be on the same
+/// board, so the post numbers and the quotes between them stay valid
+async fn merge_threads(
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(cache): Extension<Arc<ResponseCache>>,
+    Json(form): Json<MergeThreads>,
+) -> impl IntoResponse {
+    if !config.is_mod(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let merge_threads_impl = async || -> Res<Comment> {
+        if form.source_thread == form.target_thread {
+            return Err("can't merge a thread into itself".into());
+        }
+        let mut tx = pool.begin().await?;
+        let threads: Vec<(i64, Option<String>)> =
+            sqlx::query_as(r#"SELECT id, board FROM comments WHERE id IN (?, ?) AND op IS NULL"#)
+                .bind(form.source_thread)
+                .bind(form.target_thread)
+                .fetch_all(&mut *tx)
+                .await?;
+        let board_of = |id| threads.iter().find(|t| t.0 == id).map(|t| t.1.clone());
+        let (Some(source_board), Some(target_board)) =
+            (board_of(form.source_thread), board_of(form.target_thread))
+        else {
+            return Err(StatusError(StatusCode::NOT_FOUND, "thread not found".to_string()).into());
+        };
+        let board = source_board
+            .filter(|b| Some(b) == target_board.as_ref())
+            .ok_or("threads are on different boards, move the source thread first")?;
+
+        let posts: Vec<i64> =
+            sqlx::query_scalar(r#"SELECT id FROM comments WHERE id = ? OR op = ?"#)
+                .bind(form.source_thread)
+                .bind(form.source_thread)
+                .fetch_all(&mut *tx)
+                .await?;
+        let quotes = relink::snapshot(&mut tx, &posts).await?;
+        sqlx::query(r#"UPDATE comments SET op = ? WHERE op = ?"#)
+            .bind(form.target_thread)
+            .bind(form.source_thread)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            r#"UPDATE comments SET op = ?, is_sticky = FALSE, is_locked = FALSE WHERE id = ?"#,
+        )
+        .bind(form.target_thread)
+        .bind(form.source_thread)
+        .execute(&mut *tx)
+        .await?;
+        relink::rewrite(&mut tx, quotes).await?;
+        sqlx::query(r#"UPDATE OR IGNORE thread_watchers SET thread_id = ? WHERE thread_id = ?"#)
+            .bind(form.target_thread)
+            .bind(form.source_thread)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(r#"DELETE FROM thread_watchers WHERE thread_id = ?"#)
+            .bind(form.source_thread)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(r#"UPDATE thread_redirects SET target_id = ? WHERE target_id = ?"#)
+            .bind(form.target_thread)
+            .bind(form.source_thread)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            r#"INSERT OR REPLACE INTO thread_redirects (board, thread_id, target_id) VALUES (?, ?, ?)"#,
+        )
+        .bind(&board)
+        .bind(form.source_thread)
+        .bind(form.target_thread)
+        .execute(&mut *tx)
+        .await?;
+        let thread = sqlx::query_as(r#"SELECT * FROM comments WHERE id = ?"#)
+            .bind(form.target_thread)
+            .fetch_one(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        cache.invalidate_board(&board).await;
+        Ok(thread)
+    };
+    match merge_threads_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (
+            error_status(&*e, StatusCode::BAD_REQUEST),
+            Json(Err(e.to_string())),
+        ),
+    }
+}
+/// Moves the thread to another board, its posts get new numbers on that board and the origin
+/// board keeps a redirect to it
+async fn move_thread(
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(cache): Extension<Arc<ResponseCache>>,
+    Json(form): Json<MoveThread>,
+) -> impl IntoResponse {
+    if !config.is_mod(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let move_thread_impl = async || -> Res<Comment> {
+        let mut tx = pool.begin().await?;
+        let board: Option<String> =
+            sqlx::query_scalar(r#"SELECT board FROM comments WHERE id = ? AND op IS NULL"#)
+                .bind(form.thread_id)
+                .fetch_optional(&mut *tx)
+                .await?
+                .flatten();
+        let board = board
+            .ok_or_else(|| StatusError(StatusCode::NOT_FOUND, "thread not found".to_string()))?;
+        if board == form.dest_board {
+            return Err("thread is already on that board".into());
+        }
+        let dest_exists: bool =
+            sqlx::query_scalar(r#"SELECT EXISTS(SELECT 1 FROM boards WHERE code = ?)"#)
+                .bind(&form.dest_board)
+                .fetch_one(&mut *tx)
+                .await?;
+        if !dest_exists {
+            return Err(StatusError(StatusCode::NOT_FOUND, "board not found".to_string()).into());
+        }
+
+        let posts: Vec<i64> =
+            sqlx::query_scalar(r#"SELECT id FROM comments WHERE id = ? OR op = ? ORDER BY id"#)
+                .bind(form.thread_id)
+                .bind(form.thread_id)
+                .fetch_all(&mut *tx)
+                .await?;
+        let quotes = relink::snapshot(&mut tx, &posts).await?;
+        for id in &posts {
+            let post_no = next_post_no(&mut tx, &form.dest_board).await?;
+            sqlx::query(r#"UPDATE comments SET board_post_no = ?, board = ? WHERE id = ?"#)
+                .bind(post_no)
+                .bind(&form.dest_board)
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        relink::rewrite(&mut tx, quotes).await?;
+        sqlx::query(r#"DELETE FROM thread_redirects WHERE board = ? AND thread_id = ?"#)
+            .bind(&form.dest_board)
+            .bind(form.thread_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            r#"INSERT OR REPLACE INTO thread_redirects (board, thread_id, target_id) VALUES (?, ?, ?)"#,
+        )
+        .bind(&board)
+        .bind(form.thread_id)
+        .bind(form.thread_id)
+        .execute(&mut *tx)
+        .await?;
+        let thread = sqlx::query_as(r#"SELECT * FROM comments WHERE id = ?"#)
+            .bind(form.thread_id)
+            .fetch_one(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        cache.invalidate_board(&board).await;
+        cache.invalidate_board(&form.dest_board).await;
+        Ok(thread)
+    };
+    match move_thread_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (
+            error_status(&*e, StatusCode::BAD_REQUEST),
+            Json(Err(e.to_string())),
+        ),
+    }
+}
+async fn get_wordfilters(
+    headers: HeaderMap,
+    Extension(ReadPool(pool)): Extension<ReadPool>,
+    Extension(config): Extension<Arc<Config>>,
+) -> impl IntoResponse {
+    if !config.is_mod(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let get_wordfilters_impl = async || -> Res<Vec<Wordfilter>> {
+        sqlx::query_as(r#"SELECT * FROM wordfilters ORDER BY board, id"#)
+            .fetch_all(&*pool)
+            .await
+            .map_err(|e| e.into())
+    };
+    match get_wordfilters_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(Err(e.to_string()))),
+    }
+}
+async fn create_wordfilter(
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+    Json(form): Json<CreateWordfilter>,
+) -> impl IntoResponse {
+    if !config.is_mod(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let create_wordfilter_impl = async || -> Res<Wordfilter> {
+        form.validate()?;
+        if form.is_regex {
+            Regex::new(&form.pattern)?;
+        }
+        sqlx::query_as(
+            r#"
+            INSERT INTO wordfilters (board, pattern, replacement, is_regex)
+            VALUES (?, ?, ?, ?)
+            RETURNING *
+            "#,
+        )
+        .bind(form.board)
+        .bind(form.pattern)
+        .bind(form.replacement)
+        .bind(form.is_regex)
+        .fetch_one(&*pool)
+        .await
+        .map_err(|e| e.into())
+    };
+    match create_wordfilter_impl().await {
+        Ok(res) => (StatusCode::CREATED, Json(Ok(res))),
+        Err(e) => (StatusCode::BAD_REQUEST, Json(Err(e.to_string()))),
+    }
+}
+async fn update_wordfilter(
+    Path(wordfilter_id): Path<i64>,
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+    Json(form): Json<UpdateWordfilter>,
+) -> impl IntoResponse {
+    if !config.is_mod(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let update_wordfilter_impl = async || -> Res<Wordfilter> {
+        form.validate()?;
+        let mut tx = pool.begin().await?;
+        let wordfilter: Wordfilter = sqlx::query_as(
+            r#"
+            UPDATE wordfilters
+            SET pattern = COALESCE(?, pattern),
+                replacement = COALESCE(?, replacement),
+                is_regex = COALESCE(?, is_regex)
+            WHERE id = ?
+            RETURNING *
+            "#,
+        )
+        .bind(form.pattern)
+        .bind(form.replacement)
+        .bind(form.is_regex)
+        .bind(wordfilter_id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or("wordfilter not found")?;
+        if wordfilter.is_regex {
+            Regex::new(&wordfilter.pattern)?;
+        }
+        tx.commit().await?;
+        Ok(wordfilter)
+    };
+    match update_wordfilter_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (StatusCode::BAD_REQUEST, Json(Err(e.to_string()))),
+    }
+}
+async fn delete_wordfilter(
+    Path(wordfilter_id): Path<i64>,
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+) -> impl IntoResponse {
+    if !config.is_mod(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let delete_wordfilter_impl = async || -> Res<Wordfilter> {
+        sqlx::query_as(r#"DELETE FROM wordfilters WHERE id = ? RETURNING *"#)
+            .bind(wordfilter_id)
+            .fetch_optional(&*pool)
+            .await?
+            .ok_or_else(|| "wordfilter not found".into())
+    };
+    match delete_wordfilter_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (StatusCode::BAD_REQUEST, Json(Err(e.to_string()))),
+    }
+}
+async fn get_autoban_rules(
+    headers: HeaderMap,
+    Extension(ReadPool(pool)): Extension<ReadPool>,
+    Extension(config): Extension<Arc<Config>>,
+) -> impl IntoResponse {
+    if !config.is_mod(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let get_autoban_rules_impl = async || -> Res<Vec<AutobanRule>> {
+        sqlx::query_as(r#"SELECT * FROM autoban_rules ORDER BY id"#)
+            .fetch_all(&*pool)
+            .await
+            .map_err(|e| e.into())
+    };
+    match get_autoban_rules_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(Err(e.to_string()))),
+    }
+}
+/// Rules are created disabled unless asked otherwise, so they can be tried with a dry run first
+async fn create_autoban_rule(
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+    Json(form): Json<CreateAutobanRule>,
+) -> impl IntoResponse {
+    if !config.is_mod(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let create_autoban_rule_impl = async || -> Res<AutobanRule> {
+        form.validate()?;
+        Regex::new(&form.pattern)?;
+        sqlx::query_as(
+            r#"
+            INSERT INTO autoban_rules (board, pattern, target, action, reason, ban_duration, is_enabled)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            RETURNING *
+            "#,
+        )
+        .bind(form.board)
+        .bind(form.pattern)
+        .bind(form.target)
+        .bind(form.action)
+        .bind(form.reason)
+        .bind(form.ban_duration)
+        .bind(form.is_enabled)
+        .fetch_one(&*pool)
+        .await
+        .map_err(|e| e.into())
+    };
+    match create_autoban_rule_impl().await {
+        Ok(res) => (StatusCode::CREATED, Json(Ok(res))),
+        Err(e) => (StatusCode::BAD_REQUEST, Json(Err(e.to_string()))),
+    }
+}
+async fn update_autoban_rule(
+    Path(rule_id): Path<i64>,
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+    Json(form): Json<UpdateAutobanRule>,
+) -> impl IntoResponse {
+    if !config.is_mod(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let update_autoban_rule_impl = async || -> Res<AutobanRule> {
+        form.validate()?;
+        if let Some(pattern) = &form.pattern {
+            Regex::new(pattern)?;
+        }
+        sqlx::query_as(
+            r#"
+            UPDATE autoban_rules
+            SET pattern = COALESCE(?, pattern),
+                target = COALESCE(?, target),
+                action = COALESCE(?, action),
+                reason = COALESCE(?, reason),
+                ban_duration = COALESCE(?, ban_duration),
+                is_enabled = COALESCE(?, is_enabled)
+            WHERE id = ?
+            RETURNING *
+            "#,
+        )
+        .bind(form.pattern)
+        .bind(form.target)
+        .bind(form.action)
+        .bind(form.reason)
+        .bind(form.ban_duration)
+        .bind(form.is_enabled)
+        .bind(rule_id)
+        .fetch_optional(&*pool)
+        .await?
+        .ok_or_else(|| "rule not found".into())
+    };
+    match update_autoban_rule_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (StatusCode::BAD_REQUEST, Json(Err(e.to_string()))),
+    }
+}
+async fn delete_autoban_rule(
+    Path(rule_id): Path<i64>,
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+) -> impl IntoResponse {
+    if !config.is_mod(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let delete_autoban_rule_impl = async || -> Res<AutobanRule> {
+        sqlx::query_as(r#"DELETE FROM autoban_rules WHERE id = ? RETURNING *"#)
+            .bind(rule_id)
+            .fetch_optional(&*pool)
+            .await?
+            .ok_or_else(|| "rule not found".into())
+    };
+    match delete_autoban_rule_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (StatusCode::BAD_REQUEST, Json(Err(e.to_string()))),
+    }
+}
+/// The posts of the last `window` seconds (default one week) the rule would have matched
+async fn dry_run_autoban_rule(
+    Path(rule_id): Path<i64>,
+    Query(query): Query<DryRunQuery>,
+    headers: HeaderMap,
+    Extension(ReadPool(pool)): Extension<ReadPool>,
+    Extension(config): Extension<Arc<Config>>,
+) -> impl IntoResponse {
+    if !config.is_mod(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let dry_run_autoban_rule_impl = async || -> Res<DryRun> {
+        let rule: AutobanRule = sqlx::query_as(r#"SELECT * FROM autoban_rules WHERE id = ?"#)
+            .bind(rule_id)
+            .fetch_optional(&*pool)
+            .await?
+            .ok_or("rule not found")?;
+        autoban::dry_run(&pool, &rule, query.window.unwrap_or(7 * 24 * 60 * 60)).await
+    };
+    match dry_run_autoban_rule_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (StatusCode::BAD_REQUEST, Json(Err(e.to_string()))),
+    }
+}
+async fn get_held(
+    headers: HeaderMap,
+    Extension(ReadPool(pool)): Extension<ReadPool>,
+    Extension(config): Extension<Arc<Config>>,
+) -> impl IntoResponse {
+    if !config.is_mod(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let get_held_impl = async || -> Res<Vec<Comment>> {
+        sqlx::query_as(r#"SELECT * FROM comments WHERE is_held ORDER BY id"#)
+            .fetch_all(&*pool)
+            .await
+            .map_err(|e| e.into())
+    };
+    match get_held_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(Err(e.to_string()))),
+    }
+}
+async fn approve_post(
+    Path(post_id): Path<i64>,
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(cache): Extension<Arc<ResponseCache>>,
+) -> impl IntoResponse {
+    if !config.is_mod(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let approve_post_impl = async || -> Res<Comment> {
+        let comment: Comment = sqlx::query_as(
+            r#"UPDATE comments SET is_held = FALSE WHERE id = ? AND is_held RETURNING *"#,
+        )
+        .bind(post_id)
+        .fetch_optional(&*pool)
+        .await?
+        .ok_or("held post not found")?;
+        let board = fetch_post_board(&pool, post_id).await?;
+        if let Some(board) = board {
+            cache.invalidate_board(&board.code).await;
+            let event = match comment.op {
+                Some(_) => EventKind::NewReply,
+                None => EventKind::NewThread,
+            };
+            config.events.publish(event, Some(&board.code), &comment);
+        }
+        Ok(comment)
+    };
+    match approve_post_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (StatusCode::BAD_REQUEST, Json(Err(e.to_string()))),
+    }
+}
+async fn reject_post(
+    Path(post_id): Path<i64>,
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(cache): Extension<Arc<ResponseCache>>,
+) -> impl IntoResponse {
+    if !config.is_mod(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let reject_post_impl = async || -> Res<Comment> {
+        let is_held: Option<bool> =
+            sqlx::query_scalar(r#"SELECT is_held FROM comments WHERE id = ?"#)
+                .bind(post_id)
+                .fetch_optional(&*pool)
+                .await?;
+        if is_held != Some(true) {
+            return Err("held post not found".into());
+        }
+        remove_post_cached(&pool, &cache, post_id, false, None).await
+    };
+    match reject_post_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (StatusCode::BAD_REQUEST, Json(Err(e.to_string()))),
+    }
+}
+/// Bans the ip of the post and deletes what it posted in the last `delete_window` seconds,
+/// BAN_DELETE_WINDOW by default
+async fn ban_ip(
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(cache): Extension<Arc<ResponseCache>>,
+    Json(form): Json<CreateBan>,
+) -> impl IntoResponse {
+    if !config.is_mod(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let ban_ip_impl = async || -> Res<BanResult> {
+        form.validate()?;
+        let window = form.delete_window.unwrap_or(config.ban_window);
+        let res = bans::ban_and_purge(
+            &pool,
+            form.post_id,
+            form.reason.as_deref(),
+            form.duration,
+            form.is_public,
+            window,
+        )
+        .await?;
+        for board in &res.boards {
+            cache.invalidate_board(board).await;
+        }
+        let ban = PublicBan::from(&res.ban);
+        let board = res.ban.board.as_deref();
+        config.events.publish(EventKind::BanIssued, board, &ban);
+        Ok(res)
+    };
+    match ban_ip_impl().await {
+        Ok(res) => (StatusCode::CREATED, Json(Ok(res))),
+        Err(e) => (
+            error_status(&*e, StatusCode::BAD_REQUEST),
+            Json(Err(e.to_string())),
+        ),
+    }
+}
+async fn get_bans(
+    headers: HeaderMap,
+    Extension(ReadPool(pool)): Extension<ReadPool>,
+    Extension(config): Extension<Arc<Config>>,
+) -> impl IntoResponse {
+    if !config.is_mod(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let get_bans_impl = async || -> Res<Vec<Ban>> {
+        sqlx::query_as(
+            r#"
+            SELECT * FROM bans
+            WHERE expires_at IS NULL OR expires_at > strftime('%s', 'now')
+            ORDER BY created_at DESC
+            "#,
+        )
+        .fetch_all(&*pool)
+        .await
+        .map_err(|e| e.into())
+    };
+    match get_bans_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(Err(e.to_string()))),
+    }
+}
+/// The active bans that weren't hidden from the public list, for a transparency page
+async fn get_public_bans(Extension(ReadPool(pool)): Extension<ReadPool>) -> impl IntoResponse {
+    let get_public_bans_impl = async || -> Res<Vec<PublicBan>> {
+        sqlx::query_as(
+            r#"
+            SELECT board, reason, expires_at - created_at AS duration, expires_at, created_at FROM bans
+            WHERE is_public AND (expires_at IS NULL OR expires_at > strftime('%s', 'now'))
+            ORDER BY created_at DESC
+            "#,
+        )
+        .fetch_all(&*pool)
+        .await
+        .map_err(|e| e.into())
+    };
+    match get_public_bans_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(Err(e.to_string()))),
+    }
+}
+/// Hides the ban from the public list, or shows it again
+async fn set_ban_public(
+    Path(ban_id): Path<i64>,
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+    Json(form): Json<SetFlag>,
+) -> impl IntoResponse {
+    if !config.is_mod(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let set_ban_public_impl = async || -> Res<Ban> {
+        sqlx::query_as(r#"UPDATE bans SET is_public = ? WHERE id = ? RETURNING *"#)
+            .bind(form.value)
+            .bind(ban_id)
+            .fetch_optional(&*pool)
+            .await?
+            .ok_or_else(|| "ban not found".into())
+    };
+    match set_ban_public_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (StatusCode::BAD_REQUEST, Json(Err(e.to_string()))),
+    }
+}
+async fn delete_ban(
+    Path(ban_id): Path<i64>,
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+) -> impl IntoResponse {
+    if !config.is_mod(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let delete_ban_impl = async || -> Res<Ban> {
+        sqlx::query_as(r#"DELETE FROM bans WHERE id = ? RETURNING *"#)
+            .bind(ban_id)
+            .fetch_optional(&*pool)
+            .await?
+            .ok_or_else(|| "ban not found".into())
+    };
+    match delete_ban_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (StatusCode::BAD_REQUEST, Json(Err(e.to_string()))),
+    }
+}
+/// Files a legal takedown request for the posts, moderators review it before anything is removed
+async fn create_takedown(
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Json(form): Json<CreateTakedown>,
+) -> impl IntoResponse {
+    let create_takedown_impl = async || -> Res<Takedown> {
+        form.validate()?;
+        let ids = serde_json::to_string(&form.post_ids)?;
+        let mut tx = pool.begin().await?;
+        let found: i64 = sqlx::query_scalar(
+            r#"SELECT COUNT(*) FROM comments WHERE id IN (SELECT DISTINCT value FROM json_each(?))"#,
+        )
+        .bind(&ids)
+        .fetch_one(&mut *tx)
+        .await?;
+        let mut post_ids = form.post_ids.clone();
+        post_ids.sort();
+        post_ids.dedup();
+        if found != post_ids.len() as i64 {
+            return Err(StatusError(StatusCode::NOT_FOUND, "post not found".to_string()).into());
+        }
+        let mut takedown: Takedown = sqlx::query_as(
+            r#"INSERT INTO takedowns (claimant, contact, reason) VALUES (?, ?, ?) RETURNING *"#,
+        )
+        .bind(form.claimant.trim())
+        .bind(form.contact.trim())
+        .bind(form.reason.trim())
+        .fetch_one(&mut *tx)
+        .await?;
+        sqlx::query(
+            r#"
+            INSERT INTO takedown_posts (takedown_id, post_id)
+            SELECT ?, value FROM json_each(?)
+            "#,
+        )
+        .bind(takedown.id)
+        .bind(serde_json::to_string(&post_ids)?)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        takedown.post_ids = post_ids;
+        Ok(takedown)
+    };
+    match create_takedown_impl().await {
+        Ok(res) => (StatusCode::CREATED, Json(Ok(res))),
+        Err(e) => (
+            error_status(&*e, StatusCode::BAD_REQUEST),
+            Json(Err(e.to_string())),
+        ),
+    }
+}
+async fn get_takedowns(
+    headers: HeaderMap,
+    Extension(ReadPool(pool)): Extension<ReadPool>,
+    Extension(config): Extension<Arc<Config>>,
+) -> impl IntoResponse {
+    if !config.is_mod(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let get_takedowns_impl = async || -> Res<Vec<Takedown>> {
+        let mut takedowns: Vec<Takedown> = sqlx::query_as(
+            r#"SELECT * FROM takedowns ORDER BY status != 'pending', created_at DESC"#,
+        )
+        .fetch_all(&*pool)
+        .await?;
+        takedowns::fetch_post_ids(&pool, &mut takedowns).await?;
+        Ok(takedowns)
+    };
+    match get_takedowns_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(Err(e.to_string()))),
+    }
+}
+async fn get_quarantine(
+    headers: HeaderMap,
+    Extension(ReadPool(pool)): Extension<ReadPool>,
+    Extension(config): Extension<Arc<Config>>,
+) -> impl IntoResponse {
+    if !config.is_mod(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    match quarantine::list(&pool).await {
+        Ok(res) => (StatusCode::OK, Json(Ok::<Vec<QuarantinedPost>, _>(res))),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(Err(e.to_string()))),
+    }
+}
+/// The decrypted copy of a post deleted by a moderator, with the replies deleted with it
+async fn get_quarantined_post(
+    Path(post_id): Path<i64>,
+    headers: HeaderMap,
+    Extension(ReadPool(pool)): Extension<ReadPool>,
+    Extension(config): Extension<Arc<Config>>,
+) -> impl IntoResponse {
+    if !config.is_mod(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let get_quarantined_post_impl = async || -> Res<QuarantinedContent> {
+        let quarantine = config.quarantine.as_ref().ok_or_else(|| {
+            StatusError(StatusCode::NOT_FOUND, "quarantine is disabled".to_string())
+        })?;
+        quarantine.open(&pool, post_id).await
+    };
+    match get_quarantined_post_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (
+            error_status(&*e, StatusCode::INTERNAL_SERVER_ERROR),
+            Json(Err(e.to_string())),
+        ),
+    }
+}
+/// Undoes the deletion of a post still in quarantine, with its replies and media
+async fn restore_post(
+    Path(post_id): Path<i64>,
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(cache): Extension<Arc<ResponseCache>>,
+) -> impl IntoResponse {
+    if !config.is_mod(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let restore_post_impl = async || -> Res<Comment> {
+        let quarantine = config.quarantine.as_ref().ok_or_else(|| {
+            StatusError(StatusCode::NOT_FOUND, "quarantine is disabled".to_string())
+        })?;
+        quarantine.restore(&pool, post_id).await?;
+        let actor = config.staff_name(&headers);
+        audit::record(&pool, &actor, "restore_post", &post_id.to_string()).await?;
+        let board = fetch_post_board(&pool, post_id).await?;
+        if let Some(board) = board {
+            cache.invalidate_board(&board.code).await;
+        }
+        sqlx::query_as(r#"SELECT * FROM comments WHERE id = ?"#)
+            .bind(post_id)
+            .fetch_one(&*pool)
+            .await
+            .map_err(|e| e.into())
+    };
+    match restore_post_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (
+            error_status(&*e, StatusCode::INTERNAL_SERVER_ERROR),
+            Json(Err(e.to_string())),
+        ),
+    }
+}
+/// Deletes the posts, keeping them in quarantine unless they are purged
+async fn bulk_delete(
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(cache): Extension<Arc<ResponseCache>>,
+    Json(form): Json<BulkAction>,
+) -> impl IntoResponse {
+    if !config.is_mod(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let bulk_delete_impl = async || -> Res<BulkResult<Comment>> {
+        form.validate()
+            .map_err(|e| StatusError(StatusCode::BAD_REQUEST, e.to_string()))?;
+        let mut boards = HashSet::new();
+        for &post_id in &form.ids {
+            let board = fetch_post_board(&pool, post_id).await?;
+            boards.extend(board.map(|b| b.code));
+        }
+        let deleted_by = config.staff_name(&headers);
+        let quarantine = config.quarantine.as_ref().filter(|_| !form.purge);
+        let quarantine = quarantine.map(|q| (q, deleted_by.as_str()));
+        let removed = remove_posts(&pool, &form.ids, form.purge, quarantine).await?;
+        for board in boards {
+            cache.invalidate_board(&board).await;
+        }
+        Ok(removed)
+    };
+    match bulk_delete_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (
+            error_status(&*e, StatusCode::INTERNAL_SERVER_ERROR),
+            Json(Err(e.to_string())),
+        ),
+    }
+}
+async fn bulk_approve(
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(cache): Extension<Arc<ResponseCache>>,
+    Json(form): Json<BulkAction>,
+) -> impl IntoResponse {
+    if !config.is_mod(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let bulk_approve_impl = async || -> Res<BulkResult<Comment>> {
+        form.validate()
+            .map_err(|e| StatusError(StatusCode::BAD_REQUEST, e.to_string()))?;
+        let mut approved: BulkResult<Comment> = BulkResult {
+            done: Vec::new(),
+            failed: Vec::new(),
+        };
+        let mut tx = pool.begin().await?;
+        for &post_id in &form.ids {
+            let comment = sqlx::query_as(
+                r#"UPDATE comments SET is_held = FALSE WHERE id = ? AND is_held RETURNING *"#,
+            )
+            .bind(post_id)
+            .fetch_optional(&mut *tx)
+            .await?;
+            match comment {
+                Some(comment) => approved.done.push(comment),
+                None => approved.failed.push(BulkFailure {
+                    id: post_id,
+                    error: "held post not found".to_string(),
+                }),
+            }
+        }
+        tx.commit().await?;
+        for comment in &approved.done {
+            let board = fetch_post_board(&pool, comment.id).await?;
+            if let Some(board) = board {
+                cache.invalidate_board(&board.code).await;
+                let event = match comment.op {
+                    Some(_) => EventKind::NewReply,
+                    None => EventKind::NewThread,
+                };
+                config.events.publish(event, Some(&board.code), comment);
+            }
+        }
+        Ok(approved)
+    };
+    match bulk_approve_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (
+            error_status(&*e, StatusCode::INTERNAL_SERVER_ERROR),
+            Json(Err(e.to_string())),
+        ),
+    }
+}
+async fn bulk_dismiss(
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+    Json(form): Json<BulkAction>,
+) -> impl IntoResponse {
+    if !config.is_mod(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let bulk_dismiss_impl = async || -> Res<BulkResult<Report>> {
+        form.validate()
+            .map_err(|e| StatusError(StatusCode::BAD_REQUEST, e.to_string()))?;
+        let mut dismissed = Vec::new();
+        let mut failed = Vec::new();
+        let mut tx = pool.begin().await?;
+        for &report_id in &form.ids {
+            let res = sqlx::query(
+                r#"UPDATE reports SET dismissed_at = strftime('%s', 'now') WHERE id = ? AND dismissed_at IS NULL"#,
+            )
+            .bind(report_id)
+            .execute(&mut *tx)
+            .await?;
+            match res.rows_affected() {
+                0 => failed.push(BulkFailure {
+                    id: report_id,
+                    error: "report not found".to_string(),
+                }),
+                _ => dismissed.push(report_id),
+            }
+        }
+        tx.commit().await?;
+        let mut done = Vec::new();
+        for report_id in dismissed {
+            done.push(fetch_report(&pool, report_id).await?);
+        }
+        Ok(BulkResult { done, failed })
+    };
+    match bulk_dismiss_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (
+            error_status(&*e, StatusCode::INTERNAL_SERVER_ERROR),
+            Json(Err(e.to_string())),
+        ),
+    }
+}
+/// Replaces the media of the posts with a placeholder, keeping an encrypted copy of it for
+/// TAKEDOWN_RETENTION seconds
+async fn action_takedown(
+    Path(takedown_id): Path<i64>,
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(cache): Extension<Arc<ResponseCache>>,
+) -> impl IntoResponse {
+    if !config.is_mod(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let action_takedown_impl = async || -> Res<Takedown> {
+        let vault = config
+            .takedowns
+            .as_ref()
+            .ok_or("TAKEDOWN_KEY must be set to keep a copy of the media")?;
+        let takedown = takedowns::fetch(&pool, takedown_id).await?;
+        if takedown.status != TakedownStatus::Pending {
+            return Err("the takedown was already resolved".into());
+        }
+        let boards = takedowns::action(&pool, vault, &takedown).await?;
+        for board in boards {
+            cache.invalidate_board(&board).await;
+        }
+        takedowns::fetch(&pool, takedown_id).await
+    };
+    match action_takedown_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (
+            error_status(&*e, StatusCode::BAD_REQUEST),
+            Json(Err(e.to_string())),
+        ),
+    }
+}
+async fn dismiss_takedown(
+    Path(takedown_id): Path<i64>,
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+) -> impl IntoResponse {
+    if !config.is_mod(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let dismiss_takedown_impl = async || -> Res<Takedown> {
+        let dismissed = sqlx::query(
+            r#"
+            UPDATE takedowns SET status = 'dismissed', resolved_at = strftime('%s', 'now')
+            WHERE id = ? AND status = 'pending'
+            "#,
+        )
+        .bind(takedown_id)
+        .execute(&*pool)
+        .await?;
+        let takedown = takedowns::fetch(&pool, takedown_id).await?;
+        if dismissed.rows_affected() == 0 {
+            return Err("the takedown was already resolved".into());
+        }
+        Ok(takedown)
+    };
+    match dismiss_takedown_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (
+            error_status(&*e, StatusCode::BAD_REQUEST),
+            Json(Err(e.to_string())),
+        ),
+    }
+}
+/// Flags the post for the moderators, once per ip
+async fn report_post(
+    Path(post_id): Path<i64>,
+    PeerIp(ip): PeerIp,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+    Json(form): Json<CreateReport>,
+) -> impl IntoResponse {
+    let report_post_impl = async || -> Res<Report> {
+        form.validate()?;
+        let ip_hash = config.hash_ip(ip);
+        let reported: bool = sqlx::query_scalar(
+            r#"SELECT EXISTS(SELECT 1 FROM reports WHERE post_id = ? AND ip_hash = ?)"#,
+        )
+        .bind(post_id)
+        .bind(&ip_hash)
+        .fetch_one(&*pool)
+        .await?;
+        if reported {
+            return Err("you already reported this post".into());
+        }
+        let report_id: i64 = sqlx::query_scalar(
+            r#"
+            INSERT INTO reports (post_id, reason, ip_hash)
+            SELECT id, ?, ? FROM comments WHERE id = ? AND NOT is_held
+            RETURNING id
+            "#,
+        )
+        .bind(form.reason.trim())
+        .bind(&ip_hash)
+        .bind(post_id)
+        .fetch_optional(&*pool)
+        .await?
+        .ok_or_else(|| StatusError(StatusCode::NOT_FOUND, "post not found".to_string()))?;
+        let report = fetch_report(&pool, report_id).await?;
+        let board = Some(report.board.as_str());
+        config
+            .events
+            .publish(EventKind::ReportFiled, board, &report);
+        Ok(report)
+    };
+    match report_post_impl().await {
+        Ok(res) => (StatusCode::CREATED, Json(Ok(res))),
+        Err(e) => (
+            error_status(&*e, StatusCode::BAD_REQUEST),
+            Json(Err(e.to_string())),
+        ),
+    }
+}
+async fn get_reports(
+    headers: HeaderMap,
+    Extension(ReadPool(pool)): Extension<ReadPool>,
+    Extension(config): Extension<Arc<Config>>,
+) -> impl IntoResponse {
+    if !config.is_mod(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let get_reports_impl = async || -> Res<Vec<Report>> {
+        sqlx::query_as(
+            r#"
+            SELECT r.id, r.post_id, COALESCE(t.board, c.board) AS board, c.board_post_no,
+            r.reason, r.dismissed_at, r.created_at
+            FROM reports r
+            JOIN comments c ON c.id = r.post_id
+            LEFT JOIN comments t ON t.id = c.op
+            WHERE r.dismissed_at IS NULL
+            ORDER BY r.id
+            "#,
+        )
+        .fetch_all(&*pool)
+        .await
+        .map_err(|e| e.into())
+    };
+    match get_reports_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(Err(e.to_string()))),
+    }
+}
+async fn dismiss_report(
+    Path(report_id): Path<i64>,
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+) -> impl IntoResponse {
+    if !config.is_mod(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let dismiss_report_impl = async || -> Res<Report> {
+        let dismissed = sqlx::query(
+            r#"UPDATE reports SET dismissed_at = strftime('%s', 'now') WHERE id = ? AND dismissed_at IS NULL"#,
+        )
+        .bind(report_id)
+        .execute(&*pool)
+        .await?;
+        if dismissed.rows_affected() == 0 {
+            return Err("report not found".into());
+        }
+        fetch_report(&pool, report_id).await
+    };
+    match dismiss_report_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (StatusCode::BAD_REQUEST, Json(Err(e.to_string()))),
+    }
+}
+async fn get_webhooks(
+    headers: HeaderMap,
+    Extension(ReadPool(pool)): Extension<ReadPool>,
+    Extension(config): Extension<Arc<Config>>,
+) -> impl IntoResponse {
+    if !config.is_admin(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let get_webhooks_impl = async || -> Res<Vec<Webhook>> {
+        sqlx::query_as(r#"SELECT * FROM webhooks ORDER BY id"#)
+            .fetch_all(&*pool)
+            .await
+            .map_err(|e| e.into())
+    };
+    match get_webhooks_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(Err(e.to_string()))),
+    }
+}
+async fn create_webhook(
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+    Json(form): Json<CreateWebhook>,
+) -> impl IntoResponse {
+    if !config.is_admin(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let create_webhook_impl = async || -> Res<Webhook> {
+        form.validate()?;
+        sqlx::query_as(
+            r#"
+            INSERT INTO webhooks (url, secret, events, board, is_enabled)
+            VALUES (?, ?, ?, ?, ?)
+            RETURNING *
+            "#,
+        )
+        .bind(form.url)
+        .bind(form.secret)
+        .bind(sqlx::types::Json(form.events))
+        .bind(form.board)
+        .bind(form.is_enabled)
+        .fetch_one(&*pool)
+        .await
+        .map_err(|e| e.into())
+    };
+    match create_webhook_impl().await {
+        Ok(res) => (StatusCode::CREATED, Json(Ok(res))),
+        Err(e) => (StatusCode::BAD_REQUEST, Json(Err(e.to_string()))),
+    }
+}
+async fn update_webhook(
+    Path(webhook_id): Path<i64>,
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+    Json(form): Json<UpdateWebhook>,
+) -> impl IntoResponse {
+    if !config.is_admin(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let update_webhook_impl = async || -> Res<Webhook> {
+        form.validate()?;
+        sqlx::query_as(
+            r#"
+            UPDATE webhooks
+            SET url = COALESCE(?, url),
+                secret = COALESCE(?, secret),
+                events = COALESCE(?, events),
+                is_enabled = COALESCE(?, is_enabled)
+            WHERE id = ?
+            RETURNING *
+            "#,
+        )
+        .bind(form.url)
+        .bind(form.secret)
+        .bind(form.events.map(sqlx::types::Json))
+        .bind(form.is_enabled)
+        .bind(webhook_id)
+        .fetch_optional(&*pool)
+        .await?
+        .ok_or_else(|| "webhook not found".into())
+    };
+    match update_webhook_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (StatusCode::BAD_REQUEST, Json(Err(e.to_string()))),
+    }
+}
+async fn delete_webhook(
+    Path(webhook_id): Path<i64>,
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+) -> impl IntoResponse {
+    if !config.is_admin(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let delete_webhook_impl = async || -> Res<Webhook> {
+        sqlx::query_as(r#"DELETE FROM webhooks WHERE id = ? RETURNING *"#)
+            .bind(webhook_id)
+            .fetch_optional(&*pool)
+            .await?
+            .ok_or_else(|| "webhook not found".into())
+    };
+    match delete_webhook_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (StatusCode::BAD_REQUEST, Json(Err(e.to_string()))),
+    }
+}
+async fn get_whitelist(
+    headers: HeaderMap,
+    Extension(ReadPool(pool)): Extension<ReadPool>,
+    Extension(config): Extension<Arc<Config>>,
+) -> impl IntoResponse {
+    if !config.is_mod(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let get_whitelist_impl = async || -> Res<Vec<WhitelistedIp>> {
+        sqlx::query_as(r#"SELECT * FROM proxy_whitelist ORDER BY created_at"#)
+            .fetch_all(&*pool)
+            .await
+            .map_err(|e| e.into())
+    };
+    match get_whitelist_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(Err(e.to_string()))),
+    }
+}
+async fn create_whitelist(
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+    Json(form): Json<CreateWhitelistedIp>,
+) -> impl IntoResponse {
+    if !config.is_mod(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let create_whitelist_impl = async || -> Res<WhitelistedIp> {
+        sqlx::query_as(
+            r#"
+            INSERT INTO proxy_whitelist (ip_hash) VALUES (?)
+            ON CONFLICT (ip_hash) DO UPDATE SET ip_hash = excluded.ip_hash
+            RETURNING *
+            "#,
+        )
+        .bind(config.hash_ip(form.ip))
+        .fetch_one(&*pool)
+        .await
+        .map_err(|e| e.into())
+    };
+    match create_whitelist_impl().await {
+        Ok(res) => (StatusCode::CREATED, Json(Ok(res))),
+        Err(e) => (StatusCode::BAD_REQUEST, Json(Err(e.to_string()))),
+    }
+}
+async fn delete_whitelist(
+    Path(ip): Path<IpAddr>,
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+) -> impl IntoResponse {
+    if !config.is_mod(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let delete_whitelist_impl = async || -> Res<WhitelistedIp> {
+        sqlx::query_as(r#"DELETE FROM proxy_whitelist WHERE ip_hash = ? RETURNING *"#)
+            .bind(config.hash_ip(ip))
+            .fetch_optional(&*pool)
+            .await?
+            .ok_or_else(|| "ip not whitelisted".into())
+    };
+    match delete_whitelist_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (StatusCode::BAD_REQUEST, Json(Err(e.to_string()))),
+    }
+}
+async fn get_announcements(
+    headers: HeaderMap,
+    Extension(ReadPool(pool)): Extension<ReadPool>,
+    Extension(config): Extension<Arc<Config>>,
+) -> impl IntoResponse {
+    if !config.is_admin(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let get_announcements_impl = async || -> Res<Vec<Announcement>> {
+        sqlx::query_as(r#"SELECT * FROM announcements ORDER BY id DESC"#)
+            .fetch_all(&*pool)
+            .await
+            .map_err(|e| e.into())
+    };
+    match get_announcements_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(Err(e.to_string()))),
+    }
+}
+async fn create_announcement(
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(cache): Extension<Arc<ResponseCache>>,
+    Json(form): Json<CreateAnnouncement>,
+) -> impl IntoResponse {
+    if !config.is_admin(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let create_announcement_impl = async || -> Res<Announcement> {
+        form.validate()?;
+        let announcement: Announcement = sqlx::query_as(
+            r#"
+            INSERT INTO announcements (text, board, expires_at)
+            VALUES (?, ?, ?)
+            RETURNING *
+            "#,
+        )
+        .bind(encode_comment(&form.text))
+        .bind(form.board)
+        .bind(form.expires_at)
+        .fetch_one(&*pool)
+        .await?;
+        invalidate_announcement(&cache, &announcement).await;
+        Ok(announcement)
+    };
+    match create_announcement_impl().await {
+        Ok(res) => (StatusCode::CREATED, Json(Ok(res))),
+        Err(e) => (StatusCode::BAD_REQUEST, Json(Err(e.to_string()))),
+    }
+}
+async fn delete_announcement(
+    Path(announcement_id): Path<i64>,
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(cache): Extension<Arc<ResponseCache>>,
+) -> impl IntoResponse {
+    if !config.is_admin(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let delete_announcement_impl = async || -> Res<Announcement> {
+        let announcement: Announcement =
+            sqlx::query_as(r#"DELETE FROM announcements WHERE id = ? RETURNING *"#)
+                .bind(announcement_id)
+                .fetch_optional(&*pool)
+                .await?
+                .ok_or("announcement not found")?;
+        invalidate_announcement(&cache, &announcement).await;
+        Ok(announcement)
+    };
+    match delete_announcement_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (StatusCode::BAD_REQUEST, Json(Err(e.to_string()))),
+    }
+}
+
+async fn get_reencoded(
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+) -> impl IntoResponse {
+    if !config.is_admin(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let get_reencoded_impl = async || -> Res<Vec<ReencodeReport>> {
+        sqlx::query_as(
+            r#"
+            SELECT COALESCE(t.board, c.board) AS board, COUNT(*) AS images,
+                SUM(c.media_orig_size) AS original_size, SUM(c.media_size) AS size,
+                SUM(c.media_orig_size - c.media_size) AS saved
+            FROM comments c
+            LEFT JOIN comments t ON t.id = c.op
+            WHERE c.media_orig_size IS NOT NULL
+            GROUP BY COALESCE(t.board, c.board)
+            ORDER BY saved DESC
+            "#,
+        )
+        .fetch_all(&*pool)
+        .await
+        .map_err(|e| e.into())
+    };
+    match get_reencoded_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(Err(e.to_string()))),
+    }
+}
+async fn rethumb(
+    Query(query): Query<RethumbQuery>,
+    headers: HeaderMap,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(cache): Extension<Arc<ResponseCache>>,
+) -> impl IntoResponse {
+    if !config.is_admin(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let rethumb_impl = async || -> Res<RethumbReport> {
+        let boards: Vec<Board> = sqlx::query_as(r#"SELECT * FROM boards"#)
+            .fetch_all(&*pool)
+            .await?;
+        let thumbs: HashMap<String, ThumbSettings> = boards
+            .iter()
+            .map(|board| (board.code.clone(), config.thumbs_for(board)))
+            .collect();
+        let posts: Vec<(i64, String, Option<String>)> = sqlx::query_as(
+            r#"
+            SELECT c.id, c.media_name, COALESCE(t.board, c.board) FROM comments c
+            LEFT JOIN comments t ON t.id = c.op
+            WHERE c.media_name IS NOT NULL AND (c.thumb_failed OR NOT ?)
+            ORDER BY c.id
+            "#,
+        )
+        .bind(query.failed)
+        .fetch_all(&*pool)
+        .await?;
+
+        let mut report = RethumbReport {
+            updated: 0,
+            failed: Vec::new(),
+        };
+        for (post_id, media_name, board) in posts {
+            let settings = board
+                .and_then(|board| thumbs.get(&board).copied())
+                .unwrap_or(config.thumbs);
+            let old = match regenerate_thumbs(&pool, post_id, &media_name, settings).await {
+                Ok(old) => old,
+                Err(e) => {
+                    tracing::warn!("failed to regenerate the thumbnails of post {post_id}: {e}");
+                    report.failed.push(post_id);
+                    continue;
+                }
+            };
+            for name in old.into_iter().flatten() {
+                remove_media(&name).await?;
+            }
+            report.updated += 1;
+        }
+        cache.invalidate_all();
+        Ok(report)
+    };
+    match rethumb_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(Err(e.to_string()))),
+    }
+}
+async fn get_metrics(
+    headers: HeaderMap,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(cache): Extension<Arc<ResponseCache>>,
+) -> impl IntoResponse {
+    if !config.is_admin(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let metrics = Metrics {
+        cache: cache.stats().await,
+        thumbs: thumbs::worker_stats(),
+        scans: scan::scan_stats(),
+    };
+    (StatusCode::OK, Json(Ok(metrics)))
+}
+/// Every post of the ip hash on every board, held ones included, to weigh a ban. Each lookup is
+/// recorded in the audit log
+async fn get_ip_posts(
+    Path(ip_hash): Path<String>,
+    headers: HeaderMap,
+    Query(query): Query<PageQuery>,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+) -> impl IntoResponse {
+    if !config.is_admin(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    let get_ip_posts_impl = async || -> Res<Vec<IpPost>> {
+        let actor = config.staff_name(&headers);
+        audit::record(&pool, &actor, "lookup_ip_posts", &ip_hash).await?;
+        sqlx::query_as(
+            r#"
+            SELECT c.*, COALESCE(t.board, c.board) AS thread_board FROM comments c
+            LEFT JOIN comments t ON t.id = c.op
+            WHERE c.ip_hash = ?1 AND (?2 IS NULL OR c.id < ?2)
+            ORDER BY c.id DESC
+            LIMIT ?3
+            "#,
+        )
+        .bind(&ip_hash)
+        .bind(query.before)
+        .bind(query.limit())
+        .fetch_all(&*pool)
+        .await
+        .map_err(|e| e.into())
+    };
+    match get_ip_posts_impl().await {
+        Ok(res) => (StatusCode::OK, Json(Ok(res))),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(Err(e.to_string()))),
+    }
+}
+async fn get_audit_log(
+    headers: HeaderMap,
+    Query(query): Query<PageQuery>,
+    Extension(ReadPool(pool)): Extension<ReadPool>,
+    Extension(config): Extension<Arc<Config>>,
+) -> impl IntoResponse {
+    if !config.is_admin(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    match audit::fetch(&pool, query.before, query.limit()).await {
+        Ok(res) => (StatusCode::OK, Json(Ok::<Vec<AuditEntry>, _>(res))),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(Err(e.to_string()))),
+    }
+}
+async fn get_jobs(
+    headers: HeaderMap,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(scheduler): Extension<Arc<Scheduler>>,
+) -> impl IntoResponse {
+    if !config.is_admin(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err("unauthorized".to_string())),
+        );
+    }
+    (
+        StatusCode::OK,
+        Json(Ok::<Vec<JobStatus>, _>(scheduler.status())),
+    )
+}
+async fn get_backup(
+    headers: HeaderMap,
+    Query(query): Query<BackupQuery>,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(config): Extension<Arc<Config>>,
+) -> Response {
+    if !config.is_admin(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Err::<(), _>("unauthorized".to_string())),
+        )
+            .into_response();
+    }
+    let get_backup_impl = async || -> Res<File> {
+        let path = backup::temp_path();
+        backup::create_backup(&pool, &path, query.media).await?;
+        let file = File::open(&path).await?;
+        // the open file stays readable once unlinked
+        tokio::fs::remove_file(&path).await?;
+        Ok(file)
+    };
+    match get_backup_impl().await {
+        Ok(file) => {
+            let (content_type, file_name) = if query.media {
+                ("application/gzip", "blu-backup.tar.gz")
+            } else {
+                ("application/vnd.sqlite3", "blu-backup.sqlite")
+            };
+            let headers = [
+                (header::CONTENT_TYPE, content_type.to_string()),
+                (
+                    header::CONTENT_DISPOSITION,
+                    format!("attachment; filename=\"{file_name}\""),
+                ),
+            ];
+            let body = Body::from_stream(ReaderStream::new(file));
+            (StatusCode::OK, headers, body).into_response()
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(Err::<(), _>(e.to_string())),
+        )
+            .into_response(),
+    }
+}
+
+fn cached_page(page: Res<axum::body::Bytes>) -> Response {
+    match page {
+        Ok(body) => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, "application/json")],
+            body,
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(Err::<(), _>(e.to_string())),
+        )
+            .into_response(),
+    }
+}
+async fn fetch_stats(pool: &SqlitePool, board: Option<&str>) -> Res<Stats> {
+    sqlx::query_as(
+        r#"
+        SELECT
+        COUNT(CASE WHEN NOT c.is_held THEN 1 END) AS total_posts,
+        COUNT(CASE WHEN NOT c.is_held AND c.created_at >= strftime('%s', 'now') - 3600 THEN 1 END) AS posts_last_hour,
+        COUNT(CASE WHEN NOT c.is_held AND c.created_at >= strftime('%s', 'now') - 86400 THEN 1 END) AS posts_last_day,
+        COUNT(DISTINCT c.ip_hash) AS unique_posters,
+        COALESCE(SUM(CASE WHEN c.media_name IS NOT NULL THEN c.media_size END), 0)
+        + COALESCE(SUM(CASE WHEN c.thumb_name IS NOT NULL THEN c.thumb_size END), 0) AS media_bytes,
+        COUNT(CASE WHEN c.op IS NULL AND NOT c.is_held THEN 1 END) AS threads
+        FROM comments c
+        LEFT JOIN comments t ON t.id = c.op
+        WHERE ?1 IS NULL OR COALESCE(t.board, c.board) = ?1
+        "#,
+    )
+    .bind(board)
+    .fetch_one(pool)
+    .await
+    .map_err(|e| e.into())
+}
+async fn fetch_report(pool: &SqlitePool, report_id: i64) -> Res<Report> {
+    sqlx::query_as(
+        r#"
+        SELECT r.id, r.post_id, COALESCE(t.board, c.board) AS board, c.board_post_no,
+        r.reason, r.dismissed_at, r.created_at
+        FROM reports r
+        JOIN comments c ON c.id = r.post_id
+        LEFT JOIN comments t ON t.id = c.op
+        WHERE r.id = ?
+        "#,
+    )
+    .bind(report_id)
+    .fetch_one(pool)
+    .await
+    .map_err(|e| e.into())
+}
+async fn fetch_announcements(pool: &SqlitePool, board: Option<&str>) -> Res<Vec<Announcement>> {
+    sqlx::query_as(
+        r#"
+        SELECT * FROM announcements
+        WHERE (board IS NULL OR board = ?)
+        AND (expires_at IS NULL OR expires_at > strftime('%s', 'now'))
+        ORDER BY id DESC
+        "#,
+    )
+    .bind(board)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| e.into())
+}
+/// Removes the post, with its replies if it is a thread. Purging shreds the media files instead
+/// of unlinking them and blocklists the file of the post
+async fn remove_post(
+    pool: &SqlitePool,
+    post_id: i64,
+    purge: bool,
+    quarantine: Option<(&Quarantine, &str)>,
+) -> Res<Comment> {
+    let mut removed = remove_posts(pool, &[post_id], purge, quarantine).await?;
+    match removed.failed.pop() {
+        Some(failure) => Err(failure.error.into()),
+        None => removed.done.pop().ok_or_else(|| "post not found".into()),
+    }
+}
+/// Deletes the posts with their replies and media in one transaction, kept in the quarantine
+/// by who deleted them when given. The posts not found or that couldn't be quarantined are
+/// reported as failed
+async fn remove_posts(
+    pool: &SqlitePool,
+    post_ids: &[i64],
+    purge: bool,
+    quarantine: Option<(&Quarantine, &str)>,
+) -> Res<BulkResult<Comment>> {
+    let mut media: Vec<(i64, Vec<String>)> = Vec::new();
+    let mut hashes = Vec::new();
+    for &post_id in post_ids {
+        let files: Vec<(Option<String>, Option<String>, Option<String>)> = sqlx::query_as(
+            r#"SELECT media_name, thumb_name, catalog_thumb_name FROM comments WHERE id = ? OR op = ?"#,
+        )
+        .bind(post_id)
+        .bind(post_id)
+        .fetch_all(pool)
+        .await?;
+        let names = files.into_iter().flat_map(|(m, t, c)| [m, t, c]).flatten();
+        media.push((post_id, names.collect()));
+        if purge {
+            let stored: Option<(Option<String>, Option<String>)> =
+                sqlx::query_as(r#"SELECT media_name, media_hash FROM comments WHERE id = ?"#)
+                    .bind(post_id)
+                    .fetch_optional(pool)
+                    .await?;
+            // the posts from before the hashes were stored are hashed from their file
+            let hash = match stored {
+                Some((_, Some(hash))) => Some(hash),
+                Some((Some(name), None)) => media::stored_hash(&name).await?,
+                _ => None,
+            };
+            hashes.extend(hash.map(|hash| (hash, post_id)));
+        }
+    }
+
+    let mut tx = pool.begin().await?;
+    for (hash, post_id) in hashes {
+        sqlx::query(r#"INSERT OR IGNORE INTO media_blocklist (hash, post_id) VALUES (?, ?)"#)
+            .bind(hash)
+            .bind(post_id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    let mut removed: BulkResult<Comment> = BulkResult {
+        done: Vec::new(),
+        failed: Vec::new(),
+    };
+    let mut quarantined = HashSet::new();
+    for &post_id in post_ids {
+        if let Some((quarantine, deleted_by)) = quarantine {
+            match quarantine.save(&mut tx, post_id, deleted_by).await {
+                Ok(saved) => {
+                    if saved {
+                        quarantined.insert(post_id);
+                    }
+                }
+                Err(e) => {
+                    removed.failed.push(BulkFailure {
+                        id: post_id,
+                        error: format!("failed to quarantine the post: {e}"),
+                    });
+                    continue;
+                }
+            }
+        }
+        sqlx::query(r#"DELETE FROM comments WHERE op = ?"#)
+            .bind(post_id)
+            .execute(&mut *tx)
+            .await?;
+        let comment = sqlx::query_as(r#"DELETE FROM comments WHERE id = ? RETURNING *"#)
+            .bind(post_id)
+            .fetch_optional(&mut *tx)
+            .await?;
+        match comment {
+            Some(comment) => removed.done.push(comment),
+            None => removed.failed.push(BulkFailure {
+                id: post_id,
+                error: "post not found".to_string(),
+            }),
+        }
+    }
+    tx.commit().await?;
+
+    for (post_id, names) in media {
+        if !removed.done.iter().any(|c| c.id == post_id) {
+            continue;
+        }
+        if quarantined.contains(&post_id) {
+            quarantine::keep_files(post_id, &names).await?;
+            continue;
+        }
+        for name in names {
+            match purge {
+                true => shred_media(&name).await?,
+                false => remove_media(&name).await?,
+            }
+        }
+    }
+    Ok(removed)
+}
+/// Whether no visible post was made from the ip, as far as the ip hashes are retained
+async fn is_new_poster(pool: &SqlitePool, ip_hash: &str) -> Res<bool> {
+    let seen: bool = sqlx::query_scalar(
+        r#"SELECT EXISTS(SELECT 1 FROM comments WHERE ip_hash = ? AND NOT is_held)"#,
+    )
+    .bind(ip_hash)
+    .fetch_one(pool)
+    .await?;
+    Ok(!seen)
+}
+/// Whether the ip already replied the same text to the thread in the last `window` seconds, in
+/// another post than the one `edited`
+async fn is_duplicate_post(
+    pool: &SqlitePool,
+    op: i64,
+    ip_hash: &str,
+    com: &str,
+    window: i64,
+    edited: Option<i64>,
+) -> Res<bool> {
+    let duplicate = sqlx::query_scalar(
+        r#"
+        SELECT EXISTS(
+            SELECT 1 FROM comments
+            WHERE op = ? AND ip_hash = ? AND com_raw = ? AND created_at >= strftime('%s', 'now') - ?
+            AND id IS NOT ?
+        )
+        "#,
+    )
+    .bind(op)
+    .bind(ip_hash)
+    .bind(com)
+    .bind(window)
+    .bind(edited)
+    .fetch_one(pool)
+    .await?;
+    Ok(duplicate)
+}
+/// Removes the held posts no moderator reviewed within the expiry
+async fn expire_held_posts(pool: &SqlitePool, expiry: i64) -> Res<usize> {
+    let expired: Vec<i64> = sqlx::query_scalar(
+        r#"SELECT id FROM comments WHERE is_held AND created_at < strftime('%s', 'now') - ?"#,
+    )
+    .bind(expiry)
+    .fetch_all(pool)
+    .await?;
+    for id in &expired {
+        remove_post(pool, *id, false, None).await?;
+    }
+    Ok(expired.len())
+}
+/// Removes the threads past the `max_threads` of their board, the least recently bumped first.
+/// Sticky threads count towards the limit but are never removed
+async fn prune_threads(pool: &SqlitePool, cache: &ResponseCache) -> Res<usize> {
+    let pruned: Vec<(i64, String)> = sqlx::query_as(
+        r#"
+        SELECT id, board FROM (
+            SELECT c.id, c.board, c.is_sticky, b.max_threads,
+            ROW_NUMBER() OVER (
+                PARTITION BY c.board
+                ORDER BY c.is_sticky DESC, MAX(c.created_at, COALESCE(MAX(r.created_at), 0)) DESC, c.id DESC
+            ) AS position
+            FROM comments c
+            JOIN boards b ON b.code = c.board
+            LEFT JOIN comments r ON r.op = c.id AND NOT r.is_held
+            WHERE c.op IS NULL AND NOT c.is_held
+            GROUP BY c.id
+        )
+        WHERE position > max_threads AND NOT is_sticky
+        "#,
+    )
+    .fetch_all(pool)
+    .await?;
+    for (id, board) in &pruned {
+        remove_post(pool, *id, false, None).await?;
+        cache.invalidate_board(board).await;
+    }
+    Ok(pruned.len())
+}
+/// Updates the per day and board counts of the last two days, kept after the posts are gone
+async fn rollup_stats(pool: &SqlitePool) -> Res<u64> {
+    let res = sqlx::query(
+        r#"
+        INSERT INTO daily_stats (day, board, posts, threads, posters)
+        SELECT date(c.created_at, 'unixepoch') AS day, COALESCE(t.board, c.board) AS post_board,
+        COUNT(*), COUNT(CASE WHEN c.op IS NULL THEN 1 END), COUNT(DISTINCT c.ip_hash)
+        FROM comments c
+        LEFT JOIN comments t ON t.id = c.op
+        WHERE NOT c.is_held AND c.created_at >= strftime('%s', date('now', '-1 day'))
+        GROUP BY day, post_board
+        HAVING post_board IS NOT NULL
+        ON CONFLICT (day, board) DO UPDATE SET
+        posts = excluded.posts, threads = excluded.threads, posters = excluded.posters
+        "#,
+    )
+    .execute(pool)
+    .await?;
+    Ok(res.rows_affected())
+}
+/// Removes the post and drops the cached pages of its board, looked up before it is gone
+async fn remove_post_cached(
+    pool: &SqlitePool,
+    cache: &ResponseCache,
+    post_id: i64,
+    purge: bool,
+    quarantine: Option<(&Quarantine, &str)>,
+) -> Res<Comment> {
+    let board = fetch_post_board(pool, post_id).await?;
+    let comment = remove_post(pool, post_id, purge, quarantine).await?;
+    if let Some(board) = board {
+        cache.invalidate_board(&board.code).await;
+    }
+    Ok(comment)
+}
+async fn invalidate_announcement(cache: &ResponseCache, announcement: &Announcement) {
+    match &announcement.board {
+        Some(board) => cache.invalidate_board(board).await,
+        None => cache.invalidate_all(),
+    }
+}
+async fn set_thread_flag(
+    pool: &SqlitePool,
+    cache: &ResponseCache,
+    column: &'static str,
+    thread_id: i64,
+    value: bool,
+) -> Res<Comment> {
+    let thread: Comment = sqlx::query_as(&format!(
+        "UPDATE comments SET {column} = ? WHERE id = ? AND op IS NULL RETURNING *"
+    ))
+    .bind(value)
+    .bind(thread_id)
+    .fetch_optional(pool)
+    .await?
+    .ok_or("thread not found")?;
+    if let Some(board) = &thread.board {
+        cache.invalidate_board(board).await;
+    }
+    Ok(thread)
+}
+async fn fetch_post_board(pool: &SqlitePool, post_id: i64) -> Res<Option<Board>> {
+    sqlx::query_as(
+        r#"
+        SELECT b.* FROM comments c
+        JOIN comments t ON t.id = COALESCE(c.op, c.id)
+        JOIN boards b ON b.code = t.board
+        WHERE c.id = ?
+        "#,
+    )
+    .bind(post_id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| e.into())
+}
+/// Checks that replies are made to a thread of the board, returns whether the thread is locked
+async fn check_parent(conn: &mut SqliteConnection, op: i64, board: Option<&str>) -> Res<bool> {
+    let parent: Option<(Option<i64>, Option<String>, bool)> =
+        sqlx::query_as(r#"SELECT op, board, is_locked FROM comments WHERE id = ?"#)
+            .bind(op)
+            .fetch_optional(conn)
+            .await?;
+    let Some((parent_op, parent_board, is_locked)) = parent else {
+        return Err(StatusError(StatusCode::NOT_FOUND, "thread not found".to_string()).into());
+    };
+    if parent_op.is_some() {
+        return Err(StatusError(
+            StatusCode::CONFLICT,
+            "replies can only be made to threads".to_string(),
+        )
+        .into());
+    }
+    if let Some(board) = board
+        && parent_board.as_deref() != Some(board)
+    {
+        return Err(StatusError(
+            StatusCode::CONFLICT,
+            format!("thread {op} is not on /{board}/"),
+        )
+        .into());
+    }
+    Ok(is_locked)
+}
+async fn next_post_no(tx: &mut SqliteConnection, board: &str) -> Res<i64> {
+    sqlx::query_scalar(
+        r#"UPDATE boards SET post_count = post_count + 1 WHERE code = ? RETURNING post_count"#,
+    )
+    .bind(board)
+    .fetch_optional(tx)
+    .await?
+    .ok_or_else(|| "board not found".into())
+}
+/// Links the comment to the posts it quotes, quotes refer to the board post numbers
+async fn save_quotes(
+    pool: &SqlitePool,
+    comment: &mut Comment,
+    board: &str,
+    quotes: &[i64],
+) -> Res<()> {
+    let mut tx = pool.begin().await?;
+    sqlx::query(r#"DELETE FROM post_replies WHERE reply_id = ?"#)
+        .bind(comment.id)
+        .execute(&mut *tx)
+        .await?;
+    comment.replying_to.clear();
+    for &post_no in quotes {
+        let post_id: Option<i64> = sqlx::query_scalar(
+            r#"
+            INSERT OR IGNORE INTO post_replies (post_id, reply_id)
+            SELECT c.id, ? FROM comments c
+            LEFT JOIN comments t ON t.id = c.op
+            WHERE COALESCE(t.board, c.board) = ? AND c.board_post_no = ? AND c.id != ?
+            RETURNING post_id
+            "#,
+        )
+        .bind(comment.id)
+        .bind(board)
+        .bind(post_no)
+        .bind(comment.id)
+        .fetch_optional(&mut *tx)
+        .await?;
+        comment.replying_to.extend(post_id);
+    }
+    tx.commit().await?;
+    Ok(())
+}
+/// Nests each reply under the earliest post of the thread it quotes, the replies that quote
+/// nothing before them go under the op. The posts without a parent in the list are the roots,
+/// only the op for a whole thread
+fn comment_tree(comments: Vec<Comment>) -> Vec<CommentNode> {
+    let ids: HashSet<i64> = comments.iter().map(|c| c.id).collect();
+    let mut roots = Vec::new();
+    let mut children: HashMap<i64, Vec<Comment>> = HashMap::new();
+    for comment in comments {
+        let quoted = comment
+            .replying_to
+            .iter()
+            .copied()
+            .filter(|id| *id < comment.id && ids.contains(id))
+            .min();
+        match quoted.or(comment.op.filter(|op| ids.contains(op))) {
+            Some(parent) => children.entry(parent).or_default().push(comment),
+            None => roots.push(comment),
+        }
+    }
+    fn build(comment: Comment, children: &mut HashMap<i64, Vec<Comment>>) -> CommentNode {
+        let replies = children.remove(&comment.id).unwrap_or_default();
+        CommentNode {
+            children: replies.into_iter().map(|c| build(c, children)).collect(),
+            comment,
+        }
+    }
+    roots.into_iter().map(|c| build(c, &mut children)).collect()
+}
+/// Attaches the posts each comment quotes and is quoted by, and marks its dead quotes
+async fn attach_backlinks(pool: &SqlitePool, comments: &mut [Comment]) -> Res<()> {
+    let ids = serde_json::to_string(&comments.iter().map(|c| c.id).collect::<Vec<_>>())?;
+    let links: Vec<Backlink> = sqlx::query_as(
+        r#"
+        SELECT r.post_id, r.reply_id, p.board_post_no AS post_no, p.is_held
+        FROM post_replies r
+        JOIN comments p ON p.id = r.post_id
+        WHERE (r.post_id IN (SELECT value FROM json_each(?1)) OR r.reply_id IN (SELECT value FROM json_each(?1)))
+        AND r.reply_id NOT IN (SELECT id FROM comments WHERE is_held)
+        ORDER BY r.reply_id
+        "#,
+    )
+    .bind(ids)
+    .fetch_all(pool)
+    .await?;
+    for comment in comments {
+        let quoted = links.iter().filter(|l| l.reply_id == comment.id);
+        comment.replying_to = quoted.clone().map(|l| l.post_id).collect();
+        comment.replied_by = links
+            .iter()
+            .filter(|l| l.post_id == comment.id)
+            .map(|l| l.reply_id)
+            .collect();
+        let live: Vec<i64> = quoted
+            .filter(|l| !l.is_held)
+            .map(|l| l.post_no)
+            .chain([comment.board_post_no])
+            .collect();
+        comment.com = comment
+            .com
+            .as_deref()
+            .map(|com| mark_dead_quotes(com, &live));
+    }
+    Ok(())
+}
+#[derive(FromRow)]
+struct Backlink {
+    post_id: i64,
+    reply_id: i64,
+    post_no: i64,
+    is_held: bool,
+}
+/// The body of a new post: multipart with the form as JSON in a `data` field or as plain
+/// fields next to the `media` file, or a JSON object or urlencoded form without a file
+enum PostBody {
+    /// The multipart body with the largest media it can hold
+    Multipart(Multipart, u64),
+    Json(Bytes),
+    Form(Bytes),
+}
+impl<S: Send + Sync> FromRequest<S> for PostBody {
+    type Rejection = (StatusCode, Json<Result<(), String>>);
+
+    async fn from_request(req: Request<Body>, state: &S) -> Result<Self, Self::Rejection> {
+        let mime: Option<mime::Mime> = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.parse().ok());
+        let rejection = |status, text| (status, Json(Err(text)));
+        let max_upload = req
+            .extensions()
+            .get::<Arc<Config>>()
+            .map_or(u64::MAX, |c| c.body_limits.upload);
+        match mime.as_ref().map(|m| (m.type_(), m.subtype())) {
+            Some((mime::MULTIPART, mime::FORM_DATA)) => Multipart::from_request(req, state)
+                .await
+                .map(|multipart| Self::Multipart(multipart, max_upload))
+                .map_err(|e| rejection(e.status(), e.body_text())),
+            Some((mime::APPLICATION, mime::JSON)) => Bytes::from_request(req, state)
+                .await
+                .map(Self::Json)
+                .map_err(|e| rejection(e.status(), e.body_text())),
+            Some((mime::APPLICATION, mime::WWW_FORM_URLENCODED)) => Bytes::from_request(req, state)
+                .await
+                .map(Self::Form)
+                .map_err(|e| rejection(e.status(), e.body_text())),
+            _ => Err(rejection(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "expected a multipart, json or urlencoded body".to_string(),
+            )),
+        }
+    }
+}
+async fn parse_post_body<T: DeserializeOwned>(body: PostBody) -> Res<PostData<T>> {
+    let form = match body {
+        PostBody::Multipart(multipart, max_upload) => {
+            return parse_multipart(multipart, max_upload).await;
+        }
+        PostBody::Json(bytes) => serde_json::from_slice(&bytes)?,
+        PostBody::Form(bytes) => from_fields(serde_urlencoded::from_bytes(&bytes)?)?,
+    };
+    Ok(PostData { form, file: None })
+}
+/// Reads plain form fields into the form, the empty ones are left out as html forms send the
+/// inputs left blank
+fn from_fields<T: DeserializeOwned>(fields: Vec<(String, String)>) -> Res<T> {
+    let fields: Vec<_> = fields.into_iter().filter(|(_, v)| !v.is_empty()).collect();
+    Ok(serde_urlencoded::from_str(&serde_urlencoded::to_string(
+        fields,
+    )?)?)
+}
+async fn parse_multipart<T: DeserializeOwned>(
+    mut multipart: Multipart,
+    max_upload: u64,
+) -> Res<PostData<T>> {
+    let mut form: Option<T> = None;
+    let mut fields = Vec::new();
+    let mut file: Option<Upload> = None;
+
+    while let Some(mut field) = multipart.next_field().await.map_err(multipart_error)? {
+        match field.name() {
+            Some("data") => {
+                let text = field.text().await?;
+                form = Some(serde_json::from_str(&text)?);
+            }
+            Some("media") => {
+                let mut upload = Upload {
+                    path: Upload::temp_path(),
+                    size: 0,
+                    name: field.file_name().map(|name| name.to_string()),
+                };
+                let mut out = File::create(&upload.path).await?;
+                while let Some(chunk) = field.chunk().await.map_err(multipart_error)? {
+                    upload.size += chunk.len() as u64;
+                    if upload.size > max_upload {
+                        return Err(media_too_large().into());
+                    }
+                    out.write_all(&chunk).await?;
+                }
+                out.flush().await?;
+                file = Some(upload);
+            }
+            Some(name) => {
+                let name = name.to_string();
+                fields.push((name, field.text().await?));
+            }
+            None => {}
+        }
+    }
+    let form = match form {
+        Some(form) => form,
+        None => from_fields(fields)?,
+    };
+    Ok(PostData { form, file })
+}
+fn multipart_error(e: MultipartError) -> StatusError {
+    match e.status() {
+        StatusCode::PAYLOAD_TOO_LARGE => media_too_large(),
+        status => StatusError(status, e.body_text()),
+    }
+}
+fn media_too_large() -> StatusError {
+    StatusError(
+        StatusCode::PAYLOAD_TOO_LARGE,
+        "media is too large".to_string(),
+    )
+}
+fn check_file_size(file: Option<&Upload>, board: &Board) -> Res<()> {
+    match file {
+        Some(file) if file.size > board.max_file_size as u64 => Err(media_too_large().into()),
+        _ => Ok(()),
+    }
+}
+/// Watch tokens are generated by the clients, require them to be long enough not to be guessed
+fn check_watch_token(token: &str) -> Res<()> {
+    let valid = (16..=64).contains(&token.len())
+        && token
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err("invalid watch token".into());
+    }
+    Ok(())
+}
+/// `name ## Mod` is the alias `name` signed as a moderator, other markers are kept in the alias
+fn split_capcode(alias: &str) -> (Option<String>, Option<Capcode>) {
+    let capcode = alias.rsplit_once("##").and_then(|(name, code)| {
+        let capcode = match code.trim().to_lowercase().as_str() {
+            "mod" => Capcode::Mod,
+            "admin" => Capcode::Admin,
+            _ => return None,
+        };
+        Some((name.trim(), capcode))
+    });
+    match capcode {
+        Some((name, capcode)) => ((!name.is_empty()).then(|| name.to_string()), Some(capcode)),
+        None => (Some(alias.to_string()), None),
+    }
+}
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+}
+fn hash_password(password: &str) -> Res<String> {
+    let salt = SaltString::encode_b64(Uuid::new_v4().as_bytes()).map_err(|e| e.to_string())?;
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| e.to_string().into())
+}
+fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash).is_ok_and(|hash| {
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok()
+    })
+}
+/// Escapes the comment and turns its urls, quotes, greentext and line breaks into html, the
+/// urls are found in the raw text of each line and the text around them escaped apart, so
+/// neither can run into the other
+pub fn encode_comment(com: impl AsRef<str>) -> String {
+    let mut quotes = Vec::new();
+    let mut encode_text = |text: &str| {
+        RE_REPLIES
+            .replace_all(&encode_text(text), |c: &regex::Captures| {
+                let Ok(no) = c[1].parse::<i64>() else {
+                    return c[0].to_string();
+                };
+                if !quotes.contains(&no) && quotes.len() >= *MAX_QUOTE_LINKS {
+                    return c[0].to_string();
+                }
+                if !quotes.contains(&no) {
+                    quotes.push(no);
+                }
+                format!("<a href=\"#p{no}\">&gt;&gt;{no}</a>")
+            })
+            .into_owned()
+    };
+    com.as_ref()
+        .lines()
+        .map(|ln| {
+            let mut html = String::new();
+            let mut rest = 0;
+            for link in text::find_urls(ln) {
+                html += &encode_text(&ln[rest..link.start]);
+                html += &format!(
+                    "<a href=\"{}\"{}>{}</a>",
+                    encode_double_quoted_attribute(link.url.as_str()),
+                    *LINK_ATTRS,
+                    html_escape::encode_text(link.text)
+                );
+                rest = link.start + link.text.len();
+            }
+            html += &encode_text(&ln[rest..]);
+            if ln.starts_with('>') && !ln.starts_with(">>") {
+                format!("<span>{html}</span>")
+            } else {
+                html
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("<br>")
+}
+/// Like `encode_comment` with the markup of the boards that enable it
+pub fn encode_markup(com: impl AsRef<str>) -> String {
+    let com = com
+        .as_ref()
+        .chars()
+        .filter(|c| {
+            !MARKUP
+                .iter()
+                .any(|(open, close, _, _)| c == open || c == close)
+        })
+        .collect::<String>();
+
+    let mut parts = com.split("```").collect::<Vec<_>>();
+    let unclosed = (parts.len() % 2 == 0).then(|| parts.split_off(parts.len() - 2).join("```"));
+    parts.extend(unclosed.as_deref());
+
+    let mut html = String::new();
+    for (i, part) in parts.into_iter().enumerate() {
+        let part = part
+            .strip_prefix("\r\n")
+            .or(part.strip_prefix('\n'))
+            .unwrap_or(part);
+        if i % 2 == 1 {
+            let code = part.strip_suffix('\n').unwrap_or(part);
+            let code = code.strip_suffix('\r').unwrap_or(code);
+            html += &format!("<pre><code>{}</code></pre>", encode_text(code));
+            continue;
+        }
+        if part.is_empty() {
+            continue;
+        }
+        let [spoiler, bold, italic, heading] = MARKUP.map(|(open, close, _, _)| (open, close));
+        let text = RE_SPOILER.replace_all(part, |c: &regex::Captures| {
+            let inner = c.get(1).or(c.get(2)).map_or("", |m| m.as_str());
+            format!("{}{inner}{}", spoiler.0, spoiler.1)
+        });
+        let text = RE_BOLD.replace_all(&text, format!("{}$1{}", bold.0, bold.1));
+        let text = RE_ITALIC.replace_all(&text, format!("{}$1{}", italic.0, italic.1));
+        let text = RE_HEADING.replace_all(&text, format!("{}$1{}", heading.0, heading.1));
+        let mut text = encode_comment(text);
+        for (open, close, open_tag, close_tag) in MARKUP {
+            text = text.replace(open, open_tag).replace(close, close_tag);
+        }
+        html += &text;
+    }
+    html
+}
+/// Points the quote links of a post to the new numbers, the quotes of numbers missing from
+/// `numbers` are left as plain text
+fn renumber_quotes(com: &str, numbers: &HashMap<i64, i64>) -> String {
+    RE_QUOTE_LINKS
+        .replace_all(com, |c: &regex::Captures| {
+            let number = c[1].parse().ok().and_then(|no: i64| numbers.get(&no));
+            match number {
+                Some(no) => format!("<a href=\"#p{no}\">&gt;&gt;{no}</a>"),
+                None => format!("&gt;&gt;{}", &c[1]),
+            }
+        })
+        .into_owned()
+}
+/// Like `renumber_quotes` for the raw text, the quotes of numbers missing from `numbers` are
+/// left as they were written
+fn renumber_raw_quotes(com: &str, numbers: &HashMap<i64, i64>) -> String {
+    RE_QUOTES
+        .replace_all(com, |c: &regex::Captures| {
+            match c[1].parse().ok().and_then(|no: i64| numbers.get(&no)) {
+                Some(no) => format!(">>{no}"),
+                None => c[0].to_string(),
+            }
+        })
+        .into_owned()
+}
+fn parse_quotes(com: &str) -> Vec<i64> {
+    let mut quotes = Vec::new();
+    for id in RE_QUOTES
+        .captures_iter(com)
+        .filter_map(|c| c[1].parse().ok())
+    {
+        if quotes.len() >= *MAX_QUOTE_LINKS {
+            break;
+        }
+        if !quotes.contains(&id) {
+            quotes.push(id);
+        }
+    }
+    quotes
+}
+/// Marks the quote links to posts that were deleted, held or never existed with the dead
+/// class, done when the posts are read since the quoted posts can go at any time
+fn mark_dead_quotes(com: &str, live: &[i64]) -> String {
+    RE_QUOTE_LINKS
+        .replace_all(com, |c: &regex::Captures| {
+            match c[1].parse().is_ok_and(|no: i64| live.contains(&no)) {
+                true => c[0].to_string(),
+                false => format!("<a href=\"#p{0}\" class=\"dead\">&gt;&gt;{0}</a>", &c[1]),
+            }
+        })
+        .into_owned()
+}
+fn encode_subject(sub: impl AsRef<str>) -> String {
+    let sub = encode_comment(sub);
+    format!("<b>{sub}</b>")
+}
+fn default_true() -> bool {
+    true
+}
+fn default_pow_step() -> i64 {
+    1
+}
+fn default_name() -> String {
+    "Anonymous".to_string()
+}
+fn is_whitespace_empty(s: &str) -> Result<(), ValidationError> {
+    (!text::is_blank(s))
+        .then_some(())
+        .ok_or(ValidationError::new("must not be empty"))
+}
+fn is_board_code(s: &str) -> Result<(), ValidationError> {
+    check_board_code(s).map_err(ValidationError::new)
+}
+/// Board codes are the first segment of the board paths, so they are kept to lowercase letters
+/// and digits and can't be one of the other paths served at the root
+fn check_board_code(code: &str) -> Result<(), &'static str> {
+    const RESERVED: [&str; 16] = [
+        "admin",
+        "api",
+        "banner",
+        "bans",
+        "boards",
+        "media",
+        "mod",
+        "overboard",
+        "post",
+        "stats",
+        "static",
+        "takedowns",
+        "thread",
+        "v1",
+        "watch",
+        "www",
+    ];
+    if code.is_empty()
+        || !code
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit())
+    {
+        return Err("board codes can only contain lowercase letters and digits");
+    }
+    if RESERVED.contains(&code) {
+        return Err("this board code is reserved");
+    }
+    Ok(())
+}
+fn is_theme_name(s: &str) -> Result<(), ValidationError> {
+    theme::is_theme_name(s)
+        .then_some(())
+        .ok_or(ValidationError::new(
+            "must only contain letters, digits, - and _",
+        ))
+}
+fn no_hidden_controls(s: &str) -> Result<(), ValidationError> {
+    text::check_controls(s).map_err(ValidationError::new)
+}
+
+#[test]
+fn test_encode() {
+    use crate::encode_comment;
+
+    assert_eq!(encode_comment("hello >world"), "hello &gt;world");
+    assert_eq!(encode_comment(">hello"), "<span>&gt;hello</span>");
+
+    assert_eq!(
+        encode_comment("https://google.com"),
+        "<a href=\"https://google.com/\" rel=\"noreferrer nofollow\">https://google.com</a>"
+    );
+    assert_eq!(
+        encode_comment("hello >>11 >>22"),
+        "hello <a href=\"#p11\">&gt;&gt;11</a> <a href=\"#p22\">&gt;&gt;22</a>"
+    );
+    assert_eq!(
+        encode_comment("this\nis\nmultiline"),
+        "this<br>is<br>multiline"
+    );
+    assert_eq!(
+        encode_comment("(see https://a.example/?q=1&b=(2)), >>3"),
+        "(see <a href=\"https://a.example/?q=1&amp;b=(2)\" rel=\"noreferrer nofollow\">https://a.example/?q=1&amp;b=(2)</a>), <a href=\"#p3\">&gt;&gt;3</a>"
+    );
+    assert_eq!(
+        encode_comment("https://bücher.example/ü."),
+        "<a href=\"https://xn--bcher-kva.example/%C3%BC\" rel=\"noreferrer nofollow\">https://bücher.example/ü</a>."
+    );
+}
+
+/// Html of the comments once the tags they are allowed to contain are removed
+#[cfg(test)]
+fn strip_allowed_tags(html: &str) -> String {
+    static RE_ALLOWED: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(
+            r#"<a href="[^"<>]*"( rel="[^"<>]*")?( target="[^"<>]*")?>|</a>|<br>|<span( class="(spoiler|heading)")?>|</span>|</?b>|</?i>|</?pre>|</?code>"#,
+        )
+        .unwrap()
+    });
+    RE_ALLOWED.replace_all(html, "").into_owned()
+}
+
+#[test]
+fn test_encode_escapes() {
+    let fragments = [
+        "<",
+        ">",
+        "\"",
+        "&",
+        "'",
+        "<script>",
+        "http://",
+        "https://a.example/",
+        "?q=1&b=2",
+        "(",
+        ")",
+        ";",
+        ">>",
+        "12",
+        ">",
+        "\n",
+        "\r\n",
+        " ",
+        "x",
+        "é",
+        "🙂",
+        "**",
+        "'''",
+        "''",
+        "==",
+        "```",
+        "[spoiler]",
+        "[/spoiler]",
+        "\u{E000}",
+        "\u{E003}",
+        "&gt;",
+        "%41",
+        "\"><b",
+    ];
+    // xorshift, deterministic so a failure can be replayed
+    let mut state: u64 = 0x9E37_79B9_7F4A_7C15;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+    for _ in 0..5000 {
+        let len = next() % 24;
+        let com: String = (0..len)
+            .map(|_| fragments[(next() % fragments.len() as u64) as usize])
+            .collect();
+        for html in [encode_comment(&com), encode_markup(&com)] {
+            let rest = strip_allowed_tags(&html);
+            assert!(
+                !rest.contains(['<', '>']),
+                "unescaped html in {html:?} from {com:?}"
+            );
+        }
+    }
+    assert_eq!(
+        encode_comment("https://a.example/>>1"),
+        "<a href=\"https://a.example/\" rel=\"noreferrer nofollow\">https://a.example/</a><a href=\"#p1\">&gt;&gt;1</a>"
+    );
+    assert_eq!(
+        encode_comment(">https://a.example\nb"),
+        "<span>&gt;<a href=\"https://a.example/\" rel=\"noreferrer nofollow\">https://a.example</a></span><br>b"
+    );
+}
+
+#[test]
+fn test_markup() {
+    use crate::encode_markup;
+
+    assert_eq!(
+        encode_markup("a **secret** b"),
+        "a <span class=\"spoiler\">secret</span> b"
+    );
+    assert_eq!(
+        encode_markup("[spoiler]x\ny[/spoiler]"),
+        "<span class=\"spoiler\">x<br>y</span>"
+    );
+    assert_eq!(
+        encode_markup("'''bold''' ''italic''"),
+        "<b>bold</b> <i>italic</i>"
+    );
+    assert_eq!(
+        encode_markup("==title==\ntext"),
+        "<span class=\"heading\">title</span><br>text"
+    );
+    assert_eq!(
+        encode_markup(">**green**"),
+        "<span>&gt;<span class=\"spoiler\">green</span></span>"
+    );
+    assert_eq!(
+        encode_markup("**https://x.com**"),
+        "<span class=\"spoiler\"><a href=\"https://x.com/\" rel=\"noreferrer nofollow\">https://x.com</a></span>"
+    );
+    assert_eq!(
+        encode_markup("look:\n```\nif a < b {\n  **x** >>1\n}\n```\ndone"),
+        "look:<pre><code>if a &lt; b {\n  **x** &gt;&gt;1\n}</code></pre>done"
+    );
+    assert_eq!(
+        encode_markup("```a```b```"),
+        "<pre><code>a</code></pre>b```"
+    );
+    assert_eq!(encode_markup("** not closed"), "** not closed");
+    assert_eq!(encode_markup("it's ''fine"), "it's ''fine");
+    assert_eq!(
+        encode_markup("**<script>**"),
+        "<span class=\"spoiler\">&lt;script&gt;</span>"
+    );
+    assert_eq!(encode_markup("\u{E000}<b>\u{E001}"), "&lt;b&gt;");
+    assert_eq!(
+        encode_markup("```<img src=x onerror=alert(1)>```"),
+        "<pre><code>&lt;img src=x onerror=alert(1)&gt;</code></pre>"
+    );
+}
+
+#[test]
+fn test_quotes() {
+    use crate::parse_quotes;
+
+    assert_eq!(parse_quotes("hello >>11 >>22"), vec![11, 22]);
+    assert_eq!(parse_quotes(">>3\n>>3 >>>4"), vec![3, 4]);
+    assert_eq!(parse_quotes("> >1 >>x"), Vec::<i64>::new());
+}
+
+#[test]
+fn test_split_capcode() {
+    assert_eq!(split_capcode("anon"), (Some("anon".to_string()), None));
+    assert_eq!(split_capcode("## Mod"), (None, Some(Capcode::Mod)));
+    assert_eq!(
+        split_capcode("jim ##admin"),
+        (Some("jim".to_string()), Some(Capcode::Admin))
+    );
+    assert_eq!(split_capcode("a ## b"), (Some("a ## b".to_string()), None));
+}
+
+#[test]
+fn test_renumber_quotes() {
+    let numbers = HashMap::from([(3, 10), (4, 11)]);
+    assert_eq!(
+        renumber_quotes(&encode_comment(">>3 >>4\n>>5"), &numbers),
+        "<a href=\"#p10\">&gt;&gt;10</a> <a href=\"#p11\">&gt;&gt;11</a><br>&gt;&gt;5"
+    );
+}
+
+#[test]
+fn test_mark_dead_quotes() {
+    let com = encode_comment(">>1 >>2\n>>3");
+    assert_eq!(
+        mark_dead_quotes(&com, &[1, 3]),
+        "<a href=\"#p1\">&gt;&gt;1</a> <a href=\"#p2\" class=\"dead\">&gt;&gt;2</a><br><a href=\"#p3\">&gt;&gt;3</a>"
+    );
+    let many = (1..=60)
+        .map(|n| format!(">>{n}"))
+        .collect::<Vec<_>>()
+        .join(" ");
+    assert_eq!(parse_quotes(&many).len(), 50);
+    assert_eq!(encode_comment(&many).matches("<a ").count(), 50);
+}
+
+#[test]
+fn test_watch_token() {
+    assert!(check_watch_token("abcdefghijklmnop").is_ok());
+    assert!(check_watch_token("0123456789abcdef-_ABCDEF").is_ok());
+    assert!(check_watch_token("short").is_err());
+    assert!(check_watch_token(&"a".repeat(65)).is_err());
+    assert!(check_watch_token("abcdefghijklmnop/../").is_err());
+}
+
+#[test]
+fn test_content_disposition() {
+    assert_eq!(
+        content_disposition("cat.png"),
+        "inline; filename=\"cat.png\"; filename*=UTF-8''cat.png"
+    );
+    assert_eq!(
+        content_disposition("a \"b\"\r\n.jpg"),
+        "inline; filename=\"a _b___.jpg\"; filename*=UTF-8''a%20%22b%22%0D%0A.jpg"
+    );
+    assert_eq!(
+        content_disposition("café.png"),
+        "inline; filename=\"caf_.png\"; filename*=UTF-8''caf%C3%A9.png"
+    );
+}
+
+#[test]
+fn test_check_board_code() {
+    assert!(check_board_code("g").is_ok());
+    assert!(check_board_code("3dg").is_ok());
+    assert!(check_board_code("").is_err());
+    assert!(check_board_code("G").is_err());
+    assert!(check_board_code("a b").is_err());
+    assert!(check_board_code("a/b").is_err());
+    assert!(check_board_code("ç").is_err());
+    assert!(check_board_code("mod").is_err());
+    assert!(check_board_code("api").is_err());
+}
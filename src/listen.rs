@@ -0,0 +1,260 @@
+use std::net::{IpAddr, SocketAddr};
+use std::os::fd::{FromRawFd, OwnedFd};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use axum::extract::{ConnectInfo, FromRequestParts};
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::{Json, Router};
+
+use crate::{Config, Res};
+
+/// The first file descriptor passed by systemd socket activation
+const SD_LISTEN_FDS_START: i32 = 3;
+
+/// Where the api is served, chosen with LISTEN
+pub enum Listen {
+    /// `0.0.0.0:PORT`, the default
+    Tcp(u16),
+    /// `unix:/path/to/socket`
+    Unix(PathBuf),
+    /// `systemd`, the first socket passed by socket activation, tcp or unix
+    Systemd,
+}
+
+impl Listen {
+    pub fn from_env() -> Res<Self> {
+        match std::env::var("LISTEN").as_deref() {
+            Err(_) | Ok("") | Ok("tcp") => {
+                let port = std::env::var("PORT").map_err(|_| "PORT is not set")?;
+                Ok(Listen::Tcp(port.parse()?))
+            }
+            Ok("systemd") => Ok(Listen::Systemd),
+            Ok(listen) => match listen.strip_prefix("unix:") {
+                Some(path) if !path.is_empty() => Ok(Listen::Unix(path.into())),
+                _ => Err(format!("invalid LISTEN {listen}").into()),
+            },
+        }
+    }
+}
+
+/// Serves the app on the listener, unix sockets have no peer address so their clients are
+/// taken from X-Forwarded-For, see [`TrustedProxies`]
+pub async fn serve(app: Router, listen: Listen) -> Res<()> {
+    match listen {
+        Listen::Tcp(port) => {
+            let listener =
+                tokio::net::TcpListener::bind(SocketAddr::from(([0, 0, 0, 0], port))).await?;
+            serve_tcp(app, listener).await
+        }
+        Listen::Unix(path) => {
+            // a socket left by a previous run would make the bind fail
+            match tokio::fs::remove_file(&path).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => (),
+            }
+            let listener = tokio::net::UnixListener::bind(&path)?;
+            tracing::info!("serving on unix socket {}", path.display());
+            axum::serve(listener, app).await?;
+            Ok(())
+        }
+        Listen::Systemd => {
+            let pid = std::env::var("LISTEN_PID")
+                .ok()
+                .and_then(|p| p.parse().ok());
+            let fds: u32 = std::env::var("LISTEN_FDS")
+                .ok()
+                .and_then(|n| n.parse().ok())
+                .unwrap_or(0);
+            if pid != Some(std::process::id()) || fds == 0 {
+                return Err("no socket was passed by systemd".into());
+            }
+            // SAFETY: systemd passes the sockets starting at SD_LISTEN_FDS_START and they are
+            // owned by this process from now on
+            let fd = unsafe { OwnedFd::from_raw_fd(SD_LISTEN_FDS_START) };
+            let tcp = std::net::TcpListener::from(fd);
+            if tcp.local_addr().is_ok() {
+                tcp.set_nonblocking(true)?;
+                serve_tcp(app, tokio::net::TcpListener::from_std(tcp)?).await
+            } else {
+                let unix = std::os::unix::net::UnixListener::from(OwnedFd::from(tcp));
+                unix.set_nonblocking(true)?;
+                tracing::info!("serving on the unix socket passed by systemd");
+                axum::serve(tokio::net::UnixListener::from_std(unix)?, app).await?;
+                Ok(())
+            }
+        }
+    }
+}
+
+async fn serve_tcp(app: Router, listener: tokio::net::TcpListener) -> Res<()> {
+    tracing::info!("serving on {}", listener.local_addr()?);
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
+    Ok(())
+}
+
+/// The reverse proxies whose X-Forwarded-For, X-Forwarded-Proto and X-Forwarded-Host are
+/// believed, set with TRUSTED_PROXIES as a comma separated list of ips and cidrs. Clients of
+/// unix sockets are always trusted since only the proxy in front can reach them
+#[derive(Default)]
+pub struct TrustedProxies(Vec<(IpAddr, u8)>);
+
+static NO_PROXIES: TrustedProxies = TrustedProxies(Vec::new());
+
+impl TrustedProxies {
+    pub fn from_env() -> Res<Self> {
+        let Ok(proxies) = std::env::var("TRUSTED_PROXIES") else {
+            return Ok(Self::default());
+        };
+        let proxies = proxies
+            .split(',')
+            .map(str::trim)
+            .filter(|p| !p.is_empty())
+            .map(|p| parse_cidr(p).ok_or_else(|| format!("invalid TRUSTED_PROXIES entry {p}")))
+            .collect::<Result<_, _>>()?;
+        Ok(Self(proxies))
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.0.iter().any(|&(net, bits)| match (net, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - bits as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - bits as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        })
+    }
+
+    /// Whether the forwarded headers of a request from the peer are believed, no peer being a
+    /// unix socket
+    pub fn trusts(&self, peer: Option<IpAddr>) -> bool {
+        peer.is_none_or(|ip| self.contains(ip))
+    }
+
+    /// The peer, or when it's a trusted proxy the last X-Forwarded-For entry that isn't one
+    pub fn client_ip(&self, peer: Option<IpAddr>, headers: &HeaderMap) -> Option<IpAddr> {
+        if !self.trusts(peer) {
+            return peer;
+        }
+        let forwarded = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|h| h.to_str().ok())
+            .flat_map(|h| h.split(','))
+            .collect::<Vec<_>>();
+        let mut client = peer;
+        for entry in forwarded.into_iter().rev() {
+            let Ok(ip) = entry.trim().parse::<IpAddr>() else {
+                break;
+            };
+            client = Some(ip);
+            if !self.contains(ip) {
+                break;
+            }
+        }
+        client
+    }
+
+    /// The scheme, when a trusted proxy sent it, and the host the client requested
+    pub fn request_host<'a>(
+        &self,
+        peer: Option<IpAddr>,
+        headers: &'a HeaderMap,
+    ) -> (Option<&'a str>, Option<&'a str>) {
+        let header = |name| headers.get(name).and_then(|h| h.to_str().ok());
+        let forwarded = |name| {
+            header(name)
+                .and_then(|h: &str| h.split(',').next())
+                .map(str::trim)
+        };
+        match self.trusts(peer) {
+            true => (
+                forwarded("x-forwarded-proto"),
+                forwarded("x-forwarded-host").or(header(header::HOST.as_str())),
+            ),
+            false => (None, header(header::HOST.as_str())),
+        }
+    }
+
+    /// The proxies of the configuration the request is handled with
+    pub fn of(parts_extensions: &axum::http::Extensions) -> &TrustedProxies {
+        parts_extensions
+            .get::<Arc<Config>>()
+            .map_or(&NO_PROXIES, |c| &c.trusted_proxies)
+    }
+}
+
+fn parse_cidr(cidr: &str) -> Option<(IpAddr, u8)> {
+    let (ip, bits) = match cidr.split_once('/') {
+        Some((ip, bits)) => (ip.parse::<IpAddr>().ok()?, Some(bits.parse::<u8>().ok()?)),
+        None => (cidr.parse::<IpAddr>().ok()?, None),
+    };
+    let max = if ip.is_ipv4() { 32 } else { 128 };
+    let bits = bits.unwrap_or(max);
+    (bits <= max).then_some((ip, bits))
+}
+
+/// The peer address of the connection, none for unix sockets
+pub fn peer_addr(extensions: &axum::http::Extensions) -> Option<IpAddr> {
+    extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip())
+}
+
+/// The ip of the client, the peer address unless it's one of the [`TrustedProxies`]
+pub struct PeerIp(pub IpAddr);
+
+impl<S: Send + Sync> FromRequestParts<S> for PeerIp {
+    type Rejection = (StatusCode, Json<Result<(), String>>);
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        let peer = peer_addr(&parts.extensions);
+        TrustedProxies::of(&parts.extensions)
+            .client_ip(peer, &parts.headers)
+            .map(PeerIp)
+            .ok_or((
+                StatusCode::BAD_REQUEST,
+                Json(Err("missing client address".to_string())),
+            ))
+    }
+}
+
+#[test]
+fn test_client_ip() {
+    let proxies = TrustedProxies(vec![
+        parse_cidr("10.0.0.0/8").unwrap(),
+        parse_cidr("2001:db8::1").unwrap(),
+    ]);
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        "x-forwarded-for",
+        "192.0.2.9, 203.0.113.1, 10.1.2.3".parse().unwrap(),
+    );
+    let client = |peer: &str| proxies.client_ip(Some(peer.parse().unwrap()), &headers);
+    assert_eq!(client("10.0.0.1"), Some(IpAddr::from([203, 0, 113, 1])));
+    assert_eq!(
+        client("198.51.100.2"),
+        Some(IpAddr::from([198, 51, 100, 2]))
+    );
+    assert_eq!(client("2001:db8::1"), Some(IpAddr::from([203, 0, 113, 1])));
+    assert_eq!(
+        TrustedProxies::default().client_ip(None, &headers),
+        Some(IpAddr::from([10, 1, 2, 3]))
+    );
+    assert_eq!(
+        TrustedProxies::default().client_ip(None, &HeaderMap::new()),
+        None
+    );
+    assert!(proxies.contains("::ffff:10.9.9.9".parse().unwrap()));
+    assert_eq!(parse_cidr("10.0.0.0/33"), None);
+}
@@ -0,0 +1,507 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use axum::http::StatusCode;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::SqlitePool;
+use sqlx::prelude::FromRow;
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+use crate::audio::{self, AudioInfo};
+use crate::documents;
+use crate::reencode::{self, ReencodeSettings};
+use crate::scan::Clamd;
+use crate::thumbs::{Thumb, ThumbSettings};
+use crate::{Res, StatusError};
+
+pub struct MediaInfo {
+    pub media_name: String,
+    /// The sha256 of the file, which it's served under
+    pub media_hash: String,
+    pub media_size: i64,
+    pub media_ext: String,
+    pub media_type: String,
+    pub media_w: Option<i64>,
+    pub media_h: Option<i64>,
+    pub media_pages: Option<i64>,
+    pub media_duration: Option<f64>,
+    pub media_bitrate: Option<i64>,
+    pub media_orig_size: Option<i64>,
+    pub thumbs: ThumbInfo,
+}
+pub struct ThumbInfo {
+    pub thumb_name: String,
+    pub thumb_size: i64,
+    pub thumb_type: String,
+    pub thumb_w: i64,
+    pub thumb_h: i64,
+    pub catalog_thumb_name: Option<String>,
+    pub catalog_thumb_w: Option<i64>,
+    pub catalog_thumb_h: Option<i64>,
+    pub thumb_failed: bool,
+}
+
+/// A file streamed to the media directory, removed when dropped unless it was saved. The name
+/// is the one the client sent the file with
+pub struct Upload {
+    pub path: PathBuf,
+    pub size: u64,
+    pub name: Option<String>,
+}
+impl Upload {
+    pub fn temp_path() -> PathBuf {
+        PathBuf::from(format!("media/{}.part", Uuid::new_v4().simple()))
+    }
+    pub async fn from_bytes(data: &[u8]) -> Res<Self> {
+        let path = Self::temp_path();
+        let upload = Upload {
+            size: data.len() as u64,
+            path,
+            name: None,
+        };
+        tokio::fs::write(&upload.path, data).await?;
+        Ok(upload)
+    }
+    /// Rejects the files purged by moderators
+    pub async fn check_blocklist(&self, pool: &SqlitePool) -> Res<()> {
+        let hash = file_hash(&self.path).await?;
+        let blocked: bool =
+            sqlx::query_scalar(r#"SELECT EXISTS(SELECT 1 FROM media_blocklist WHERE hash = ?)"#)
+                .bind(hash)
+                .fetch_one(pool)
+                .await?;
+        if blocked {
+            return Err(StatusError(
+                StatusCode::FORBIDDEN,
+                "this file was removed and can't be posted again".to_string(),
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+impl Drop for Upload {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// The kinds of uploads a board accepts besides images and videos
+#[derive(Clone, Copy, Default)]
+pub struct Accepts {
+    pub documents: bool,
+    pub audio: bool,
+}
+
+/// Scans the upload, creates its thumbnails and moves it to the media directory. Images and
+/// videos that can't be thumbnailed get a placeholder and are flagged with `thumb_failed`, so
+/// they can be thumbnailed again with `/admin/rethumb`
+pub async fn save_media(
+    upload: Upload,
+    thumbs: ThumbSettings,
+    scanner: Option<&Clamd>,
+    reencode: Option<ReencodeSettings>,
+    accepts: Accepts,
+) -> Res<MediaInfo> {
+    let mut upload = upload;
+    if let Some(scanner) = scanner {
+        scanner.scan(&upload.path).await?;
+    }
+    let media_orig_size = match reencode {
+        Some(settings) => reencode::reencode_upload(&mut upload, settings).await?,
+        None => None,
+    };
+    let uuid = Uuid::new_v4().to_string();
+    let (media_kind, media_w, media_h) = inspect_media(&upload.path).await?;
+    let mime = media_kind.mime_type();
+    let media_name = uuid.clone();
+    let is_media = matches!(
+        media_kind.matcher_type(),
+        infer::MatcherType::Image | infer::MatcherType::Video
+    );
+    let is_audio = accepts.audio && audio::is_audio(mime);
+    if !(is_media || is_audio || accepts.documents && documents::is_document(mime)) {
+        return Err(StatusError(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            format!("{mime} files are not supported"),
+        )
+        .into());
+    }
+    let mut media_pages = None;
+    let mut audio_info = AudioInfo::default();
+    let thumbs = if is_media {
+        let info = save_thumbs(&upload.path, mime, thumbs, &uuid)
+            .await
+            .inspect_err(|e| {
+                tracing::warn!("failed to thumbnail {media_name}, using a placeholder: {e}")
+            })
+            .ok();
+        match info {
+            Some(info) => info,
+            None => {
+                let placeholder = thumbs.placeholder()?;
+                write_thumbs(placeholder, &uuid, true).await?
+            }
+        }
+    } else if is_audio {
+        audio_info = audio::probe(&tokio::fs::read(&upload.path).await?, mime);
+        let waveform = audio::thumbnail(&upload.path, thumbs).await?;
+        write_thumbs(waveform, &uuid, false).await?
+    } else {
+        media_pages = documents::page_count(&upload.path, mime).await?;
+        let cover = documents::thumbnail(&upload.path, mime, thumbs).await?;
+        write_thumbs(cover, &uuid, false).await?
+    };
+    let media_size = upload.size as i64;
+    let media_ext = media_kind.extension().to_string();
+    let media_hash = file_hash(&upload.path).await?;
+
+    tokio::fs::rename(&upload.path, format!("media/{media_name}")).await?;
+
+    Ok(MediaInfo {
+        media_name,
+        media_hash,
+        media_size,
+        media_ext,
+        media_type: mime.to_string(),
+        media_w,
+        media_h,
+        media_pages,
+        media_duration: audio_info.duration,
+        media_bitrate: audio_info.bitrate,
+        media_orig_size,
+        thumbs,
+    })
+}
+pub async fn save_thumbs(
+    media_path: &Path,
+    mime: &str,
+    thumbs: ThumbSettings,
+    uuid: &str,
+) -> Res<ThumbInfo> {
+    let thumbs = thumbs
+        .create_blocking(media_path.to_owned(), mime.to_string())
+        .await?;
+    write_thumbs(thumbs, uuid, false).await
+}
+async fn write_thumbs(
+    (thumb, catalog_thumb): (Thumb, Option<Thumb>),
+    uuid: &str,
+    thumb_failed: bool,
+) -> Res<ThumbInfo> {
+    let thumb_name = format!("{uuid}t");
+    let catalog_thumb_name = format!("{uuid}c");
+
+    File::create(format!("media/{thumb_name}"))
+        .await?
+        .write_all(&thumb.data)
+        .await?;
+
+    if let Some(catalog_thumb) = &catalog_thumb {
+        File::create(format!("media/{catalog_thumb_name}"))
+            .await?
+            .write_all(&catalog_thumb.data)
+            .await?;
+    }
+
+    Ok(ThumbInfo {
+        thumb_name,
+        thumb_size: thumb.data.len() as i64,
+        thumb_type: thumb.format.mime_type().to_string(),
+        thumb_w: thumb.width as i64,
+        thumb_h: thumb.height as i64,
+        catalog_thumb_w: catalog_thumb.as_ref().map(|t| t.width as i64),
+        catalog_thumb_h: catalog_thumb.as_ref().map(|t| t.height as i64),
+        catalog_thumb_name: catalog_thumb.map(|_| catalog_thumb_name),
+        thumb_failed,
+    })
+}
+/// Regenerates the thumbnails of a post under new names, returning the names of the old ones
+pub async fn regenerate_thumbs(
+    pool: &SqlitePool,
+    post_id: i64,
+    media_name: &str,
+    thumbs: ThumbSettings,
+) -> Res<[Option<String>; 2]> {
+    let media_path = PathBuf::from(format!("media/{media_name}"));
+    let (media_kind, media_w, media_h) = inspect_media(&media_path).await?;
+    let uuid = Uuid::new_v4().to_string();
+    let info = save_thumbs(&media_path, media_kind.mime_type(), thumbs, &uuid).await?;
+
+    let mut tx = pool.begin().await?;
+    let old: Option<(Option<String>, Option<String>)> = sqlx::query_as(
+        r#"SELECT thumb_name, catalog_thumb_name FROM comments WHERE id = ? AND media_name = ?"#,
+    )
+    .bind(post_id)
+    .bind(media_name)
+    .fetch_optional(&mut *tx)
+    .await?;
+    sqlx::query(
+        r#"
+        UPDATE comments
+        SET thumb_name = ?, thumb_size = ?, thumb_type = ?, thumb_w = ?, thumb_h = ?,
+            catalog_thumb_name = ?, catalog_thumb_w = ?, catalog_thumb_h = ?, thumb_failed = FALSE,
+            media_w = COALESCE(media_w, ?), media_h = COALESCE(media_h, ?)
+        WHERE id = ?
+        "#,
+    )
+    .bind(&info.thumb_name)
+    .bind(info.thumb_size)
+    .bind(&info.thumb_type)
+    .bind(info.thumb_w)
+    .bind(info.thumb_h)
+    .bind(&info.catalog_thumb_name)
+    .bind(info.catalog_thumb_w)
+    .bind(info.catalog_thumb_h)
+    .bind(media_w)
+    .bind(media_h)
+    .bind(post_id)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    match old {
+        Some((thumb_name, catalog_thumb_name)) => Ok([thumb_name, catalog_thumb_name]),
+        None => {
+            // the post was deleted in the meantime
+            for name in [Some(info.thumb_name), info.catalog_thumb_name]
+                .into_iter()
+                .flatten()
+            {
+                remove_media(&name).await?;
+            }
+            Ok([None, None])
+        }
+    }
+}
+
+/// Infers the type of the media and reads its dimensions, only the headers are read and
+/// videos have no dimensions without decoding a frame
+pub async fn inspect_media(path: &Path) -> Res<(infer::Type, Option<i64>, Option<i64>)> {
+    let path = path.to_owned();
+    let (media_kind, dimensions) = tokio::task::spawn_blocking(move || {
+        let dimensions = image::io::Reader::open(&path)
+            .and_then(|reader| reader.with_guessed_format())
+            .ok()
+            .and_then(|reader| reader.into_dimensions().ok());
+        infer::get_from_path(&path).map(|kind| (kind, dimensions))
+    })
+    .await??;
+    let media_kind = media_kind.ok_or("Failed to infer media type")?;
+    let (media_w, media_h) =
+        dimensions.map_or((None, None), |(w, h)| (Some(w as i64), Some(h as i64)));
+    Ok((media_kind, media_w, media_h))
+}
+/// The media and thumbnails still referenced by a post, and the banners of the boards
+pub async fn fetch_media_names(pool: &SqlitePool) -> Res<HashSet<String>> {
+    let names: Vec<(Option<String>, Option<String>, Option<String>)> = sqlx::query_as(
+        r#"
+        SELECT media_name, thumb_name, catalog_thumb_name FROM comments
+        UNION ALL SELECT media_name, NULL, NULL FROM banners
+        "#,
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(names
+        .into_iter()
+        .flat_map(|(m, t, c)| [m, t, c])
+        .flatten()
+        .collect())
+}
+/// Names of files in the media directory, which can't be hidden files, the uploads still being
+/// written or point to another directory
+pub fn is_media_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 255
+        && !name.starts_with('.')
+        && !name.ends_with(".part")
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_')
+}
+
+/// Hashes the media uploaded before the files were served by their hash, the missing files
+/// are skipped
+pub async fn backfill_hashes(pool: &SqlitePool) -> Res<u64> {
+    let names: Vec<(i64, String)> = sqlx::query_as(
+        r#"SELECT id, media_name FROM comments WHERE media_name IS NOT NULL AND media_hash IS NULL"#,
+    )
+    .fetch_all(pool)
+    .await?;
+    let mut hashed = 0;
+    for (id, name) in names {
+        let Ok(hash) = file_hash(format!("media/{name}").as_ref()).await else {
+            continue;
+        };
+        sqlx::query(r#"UPDATE comments SET media_hash = ? WHERE id = ?"#)
+            .bind(hash)
+            .bind(id)
+            .execute(pool)
+            .await?;
+        hashed += 1;
+    }
+    Ok(hashed)
+}
+
+/// The hash and extension of a content addressed name, `{sha256}.{ext}`
+pub fn split_hashed_name(name: &str) -> Option<(&str, &str)> {
+    let (hash, ext) = name.split_once('.')?;
+    let is_ext = !ext.is_empty() && ext.bytes().all(|b| b.is_ascii_alphanumeric());
+    (is_sha256(hash) && is_ext).then_some((hash, ext))
+}
+
+fn is_sha256(hash: &str) -> bool {
+    hash.len() == 64 && hash.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// A file already stored, by the `{hash}.{ext}` name it's served under
+#[derive(Serialize, Deserialize, FromRow)]
+pub struct StoredMedia {
+    pub media: String,
+    pub media_type: String,
+    pub media_size: i64,
+}
+
+/// The stored file with the sha256, so clients can post it without uploading it again
+pub async fn find_by_hash(pool: &SqlitePool, hash: &str) -> Res<StoredMedia> {
+    let hash = hash.to_ascii_lowercase();
+    if !is_sha256(&hash) {
+        return Err("invalid sha256".into());
+    }
+    let stored = sqlx::query_as(
+        r#"
+        SELECT media_hash || '.' || media_ext AS media, media_type, media_size FROM comments
+        WHERE media_hash = ? AND NOT is_held LIMIT 1
+        "#,
+    )
+    .bind(hash)
+    .fetch_optional(pool)
+    .await?;
+    stored.ok_or_else(|| StatusError(StatusCode::NOT_FOUND, "media not found".to_string()).into())
+}
+
+/// The file of the post, sent in the body or the stored file named by [`find_by_hash`]. The
+/// stored file is copied to a new upload, so it goes through the checks of the board again
+pub async fn attach_stored(
+    pool: &SqlitePool,
+    file: Option<Upload>,
+    media_ref: Option<&str>,
+) -> Res<Option<Upload>> {
+    let Some(media_ref) = media_ref else {
+        return Ok(file);
+    };
+    if file.is_some() {
+        return Err("send either media or media_ref".into());
+    }
+    let (hash, ext) = split_hashed_name(media_ref).ok_or("invalid media_ref")?;
+    let stored: Option<(String, i64)> = sqlx::query_as(
+        r#"
+        SELECT media_name, media_size FROM comments
+        WHERE media_hash = ? AND media_ext = ? AND NOT is_held LIMIT 1
+        "#,
+    )
+    .bind(hash)
+    .bind(ext)
+    .fetch_optional(pool)
+    .await?;
+    let Some((media_name, media_size)) = stored else {
+        return Err(StatusError(StatusCode::NOT_FOUND, "media not found".to_string()).into());
+    };
+    let upload = Upload {
+        path: Upload::temp_path(),
+        size: media_size as u64,
+        name: None,
+    };
+    tokio::fs::copy(format!("media/{media_name}"), &upload.path).await?;
+    Ok(Some(upload))
+}
+
+/// The path of the media file, none when the name isn't a media name or the file resolves
+/// outside of the media directory, through a symlink
+pub async fn media_path(name: &str) -> Option<PathBuf> {
+    if !is_media_name(name) {
+        return None;
+    }
+    let dir = tokio::fs::canonicalize("media").await.ok()?;
+    let path = tokio::fs::canonicalize(dir.join(name)).await.ok()?;
+    (path.parent() == Some(&dir)).then_some(path)
+}
+
+pub async fn remove_media(name: &str) -> Res<()> {
+    match tokio::fs::remove_file(format!("media/{name}")).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Overwrites the file with zeros before removing it, so the content doesn't linger on disk
+pub async fn shred_media(name: &str) -> Res<()> {
+    let path = format!("media/{name}");
+    let mut file = match tokio::fs::OpenOptions::new().write(true).open(&path).await {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    let zeros = vec![0; 64 * 1024];
+    let mut left = file.metadata().await?.len();
+    while left > 0 {
+        let n = left.min(zeros.len() as u64);
+        file.write_all(&zeros[..n as usize]).await?;
+        left -= n;
+    }
+    file.sync_all().await?;
+    drop(file);
+    remove_media(name).await
+}
+/// The sha256 of the stored media, none if the file is already gone like [`remove_media`]
+pub async fn stored_hash(name: &str) -> Res<Option<String>> {
+    match file_hash(format!("media/{name}").as_ref()).await {
+        Ok(hash) => Ok(Some(hash)),
+        Err(e) => match e.downcast_ref::<std::io::Error>() {
+            Some(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            _ => Err(e),
+        },
+    }
+}
+/// The sha256 of the file, read in chunks on the blocking pool like the thumbnails so large
+/// videos aren't held in memory
+pub async fn file_hash(path: &Path) -> Res<String> {
+    let path = path.to_owned();
+    let digest = tokio::task::spawn_blocking(move || -> std::io::Result<_> {
+        let mut hasher = Sha256::new();
+        std::io::copy(&mut std::fs::File::open(path)?, &mut hasher)?;
+        Ok(hasher.finalize())
+    })
+    .await??;
+    Ok(digest.iter().map(|b| format!("{b:02x}")).collect())
+}
+
+#[test]
+fn test_is_media_name() {
+    assert!(is_media_name("0b9f6c1e-4a52-4d8e-9f0a-3c2d1b0a9e8ft"));
+    assert!(is_media_name("bg.png"));
+    assert!(!is_media_name(""));
+    assert!(!is_media_name(".."));
+    assert!(!is_media_name("../db.sqlite"));
+    assert!(!is_media_name("..\\db.sqlite"));
+    assert!(!is_media_name("/etc/passwd"));
+    assert!(!is_media_name("a/../../db.sqlite"));
+    assert!(!is_media_name("%2e%2e%2fdb.sqlite"));
+    assert!(!is_media_name("a\0.png"));
+    assert!(!is_media_name("0b9f6c1e4a524d8e9f0a3c2d1b0a9e8f.part"));
+}
+
+#[tokio::test]
+async fn test_file_hash() {
+    let path = std::env::temp_dir().join(format!("blu-hash-{}", Uuid::new_v4()));
+    std::fs::write(&path, b"abc").unwrap();
+    let hash = file_hash(&path).await;
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(
+        hash.unwrap(),
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+    assert_eq!(stored_hash("gone.png").await.unwrap(), None);
+}
@@ -0,0 +1,363 @@
+//! Staff login through an OpenID Connect or OAuth2 provider, enabled with OIDC_CLIENT_ID. The
+//! endpoints are discovered from OIDC_ISSUER or set with OIDC_AUTH_URL, OIDC_TOKEN_URL and
+//! OIDC_USERINFO_URL, and the role comes from the OIDC_ROLE_CLAIM of the userinfo claims.
+//! Sessions are kept in memory, staff log in again after a restart. New sessions only grant
+//! their role once verified with a one time password
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use axum::http::StatusCode;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tokio::sync::OnceCell;
+use uuid::Uuid;
+
+use crate::privacy::hash_token;
+use crate::{Res, StatusError};
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long the provider has to send the staff back to the callback
+const LOGIN_TIMEOUT: i64 = 10 * 60;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum StaffRole {
+    Mod,
+    Admin,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct StaffSession {
+    pub role: StaffRole,
+    pub subject: String,
+    pub name: String,
+    pub expires_at: i64,
+    /// Whether the session was verified with a one time password
+    pub verified: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct StaffLogin {
+    #[serde(flatten)]
+    pub staff: StaffSession,
+    pub session: String,
+    /// Whether the staff has to verify the session with their authenticator, or enroll one
+    pub totp_enabled: bool,
+}
+
+#[derive(Deserialize)]
+struct Endpoints {
+    authorization_endpoint: String,
+    token_endpoint: String,
+    userinfo_endpoint: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+}
+
+/// A login sent to the provider and not back yet
+struct PendingLogin {
+    verifier: String,
+    created_at: i64,
+}
+
+pub struct Oidc {
+    issuer: Option<String>,
+    endpoints: OnceCell<Endpoints>,
+    client_id: String,
+    client_secret: Option<String>,
+    redirect_url: String,
+    scopes: String,
+    role_claim: String,
+    admin_roles: Vec<String>,
+    mod_roles: Vec<String>,
+    session_ttl: i64,
+    client: reqwest::Client,
+    pending: Mutex<HashMap<String, PendingLogin>>,
+    sessions: Mutex<HashMap<String, StaffSession>>,
+}
+
+impl Oidc {
+    pub fn from_env() -> Res<Option<Self>> {
+        let Some(client_id) = env_var("OIDC_CLIENT_ID") else {
+            return Ok(None);
+        };
+        let endpoints = OnceCell::new();
+        if let (Some(auth), Some(token), Some(userinfo)) = (
+            env_var("OIDC_AUTH_URL"),
+            env_var("OIDC_TOKEN_URL"),
+            env_var("OIDC_USERINFO_URL"),
+        ) {
+            let _ = endpoints.set(Endpoints {
+                authorization_endpoint: auth,
+                token_endpoint: token,
+                userinfo_endpoint: userinfo,
+            });
+        }
+        let issuer = env_var("OIDC_ISSUER");
+        if issuer.is_none() && !endpoints.initialized() {
+            return Err("OIDC_CLIENT_ID needs OIDC_ISSUER or the OIDC_*_URL endpoints".into());
+        }
+        let roles = |name| -> Vec<String> {
+            env_var(name)
+                .map(|r| r.split(',').map(|r| r.trim().to_string()).collect())
+                .unwrap_or_default()
+        };
+        Ok(Some(Self {
+            issuer,
+            endpoints,
+            client_id,
+            client_secret: env_var("OIDC_CLIENT_SECRET"),
+            redirect_url: env_var("OIDC_REDIRECT_URL").ok_or("OIDC_REDIRECT_URL is required")?,
+            scopes: env_var("OIDC_SCOPES").unwrap_or("openid profile".to_string()),
+            role_claim: env_var("OIDC_ROLE_CLAIM").unwrap_or("groups".to_string()),
+            admin_roles: roles("OIDC_ADMIN_ROLES"),
+            mod_roles: roles("OIDC_MOD_ROLES"),
+            session_ttl: env_var("STAFF_SESSION_TTL")
+                .and_then(|t| t.parse().ok())
+                .unwrap_or(12 * 60 * 60),
+            client: reqwest::Client::builder()
+                .timeout(FETCH_TIMEOUT)
+                .user_agent(concat!("blu/", env!("CARGO_PKG_VERSION")))
+                .build()?,
+            pending: Mutex::default(),
+            sessions: Mutex::default(),
+        }))
+    }
+
+    async fn endpoints(&self) -> Res<&Endpoints> {
+        self.endpoints
+            .get_or_try_init(async || {
+                let issuer = self.issuer.as_deref().unwrap_or_default();
+                let url = format!(
+                    "{}/.well-known/openid-configuration",
+                    issuer.trim_end_matches('/')
+                );
+                let text = self.client.get(url).send().await?.error_for_status()?;
+                Ok(serde_json::from_str(&text.text().await?)?)
+            })
+            .await
+    }
+
+    /// The authorization url to send the staff to, with the state and the PKCE challenge of
+    /// a new login
+    pub async fn login_url(&self) -> Res<String> {
+        let endpoints = self.endpoints().await?;
+        let state = Uuid::new_v4().simple().to_string();
+        let verifier = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(&verifier));
+        let url = Url::parse_with_params(
+            &endpoints.authorization_endpoint,
+            [
+                ("response_type", "code"),
+                ("client_id", &self.client_id),
+                ("redirect_uri", &self.redirect_url),
+                ("scope", &self.scopes),
+                ("state", &state),
+                ("code_challenge", &challenge),
+                ("code_challenge_method", "S256"),
+            ],
+        )?;
+        let now = unix_time();
+        let mut pending = self.pending.lock().unwrap();
+        pending.retain(|_, login| login.created_at > now - LOGIN_TIMEOUT);
+        pending.insert(
+            state,
+            PendingLogin {
+                verifier,
+                created_at: now,
+            },
+        );
+        Ok(url.to_string())
+    }
+
+    /// Exchanges the code the provider sent back for the claims of the staff, and starts a
+    /// session with the role they map to
+    pub async fn callback(&self, code: &str, state: &str) -> Res<StaffLogin> {
+        let login = self
+            .pending
+            .lock()
+            .unwrap()
+            .remove(state)
+            .filter(|login| login.created_at > unix_time() - LOGIN_TIMEOUT);
+        let Some(login) = login else {
+            let message = "the login expired or was already used".to_string();
+            return Err(StatusError(StatusCode::BAD_REQUEST, message).into());
+        };
+        let endpoints = self.endpoints().await?;
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &self.redirect_url),
+            ("client_id", &self.client_id),
+            ("code_verifier", &login.verifier),
+        ];
+        if let Some(secret) = &self.client_secret {
+            form.push(("client_secret", secret));
+        }
+        let res = self
+            .client
+            .post(&endpoints.token_endpoint)
+            .header(reqwest::header::ACCEPT, "application/json")
+            .form(&form)
+            .send()
+            .await?
+            .error_for_status()?;
+        let token: TokenResponse = serde_json::from_str(&res.text().await?)?;
+        let res = self
+            .client
+            .get(&endpoints.userinfo_endpoint)
+            .bearer_auth(token.access_token)
+            .header(reqwest::header::ACCEPT, "application/json")
+            .send()
+            .await?
+            .error_for_status()?;
+        let claims: Value = serde_json::from_str(&res.text().await?)?;
+
+        let Some(role) = map_role(
+            &claims,
+            &self.role_claim,
+            &self.admin_roles,
+            &self.mod_roles,
+        ) else {
+            let message = "the account has no staff role".to_string();
+            return Err(StatusError(StatusCode::FORBIDDEN, message).into());
+        };
+        let claim = |names: &[&str]| names.iter().find_map(|name| claim_string(&claims[name]));
+        let subject = claim(&["sub", "id"]).ok_or("the userinfo has no subject")?;
+        let staff = StaffSession {
+            role,
+            name: claim(&["preferred_username", "login", "name"]).unwrap_or(subject.clone()),
+            subject,
+            expires_at: unix_time() + self.session_ttl,
+            verified: false,
+        };
+        let session = Uuid::new_v4().simple().to_string();
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|_, s| s.expires_at > unix_time());
+        sessions.insert(hash_token(&session), staff.clone());
+        Ok(StaffLogin {
+            staff,
+            session,
+            totp_enabled: false,
+        })
+    }
+
+    /// The staff of a session that didn't expire
+    pub fn session(&self, session: &str) -> Option<StaffSession> {
+        let sessions = self.sessions.lock().unwrap();
+        let staff = sessions.get(&hash_token(session))?;
+        Some(staff.clone()).filter(|s| s.expires_at > unix_time())
+    }
+
+    /// Marks the session as verified, once its one time password was checked
+    pub fn verify(&self, session: &str) -> Option<StaffSession> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let staff = sessions.get_mut(&hash_token(session))?;
+        staff.verified = true;
+        Some(staff.clone())
+    }
+
+    pub fn logout(&self, session: &str) -> bool {
+        self.revoke(&hash_token(session))
+    }
+
+    /// The sessions that didn't expire, by the hash of their token
+    pub fn sessions(&self) -> Vec<(String, StaffSession)> {
+        let sessions = self.sessions.lock().unwrap();
+        let mut sessions: Vec<_> = sessions
+            .iter()
+            .filter(|(_, s)| s.expires_at > unix_time())
+            .map(|(id, s)| (id.clone(), s.clone()))
+            .collect();
+        sessions.sort_by_key(|(_, s)| -s.expires_at);
+        sessions
+    }
+
+    /// Logs out the session with the hash
+    pub fn revoke(&self, id: &str) -> bool {
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.remove(id).is_some()
+    }
+
+    /// Logs out every session of the staff, or of all staff
+    pub fn revoke_all(&self, subject: Option<&str>) -> usize {
+        let mut sessions = self.sessions.lock().unwrap();
+        let before = sessions.len();
+        sessions.retain(|_, s| subject.is_some_and(|subject| s.subject != subject));
+        before - sessions.len()
+    }
+}
+
+/// The highest role the claim grants, the claim is a dotted path into the claims, such as
+/// `realm_access.roles`, to a string or an array of strings
+pub fn map_role(
+    claims: &Value,
+    claim: &str,
+    admin_roles: &[String],
+    mod_roles: &[String],
+) -> Option<StaffRole> {
+    let value = claim.split('.').fold(claims, |value, key| &value[key]);
+    let values: Vec<String> = match value {
+        Value::Array(values) => values.iter().filter_map(claim_string).collect(),
+        value => claim_string(value).into_iter().collect(),
+    };
+    let has = |roles: &[String]| values.iter().any(|v| roles.contains(v));
+    match (has(admin_roles), has(mod_roles)) {
+        (true, _) => Some(StaffRole::Admin),
+        (false, true) => Some(StaffRole::Mod),
+        _ => None,
+    }
+}
+
+fn claim_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+fn unix_time() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64)
+}
+
+fn env_var(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|v| !v.is_empty())
+}
+
+#[test]
+fn test_map_role() {
+    let admins = ["blu-admins".to_string()];
+    let mods = ["blu-mods".to_string(), "1234".to_string()];
+    let claims = serde_json::json!({
+        "sub": "a",
+        "groups": ["users", "blu-mods"],
+        "realm_access": {"roles": ["blu-admins"]},
+        "id": 1234,
+    });
+    assert_eq!(
+        map_role(&claims, "groups", &admins, &mods),
+        Some(StaffRole::Mod)
+    );
+    assert_eq!(
+        map_role(&claims, "realm_access.roles", &admins, &mods),
+        Some(StaffRole::Admin)
+    );
+    assert_eq!(
+        map_role(&claims, "id", &admins, &mods),
+        Some(StaffRole::Mod)
+    );
+    assert_eq!(map_role(&claims, "sub", &admins, &mods), None);
+    assert_eq!(map_role(&claims, "missing.path", &admins, &mods), None);
+}
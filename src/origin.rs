@@ -0,0 +1,131 @@
+use std::sync::Arc;
+
+use axum::Json;
+use axum::body::Body;
+use axum::extract::State;
+use axum::http::{HeaderMap, Request, StatusCode, header};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+
+use crate::listen::{self, TrustedProxies};
+
+/// The origins browsers may post from, set with POST_ORIGINS as a comma separated list, the
+/// origin of the api itself is always allowed
+pub struct AllowedOrigins(Vec<String>);
+
+impl AllowedOrigins {
+    pub fn from_env() -> Option<Arc<Self>> {
+        let origins = std::env::var("POST_ORIGINS")
+            .ok()
+            .filter(|o| !o.is_empty())?;
+        let origins = origins
+            .split(',')
+            .map(|o| o.trim().trim_end_matches('/').to_string())
+            .collect();
+        Some(Arc::new(Self(origins)))
+    }
+
+    /// Requests without Origin and Referer don't come from a browser and are let through. The
+    /// origin of the api is the scheme and host the client requested, as forwarded by a trusted
+    /// proxy
+    fn allows(&self, headers: &HeaderMap, (scheme, host): (Option<&str>, Option<&str>)) -> bool {
+        let Some(origin) = request_origin(headers) else {
+            return true;
+        };
+        self.0.contains(&origin)
+            || host.is_some_and(|host| {
+                origin
+                    .split_once("://")
+                    .is_some_and(|(s, h)| h == host && scheme.is_none_or(|scheme| s == scheme))
+            })
+    }
+}
+
+/// Rejects the posts sent by pages of other origins, so they can't post on behalf of visitors
+pub async fn verify_origin(
+    State(origins): State<Arc<AllowedOrigins>>,
+    req: Request<Body>,
+    next: Next,
+) -> Response {
+    let peer = listen::peer_addr(req.extensions());
+    let requested = TrustedProxies::of(req.extensions()).request_host(peer, req.headers());
+    if !origins.allows(req.headers(), requested) {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(Err::<(), _>("origin not allowed".to_string())),
+        )
+            .into_response();
+    }
+    next.run(req).await
+}
+
+fn request_origin(headers: &HeaderMap) -> Option<String> {
+    if let Some(origin) = headers.get(header::ORIGIN) {
+        return Some(origin.to_str().unwrap_or("null").to_string());
+    }
+    let referer = headers.get(header::REFERER)?.to_str().ok()?;
+    let (scheme, rest) = referer.split_once("://")?;
+    let host = rest.split(['/', '?', '#']).next()?;
+    Some(format!("{scheme}://{host}"))
+}
+
+#[test]
+fn test_allows() {
+    let origins = AllowedOrigins(vec!["https://front.example".to_string()]);
+    let headers = |pairs: &[(header::HeaderName, &str)]| {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(name, value.parse().unwrap());
+        }
+        headers
+    };
+    assert!(origins.allows(&headers(&[]), (None, None)));
+    assert!(origins.allows(
+        &headers(&[(header::ORIGIN, "https://front.example")]),
+        (None, None)
+    ));
+    assert!(origins.allows(
+        &headers(&[
+            (header::ORIGIN, "https://api.example"),
+            (header::HOST, "api.example"),
+        ]),
+        (None, Some("api.example"))
+    ));
+    assert!(origins.allows(
+        &headers(&[(header::REFERER, "https://front.example/g/thread/1")]),
+        (None, None)
+    ));
+    assert!(!origins.allows(
+        &headers(&[(header::ORIGIN, "https://evil.example")]),
+        (None, None)
+    ));
+    assert!(!origins.allows(&headers(&[(header::ORIGIN, "null")]), (None, None)));
+    assert!(!origins.allows(
+        &headers(&[(header::REFERER, "https://evil.example/")]),
+        (None, None)
+    ));
+    let api = headers(&[(header::ORIGIN, "https://api.example")]);
+    assert!(origins.allows(&api, (Some("https"), Some("api.example"))));
+    assert!(!origins.allows(&api, (Some("http"), Some("api.example"))));
+    assert!(!origins.allows(&api, (None, Some("internal:3000"))));
+    let proxies = TrustedProxies::default();
+    let forwarded = headers(&[
+        (header::HOST, "internal:3000"),
+        (
+            header::HeaderName::from_static("x-forwarded-host"),
+            "api.example",
+        ),
+        (
+            header::HeaderName::from_static("x-forwarded-proto"),
+            "https",
+        ),
+    ]);
+    assert_eq!(
+        proxies.request_host(None, &forwarded),
+        (Some("https"), Some("api.example"))
+    );
+    assert_eq!(
+        proxies.request_host(Some([192, 0, 2, 1].into()), &forwarded),
+        (None, Some("internal:3000"))
+    );
+}
@@ -0,0 +1,183 @@
+//! Hashcash style proof of work asked of the posters of the boards with `pow_difficulty`, as an
+//! alternative to captchas. The challenge is signed with the ip salt for the board and ip hash it
+//! was issued to, the poster finds a nonce such that the sha256 of `{challenge}:{nonce}` starts
+//! with `difficulty` zero bits. Each post of the ip hash in the last ten minutes adds `pow_step`
+//! bits, so flooding gets slower the more it's done
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use axum::http::StatusCode;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::SqlitePool;
+
+use crate::{Res, StatusError};
+
+/// How long a challenge can be solved for, and how far back the posts are counted
+const TTL: i64 = 10 * 60;
+const MAX_DIFFICULTY: i64 = 32;
+
+/// The proof of work setting of a board
+#[derive(Clone, Copy)]
+pub struct PowPolicy {
+    /// The zero bits asked of everyone, none disables the proof of work
+    pub difficulty: i64,
+    /// The bits added for each recent post of the ip hash
+    pub step: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Challenge {
+    pub challenge: String,
+    pub difficulty: i64,
+    pub expires_at: i64,
+}
+
+/// The bits the ip hash has to solve for now
+pub async fn difficulty(pool: &SqlitePool, policy: PowPolicy, ip_hash: &str) -> Res<i64> {
+    if policy.difficulty == 0 {
+        return Ok(0);
+    }
+    let recent: i64 = sqlx::query_scalar(
+        r#"SELECT COUNT(*) FROM comments WHERE ip_hash = ? AND created_at > strftime('%s', 'now') - ?"#,
+    )
+    .bind(ip_hash)
+    .bind(TTL)
+    .fetch_one(pool)
+    .await?;
+    Ok((policy.difficulty + recent * policy.step).min(MAX_DIFFICULTY))
+}
+
+pub async fn issue(
+    pool: &SqlitePool,
+    secret: &str,
+    policy: PowPolicy,
+    board: &str,
+    ip_hash: &str,
+) -> Res<Challenge> {
+    let difficulty = difficulty(pool, policy, ip_hash).await?;
+    let expires_at = now()? + TTL;
+    let mut nonce = [0; 16];
+    SystemRandom::new()
+        .fill(&mut nonce)
+        .map_err(|_| "failed to generate a challenge")?;
+    let payload = format!(
+        "{expires_at}.{difficulty}.{}.{board}",
+        URL_SAFE_NO_PAD.encode(nonce)
+    );
+    let tag = hmac::sign(&key(secret), signed(&payload, ip_hash).as_bytes());
+    Ok(Challenge {
+        challenge: format!("{payload}.{}", URL_SAFE_NO_PAD.encode(tag)),
+        difficulty,
+        expires_at,
+    })
+}
+
+/// Checks the solution of a challenge issued to the ip hash for the board, which can only be
+/// used once and has to be at least as hard as a new one would be
+pub async fn verify(
+    pool: &SqlitePool,
+    secret: &str,
+    policy: PowPolicy,
+    board: &str,
+    ip_hash: &str,
+    solution: (Option<&str>, Option<&str>),
+) -> Res<()> {
+    if policy.difficulty == 0 {
+        return Ok(());
+    }
+    let (Some(challenge), Some(nonce)) = solution else {
+        return Err(rejected(
+            "a proof of work is required, get a challenge from /pow",
+        ));
+    };
+    let (expires_at, difficulty, challenge_board) =
+        open(secret, challenge, ip_hash).ok_or_else(|| rejected("invalid challenge"))?;
+    if challenge_board != board {
+        return Err(rejected("the challenge is for another board"));
+    }
+    if expires_at <= now()? {
+        return Err(rejected("the challenge expired"));
+    }
+    if difficulty < self::difficulty(pool, policy, ip_hash).await? {
+        return Err(rejected("the challenge is too easy now, get a new one"));
+    }
+    if nonce.len() > 64 || leading_zeros(&format!("{challenge}:{nonce}")) < difficulty as u32 {
+        return Err(rejected("invalid proof of work"));
+    }
+    sqlx::query(r#"DELETE FROM pow_solutions WHERE expires_at <= strftime('%s', 'now')"#)
+        .execute(pool)
+        .await?;
+    let saved = sqlx::query(
+        r#"INSERT INTO pow_solutions (challenge, expires_at) VALUES (?, ?) ON CONFLICT DO NOTHING"#,
+    )
+    .bind(challenge)
+    .bind(expires_at)
+    .execute(pool)
+    .await?;
+    if saved.rows_affected() == 0 {
+        return Err(rejected("the challenge was already used"));
+    }
+    Ok(())
+}
+
+/// The expiry, difficulty and board of a challenge signed for the ip hash
+fn open(secret: &str, challenge: &str, ip_hash: &str) -> Option<(i64, i64, String)> {
+    let (payload, tag) = challenge.rsplit_once('.')?;
+    let tag = URL_SAFE_NO_PAD.decode(tag).ok()?;
+    hmac::verify(&key(secret), signed(payload, ip_hash).as_bytes(), &tag).ok()?;
+    let mut fields = payload.splitn(4, '.');
+    let expires_at = fields.next()?.parse().ok()?;
+    let difficulty = fields.next()?.parse().ok()?;
+    let board = fields.nth(1)?.to_string();
+    Some((expires_at, difficulty, board))
+}
+
+fn leading_zeros(data: &str) -> u32 {
+    let mut zeros = 0;
+    for byte in Sha256::digest(data) {
+        zeros += byte.leading_zeros();
+        if byte != 0 {
+            break;
+        }
+    }
+    zeros
+}
+
+fn key(secret: &str) -> hmac::Key {
+    hmac::Key::new(hmac::HMAC_SHA256, format!("pow:{secret}").as_bytes())
+}
+
+fn signed(payload: &str, ip_hash: &str) -> String {
+    format!("{payload}|{ip_hash}")
+}
+
+fn now() -> Res<i64> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64)
+}
+
+fn rejected(message: &str) -> Box<dyn std::error::Error> {
+    StatusError(StatusCode::FORBIDDEN, message.to_string()).into()
+}
+
+#[test]
+fn test_challenge() {
+    let payload = "1900000000.8.bm9uY2U.g";
+    let tag = hmac::sign(&key("salt"), signed(payload, "hash").as_bytes());
+    let challenge = format!("{payload}.{}", URL_SAFE_NO_PAD.encode(tag));
+    assert_eq!(
+        open("salt", &challenge, "hash"),
+        Some((1900000000, 8, "g".to_string()))
+    );
+    assert_eq!(open("salt", &challenge, "other"), None);
+    assert_eq!(open("other", &challenge, "hash"), None);
+    let nonce = (0..)
+        .find(|n| leading_zeros(&format!("{challenge}:{n}")) >= 8)
+        .unwrap();
+    assert!(leading_zeros(&format!("{challenge}:{}", nonce + 1)) < 32);
+    assert_eq!(leading_zeros(""), 0);
+}
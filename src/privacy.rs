@@ -0,0 +1,108 @@
+use std::net::IpAddr;
+
+use axum::http::{HeaderMap, header};
+use sha2::{Digest, Sha256};
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+use crate::Res;
+
+/// Uses the IP_SALT env var, or a random salt generated on the first run and kept in the database,
+/// so hashes stay stable across restarts
+pub async fn load_ip_salt(pool: &SqlitePool) -> Res<String> {
+    if let Ok(salt) = std::env::var("IP_SALT")
+        && !salt.is_empty()
+    {
+        return Ok(salt);
+    }
+    sqlx::query(r#"INSERT OR IGNORE INTO settings (name, value) VALUES ('ip_salt', ?)"#)
+        .bind(Uuid::new_v4().simple().to_string())
+        .execute(pool)
+        .await?;
+    sqlx::query_scalar(r#"SELECT value FROM settings WHERE name = 'ip_salt'"#)
+        .fetch_one(pool)
+        .await
+        .map_err(|e| e.into())
+}
+
+pub fn hash_ip(salt: &str, ip: IpAddr) -> String {
+    let digest = Sha256::new()
+        .chain_update(salt)
+        .chain_update(ip.to_canonical().to_string())
+        .finalize();
+    digest.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// The cookie posters are recognized by, an opaque random token only stored as a salted hash
+pub const POSTER_COOKIE: &str = "blu_poster";
+
+/// The poster token of the request cookies, if it's one the server could have issued
+pub fn poster_token(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|c| c.trim().split_once('='))
+        .find(|(name, _)| *name == POSTER_COOKIE)
+        .map(|(_, token)| token.to_string())
+        .filter(|t| t.len() == 32 && t.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')))
+}
+
+pub fn poster_cookie(token: &str, max_age: i64) -> String {
+    format!("{POSTER_COOKIE}={token}; Path=/; Max-Age={max_age}; HttpOnly; SameSite=Lax")
+}
+
+pub fn hash_poster_token(salt: &str, token: &str) -> String {
+    let digest = Sha256::new()
+        .chain_update(salt)
+        .chain_update("poster:")
+        .chain_update(token)
+        .finalize();
+    digest.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Sessions and tokens are kept hashed, so the tables can't be used to log in
+pub fn hash_token(token: &str) -> String {
+    let digest = Sha256::digest(token);
+    digest.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Forgets the ip and poster hashes of posts older than the retention period
+pub async fn purge_ip_hashes(pool: &SqlitePool, retention: i64) -> Res<u64> {
+    let purged = sqlx::query(
+        r#"
+        UPDATE comments SET ip_hash = NULL, poster_hash = NULL
+        WHERE (ip_hash IS NOT NULL OR poster_hash IS NOT NULL)
+        AND created_at < strftime('%s', 'now') - ?
+        "#,
+    )
+    .bind(retention)
+    .execute(pool)
+    .await?;
+    Ok(purged.rows_affected())
+}
+
+#[test]
+fn test_hash_ip() {
+    let v4 = "10.0.0.1".parse().unwrap();
+    let mapped = "::ffff:10.0.0.1".parse().unwrap();
+    assert_eq!(hash_ip("salt", v4), hash_ip("salt", mapped));
+    assert_ne!(hash_ip("salt", v4), hash_ip("pepper", v4));
+    assert_eq!(hash_ip("salt", v4).len(), 64);
+}
+
+#[test]
+fn test_poster_token() {
+    let token = "0123456789abcdef0123456789abcdef";
+    let mut headers = HeaderMap::new();
+    let cookie = format!("theme=dark; {POSTER_COOKIE}={token}");
+    headers.insert(header::COOKIE, cookie.parse().unwrap());
+    assert_eq!(poster_token(&headers).as_deref(), Some(token));
+    headers.insert(header::COOKIE, "blu_poster=../x".parse().unwrap());
+    assert_eq!(poster_token(&headers), None);
+    assert_ne!(
+        hash_poster_token("salt", token),
+        hash_poster_token("pepper", token)
+    );
+}
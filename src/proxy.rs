@@ -0,0 +1,166 @@
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+use crate::Res;
+
+const CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+const CACHE_MAX_ENTRIES: usize = 10_000;
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(2);
+const TOR_REFRESH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Serialize, Deserialize, sqlx::Type, Clone, Copy, PartialEq, Eq, Default, Debug)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum ProxyPolicy {
+    #[default]
+    Allow,
+    NoMedia,
+    Block,
+}
+
+pub struct ProxyCheck {
+    dnsbl: Vec<String>,
+    tor_exit_list: Option<String>,
+    tor_exits: RwLock<HashSet<IpAddr>>,
+    cache: Mutex<HashMap<IpAddr, (bool, Instant)>>,
+}
+
+impl ProxyCheck {
+    pub fn from_env() -> Self {
+        let dnsbl = std::env::var("DNSBL")
+            .unwrap_or_default()
+            .split(',')
+            .map(|zone| zone.trim().trim_matches('.').to_string())
+            .filter(|zone| !zone.is_empty())
+            .collect();
+        Self {
+            dnsbl,
+            tor_exit_list: std::env::var("TOR_EXIT_LIST")
+                .ok()
+                .filter(|u| !u.is_empty()),
+            tor_exits: RwLock::new(HashSet::new()),
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Periodically downloads the tor exit list, if one is configured
+    pub async fn refresh_tor_exits(&self) {
+        let Some(url) = &self.tor_exit_list else {
+            return;
+        };
+        loop {
+            match download_exit_list(url).await {
+                Ok(exits) => {
+                    tracing::info!("loaded {} tor exit nodes", exits.len());
+                    *self.tor_exits.write().unwrap() = exits;
+                }
+                Err(e) => tracing::warn!("failed to download tor exit list: {e}"),
+            }
+            tokio::time::sleep(TOR_REFRESH_INTERVAL).await;
+        }
+    }
+
+    /// Rejects the post if the board policy forbids it and the ip looks like a proxy
+    pub async fn enforce(
+        &self,
+        pool: &SqlitePool,
+        policy: ProxyPolicy,
+        ip: IpAddr,
+        ip_hash: &str,
+        has_media: bool,
+    ) -> Res<()> {
+        if policy == ProxyPolicy::Allow || (policy == ProxyPolicy::NoMedia && !has_media) {
+            return Ok(());
+        }
+        if !self.is_proxy(pool, ip, ip_hash).await? {
+            return Ok(());
+        }
+        match policy {
+            ProxyPolicy::Block => {
+                Err("posting through a proxy is not allowed on this board".into())
+            }
+            _ => Err("media uploads through a proxy are not allowed on this board".into()),
+        }
+    }
+
+    /// Whether the ip is a known tor exit or listed in a dnsbl, unless whitelisted by a moderator
+    pub async fn is_proxy(&self, pool: &SqlitePool, ip: IpAddr, ip_hash: &str) -> Res<bool> {
+        let ip = ip.to_canonical();
+        let whitelisted: bool =
+            sqlx::query_scalar(r#"SELECT EXISTS(SELECT 1 FROM proxy_whitelist WHERE ip_hash = ?)"#)
+                .bind(ip_hash)
+                .fetch_one(pool)
+                .await?;
+        if whitelisted {
+            return Ok(false);
+        }
+        if self.tor_exits.read().unwrap().contains(&ip) {
+            return Ok(true);
+        }
+        if let Some((listed, at)) = self.cache.lock().unwrap().get(&ip)
+            && at.elapsed() < CACHE_TTL
+        {
+            return Ok(*listed);
+        }
+
+        let mut listed = false;
+        for zone in &self.dnsbl {
+            if is_listed(ip, zone).await {
+                listed = true;
+                break;
+            }
+        }
+
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() >= CACHE_MAX_ENTRIES {
+            cache.retain(|_, (_, at)| at.elapsed() < CACHE_TTL);
+        }
+        cache.insert(ip, (listed, Instant::now()));
+        Ok(listed)
+    }
+}
+
+async fn download_exit_list(url: &str) -> Res<HashSet<IpAddr>> {
+    let text = reqwest::get(url).await?.error_for_status()?.text().await?;
+    Ok(text
+        .lines()
+        .filter_map(|ln| ln.trim().parse().ok())
+        .collect())
+}
+async fn is_listed(ip: IpAddr, zone: &str) -> bool {
+    let query = format!("{}.{zone}:0", reverse_name(ip));
+    match tokio::time::timeout(LOOKUP_TIMEOUT, tokio::net::lookup_host(query)).await {
+        Ok(Ok(mut addrs)) => addrs.next().is_some(),
+        _ => false,
+    }
+}
+fn reverse_name(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, d] = ip.octets();
+            format!("{d}.{c}.{b}.{a}")
+        }
+        IpAddr::V6(ip) => ip
+            .octets()
+            .iter()
+            .rev()
+            .flat_map(|byte| [byte & 0xf, byte >> 4])
+            .map(|nibble| format!("{nibble:x}"))
+            .collect::<Vec<_>>()
+            .join("."),
+    }
+}
+
+#[test]
+fn test_reverse_name() {
+    assert_eq!(reverse_name("127.0.0.2".parse().unwrap()), "2.0.0.127");
+    assert_eq!(
+        reverse_name("2001:db8::1".parse().unwrap()),
+        "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2"
+    );
+}
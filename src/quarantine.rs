@@ -0,0 +1,299 @@
+//! The posts moderators delete are kept encrypted for QUARANTINE_DAYS (default 30) when
+//! QUARANTINE_KEY is set, so the deletions made by mistake can be undone. The copy holds every
+//! column of the post and of the replies deleted with it, their media is moved to
+//! `quarantine/{post_id}/` until the copy expires
+
+use axum::http::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::prelude::FromRow;
+use sqlx::{SqliteConnection, SqlitePool};
+
+use crate::media::stored_hash;
+use crate::vault::SealingKey;
+use crate::{Res, StatusError};
+
+pub struct Quarantine {
+    key: SealingKey,
+    retention: i64,
+}
+
+/// A quarantined deletion as listed to moderators, without its content
+#[derive(Serialize, Deserialize, FromRow)]
+pub struct QuarantinedPost {
+    pub post_id: i64,
+    pub board: Option<String>,
+    pub board_post_no: i64,
+    pub op: Option<i64>,
+    pub posts: i64,
+    pub media_hash: Option<String>,
+    pub deleted_by: String,
+    pub deleted_at: i64,
+    pub expires_at: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct QuarantinedContent {
+    #[serde(flatten)]
+    pub entry: QuarantinedPost,
+    #[serde(flatten)]
+    pub copy: PostCopy,
+}
+
+/// What is encrypted of a deletion
+#[derive(Serialize, Deserialize)]
+pub struct PostCopy {
+    /// The rows of the post and its replies, as they were in the database
+    pub rows: Vec<Value>,
+    /// The quotes from and to the posts, as pairs of the quoted post and the reply
+    pub quotes: Vec<(i64, i64)>,
+}
+
+impl Quarantine {
+    pub fn from_env() -> Res<Option<Self>> {
+        let Some(key) = SealingKey::from_env("QUARANTINE_KEY")? else {
+            return Ok(None);
+        };
+        let days: i64 = std::env::var("QUARANTINE_DAYS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(30);
+        Ok(Some(Self {
+            key,
+            retention: days * 24 * 60 * 60,
+        }))
+    }
+
+    /// Keeps an encrypted copy of the post and its replies in the transaction deleting them,
+    /// false if there is no such post. Their files are moved with [`keep_files`] once the
+    /// deletion is committed
+    pub async fn save(
+        &self,
+        conn: &mut SqliteConnection,
+        post_id: i64,
+        deleted_by: &str,
+    ) -> Res<bool> {
+        let query = rows_query(&mut *conn).await?;
+        let rows: String = sqlx::query_scalar(&query)
+            .bind(post_id)
+            .fetch_one(&mut *conn)
+            .await?;
+        let rows: Vec<Value> = serde_json::from_str(&rows)?;
+        // the post comes first, its replies were made after it. A missing post is left to the
+        // deletion to report
+        let Some(post) = rows.first().filter(|p| p["id"] == post_id) else {
+            return Ok(false);
+        };
+        let media_hash = match (post["media_hash"].as_str(), post["media_name"].as_str()) {
+            (Some(hash), _) => Some(hash.to_string()),
+            (None, Some(name)) => stored_hash(name).await?,
+            (None, None) => None,
+        };
+        let quotes = sqlx::query_as(
+            r#"
+            SELECT post_id, reply_id FROM post_replies
+            WHERE post_id IN (SELECT id FROM comments WHERE id = ?1 OR op = ?1)
+            OR reply_id IN (SELECT id FROM comments WHERE id = ?1 OR op = ?1)
+            "#,
+        )
+        .bind(post_id)
+        .fetch_all(&mut *conn)
+        .await?;
+        let (board, board_post_no, op) = (
+            post["board"].as_str().map(str::to_string),
+            post["board_post_no"].as_i64(),
+            post["op"].as_i64(),
+        );
+        let copy = PostCopy { rows, quotes };
+        let data = self.key.seal(serde_json::to_vec(&copy)?)?;
+        sqlx::query(
+            r#"
+            INSERT OR REPLACE INTO quarantined_posts
+            (post_id, board, board_post_no, op, posts, media_hash, data, deleted_by, expires_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, strftime('%s', 'now') + ?)
+            "#,
+        )
+        .bind(post_id)
+        .bind(board)
+        .bind(board_post_no)
+        .bind(op)
+        .bind(copy.rows.len() as i64)
+        .bind(media_hash)
+        .bind(data)
+        .bind(deleted_by)
+        .bind(self.retention)
+        .execute(&mut *conn)
+        .await?;
+        Ok(true)
+    }
+
+    /// Inserts the post and its replies back with their quotes and media, a reply can only be
+    /// restored once its thread is
+    pub async fn restore(&self, pool: &SqlitePool, post_id: i64) -> Res<()> {
+        let QuarantinedContent { entry, copy } = self.open(pool, post_id).await?;
+        let mut tx = pool.begin().await?;
+        let restored: bool =
+            sqlx::query_scalar(r#"SELECT EXISTS(SELECT 1 FROM comments WHERE id = ?)"#)
+                .bind(post_id)
+                .fetch_one(&mut *tx)
+                .await?;
+        if restored {
+            let message = "the post wasn't deleted".to_string();
+            return Err(StatusError(StatusCode::CONFLICT, message).into());
+        }
+        if let Some(op) = entry.op {
+            let thread: bool =
+                sqlx::query_scalar(r#"SELECT EXISTS(SELECT 1 FROM comments WHERE id = ?)"#)
+                    .bind(op)
+                    .fetch_one(&mut *tx)
+                    .await?;
+            if !thread {
+                let message = "the thread of the post must be restored first".to_string();
+                return Err(StatusError(StatusCode::CONFLICT, message).into());
+            }
+        }
+        // the columns added since the deletion keep their defaults
+        let columns: Vec<String> =
+            sqlx::query_scalar(r#"SELECT name FROM pragma_table_info('comments') ORDER BY cid"#)
+                .fetch_all(&mut *tx)
+                .await?;
+        let columns: Vec<String> = columns
+            .into_iter()
+            .filter(|c| copy.rows.iter().all(|row| row.get(c).is_some()))
+            .collect();
+        let names: Vec<String> = columns.iter().map(|c| format!("\"{c}\"")).collect();
+        let values: Vec<String> = columns.iter().map(|c| format!("value ->> '{c}'")).collect();
+        let rows = serde_json::to_string(&copy.rows)?;
+        sqlx::query(&format!(
+            "INSERT INTO comments ({}) SELECT {} FROM json_each(?)",
+            names.join(", "),
+            values.join(", ")
+        ))
+        .bind(rows)
+        .execute(&mut *tx)
+        .await?;
+        for (quoted, reply) in copy.quotes {
+            sqlx::query(
+                r#"
+                INSERT OR IGNORE INTO post_replies (post_id, reply_id) SELECT ?1, ?2
+                WHERE EXISTS(SELECT 1 FROM comments WHERE id = ?1)
+                AND EXISTS(SELECT 1 FROM comments WHERE id = ?2)
+                "#,
+            )
+            .bind(quoted)
+            .bind(reply)
+            .execute(&mut *tx)
+            .await?;
+        }
+        sqlx::query(r#"DELETE FROM quarantined_posts WHERE post_id = ?"#)
+            .bind(post_id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        let dir = format!("quarantine/{post_id}");
+        let mut files = match tokio::fs::read_dir(&dir).await {
+            Ok(files) => files,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        while let Some(file) = files.next_entry().await? {
+            let name = file.file_name().to_string_lossy().to_string();
+            move_file(&format!("{dir}/{name}"), &format!("media/{name}")).await?;
+        }
+        tokio::fs::remove_dir(&dir).await?;
+        Ok(())
+    }
+
+    /// The deletion with its decrypted content
+    pub async fn open(&self, pool: &SqlitePool, post_id: i64) -> Res<QuarantinedContent> {
+        let entry: Option<Sealed> = sqlx::query_as(
+            r#"SELECT * FROM quarantined_posts WHERE post_id = ? AND expires_at > strftime('%s', 'now')"#,
+        )
+        .bind(post_id)
+        .fetch_optional(pool)
+        .await?;
+        let Some(Sealed { entry, data }) = entry else {
+            let message = "post not in quarantine".to_string();
+            return Err(StatusError(StatusCode::NOT_FOUND, message).into());
+        };
+        Ok(QuarantinedContent {
+            entry,
+            copy: serde_json::from_slice(&self.key.open(&data)?)?,
+        })
+    }
+}
+
+#[derive(FromRow)]
+struct Sealed {
+    #[sqlx(flatten)]
+    entry: QuarantinedPost,
+    data: Vec<u8>,
+}
+
+pub async fn list(pool: &SqlitePool) -> Res<Vec<QuarantinedPost>> {
+    sqlx::query_as(
+        r#"
+        SELECT post_id, board, board_post_no, op, posts, media_hash, deleted_by, deleted_at, expires_at
+        FROM quarantined_posts WHERE expires_at > strftime('%s', 'now')
+        ORDER BY deleted_at DESC, post_id DESC
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| e.into())
+}
+
+/// Deletes the copies kept past the retention period with their media
+pub async fn purge_expired(pool: &SqlitePool) -> Res<u64> {
+    let purged: Vec<i64> = sqlx::query_scalar(
+        r#"DELETE FROM quarantined_posts WHERE expires_at <= strftime('%s', 'now') RETURNING post_id"#,
+    )
+    .fetch_all(pool)
+    .await?;
+    for post_id in &purged {
+        match tokio::fs::remove_dir_all(format!("quarantine/{post_id}")).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+    }
+    Ok(purged.len() as u64)
+}
+
+/// Moves the media of a quarantined post and its replies out of `media/`
+pub async fn keep_files(post_id: i64, names: &[String]) -> Res<()> {
+    let dir = format!("quarantine/{post_id}");
+    tokio::fs::create_dir_all(&dir).await?;
+    for name in names {
+        move_file(&format!("media/{name}"), &format!("{dir}/{name}")).await?;
+    }
+    Ok(())
+}
+
+async fn move_file(from: &str, to: &str) -> Res<()> {
+    match tokio::fs::rename(from, to).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Selects the post and its replies as a json array of objects with every column, built from
+/// the columns the table has so the copy keeps up with the migrations
+async fn rows_query(conn: &mut SqliteConnection) -> Res<String> {
+    let columns: Vec<String> =
+        sqlx::query_scalar(r#"SELECT name FROM pragma_table_info('comments') ORDER BY cid"#)
+            .fetch_all(&mut *conn)
+            .await?;
+    // json_object takes a limited number of arguments, so the columns are split in chunks
+    let object = columns
+        .chunks(40)
+        .map(|chunk| {
+            let fields: Vec<String> = chunk.iter().map(|c| format!("'{c}', \"{c}\"")).collect();
+            format!("json_object({})", fields.join(", "))
+        })
+        .reduce(|a, b| format!("json_patch({a}, {b})"))
+        .ok_or("the comments table has no columns")?;
+    Ok(format!(
+        "SELECT json_group_array(json({object})) FROM (SELECT * FROM comments WHERE id = ?1 OR op = ?1 ORDER BY id)"
+    ))
+}
@@ -0,0 +1,143 @@
+//! Large png and jpeg uploads are re-encoded on the boards with `reencode_images`, when
+//! REENCODE_MAX_DIMENSION is set. Images wider or taller than it are downscaled, and jpegs
+//! above REENCODE_TARGET_SIZE are encoded with a lower quality until they fit
+
+use std::io::Cursor;
+use std::str::FromStr;
+
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+use image::imageops::FilterType as ResizeFilter;
+use image::{DynamicImage, GenericImageView, ImageEncoder, ImageFormat};
+
+use crate::Res;
+use crate::media::Upload;
+
+/// The lowest quality jpegs are encoded with to reach the target size
+const MIN_QUALITY: u8 = 50;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ReencodeSettings {
+    pub max_dimension: u32,
+    pub target_size: u64,
+    pub quality: u8,
+}
+
+impl ReencodeSettings {
+    pub fn from_env() -> Option<Self> {
+        Some(Self {
+            max_dimension: env_var("REENCODE_MAX_DIMENSION").filter(|d: &u32| *d > 0)?,
+            target_size: env_var("REENCODE_TARGET_SIZE").unwrap_or(2_000_000),
+            quality: env_var("REENCODE_QUALITY")
+                .unwrap_or(85)
+                .clamp(MIN_QUALITY, 100),
+        })
+    }
+}
+
+/// Replaces the upload with its re-encoded image when it's smaller, returns the size of the
+/// original
+pub async fn reencode_upload(upload: &mut Upload, settings: ReencodeSettings) -> Res<Option<i64>> {
+    let data = tokio::fs::read(&upload.path).await?;
+    let format = match infer::get(&data).map(|kind| kind.mime_type()) {
+        Some("image/png") => ImageFormat::Png,
+        Some("image/jpeg") => ImageFormat::Jpeg,
+        _ => return Ok(None),
+    };
+    let (data, encoded) = tokio::task::spawn_blocking(move || -> Result<_, String> {
+        let encoded = reencode(&data, format, settings).map_err(|e| e.to_string())?;
+        Ok((data, encoded))
+    })
+    .await??;
+    let Some(encoded) = encoded else {
+        return Ok(None);
+    };
+    tokio::fs::write(&upload.path, &encoded).await?;
+    upload.size = encoded.len() as u64;
+    Ok(Some(data.len() as i64))
+}
+
+/// The image downscaled to the max dimension and encoded again, none if it's already small
+/// enough or the new encoding isn't smaller
+pub fn reencode(
+    data: &[u8],
+    format: ImageFormat,
+    settings: ReencodeSettings,
+) -> Res<Option<Vec<u8>>> {
+    let image = image::load_from_memory_with_format(data, format)?;
+    let (width, height) = image.dimensions();
+    let oversized = width.max(height) > settings.max_dimension;
+    if !oversized && data.len() as u64 <= settings.target_size {
+        return Ok(None);
+    }
+    let image = match oversized {
+        true => image.resize(
+            settings.max_dimension,
+            settings.max_dimension,
+            ResizeFilter::Lanczos3,
+        ),
+        false => image,
+    };
+    let encoded = match format {
+        ImageFormat::Jpeg => {
+            let mut quality = settings.quality;
+            loop {
+                let encoded = encode_jpeg(&image, quality)?;
+                if encoded.len() as u64 <= settings.target_size || quality <= MIN_QUALITY {
+                    break encoded;
+                }
+                quality = quality.saturating_sub(10).max(MIN_QUALITY);
+            }
+        }
+        _ => encode_png(&image)?,
+    };
+    Ok(Some(encoded).filter(|e| e.len() < data.len()))
+}
+
+fn encode_jpeg(image: &DynamicImage, quality: u8) -> Res<Vec<u8>> {
+    let mut data = Cursor::new(Vec::new());
+    JpegEncoder::new_with_quality(&mut data, quality).encode_image(&image.to_rgb8())?;
+    Ok(data.into_inner())
+}
+
+fn encode_png(image: &DynamicImage) -> Res<Vec<u8>> {
+    let mut data = Vec::new();
+    PngEncoder::new_with_quality(&mut data, CompressionType::Best, FilterType::Adaptive)
+        .write_image(
+            image.as_bytes(),
+            image.width(),
+            image.height(),
+            image.color(),
+        )?;
+    Ok(data)
+}
+
+fn env_var<T: FromStr>(name: &str) -> Option<T> {
+    std::env::var(name).ok().and_then(|v| v.parse().ok())
+}
+
+#[test]
+fn test_reencode() {
+    let settings = ReencodeSettings {
+        max_dimension: 32,
+        target_size: 1_000_000,
+        quality: 85,
+    };
+    let image = image::RgbImage::from_fn(128, 64, |x, y| image::Rgb([x as u8, y as u8, 90]));
+    let mut png = Cursor::new(Vec::new());
+    image
+        .write_to(&mut png, image::ImageOutputFormat::Png)
+        .unwrap();
+    let png = png.into_inner();
+
+    let encoded = reencode(&png, ImageFormat::Png, settings).unwrap().unwrap();
+    assert!(encoded.len() < png.len());
+    let resized = image::load_from_memory(&encoded).unwrap();
+    assert_eq!(resized.dimensions(), (32, 16));
+
+    let small = ReencodeSettings {
+        max_dimension: 128,
+        ..settings
+    };
+    assert_eq!(reencode(&png, ImageFormat::Png, small).unwrap(), None);
+}
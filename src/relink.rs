@@ -0,0 +1,181 @@
+//! Keeps the quote links stored in the posts pointing to the posts they quoted when threads are
+//! merged or moved and posts renumbered. The quotes are taken by the ids of the quoted posts
+//! before the change, and written again with the numbers the posts have after it. Quotes that
+//! end up across boards are unlinked, since their numbers mean other posts on the new board
+
+use std::collections::{HashMap, HashSet};
+
+use sqlx::SqliteConnection;
+use sqlx::prelude::FromRow;
+
+use crate::{RE_QUOTE_LINKS, Res, renumber_quotes, renumber_raw_quotes};
+
+/// The quotes from and to a set of posts before they change
+pub struct QuoteSnapshot {
+    /// The posts whose quotes may need rewriting, with the board they were on
+    replies: Vec<(i64, String)>,
+    links: Vec<QuoteLink>,
+}
+
+#[derive(FromRow)]
+struct QuoteLink {
+    reply_id: i64,
+    post_id: i64,
+    post_no: i64,
+}
+
+/// Takes the quotes of the posts and of the posts quoting them
+pub async fn snapshot(conn: &mut SqliteConnection, post_ids: &[i64]) -> Res<QuoteSnapshot> {
+    let ids = serde_json::to_string(post_ids)?;
+    let replies = sqlx::query_as(
+        r#"
+        SELECT c.id, COALESCE(t.board, c.board) AS post_board FROM comments c
+        LEFT JOIN comments t ON t.id = c.op
+        WHERE c.id IN (SELECT value FROM json_each(?1))
+        OR c.id IN (SELECT reply_id FROM post_replies WHERE post_id IN (SELECT value FROM json_each(?1)))
+        "#,
+    )
+    .bind(&ids)
+    .fetch_all(&mut *conn)
+    .await?;
+    let links = sqlx::query_as(
+        r#"
+        SELECT r.reply_id, r.post_id, p.board_post_no AS post_no FROM post_replies r
+        JOIN comments p ON p.id = r.post_id
+        WHERE r.reply_id IN (SELECT value FROM json_each(?1))
+        OR r.reply_id IN (SELECT reply_id FROM post_replies WHERE post_id IN (SELECT value FROM json_each(?1)))
+        "#,
+    )
+    .bind(&ids)
+    .fetch_all(&mut *conn)
+    .await?;
+    Ok(QuoteSnapshot { replies, links })
+}
+
+/// Rewrites the quotes of the snapshot to the current numbers of the quoted posts, returns how
+/// many posts changed
+pub async fn rewrite(conn: &mut SqliteConnection, snapshot: QuoteSnapshot) -> Res<u64> {
+    let mut rewritten = 0;
+    for (reply_id, old_board) in snapshot.replies {
+        let Some((com, com_raw, board)) = fetch_post(conn, reply_id).await? else {
+            continue;
+        };
+        let mut numbers = HashMap::new();
+        let mut unlinked = HashSet::new();
+        for link in snapshot.links.iter().filter(|l| l.reply_id == reply_id) {
+            let quoted = fetch_post_no(conn, link.post_id).await?;
+            match quoted {
+                Some((post_no, post_board)) if post_board == board => {
+                    numbers.insert(link.post_no, post_no);
+                }
+                _ => {
+                    unlinked.insert(link.post_no);
+                    sqlx::query(r#"DELETE FROM post_replies WHERE post_id = ? AND reply_id = ?"#)
+                        .bind(link.post_id)
+                        .bind(reply_id)
+                        .execute(&mut *conn)
+                        .await?;
+                }
+            }
+        }
+        // the quotes of posts already gone stay as they were, unless the post changed board
+        if board == old_board {
+            for no in link_numbers(com.as_deref().unwrap_or_default()) {
+                if !unlinked.contains(&no) {
+                    numbers.entry(no).or_insert(no);
+                }
+            }
+        }
+        if save(conn, reply_id, com, com_raw, &numbers).await? {
+            rewritten += 1;
+        }
+    }
+    Ok(rewritten)
+}
+
+/// Unlinks the quotes of the posts of an imported board to the numbers that weren't imported
+pub async fn unlink_missing(conn: &mut SqliteConnection, board: &str) -> Res<u64> {
+    let posts: Vec<(i64, i64, Option<String>, Option<String>)> = sqlx::query_as(
+        r#"
+        SELECT c.id, c.board_post_no, c.com, c.com_raw FROM comments c
+        LEFT JOIN comments t ON t.id = c.op
+        WHERE COALESCE(t.board, c.board) = ?
+        "#,
+    )
+    .bind(board)
+    .fetch_all(&mut *conn)
+    .await?;
+    let imported: HashSet<i64> = posts.iter().map(|p| p.1).collect();
+    let mut rewritten = 0;
+    for (id, _, com, com_raw) in posts {
+        let numbers = link_numbers(com.as_deref().unwrap_or_default())
+            .filter(|no| imported.contains(no))
+            .map(|no| (no, no))
+            .collect();
+        if save(conn, id, com, com_raw, &numbers).await? {
+            rewritten += 1;
+        }
+    }
+    Ok(rewritten)
+}
+
+/// Renumbers the quotes of the post, the links to numbers missing from `numbers` are unlinked
+async fn save(
+    conn: &mut SqliteConnection,
+    id: i64,
+    com: Option<String>,
+    com_raw: Option<String>,
+    numbers: &HashMap<i64, i64>,
+) -> Res<bool> {
+    let new_com = com.as_deref().map(|com| renumber_quotes(com, numbers));
+    let new_com_raw = com_raw
+        .as_deref()
+        .map(|com| renumber_raw_quotes(com, numbers));
+    if new_com == com && new_com_raw == com_raw {
+        return Ok(false);
+    }
+    sqlx::query(r#"UPDATE comments SET com = ?, com_raw = ? WHERE id = ?"#)
+        .bind(new_com)
+        .bind(new_com_raw)
+        .bind(id)
+        .execute(&mut *conn)
+        .await?;
+    Ok(true)
+}
+
+async fn fetch_post(
+    conn: &mut SqliteConnection,
+    id: i64,
+) -> Res<Option<(Option<String>, Option<String>, String)>> {
+    sqlx::query_as(
+        r#"
+        SELECT c.com, c.com_raw, COALESCE(t.board, c.board) FROM comments c
+        LEFT JOIN comments t ON t.id = c.op
+        WHERE c.id = ?
+        "#,
+    )
+    .bind(id)
+    .fetch_optional(&mut *conn)
+    .await
+    .map_err(|e| e.into())
+}
+
+async fn fetch_post_no(conn: &mut SqliteConnection, id: i64) -> Res<Option<(i64, String)>> {
+    sqlx::query_as(
+        r#"
+        SELECT c.board_post_no, COALESCE(t.board, c.board) FROM comments c
+        LEFT JOIN comments t ON t.id = c.op
+        WHERE c.id = ?
+        "#,
+    )
+    .bind(id)
+    .fetch_optional(&mut *conn)
+    .await
+    .map_err(|e| e.into())
+}
+
+fn link_numbers(com: &str) -> impl Iterator<Item = i64> + '_ {
+    RE_QUOTE_LINKS
+        .captures_iter(com)
+        .filter_map(|c| c[1].parse().ok())
+}
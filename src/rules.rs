@@ -0,0 +1,94 @@
+//! The rules of a board, kept with the board so every frontend shows the same. Boards that
+//! require it reject the first post of an ip until it is sent with `accepted_rules`
+
+use axum::http::StatusCode;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+use crate::{Res, StatusError, is_new_poster};
+
+const MAX_RULE_LEN: usize = 2000;
+
+#[derive(Serialize, Deserialize)]
+pub struct BoardRules {
+    pub rules: Vec<String>,
+    pub require_acceptance: bool,
+    pub updated_at: Option<i64>,
+}
+
+/// The rules of the board, none if the board doesn't exist
+pub async fn fetch(pool: &SqlitePool, board: &str) -> Res<Option<BoardRules>> {
+    let row: Option<(sqlx::types::Json<Vec<String>>, bool, Option<i64>)> = sqlx::query_as(
+        r#"
+        SELECT COALESCE(r.rules, '[]'), COALESCE(r.require_acceptance, FALSE), r.updated_at
+        FROM boards b
+        LEFT JOIN board_rules r ON r.board = b.code WHERE b.code = ?
+        "#,
+    )
+    .bind(board)
+    .fetch_optional(pool)
+    .await?;
+    Ok(
+        row.map(|(rules, require_acceptance, updated_at)| BoardRules {
+            rules: rules.0,
+            require_acceptance,
+            updated_at,
+        }),
+    )
+}
+
+pub async fn save(
+    pool: &SqlitePool,
+    board: &str,
+    rules: Vec<String>,
+    require_acceptance: bool,
+) -> Res<BoardRules> {
+    let rules: Vec<String> = rules.iter().map(|r| r.trim().to_string()).collect();
+    if rules.iter().any(|r| r.is_empty() || r.len() > MAX_RULE_LEN) {
+        return Err(format!("rules must be between 1 and {MAX_RULE_LEN} characters").into());
+    }
+    if require_acceptance && rules.is_empty() {
+        return Err("a board without rules can't require accepting them".into());
+    }
+    let (rules, require_acceptance, updated_at): (sqlx::types::Json<Vec<String>>, bool, i64) =
+        sqlx::query_as(
+            r#"
+            INSERT INTO board_rules (board, rules, require_acceptance)
+            SELECT code, ?, ? FROM boards WHERE code = ?
+            ON CONFLICT (board) DO UPDATE
+            SET rules = excluded.rules,
+                require_acceptance = excluded.require_acceptance,
+                updated_at = strftime('%s', 'now')
+            RETURNING rules, require_acceptance, updated_at
+            "#,
+        )
+        .bind(sqlx::types::Json(rules))
+        .bind(require_acceptance)
+        .bind(board)
+        .fetch_optional(pool)
+        .await?
+        .ok_or("board not found")?;
+    Ok(BoardRules {
+        rules: rules.0,
+        require_acceptance,
+        updated_at: Some(updated_at),
+    })
+}
+
+/// Rejects the first post of the ip on a board requiring its rules, unless they were accepted
+pub async fn check(pool: &SqlitePool, board: &str, ip_hash: &str, accepted: bool) -> Res<()> {
+    if accepted {
+        return Ok(());
+    }
+    let required: bool = sqlx::query_scalar(
+        r#"SELECT EXISTS(SELECT 1 FROM board_rules WHERE board = ? AND require_acceptance)"#,
+    )
+    .bind(board)
+    .fetch_one(pool)
+    .await?;
+    if required && is_new_poster(pool, ip_hash).await? {
+        let message = format!("accept the rules in /{board}/rules first, with accepted_rules");
+        return Err(StatusError(StatusCode::FORBIDDEN, message).into());
+    }
+    Ok(())
+}
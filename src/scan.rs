@@ -0,0 +1,163 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use axum::http::StatusCode;
+use serde::Serialize;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, UnixStream};
+
+use crate::{Res, StatusError};
+
+const CHUNK_SIZE: usize = 64 * 1024;
+const SCAN_TIMEOUT: Duration = Duration::from_secs(30);
+
+static SCANS: ScanCounters = ScanCounters {
+    scans: AtomicU64::new(0),
+    rejected: AtomicU64::new(0),
+    failed: AtomicU64::new(0),
+    scan_us: AtomicU64::new(0),
+    max_scan_us: AtomicU64::new(0),
+};
+
+struct ScanCounters {
+    scans: AtomicU64,
+    rejected: AtomicU64,
+    failed: AtomicU64,
+    scan_us: AtomicU64,
+    max_scan_us: AtomicU64,
+}
+
+#[derive(Serialize)]
+pub struct ScanStats {
+    scans: u64,
+    rejected: u64,
+    failed: u64,
+    avg_scan_ms: f64,
+    max_scan_ms: f64,
+}
+
+/// A clamd daemon, CLAMD is either `unix:/path/to/clamd.sock` or `host:port`
+pub enum Clamd {
+    Unix(PathBuf),
+    Tcp(String),
+}
+
+impl Clamd {
+    pub fn from_env() -> Option<Self> {
+        let addr = std::env::var("CLAMD").ok().filter(|a| !a.is_empty())?;
+        Some(match addr.strip_prefix("unix:") {
+            Some(path) => Clamd::Unix(path.into()),
+            None => Clamd::Tcp(addr),
+        })
+    }
+
+    /// Rejects the file if clamd finds something, or if it can't be reached so uploads are
+    /// never let through unscanned
+    pub async fn scan(&self, path: &Path) -> Res<()> {
+        let started = Instant::now();
+        let reply = tokio::time::timeout(SCAN_TIMEOUT, self.instream(path)).await;
+        let scan_us = started.elapsed().as_micros() as u64;
+        SCANS.scans.fetch_add(1, Ordering::Relaxed);
+        SCANS.scan_us.fetch_add(scan_us, Ordering::Relaxed);
+        SCANS.max_scan_us.fetch_max(scan_us, Ordering::Relaxed);
+
+        let reply = match reply {
+            Ok(Ok(reply)) => reply,
+            Ok(Err(e)) => return Err(scan_failed(&e.to_string())),
+            Err(_) => return Err(scan_failed("timed out")),
+        };
+        match parse_reply(&reply) {
+            Ok(None) => Ok(()),
+            Ok(Some(signature)) => {
+                SCANS.rejected.fetch_add(1, Ordering::Relaxed);
+                tracing::warn!("rejected an upload matching {signature}");
+                Err(StatusError(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "media was rejected by the virus scanner".to_string(),
+                )
+                .into())
+            }
+            Err(e) => Err(scan_failed(&e)),
+        }
+    }
+
+    async fn instream(&self, path: &Path) -> std::io::Result<String> {
+        match self {
+            Clamd::Unix(socket) => instream(UnixStream::connect(socket).await?, path).await,
+            Clamd::Tcp(addr) => instream(TcpStream::connect(addr).await?, path).await,
+        }
+    }
+}
+
+pub fn scan_stats() -> ScanStats {
+    let scans = SCANS.scans.load(Ordering::Relaxed);
+    ScanStats {
+        scans,
+        rejected: SCANS.rejected.load(Ordering::Relaxed),
+        failed: SCANS.failed.load(Ordering::Relaxed),
+        avg_scan_ms: SCANS.scan_us.load(Ordering::Relaxed) as f64 / scans.max(1) as f64 / 1000.0,
+        max_scan_ms: SCANS.max_scan_us.load(Ordering::Relaxed) as f64 / 1000.0,
+    }
+}
+
+fn scan_failed(reason: &str) -> Box<dyn std::error::Error> {
+    SCANS.failed.fetch_add(1, Ordering::Relaxed);
+    tracing::warn!("failed to scan an upload: {reason}");
+    StatusError(
+        StatusCode::SERVICE_UNAVAILABLE,
+        "media could not be scanned".to_string(),
+    )
+    .into()
+}
+
+/// Streams the file with the INSTREAM command, as chunks prefixed by their length and ended
+/// by an empty one
+async fn instream<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    path: &Path,
+) -> std::io::Result<String> {
+    stream.write_all(b"zINSTREAM\0").await?;
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut chunk = vec![0; CHUNK_SIZE];
+    loop {
+        let n = file.read(&mut chunk).await?;
+        stream.write_all(&(n as u32).to_be_bytes()).await?;
+        if n == 0 {
+            break;
+        }
+        stream.write_all(&chunk[..n]).await?;
+    }
+    // replies to z prefixed commands end with a null byte
+    let mut reply = Vec::new();
+    let mut buf = [0; 256];
+    while !reply.contains(&0) {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        reply.extend_from_slice(&buf[..n]);
+    }
+    Ok(String::from_utf8_lossy(&reply).into_owned())
+}
+
+/// Returns the signature found, if any
+fn parse_reply(reply: &str) -> Result<Option<String>, String> {
+    let reply = reply.trim_end_matches(['\0', '\n']);
+    let status = reply.strip_prefix("stream: ").unwrap_or(reply);
+    match status.strip_suffix(" FOUND") {
+        _ if status == "OK" => Ok(None),
+        Some(signature) => Ok(Some(signature.to_string())),
+        None => Err(format!("unexpected reply {status}")),
+    }
+}
+
+#[test]
+fn test_parse_reply() {
+    assert_eq!(parse_reply("stream: OK\0"), Ok(None));
+    assert_eq!(
+        parse_reply("stream: Eicar-Signature FOUND\0"),
+        Ok(Some("Eicar-Signature".to_string()))
+    );
+    assert!(parse_reply("INSTREAM size limit exceeded. ERROR\0").is_err());
+}
@@ -0,0 +1,334 @@
+//! Runs the recurring maintenance jobs on cron-like schedules, each job is configured with
+//! `SCHEDULE_{JOB}` such as `SCHEDULE_GC_MEDIA="30 4 * * *"`, or `off` to disable it
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use sqlx::SqlitePool;
+
+use crate::cache::ResponseCache;
+use crate::{
+    Config, Res, cli, expire_held_posts, privacy, prune_threads, quarantine, rollup_stats,
+    takedowns, tus,
+};
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Job {
+    PruneThreads,
+    ExpireHeldPosts,
+    GcMedia,
+    PurgeIpHashes,
+    PurgeTakedowns,
+    PurgeQuarantine,
+    PurgeUploads,
+    RollupStats,
+}
+
+impl Job {
+    const ALL: [Job; 8] = [
+        Job::PruneThreads,
+        Job::ExpireHeldPosts,
+        Job::GcMedia,
+        Job::PurgeIpHashes,
+        Job::PurgeTakedowns,
+        Job::PurgeQuarantine,
+        Job::PurgeUploads,
+        Job::RollupStats,
+    ];
+
+    fn env_var(self) -> &'static str {
+        match self {
+            Job::PruneThreads => "SCHEDULE_PRUNE_THREADS",
+            Job::ExpireHeldPosts => "SCHEDULE_EXPIRE_HELD_POSTS",
+            Job::GcMedia => "SCHEDULE_GC_MEDIA",
+            Job::PurgeIpHashes => "SCHEDULE_PURGE_IP_HASHES",
+            Job::PurgeTakedowns => "SCHEDULE_PURGE_TAKEDOWNS",
+            Job::PurgeQuarantine => "SCHEDULE_PURGE_QUARANTINE",
+            Job::PurgeUploads => "SCHEDULE_PURGE_UPLOADS",
+            Job::RollupStats => "SCHEDULE_ROLLUP_STATS",
+        }
+    }
+
+    /// Pruning deletes threads, so it only runs once the operator opts in
+    fn default_schedule(self) -> Option<&'static str> {
+        match self {
+            Job::PruneThreads => None,
+            Job::ExpireHeldPosts => Some("0 * * * *"),
+            Job::GcMedia => Some("30 4 * * *"),
+            Job::PurgeIpHashes => Some("0 * * * *"),
+            Job::PurgeTakedowns => Some("0 * * * *"),
+            Job::PurgeQuarantine => Some("0 * * * *"),
+            Job::PurgeUploads => Some("0 * * * *"),
+            Job::RollupStats => Some("*/15 * * * *"),
+        }
+    }
+
+    /// Returns a short summary of what the job did
+    async fn run(self, pool: &SqlitePool, config: &Config, cache: &ResponseCache) -> Res<String> {
+        Ok(match self {
+            Job::PruneThreads => format!("removed {} threads", prune_threads(pool, cache).await?),
+            Job::ExpireHeldPosts => {
+                let removed = expire_held_posts(pool, config.held_expiry).await?;
+                format!("removed {removed} held posts")
+            }
+            Job::GcMedia => {
+                let (files, bytes) = cli::collect_media(pool, false).await?;
+                format!("removed {} files, {bytes} bytes", files.len())
+            }
+            Job::PurgeIpHashes => {
+                let purged = privacy::purge_ip_hashes(pool, config.ip_retention).await?;
+                format!("purged {purged} ip hashes")
+            }
+            Job::PurgeTakedowns => {
+                let purged = takedowns::purge_expired(pool).await?;
+                format!("purged {purged} takedown copies")
+            }
+            Job::PurgeQuarantine => {
+                let purged = quarantine::purge_expired(pool).await?;
+                format!("purged {purged} quarantined posts")
+            }
+            Job::PurgeUploads => format!("purged {} uploads", tus::purge_expired(pool).await?),
+            Job::RollupStats => format!("updated {} daily stats", rollup_stats(pool).await?),
+        })
+    }
+}
+
+/// A cron expression of five fields: minute, hour, day of month, month and day of week (0 is
+/// sunday), each `*`, `*/step`, a value, a range `a-b` or a list of those, in UTC
+#[derive(Clone, PartialEq, Debug)]
+pub struct Schedule {
+    expr: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+}
+
+impl FromStr for Schedule {
+    type Err = String;
+
+    fn from_str(expr: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(format!("expected 5 fields in the schedule {expr:?}"));
+        };
+        Ok(Self {
+            expr: fields.join(" "),
+            minutes: parse_field(minutes, 0, 59)?,
+            hours: parse_field(hours, 0, 23)?,
+            days: parse_field(days, 1, 31)?,
+            months: parse_field(months, 1, 12)?,
+            weekdays: parse_field(weekdays, 0, 6)?,
+        })
+    }
+}
+
+fn parse_field(field: &str, min: u64, max: u64) -> Result<u64, String> {
+    let mut bits = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse().map_err(|_| bad_field(field))?),
+            None => (part, 1),
+        };
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((a, b)) => (
+                a.parse().map_err(|_| bad_field(field))?,
+                b.parse().map_err(|_| bad_field(field))?,
+            ),
+            None => {
+                let value = range.parse().map_err(|_| bad_field(field))?;
+                (value, value)
+            }
+        };
+        if start < min || end > max || start > end || step == 0 {
+            return Err(bad_field(field));
+        }
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+fn bad_field(field: &str) -> String {
+    format!("invalid schedule field {field:?}")
+}
+
+impl Schedule {
+    /// Whether the job is due in the minute of the unix time
+    pub fn matches(&self, time: i64) -> bool {
+        let days = time.div_euclid(86400);
+        let secs = time.rem_euclid(86400);
+        let (_, month, day) = civil_from_days(days);
+        // the epoch was a thursday
+        let weekday = (days + 4).rem_euclid(7);
+        let bit = |set: u64, value: i64| set & (1 << value) != 0;
+        bit(self.minutes, secs / 60 % 60)
+            && bit(self.hours, secs / 3600)
+            && bit(self.days, day)
+            && bit(self.months, month)
+            && bit(self.weekdays, weekday)
+    }
+}
+
+/// The year, month and day of the days since the epoch, from Howard Hinnant's date algorithms
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[derive(Serialize, Clone)]
+pub struct JobStatus {
+    pub job: Job,
+    pub schedule: Option<String>,
+    pub is_running: bool,
+    pub runs: u64,
+    pub failures: u64,
+    pub last_started_at: Option<i64>,
+    pub last_finished_at: Option<i64>,
+    pub last_result: Option<Result<String, String>>,
+}
+
+pub struct Scheduler {
+    schedules: Vec<(Job, Schedule)>,
+    status: Mutex<HashMap<Job, JobStatus>>,
+}
+
+impl Scheduler {
+    pub fn from_env() -> Res<Self> {
+        let mut schedules = Vec::new();
+        let mut status = HashMap::new();
+        for job in Job::ALL {
+            let expr = match std::env::var(job.env_var()) {
+                Ok(expr) => Some(expr).filter(|e| !e.trim().eq_ignore_ascii_case("off")),
+                Err(_) => job.default_schedule().map(str::to_string),
+            };
+            let schedule = match expr {
+                Some(expr) => Some(
+                    expr.parse::<Schedule>()
+                        .map_err(|e| format!("{}: {e}", job.env_var()))?,
+                ),
+                None => None,
+            };
+            status.insert(
+                job,
+                JobStatus {
+                    job,
+                    schedule: schedule.as_ref().map(|s| s.expr.clone()),
+                    is_running: false,
+                    runs: 0,
+                    failures: 0,
+                    last_started_at: None,
+                    last_finished_at: None,
+                    last_result: None,
+                },
+            );
+            schedules.extend(schedule.map(|s| (job, s)));
+        }
+        Ok(Self {
+            schedules,
+            status: Mutex::new(status),
+        })
+    }
+
+    pub fn status(&self) -> Vec<JobStatus> {
+        let status = self.status.lock().unwrap();
+        Job::ALL.iter().map(|job| status[job].clone()).collect()
+    }
+
+    /// Starts the due jobs at the start of every minute, a job still running is not started again
+    pub async fn run(
+        self: Arc<Self>,
+        pool: Arc<SqlitePool>,
+        config: Arc<Config>,
+        cache: Arc<ResponseCache>,
+    ) {
+        loop {
+            let now = unix_time();
+            tokio::time::sleep(Duration::from_secs((60 - now % 60) as u64)).await;
+            let minute = unix_time() / 60 * 60;
+            for (job, schedule) in &self.schedules {
+                if !schedule.matches(minute) || !self.start(*job) {
+                    continue;
+                }
+                tokio::spawn({
+                    let (this, pool, config, cache) =
+                        (self.clone(), pool.clone(), config.clone(), cache.clone());
+                    let job = *job;
+                    async move {
+                        let res = job.run(&pool, &config, &cache).await;
+                        this.finish(job, res.map_err(|e| e.to_string()));
+                    }
+                });
+            }
+        }
+    }
+
+    fn start(&self, job: Job) -> bool {
+        let mut status = self.status.lock().unwrap();
+        let status = status.get_mut(&job).unwrap();
+        if status.is_running {
+            tracing::warn!("skipping {job:?}, the previous run didn't finish");
+            return false;
+        }
+        status.is_running = true;
+        status.last_started_at = Some(unix_time());
+        true
+    }
+
+    fn finish(&self, job: Job, res: Result<String, String>) {
+        match &res {
+            Ok(summary) => tracing::info!("{job:?}: {summary}"),
+            Err(e) => tracing::warn!("{job:?} failed: {e}"),
+        }
+        let mut status = self.status.lock().unwrap();
+        let status = status.get_mut(&job).unwrap();
+        status.is_running = false;
+        status.runs += 1;
+        status.failures += u64::from(res.is_err());
+        status.last_finished_at = Some(unix_time());
+        status.last_result = Some(res);
+    }
+}
+
+fn unix_time() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64)
+}
+
+#[test]
+fn test_schedule() {
+    assert!("* * * *".parse::<Schedule>().is_err());
+    assert!("60 * * * *".parse::<Schedule>().is_err());
+    assert!("*/0 * * * *".parse::<Schedule>().is_err());
+    assert!("5-1 * * * *".parse::<Schedule>().is_err());
+
+    // 2026-10-14 04:30 UTC, a wednesday
+    let time = 1791952200;
+    assert_eq!(civil_from_days(time / 86400), (2026, 10, 14));
+    let matches = |expr: &str| expr.parse::<Schedule>().unwrap().matches(time);
+    assert!(matches("* * * * *"));
+    assert!(matches("30 4 * * *"));
+    assert!(matches("*/15 * * * *"));
+    assert!(matches("0-10,30 4 14 10 3"));
+    assert!(matches("30 4 * * 1-5"));
+    assert!(!matches("30 4 * * 0,6"));
+    assert!(!matches("*/7 * * * *"));
+    assert!(!matches("30 5 * * *"));
+    assert!(!matches("30 4 1 * *"));
+}
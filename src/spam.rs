@@ -0,0 +1,187 @@
+use std::sync::LazyLock;
+
+use regex::Regex;
+use serde::{Deserialize, Deserializer};
+use sqlx::SqlitePool;
+
+use crate::Res;
+
+static RE_LINK: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)https?://").unwrap());
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SpamAction {
+    Hold,
+    Reject,
+}
+
+#[derive(Deserialize)]
+pub struct BlacklistRule {
+    #[serde(deserialize_with = "deserialize_regex")]
+    pattern: Regex,
+    action: SpamAction,
+}
+#[derive(Deserialize)]
+pub struct LinkRule {
+    max_links: usize,
+    action: SpamAction,
+}
+#[derive(Deserialize)]
+pub struct DuplicateRule {
+    window: i64,
+    action: SpamAction,
+}
+#[derive(Deserialize)]
+pub struct CapsRule {
+    ratio: f64,
+    min_letters: usize,
+    action: SpamAction,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct SpamRules {
+    blacklist: Vec<BlacklistRule>,
+    links: Option<LinkRule>,
+    duplicate: Option<DuplicateRule>,
+    caps: Option<CapsRule>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Verdict {
+    pub action: SpamAction,
+    pub reason: &'static str,
+}
+
+impl SpamRules {
+    /// The rules of the SPAM_RULES file, none without it
+    pub fn from_env() -> Res<Self> {
+        match std::env::var("SPAM_RULES") {
+            Ok(path) => Self::load(&path),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+    pub fn load(path: &str) -> Res<Self> {
+        let text = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&text)?)
+    }
+
+    /// Evaluates the raw text of a post, and its rendered comment for duplicate detection,
+    /// returning the strictest action triggered by the rules
+    pub async fn check(
+        &self,
+        pool: &SqlitePool,
+        text: &str,
+        com: Option<&str>,
+    ) -> Res<Option<Verdict>> {
+        let mut verdict = self.check_text(text);
+        if let (Some(rule), Some(com)) = (&self.duplicate, com) {
+            let duplicate: bool = sqlx::query_scalar(
+                r#"
+                SELECT EXISTS(
+                    SELECT 1 FROM comments
+                    WHERE com = ? AND created_at >= strftime('%s', 'now') - ?
+                )
+                "#,
+            )
+            .bind(com)
+            .bind(rule.window)
+            .fetch_one(pool)
+            .await?;
+            if duplicate {
+                verdict = strictest(verdict, rule.action, "duplicate post");
+            }
+        }
+        Ok(verdict)
+    }
+    /// Fails with the reason of a rejection, otherwise returns whether the post should be held
+    pub async fn screen(&self, pool: &SqlitePool, text: &str, com: Option<&str>) -> Res<bool> {
+        match self.check(pool, text, com).await? {
+            Some(Verdict {
+                action: SpamAction::Reject,
+                reason,
+            }) => Err(reason.into()),
+            verdict => Ok(verdict.is_some()),
+        }
+    }
+
+    fn check_text(&self, text: &str) -> Option<Verdict> {
+        let mut verdict = None;
+        for rule in &self.blacklist {
+            if rule.pattern.is_match(text) {
+                verdict = strictest(verdict, rule.action, "blacklisted content");
+            }
+        }
+        if let Some(rule) = &self.links
+            && RE_LINK.find_iter(text).count() > rule.max_links
+        {
+            verdict = strictest(verdict, rule.action, "too many links");
+        }
+        if let Some(rule) = &self.caps {
+            let letters = text.chars().filter(|c| c.is_alphabetic()).count();
+            let upper = text.chars().filter(|c| c.is_uppercase()).count();
+            if letters >= rule.min_letters && upper as f64 >= rule.ratio * letters as f64 {
+                verdict = strictest(verdict, rule.action, "too many capital letters");
+            }
+        }
+        verdict
+    }
+}
+
+fn deserialize_regex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Regex, D::Error> {
+    let pattern = String::deserialize(deserializer)?;
+    Regex::new(&pattern).map_err(serde::de::Error::custom)
+}
+fn strictest(
+    verdict: Option<Verdict>,
+    action: SpamAction,
+    reason: &'static str,
+) -> Option<Verdict> {
+    match verdict {
+        Some(v) if v.action >= action => Some(v),
+        _ => Some(Verdict { action, reason }),
+    }
+}
+
+#[test]
+fn test_check_text() {
+    let rules: SpamRules = serde_json::from_str(
+        r#"{
+            "blacklist": [{ "pattern": "(?i)viagra", "action": "reject" }],
+            "links": { "max_links": 1, "action": "hold" },
+            "caps": { "ratio": 0.8, "min_letters": 10, "action": "reject" }
+        }"#,
+    )
+    .unwrap();
+
+    assert_eq!(rules.check_text("hello world"), None);
+    assert_eq!(rules.check_text("SHORT"), None);
+    assert_eq!(
+        rules.check_text("buy VIAgra"),
+        Some(Verdict {
+            action: SpamAction::Reject,
+            reason: "blacklisted content"
+        })
+    );
+    assert_eq!(
+        rules.check_text("http://a.com https://b.com"),
+        Some(Verdict {
+            action: SpamAction::Hold,
+            reason: "too many links"
+        })
+    );
+    assert_eq!(
+        rules.check_text("http://a.com https://b.com VIAGRA"),
+        Some(Verdict {
+            action: SpamAction::Reject,
+            reason: "blacklisted content"
+        })
+    );
+    assert_eq!(
+        rules.check_text("WHY IS EVERYONE SHOUTING"),
+        Some(Verdict {
+            action: SpamAction::Reject,
+            reason: "too many capital letters"
+        })
+    );
+}
@@ -0,0 +1,195 @@
+use std::path::Path;
+
+use axum::http::StatusCode;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use sqlx::prelude::FromRow;
+
+use crate::media::remove_media;
+use crate::vault::SealingKey;
+use crate::{Res, StatusError};
+
+pub const REMOVED_FOR_LEGAL_REASONS: &str = "removed for legal reasons";
+
+#[derive(Serialize, Deserialize, sqlx::Type, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum TakedownStatus {
+    Pending,
+    Actioned,
+    Dismissed,
+}
+
+#[derive(Serialize, Deserialize, FromRow)]
+pub struct Takedown {
+    pub id: i64,
+    pub claimant: String,
+    pub contact: String,
+    pub reason: String,
+    pub status: TakedownStatus,
+    pub created_at: i64,
+    pub resolved_at: Option<i64>,
+    #[sqlx(skip)]
+    pub post_ids: Vec<i64>,
+}
+
+#[derive(FromRow)]
+struct TakenMedia {
+    id: i64,
+    media_name: String,
+    thumb_name: Option<String>,
+    catalog_thumb_name: Option<String>,
+    board: String,
+}
+
+/// Keeps encrypted copies of the media taken down, TAKEDOWN_KEY is a base64 encoded 32 bytes key
+/// and the copies are deleted after TAKEDOWN_RETENTION seconds (default 180 days)
+pub struct TakedownVault {
+    key: SealingKey,
+    retention: i64,
+}
+
+impl TakedownVault {
+    pub fn from_env() -> Res<Option<Self>> {
+        let Some(key) = SealingKey::from_env("TAKEDOWN_KEY")? else {
+            return Ok(None);
+        };
+        Ok(Some(Self {
+            key,
+            retention: std::env::var("TAKEDOWN_RETENTION")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(180 * 24 * 60 * 60),
+        }))
+    }
+
+    pub fn open(&self, sealed: &[u8]) -> Res<Vec<u8>> {
+        self.key.open(sealed)
+    }
+}
+
+pub async fn fetch(pool: &SqlitePool, id: i64) -> Res<Takedown> {
+    let takedown = sqlx::query_as(r#"SELECT * FROM takedowns WHERE id = ?"#)
+        .bind(id)
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| StatusError(StatusCode::NOT_FOUND, "takedown not found".to_string()))?;
+    let mut takedowns = [takedown];
+    fetch_post_ids(pool, &mut takedowns).await?;
+    let [takedown] = takedowns;
+    Ok(takedown)
+}
+
+pub async fn fetch_post_ids(pool: &SqlitePool, takedowns: &mut [Takedown]) -> Res<()> {
+    let ids = serde_json::to_string(&takedowns.iter().map(|t| t.id).collect::<Vec<_>>())?;
+    let posts: Vec<(i64, i64)> = sqlx::query_as(
+        r#"
+        SELECT takedown_id, post_id FROM takedown_posts
+        WHERE takedown_id IN (SELECT value FROM json_each(?))
+        ORDER BY post_id
+        "#,
+    )
+    .bind(ids)
+    .fetch_all(pool)
+    .await?;
+    for takedown in takedowns {
+        takedown.post_ids = posts
+            .iter()
+            .filter(|(id, _)| *id == takedown.id)
+            .map(|(_, post_id)| *post_id)
+            .collect();
+    }
+    Ok(())
+}
+
+/// Replaces the media of the posts with the legal placeholder, the media being kept encrypted in
+/// `legal/` and the thumbnails removed. Returns the boards of the posts
+pub async fn action(
+    pool: &SqlitePool,
+    vault: &TakedownVault,
+    takedown: &Takedown,
+) -> Res<Vec<String>> {
+    let ids = serde_json::to_string(&takedown.post_ids)?;
+    let posts: Vec<TakenMedia> = sqlx::query_as(
+        r#"
+        SELECT c.id, c.media_name, c.thumb_name, c.catalog_thumb_name, COALESCE(t.board, c.board) AS board
+        FROM comments c
+        LEFT JOIN comments t ON t.id = c.op
+        WHERE c.id IN (SELECT value FROM json_each(?)) AND c.media_name IS NOT NULL
+        "#,
+    )
+    .bind(&ids)
+    .fetch_all(pool)
+    .await?;
+
+    tokio::fs::create_dir_all("legal").await?;
+    for post in &posts {
+        let data = tokio::fs::read(Path::new("media").join(&post.media_name)).await?;
+        let sealed = vault.key.seal(data)?;
+        tokio::fs::write(Path::new("legal").join(&post.media_name), sealed).await?;
+        sqlx::query(
+            r#"
+            INSERT INTO takedown_media (takedown_id, post_id, file_name, expires_at)
+            VALUES (?, ?, ?, strftime('%s', 'now') + ?)
+            "#,
+        )
+        .bind(takedown.id)
+        .bind(post.id)
+        .bind(&post.media_name)
+        .bind(vault.retention)
+        .execute(pool)
+        .await?;
+    }
+
+    let mut tx = pool.begin().await?;
+    sqlx::query(
+        r#"
+        UPDATE comments
+        SET media_name = NULL, media_hash = NULL, thumb_name = NULL, catalog_thumb_name = NULL, media_removed = ?
+        WHERE id IN (SELECT value FROM json_each(?)) AND media_name IS NOT NULL
+        "#,
+    )
+    .bind(REMOVED_FOR_LEGAL_REASONS)
+    .bind(&ids)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query(
+        r#"UPDATE takedowns SET status = 'actioned', resolved_at = strftime('%s', 'now') WHERE id = ?"#,
+    )
+    .bind(takedown.id)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    let mut boards = Vec::new();
+    for post in posts {
+        let names = [
+            Some(post.media_name),
+            post.thumb_name,
+            post.catalog_thumb_name,
+        ];
+        for name in names.into_iter().flatten() {
+            remove_media(&name).await?;
+        }
+        if !boards.contains(&post.board) {
+            boards.push(post.board);
+        }
+    }
+    Ok(boards)
+}
+
+/// Deletes the encrypted copies kept longer than the retention period
+pub async fn purge_expired(pool: &SqlitePool) -> Res<usize> {
+    let names: Vec<String> = sqlx::query_scalar(
+        r#"DELETE FROM takedown_media WHERE expires_at < strftime('%s', 'now') RETURNING file_name"#,
+    )
+    .fetch_all(pool)
+    .await?;
+    for name in &names {
+        match tokio::fs::remove_file(Path::new("legal").join(name)).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+    }
+    Ok(names.len())
+}
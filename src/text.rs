@@ -0,0 +1,217 @@
+use std::sync::LazyLock;
+
+use regex::Regex;
+use reqwest::Url;
+use unicode_properties::{GeneralCategory, UnicodeEmoji, UnicodeGeneralCategory, emoji};
+
+static RE_SCHEME: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)\bhttps?://").unwrap());
+
+/// A url found in a text, `text` is how it was written and `url` its parsed form, with the
+/// host of internationalized domains in punycode
+pub struct Link<'a> {
+    pub start: usize,
+    pub text: &'a str,
+    pub url: Url,
+}
+
+/// Counts the characters as readers see them, a base with its combining marks, an emoji with
+/// its modifiers and joined emojis, a flag or a CRLF count as one. A simpler take on the
+/// extended grapheme clusters of UAX #29, without the hangul and prepend rules
+pub fn graphemes(s: &str) -> usize {
+    let mut count = 0;
+    let mut prev: Option<char> = None;
+    let mut pictographic = false;
+    let mut flag_open = false;
+    for c in s.chars() {
+        let regional = emoji::is_regional_indicator(c);
+        let joins = match prev {
+            None => false,
+            Some('\r') => c == '\n',
+            Some(p) if p.is_control() || c.is_control() => false,
+            Some(p) => {
+                extends(c)
+                    || (emoji::is_zwj(p) && pictographic && is_pictographic(c))
+                    || (regional && flag_open)
+            }
+        };
+        if !joins {
+            count += 1;
+            pictographic = is_pictographic(c);
+        }
+        flag_open = regional && !(joins && flag_open);
+        prev = Some(c);
+    }
+    count
+}
+
+fn extends(c: char) -> bool {
+    matches!(
+        c.general_category(),
+        GeneralCategory::NonspacingMark
+            | GeneralCategory::SpacingMark
+            | GeneralCategory::EnclosingMark
+    ) || emoji::is_zwj(c)
+        || c == '\u{200C}'
+        || emoji::is_tag_character(c)
+        || ('\u{1F3FB}'..='\u{1F3FF}').contains(&c)
+}
+
+fn is_pictographic(c: char) -> bool {
+    !c.is_ascii() && c.is_emoji_char()
+}
+
+/// Finds the http and https urls of the text, ending at the first space or character that
+/// can't be in a url, without the punctuation that closes the sentence around them. The
+/// closing parentheses and brackets are kept when the url opens them
+pub fn find_urls(s: &str) -> Vec<Link<'_>> {
+    let mut links = Vec::new();
+    let mut from = 0;
+    while let Some(scheme) = RE_SCHEME.find_at(s, from) {
+        let start = scheme.start();
+        let end = s[start..]
+            .char_indices()
+            .find(|(_, c)| !is_url_char(*c))
+            .map_or(s.len(), |(i, _)| start + i);
+        let text = trim_url(&s[start..end]);
+        from = start + text.len().max(scheme.len());
+        let url = Url::parse(text)
+            .ok()
+            .filter(|u| u.host_str().is_some_and(|h| !h.is_empty()));
+        if let Some(url) = url {
+            links.push(Link { start, text, url });
+        }
+    }
+    links
+}
+
+fn is_url_char(c: char) -> bool {
+    !c.is_whitespace()
+        && !c.is_control()
+        && !matches!(c, '<' | '>' | '"' | '`' | '{' | '}' | '|' | '\\' | '^')
+        && !matches!(c.general_category(), GeneralCategory::PrivateUse)
+        && !is_zero_width(c)
+        && !is_bidi_control(c)
+}
+
+fn trim_url(mut url: &str) -> &str {
+    let unbalanced =
+        |url: &str, open, close| url.matches(open).count() < url.matches(close).count();
+    while let Some(last) = url.chars().next_back() {
+        let trailing = match last {
+            '.' | ',' | ':' | ';' | '!' | '?' | '\'' | '*' => true,
+            ')' => unbalanced(url, '(', ')'),
+            ']' => unbalanced(url, '[', ']'),
+            _ => false,
+        };
+        if !trailing {
+            break;
+        }
+        url = &url[..url.len() - last.len_utf8()];
+    }
+    url
+}
+
+/// The bidi embeddings, overrides and isolates, which reorder the text around them and can make
+/// a post read differently from what it says
+fn is_bidi_control(c: char) -> bool {
+    matches!(c, '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}')
+}
+
+/// The characters that take no space, including the joiners and direction marks some scripts
+/// and emojis need
+fn is_zero_width(c: char) -> bool {
+    matches!(
+        c,
+        '\u{200B}'..='\u{200F}' | '\u{2060}'..='\u{2064}' | '\u{FEFF}' | '\u{180E}'
+    )
+}
+
+/// Whether nothing of the text would be visible
+pub fn is_blank(s: &str) -> bool {
+    s.chars()
+        .all(|c| c.is_whitespace() || is_zero_width(c) || is_bidi_control(c))
+}
+
+/// Rejects the bidi controls and the zero width characters that don't sit alone between two
+/// visible characters, which is how they are used to hide text or dodge filters
+pub fn check_controls(s: &str) -> Result<(), &'static str> {
+    if s.chars().any(is_bidi_control) {
+        return Err("bidi control characters are not allowed");
+    }
+    let visible = |c: Option<char>| c.is_some_and(|c| !c.is_whitespace() && !is_zero_width(c));
+    let chars: Vec<char> = s.chars().collect();
+    for (i, &c) in chars.iter().enumerate() {
+        if is_zero_width(c) {
+            let before = i.checked_sub(1).map(|i| chars[i]);
+            let after = chars.get(i + 1).copied();
+            if !visible(before) || !visible(after) {
+                return Err("zero width characters are only allowed between visible characters");
+            }
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_graphemes() {
+    assert_eq!(graphemes(""), 0);
+    assert_eq!(graphemes("abc"), 3);
+    assert_eq!(graphemes("e\u{301}"), 1);
+    assert_eq!(graphemes("\r\n"), 1);
+    assert_eq!(graphemes("\n\u{301}"), 2);
+    assert_eq!(graphemes("👍🏽"), 1);
+    assert_eq!(graphemes("❤\u{FE0F}"), 1);
+    assert_eq!(graphemes("👨\u{200D}👩\u{200D}👧"), 1);
+    assert_eq!(graphemes("🇮🇹🇫🇷🇩"), 3);
+    assert_eq!(graphemes("a\u{200D}1"), 2);
+}
+
+#[test]
+fn test_find_urls() {
+    let urls = |s| {
+        find_urls(s)
+            .into_iter()
+            .map(|l| (l.text, l.url.to_string()))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        urls("see https://a.example/x."),
+        [("https://a.example/x", "https://a.example/x".to_string())]
+    );
+    assert_eq!(
+        urls("(https://en.wikipedia.org/wiki/Rust_(language)), ok?"),
+        [(
+            "https://en.wikipedia.org/wiki/Rust_(language)",
+            "https://en.wikipedia.org/wiki/Rust_(language)".to_string()
+        )]
+    );
+    assert_eq!(
+        urls("https://münchen.de/straße! and HTTP://b.example"),
+        [
+            (
+                "https://münchen.de/straße",
+                "https://xn--mnchen-3ya.de/stra%C3%9Fe".to_string()
+            ),
+            ("HTTP://b.example", "http://b.example/".to_string())
+        ]
+    );
+    assert_eq!(
+        urls("<https://a.example>"),
+        [("https://a.example", "https://a.example/".to_string())]
+    );
+    assert!(urls("https:// http://. xhttps://a.example").is_empty());
+}
+
+#[test]
+fn test_check_controls() {
+    assert!(check_controls("hello").is_ok());
+    assert!(check_controls("👨\u{200D}👩\u{200D}👧").is_ok());
+    assert!(check_controls("می\u{200C}خواهم").is_ok());
+    assert!(check_controls("abc\u{202E}fdp.exe").is_err());
+    assert!(check_controls("\u{2067}x").is_err());
+    assert!(check_controls("a\u{200B}\u{200B}b").is_err());
+    assert!(check_controls("\u{200B}a").is_err());
+    assert!(check_controls("a \u{200B}").is_err());
+    assert!(is_blank(" \u{200B}\u{FEFF}\n"));
+    assert!(!is_blank(" a "));
+}
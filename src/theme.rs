@@ -0,0 +1,85 @@
+use std::sync::LazyLock;
+
+use regex::Regex;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+use crate::media::is_media_name;
+
+static RE_COMMENTS: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?s)/\*.*?\*/").unwrap());
+static RE_FORBIDDEN: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?i)@import|@charset|@namespace|expression\s*\(|javascript:|behavior\s*:|-moz-binding",
+    )
+    .unwrap()
+});
+static RE_URLS: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(?i)url\(\s*['"]?([^'")\s]*)"#).unwrap());
+
+/// How a frontend may style a board, the banners are media names or https urls
+#[derive(Serialize, Deserialize, Default)]
+pub struct BoardSettings {
+    pub theme: Option<String>,
+    #[serde(default)]
+    pub banners: Vec<String>,
+    pub custom_css: Option<String>,
+}
+
+/// Returns the stylesheet without its comments, rejecting what could run scripts, pull in other
+/// stylesheets or close the style element it is put in. Escapes are refused since they could
+/// spell any of these, and urls can only point to the media of the board or to https
+pub fn sanitize_css(css: &str) -> Result<String, &'static str> {
+    let css = RE_COMMENTS.replace_all(css, "");
+    if css.contains(['<', '\\']) || css.contains("/*") {
+        return Err("the css can't contain <, \\ or unclosed comments");
+    }
+    if RE_FORBIDDEN.is_match(&css) {
+        return Err("the css can't contain imports, expressions, bindings or scripts");
+    }
+    for c in RE_URLS.captures_iter(&css) {
+        let url = &c[1];
+        let allowed = match url.strip_prefix("/media/") {
+            Some(name) => is_media_name(name),
+            None => Url::parse(url).is_ok_and(|u| u.scheme() == "https"),
+        };
+        if !allowed {
+            return Err("the css can only load urls from /media/ or https");
+        }
+    }
+    Ok(css.trim().to_string())
+}
+
+pub fn check_banners(banners: &[String]) -> Result<(), &'static str> {
+    let valid = |b: &String| is_media_name(b) || Url::parse(b).is_ok_and(|u| u.scheme() == "https");
+    match banners.iter().all(valid) {
+        true => Ok(()),
+        false => Err("banners have to be media names or https urls"),
+    }
+}
+
+pub fn is_theme_name(theme: &str) -> bool {
+    !theme.is_empty()
+        && theme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+#[test]
+fn test_sanitize_css() {
+    assert_eq!(
+        sanitize_css(" /* dark */ body { color: #eee; } ").as_deref(),
+        Ok("body { color: #eee; }")
+    );
+    assert!(sanitize_css("a > b { background: url('/media/bg.png') }").is_ok());
+    assert!(sanitize_css(r#"body { background: url("https://cdn.example/a.png") }"#).is_ok());
+    assert!(sanitize_css("</style><script>alert(1)</script>").is_err());
+    assert!(sanitize_css("@IMPORT 'https://a.example/x.css';").is_err());
+    assert!(sanitize_css("a { width: expr/**/ession(alert(1)) }").is_err());
+    assert!(sanitize_css(r"a { b: \65 xpression(1) }").is_err());
+    assert!(sanitize_css("a { background: url(javascript:alert(1)) }").is_err());
+    assert!(sanitize_css("a { background: url(http://a.example/x.png) }").is_err());
+    assert!(sanitize_css("a { background: url(/media/../db.sqlite) }").is_err());
+    assert!(sanitize_css("a { b: c } /* open").is_err());
+    assert!(check_banners(&["a.png".to_string(), "https://a.example/b.gif".to_string()]).is_ok());
+    assert!(check_banners(&["../a.png".to_string()]).is_err());
+}
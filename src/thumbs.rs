@@ -0,0 +1,304 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Cursor, Seek};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::LazyLock;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Instant;
+
+use image::codecs::jpeg::JpegEncoder;
+use image::{Rgb, RgbImage};
+use serde::{Deserialize, Serialize};
+use thumbnailer::{Thumbnail, ThumbnailSize, create_thumbnails};
+use tokio::sync::Semaphore;
+
+use crate::Res;
+
+/// Thumbnails are created on the blocking pool, at most THUMB_WORKERS at once (default the
+/// number of cores), the other uploads wait for a worker in the queue
+static WORKERS: LazyLock<Semaphore> = LazyLock::new(|| {
+    let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+    Semaphore::new(env_var("THUMB_WORKERS").unwrap_or(cores).max(1))
+});
+static QUEUE: QueueStats = QueueStats {
+    waiting: AtomicUsize::new(0),
+    jobs: AtomicU64::new(0),
+    queued_us: AtomicU64::new(0),
+    max_queued_us: AtomicU64::new(0),
+};
+
+struct QueueStats {
+    waiting: AtomicUsize,
+    jobs: AtomicU64,
+    queued_us: AtomicU64,
+    max_queued_us: AtomicU64,
+}
+
+#[derive(Serialize)]
+pub struct WorkerStats {
+    waiting: usize,
+    jobs: u64,
+    avg_queue_ms: f64,
+    max_queue_ms: f64,
+}
+
+#[derive(Serialize, Deserialize, sqlx::Type, Clone, Copy, PartialEq, Eq, Default, Debug)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum ThumbFormat {
+    #[default]
+    Jpeg,
+    Webp,
+}
+impl ThumbFormat {
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            ThumbFormat::Jpeg => "image/jpeg",
+            ThumbFormat::Webp => "image/webp",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ThumbSettings {
+    pub size: u32,
+    pub catalog_size: Option<u32>,
+    pub quality: u8,
+    pub format: ThumbFormat,
+}
+
+pub struct Thumb {
+    pub data: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+    pub format: ThumbFormat,
+}
+
+impl ThumbSettings {
+    pub fn from_env() -> Self {
+        Self {
+            size: env_var("THUMB_SIZE").unwrap_or(256),
+            catalog_size: env_var("THUMB_CATALOG_SIZE"),
+            quality: env_var("THUMB_QUALITY").unwrap_or(100).clamp(1, 100),
+            format: match std::env::var("THUMB_FORMAT").as_deref() {
+                Ok("webp") => ThumbFormat::Webp,
+                _ => ThumbFormat::Jpeg,
+            },
+        }
+    }
+
+    /// Applies the settings a board overrides, values are expected to be validated already
+    pub fn with_overrides(
+        self,
+        size: Option<i64>,
+        catalog_size: Option<i64>,
+        quality: Option<i64>,
+        format: Option<ThumbFormat>,
+    ) -> Self {
+        Self {
+            size: size.map_or(self.size, |s| s as u32),
+            catalog_size: catalog_size.map(|s| s as u32).or(self.catalog_size),
+            quality: quality.map_or(self.quality, |q| q as u8),
+            format: format.unwrap_or(self.format),
+        }
+    }
+
+    /// Creates the thumbnail, and the smaller catalog thumbnail when enabled
+    pub fn create(&self, media: impl BufRead + Seek, mime: &str) -> Res<(Thumb, Option<Thumb>)> {
+        let mut sizes = vec![ThumbnailSize::Custom((self.size, self.size))];
+        sizes.extend(self.catalog_size.map(|s| ThumbnailSize::Custom((s, s))));
+        let mut thumbs = create_thumbnails(media, mime::Mime::from_str(mime)?, sizes)?
+            .into_iter()
+            .map(|thumb| self.encode(thumb));
+        let thumb = thumbs.next().ok_or("Failed to create thumbnails")??;
+        let catalog = thumbs.next().transpose()?;
+        Ok((thumb, catalog))
+    }
+
+    /// Same as [`Self::create`] for the file at `path`, waiting for a free worker and running
+    /// on the blocking pool
+    pub async fn create_blocking(self, path: PathBuf, mime: String) -> Res<(Thumb, Option<Thumb>)> {
+        let queued = Instant::now();
+        QUEUE.waiting.fetch_add(1, Ordering::Relaxed);
+        let permit = WORKERS.acquire().await;
+        QUEUE.waiting.fetch_sub(1, Ordering::Relaxed);
+        let _permit = permit?;
+
+        let queued_us = queued.elapsed().as_micros() as u64;
+        QUEUE.jobs.fetch_add(1, Ordering::Relaxed);
+        QUEUE.queued_us.fetch_add(queued_us, Ordering::Relaxed);
+        QUEUE.max_queued_us.fetch_max(queued_us, Ordering::Relaxed);
+
+        tokio::task::spawn_blocking(move || {
+            let media = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
+            self.create(media, &mime).map_err(|e| e.to_string())
+        })
+        .await?
+        .map_err(|e| e.into())
+    }
+
+    /// A crossed gray square in place of the thumbnails of media that couldn't be thumbnailed
+    pub fn placeholder(&self) -> Res<(Thumb, Option<Thumb>)> {
+        let thumb = self.encode_image(placeholder_image(self.size))?;
+        let catalog = self
+            .catalog_size
+            .map(|s| self.encode_image(placeholder_image(s)))
+            .transpose()?;
+        Ok((thumb, catalog))
+    }
+
+    /// A page with a folded corner, the thumbnail of the documents without a cover
+    pub fn document_icon(&self) -> Res<(Thumb, Option<Thumb>)> {
+        let thumb = self.encode_image(document_image(self.size))?;
+        let catalog = self
+            .catalog_size
+            .map(|s| self.encode_image(document_image(s)))
+            .transpose()?;
+        Ok((thumb, catalog))
+    }
+
+    /// Bars of a stylized waveform, the thumbnail of the audio without a drawn waveform
+    pub fn audio_icon(&self) -> Res<(Thumb, Option<Thumb>)> {
+        let thumb = self.encode_image(audio_image(self.size))?;
+        let catalog = self
+            .catalog_size
+            .map(|s| self.encode_image(audio_image(s)))
+            .transpose()?;
+        Ok((thumb, catalog))
+    }
+
+    fn encode_image(&self, image: RgbImage) -> Res<Thumb> {
+        let (width, height) = image.dimensions();
+        let mut data = Cursor::new(Vec::new());
+        match self.format {
+            ThumbFormat::Jpeg => {
+                JpegEncoder::new_with_quality(&mut data, self.quality).encode_image(&image)?
+            }
+            ThumbFormat::Webp => {
+                let webp = webp::Encoder::from_rgb(&image, width, height)
+                    .encode(self.quality as f32)
+                    .to_vec();
+                data = Cursor::new(webp);
+            }
+        }
+        Ok(Thumb {
+            data: data.into_inner(),
+            width,
+            height,
+            format: self.format,
+        })
+    }
+
+    fn encode(&self, thumb: Thumbnail) -> Res<Thumb> {
+        let (width, height) = thumb.size();
+        let mut data = Cursor::new(Vec::new());
+        match self.format {
+            ThumbFormat::Jpeg => thumb.write_jpeg(&mut data, self.quality)?,
+            ThumbFormat::Webp => {
+                // thumbnailer only writes png and jpeg, so the png is reencoded
+                thumb.write_png(&mut data)?;
+                let image = image::load_from_memory(data.get_ref())?.into_rgba8();
+                let webp = webp::Encoder::from_rgba(&image, width, height)
+                    .encode(self.quality as f32)
+                    .to_vec();
+                data = Cursor::new(webp);
+            }
+        }
+        Ok(Thumb {
+            data: data.into_inner(),
+            width,
+            height,
+            format: self.format,
+        })
+    }
+}
+
+fn placeholder_image(size: u32) -> RgbImage {
+    let last = size.saturating_sub(1);
+    RgbImage::from_fn(size, size, |x, y| {
+        let edge = x.min(y) < 2 || x.max(y) + 2 > last;
+        let cross = x.abs_diff(y) < 2 || (x + y).abs_diff(last) < 2;
+        match edge || cross {
+            true => Rgb([150, 150, 150]),
+            false => Rgb([210, 210, 210]),
+        }
+    })
+}
+
+fn audio_image(size: u32) -> RgbImage {
+    const BARS: [u32; 9] = [3, 6, 9, 5, 10, 7, 4, 8, 2];
+    let width = (size / (BARS.len() as u32 * 2 + 1)).max(1);
+    let mid = size / 2;
+    RgbImage::from_fn(size, size, |x, y| {
+        let bar = (x / width)
+            .checked_sub(1)
+            .filter(|b| b % 2 == 0)
+            .map(|b| b / 2);
+        let height = bar
+            .and_then(|b| BARS.get(b as usize))
+            .map(|h| h * size / 24);
+        match height {
+            Some(h) if y.abs_diff(mid) <= h => Rgb([90, 125, 191]),
+            _ => Rgb([230, 230, 230]),
+        }
+    })
+}
+
+fn document_image(size: u32) -> RgbImage {
+    let (left, right, top, bottom) = (size / 5, size - size / 5, size / 10, size - size / 10);
+    let fold = size / 5;
+    RgbImage::from_fn(size, size, |x, y| {
+        let inside = (left..right).contains(&x) && (top..bottom).contains(&y);
+        // the distance of the pixel to the top right corner of the page, along the diagonal
+        let corner = (right - 1).saturating_sub(x) + y.saturating_sub(top);
+        let color = match inside {
+            false => [230, 230, 230],
+            true if corner < fold => [200, 200, 200],
+            true if corner == fold
+                || x < left + 2
+                || x + 2 >= right
+                || y < top + 2
+                || y + 2 >= bottom =>
+            {
+                [150, 150, 150]
+            }
+            true => [255, 255, 255],
+        };
+        Rgb(color)
+    })
+}
+
+pub fn worker_stats() -> WorkerStats {
+    let jobs = QUEUE.jobs.load(Ordering::Relaxed);
+    let queued_us = QUEUE.queued_us.load(Ordering::Relaxed);
+    WorkerStats {
+        waiting: QUEUE.waiting.load(Ordering::Relaxed),
+        jobs,
+        avg_queue_ms: queued_us as f64 / jobs.max(1) as f64 / 1000.0,
+        max_queue_ms: QUEUE.max_queued_us.load(Ordering::Relaxed) as f64 / 1000.0,
+    }
+}
+
+fn env_var<T: FromStr>(name: &str) -> Option<T> {
+    std::env::var(name).ok().and_then(|v| v.parse().ok())
+}
+
+#[test]
+fn test_with_overrides() {
+    let global = ThumbSettings {
+        size: 256,
+        catalog_size: Some(128),
+        quality: 100,
+        format: ThumbFormat::Jpeg,
+    };
+    assert_eq!(global.with_overrides(None, None, None, None), global);
+    assert_eq!(
+        global.with_overrides(Some(512), None, Some(80), Some(ThumbFormat::Webp)),
+        ThumbSettings {
+            size: 512,
+            catalog_size: Some(128),
+            quality: 80,
+            format: ThumbFormat::Webp,
+        }
+    );
+}
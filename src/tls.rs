@@ -0,0 +1,63 @@
+use std::net::SocketAddr;
+
+use axum::Router;
+use axum::http::{HeaderMap, Uri};
+use axum::response::Redirect;
+use axum_server::tls_rustls::RustlsConfig;
+
+use crate::Res;
+
+/// Serves the app over https with the pem encoded certificate chain and private key
+pub async fn serve_tls(app: Router, port: u16, cert: &str, key: &str) -> Res<()> {
+    let _ = rustls::crypto::ring::default_provider().install_default();
+    let config = RustlsConfig::from_pem_file(cert, key).await?;
+    tracing::info!("serving https on port {port}");
+    axum_server::bind_rustls(SocketAddr::from(([0, 0, 0, 0], port)), config)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .await?;
+    Ok(())
+}
+
+/// Redirects every plain http request on `port` to the same url over https on `https_port`
+pub async fn redirect_http(port: u16, https_port: u16) -> Res<()> {
+    let app = Router::new().fallback(move |headers: HeaderMap, uri: Uri| async move {
+        let host = headers
+            .get("host")
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or("localhost");
+        Redirect::permanent(&https_url(host, https_port, &uri))
+    });
+    let listener = tokio::net::TcpListener::bind(SocketAddr::from(([0, 0, 0, 0], port))).await?;
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+fn https_url(host: &str, port: u16, uri: &Uri) -> String {
+    // the port of the host is dropped, ipv6 hosts are bracketed so only look past the bracket
+    let host = match host.rfind(':') {
+        Some(i) if !host[i..].contains(']') => &host[..i],
+        _ => host,
+    };
+    let path = uri.path_and_query().map_or("/", |p| p.as_str());
+    match port {
+        443 => format!("https://{host}{path}"),
+        _ => format!("https://{host}:{port}{path}"),
+    }
+}
+
+#[test]
+fn test_https_url() {
+    let uri: Uri = "/g/thread/1?since_id=2".parse().unwrap();
+    assert_eq!(
+        https_url("example.org:80", 443, &uri),
+        "https://example.org/g/thread/1?since_id=2"
+    );
+    assert_eq!(
+        https_url("example.org", 8443, &uri),
+        "https://example.org:8443/g/thread/1?since_id=2"
+    );
+    assert_eq!(
+        https_url("[::1]:8080", 443, &"/".parse().unwrap()),
+        "https://[::1]/"
+    );
+}
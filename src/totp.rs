@@ -0,0 +1,290 @@
+//! Time based one time passwords (RFC 6238) for the staff logged in with OIDC, every staff
+//! session has to be verified with a code or a recovery code before it grants its role. After
+//! [`MAX_FAILURES`] invalid codes in a row the staff is locked out for [`LOCKOUT`] seconds
+
+use axum::http::StatusCode;
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::SqlitePool;
+use sqlx::prelude::FromRow;
+use sqlx::types::Json;
+
+use crate::{Res, StatusError};
+
+const STEP: u64 = 30;
+const DIGITS: u32 = 6;
+const RECOVERY_CODES: usize = 10;
+const BASE32: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+pub const MAX_FAILURES: i64 = 5;
+pub const LOCKOUT: i64 = 15 * 60;
+
+/// What a staff needs to add the secret to an authenticator app, the recovery codes are only
+/// shown once
+#[derive(Serialize, Deserialize)]
+pub struct Enrollment {
+    pub secret: String,
+    pub otpauth_url: String,
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(FromRow)]
+struct StaffTotp {
+    secret: String,
+    recovery_codes: Json<Vec<String>>,
+    last_counter: i64,
+    enabled_at: Option<i64>,
+    locked_until: Option<i64>,
+}
+
+/// Whether the staff finished enrolling
+pub async fn is_enabled(pool: &SqlitePool, subject: &str) -> Res<bool> {
+    sqlx::query_scalar(
+        r#"SELECT EXISTS(SELECT 1 FROM staff_totp WHERE subject = ? AND enabled_at IS NOT NULL)"#,
+    )
+    .bind(subject)
+    .fetch_one(pool)
+    .await
+    .map_err(|e| e.into())
+}
+
+/// Generates a new secret for the staff, enabled by the first code verified with it
+pub async fn enroll(pool: &SqlitePool, subject: &str, name: &str) -> Res<Enrollment> {
+    if is_enabled(pool, subject).await? {
+        let message = "two factor authentication is already enabled".to_string();
+        return Err(StatusError(StatusCode::CONFLICT, message).into());
+    }
+    let secret = base32(&random_bytes::<20>()?);
+    let recovery_codes = (0..RECOVERY_CODES)
+        .map(|_| Ok(hex(&random_bytes::<5>()?)))
+        .collect::<Res<Vec<_>>>()?;
+    let hashes: Vec<String> = recovery_codes.iter().map(|c| hash_code(c)).collect();
+    sqlx::query(
+        r#"
+        INSERT INTO staff_totp (subject, secret, recovery_codes) VALUES (?, ?, ?)
+        ON CONFLICT (subject) DO UPDATE SET secret = excluded.secret,
+        recovery_codes = excluded.recovery_codes, last_counter = 0
+        "#,
+    )
+    .bind(subject)
+    .bind(&secret)
+    .bind(Json(hashes))
+    .execute(pool)
+    .await?;
+    let otpauth_url = reqwest::Url::parse_with_params(
+        &format!("otpauth://totp/blu:{name}"),
+        [("secret", secret.as_str()), ("issuer", "blu")],
+    )?;
+    Ok(Enrollment {
+        secret,
+        otpauth_url: otpauth_url.to_string(),
+        recovery_codes,
+    })
+}
+
+/// Checks a code of the authenticator, which can't be used twice, or a recovery code of an
+/// enabled secret, which is used up. The lockout is answered with 429
+pub async fn verify(pool: &SqlitePool, subject: &str, code: &str, now: u64) -> Res<()> {
+    let totp: Option<StaffTotp> = sqlx::query_as(r#"SELECT * FROM staff_totp WHERE subject = ?"#)
+        .bind(subject)
+        .fetch_optional(pool)
+        .await?;
+    let Some(totp) = totp else {
+        let message = "two factor authentication isn't enrolled".to_string();
+        return Err(StatusError(StatusCode::CONFLICT, message).into());
+    };
+    if let Some(locked_until) = totp.locked_until.filter(|t| *t > now as i64) {
+        return Err(locked_out(locked_until - now as i64).into());
+    }
+    let secret = decode_base32(&totp.secret).ok_or("invalid totp secret")?;
+    if let Some(counter) = check_code(&secret, code, now, totp.last_counter as u64) {
+        sqlx::query(
+            r#"
+            UPDATE staff_totp SET last_counter = ?, enabled_at = COALESCE(enabled_at, strftime('%s', 'now')),
+            failed_attempts = 0
+            WHERE subject = ?
+            "#,
+        )
+        .bind(counter as i64)
+        .bind(subject)
+        .execute(pool)
+        .await?;
+        return Ok(());
+    }
+    let hash = hash_code(code.trim());
+    let mut codes = totp.recovery_codes.0;
+    if totp.enabled_at.is_some()
+        && let Some(i) = codes.iter().position(|c| *c == hash)
+    {
+        codes.remove(i);
+        sqlx::query(
+            r#"UPDATE staff_totp SET recovery_codes = ?, failed_attempts = 0 WHERE subject = ?"#,
+        )
+        .bind(Json(codes))
+        .bind(subject)
+        .execute(pool)
+        .await?;
+        return Ok(());
+    }
+    // the attempts are counted again after a lockout
+    let locked_until: Option<i64> = sqlx::query_scalar(
+        r#"
+        UPDATE staff_totp SET
+        locked_until = CASE WHEN failed_attempts + 1 >= ?1 THEN ?2 + ?3 ELSE locked_until END,
+        failed_attempts = CASE WHEN failed_attempts + 1 >= ?1 THEN 0 ELSE failed_attempts + 1 END
+        WHERE subject = ?4 RETURNING locked_until
+        "#,
+    )
+    .bind(MAX_FAILURES)
+    .bind(now as i64)
+    .bind(LOCKOUT)
+    .bind(subject)
+    .fetch_one(pool)
+    .await?;
+    match locked_until.filter(|t| *t > now as i64) {
+        Some(_) => Err(locked_out(LOCKOUT).into()),
+        None => Err(StatusError(StatusCode::UNAUTHORIZED, "invalid code".to_string()).into()),
+    }
+}
+
+fn locked_out(retry_after: i64) -> StatusError {
+    let message = format!("too many invalid codes, try again in {retry_after} seconds");
+    StatusError(StatusCode::TOO_MANY_REQUESTS, message)
+}
+
+/// Removes the secret of the staff, who enroll again on their next login
+pub async fn reset(pool: &SqlitePool, subject: &str) -> Res<bool> {
+    let deleted = sqlx::query(r#"DELETE FROM staff_totp WHERE subject = ?"#)
+        .bind(subject)
+        .execute(pool)
+        .await?;
+    Ok(deleted.rows_affected() > 0)
+}
+
+/// The counter of the code if it matches the previous, current or next step and wasn't used
+fn check_code(secret: &[u8], code: &str, now: u64, last_counter: u64) -> Option<u64> {
+    let code = code.trim();
+    if code.len() != DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let counter = now / STEP;
+    [counter.saturating_sub(1), counter, counter + 1]
+        .into_iter()
+        .filter(|c| *c > last_counter)
+        .find(|c| format!("{:0width$}", code_at(secret, *c), width = DIGITS as usize) == code)
+}
+
+fn code_at(secret: &[u8], counter: u64) -> u32 {
+    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, secret);
+    let digest = hmac::sign(&key, &counter.to_be_bytes());
+    let digest = digest.as_ref();
+    let offset = (digest[digest.len() - 1] & 0xf) as usize;
+    let bytes = [
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ];
+    u32::from_be_bytes(bytes) % 10u32.pow(DIGITS)
+}
+
+fn base32(data: &[u8]) -> String {
+    let mut out = String::new();
+    for chunk in data.chunks(5) {
+        let mut buf = [0u8; 5];
+        buf[..chunk.len()].copy_from_slice(chunk);
+        let bits = u64::from_be_bytes([0, 0, 0, buf[0], buf[1], buf[2], buf[3], buf[4]]);
+        let chars = (chunk.len() * 8).div_ceil(5);
+        for i in 0..chars {
+            out.push(BASE32[((bits >> (35 - i * 5)) & 0x1f) as usize] as char);
+        }
+    }
+    out
+}
+
+fn decode_base32(s: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    let (mut bits, mut len) = (0u64, 0);
+    for c in s.bytes() {
+        let value = BASE32.iter().position(|b| *b == c.to_ascii_uppercase())?;
+        bits = (bits << 5) | value as u64;
+        len += 5;
+        if len >= 8 {
+            len -= 8;
+            out.push((bits >> len) as u8);
+        }
+    }
+    Some(out)
+}
+
+fn random_bytes<const N: usize>() -> Res<[u8; N]> {
+    let mut bytes = [0; N];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| "failed to generate a secret")?;
+    Ok(bytes)
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn hash_code(code: &str) -> String {
+    hex(&Sha256::digest(code))
+}
+
+#[test]
+fn test_totp() {
+    // the sha1 vectors of RFC 6238, truncated to 6 digits
+    let secret = b"12345678901234567890";
+    assert_eq!(code_at(secret, 59 / STEP), 287082);
+    assert_eq!(code_at(secret, 1111111109 / STEP), 81804);
+    assert_eq!(check_code(secret, "081804", 1111111109, 0), Some(37037036));
+    assert_eq!(
+        check_code(secret, "081804", 1111111109 + 30, 0),
+        Some(37037036)
+    );
+    assert_eq!(check_code(secret, "081804", 1111111109, 37037036), None);
+    assert_eq!(check_code(secret, "81804", 1111111109, 0), None);
+    assert_eq!(
+        base32(b"12345678901234567890"),
+        "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ"
+    );
+    assert_eq!(decode_base32(&base32(secret)).unwrap(), secret);
+}
+
+#[tokio::test]
+async fn test_lockout() {
+    let pool = sqlx::sqlite::SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+    crate::MIGRATOR.run(&pool).await.unwrap();
+    let enrollment = enroll(&pool, "staff", "staff").await.unwrap();
+    let secret = decode_base32(&enrollment.secret).unwrap();
+    let now = 1_800_000_000;
+    let code = format!("{:06}", code_at(&secret, now / STEP));
+    let wrong = format!("{:06}", (code_at(&secret, now / STEP) + 1) % 1_000_000);
+
+    let status = |e: Box<dyn std::error::Error>| e.downcast::<StatusError>().unwrap().0;
+    for _ in 1..MAX_FAILURES {
+        let e = verify(&pool, "staff", &wrong, now).await.unwrap_err();
+        assert_eq!(status(e), StatusCode::UNAUTHORIZED);
+    }
+    let e = verify(&pool, "staff", &wrong, now).await.unwrap_err();
+    assert_eq!(status(e), StatusCode::TOO_MANY_REQUESTS);
+    // neither the right code nor a recovery code get through the lockout
+    let e = verify(&pool, "staff", &code, now + 60).await.unwrap_err();
+    assert_eq!(status(e), StatusCode::TOO_MANY_REQUESTS);
+    let recovery = &enrollment.recovery_codes[0];
+    let e = verify(&pool, "staff", recovery, now + 60)
+        .await
+        .unwrap_err();
+    assert_eq!(status(e), StatusCode::TOO_MANY_REQUESTS);
+
+    let later = now + LOCKOUT as u64 + STEP;
+    let code = format!("{:06}", code_at(&secret, later / STEP));
+    verify(&pool, "staff", &code, later).await.unwrap();
+}
@@ -0,0 +1,364 @@
+//! Export and import of boards as JSON, the format is
+//! ```json
+//! {
+//!   "version": 1,
+//!   "boards": [{
+//!     "board": { "code": "g", "name": "Technology", ... },
+//!     "threads": [{
+//!       "op": { "board_post_no": 1, "sub": "hello", "com": "first", "quotes": [], ... },
+//!       "replies": [{ "board_post_no": 2, "com": "&gt;&gt;1", "quotes": [1], ... }]
+//!     }]
+//!   }]
+//! }
+//! ```
+//! where `board` holds the same fields returned by the api, posts keep their board post numbers,
+//! `com` and `sub` are stored already rendered, with the text as it was posted in `com_raw`
+//! and `sub_raw` when it is known, and `quotes` lists the board post numbers a post
+//! replies to. Media is only carried when exported with `--media`, as base64 in `media`, and
+//! is thumbnailed again on import with the current settings. Ip hashes, edit tokens and
+//! passwords are never exported.
+
+use std::collections::HashMap;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use serde::{Deserialize, Serialize};
+use sqlx::prelude::FromRow;
+use sqlx::types::Json;
+use sqlx::{SqliteConnection, SqlitePool};
+
+use crate::extras::PostExtras;
+use crate::media::{Upload, save_media};
+use crate::{Board, Capcode, Config, Res, check_board_code, relink};
+
+const VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+pub struct Archive {
+    version: u32,
+    boards: Vec<ArchivedBoard>,
+}
+#[derive(Serialize, Deserialize)]
+struct ArchivedBoard {
+    board: Board,
+    threads: Vec<ArchivedThread>,
+}
+#[derive(Serialize, Deserialize)]
+struct ArchivedThread {
+    op: ArchivedPost,
+    replies: Vec<ArchivedPost>,
+}
+#[derive(Serialize, Deserialize, FromRow)]
+pub struct ArchivedPost {
+    #[serde(skip)]
+    pub id: i64,
+    #[serde(skip)]
+    pub op: Option<i64>,
+    #[serde(skip)]
+    pub media_name: Option<String>,
+    pub board_post_no: i64,
+    pub alias: Option<String>,
+    pub sub: Option<String>,
+    pub com: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sub_raw: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub com_raw: Option<String>,
+    pub file_name: Option<String>,
+    pub media_ext: Option<String>,
+    pub media_size: Option<i64>,
+    pub media_w: Option<i64>,
+    pub media_h: Option<i64>,
+    pub media_desc: Option<String>,
+    pub is_sticky: bool,
+    pub is_locked: bool,
+    pub is_held: bool,
+    pub created_at: i64,
+    pub edited_at: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_extras: Option<Json<PostExtras>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capcode: Option<Capcode>,
+    #[sqlx(skip)]
+    #[serde(default)]
+    pub quotes: Vec<i64>,
+    #[sqlx(skip)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media: Option<String>,
+}
+
+/// Exports the given boards, or all of them if none is given
+pub async fn export(pool: &SqlitePool, codes: &[String], with_media: bool) -> Res<Archive> {
+    let mut boards: Vec<Board> = sqlx::query_as(r#"SELECT * FROM boards ORDER BY code"#)
+        .fetch_all(pool)
+        .await?;
+    if !codes.is_empty() {
+        if let Some(code) = codes.iter().find(|c| !boards.iter().any(|b| &&b.code == c)) {
+            return Err(format!("board {code} not found").into());
+        }
+        boards.retain(|b| codes.contains(&b.code));
+    }
+
+    let mut archived = Vec::new();
+    for board in boards {
+        let posts: Vec<ArchivedPost> = sqlx::query_as(
+            r#"
+            SELECT c.* FROM comments c
+            LEFT JOIN comments t ON t.id = c.op
+            WHERE COALESCE(t.board, c.board) = ?
+            ORDER BY c.id
+            "#,
+        )
+        .bind(&board.code)
+        .fetch_all(pool)
+        .await?;
+        let quotes: Vec<(i64, i64)> = sqlx::query_as(
+            r#"
+            SELECT r.reply_id, p.board_post_no FROM post_replies r
+            JOIN comments p ON p.id = r.post_id
+            LEFT JOIN comments t ON t.id = p.op
+            WHERE COALESCE(t.board, p.board) = ?
+            ORDER BY r.reply_id, p.board_post_no
+            "#,
+        )
+        .bind(&board.code)
+        .fetch_all(pool)
+        .await?;
+
+        let mut threads: Vec<ArchivedThread> = Vec::new();
+        let mut thread_idx = HashMap::new();
+        for mut post in posts {
+            post.quotes = quotes
+                .iter()
+                .filter(|(reply_id, _)| *reply_id == post.id)
+                .map(|(_, post_no)| *post_no)
+                .collect();
+            if with_media && let Some(media_name) = &post.media_name {
+                let data = tokio::fs::read(format!("media/{media_name}")).await?;
+                post.media = Some(STANDARD.encode(data));
+            }
+            match post.op {
+                None => {
+                    thread_idx.insert(post.id, threads.len());
+                    threads.push(ArchivedThread {
+                        op: post,
+                        replies: Vec::new(),
+                    });
+                }
+                Some(op) => {
+                    let idx = thread_idx.get(&op).ok_or("reply to a missing thread")?;
+                    threads[*idx].replies.push(post);
+                }
+            }
+        }
+        archived.push(ArchivedBoard { board, threads });
+    }
+    Ok(Archive {
+        version: VERSION,
+        boards: archived,
+    })
+}
+
+/// Imports the boards of an archive, none of them may exist yet
+pub async fn import(pool: &SqlitePool, config: &Config, archive: Archive) -> Res<()> {
+    if archive.version != VERSION {
+        return Err(format!("unsupported archive version {}", archive.version).into());
+    }
+    for ArchivedBoard { board, threads } in archive.boards {
+        let exists: bool =
+            sqlx::query_scalar(r#"SELECT EXISTS(SELECT 1 FROM boards WHERE code = ?)"#)
+                .bind(&board.code)
+                .fetch_one(pool)
+                .await?;
+        if exists {
+            return Err(format!("board {} already exists", board.code).into());
+        }
+        let post_count = threads
+            .iter()
+            .flat_map(|t| std::iter::once(&t.op).chain(&t.replies))
+            .map(|p| p.board_post_no)
+            .max()
+            .unwrap_or(0);
+
+        let mut tx = pool.begin().await?;
+        insert_board(&mut tx, &board, post_count).await?;
+
+        let mut quotes = Vec::new();
+        let mut posts = 0;
+        for ArchivedThread { op, replies } in threads {
+            let media = op
+                .media
+                .as_deref()
+                .map(|m| STANDARD.decode(m))
+                .transpose()?;
+            let thread_id =
+                insert_post(&mut tx, config, &board, op, media, None, &mut quotes).await?;
+            for reply in replies {
+                let media = reply
+                    .media
+                    .as_deref()
+                    .map(|m| STANDARD.decode(m))
+                    .transpose()?;
+                insert_post(
+                    &mut tx,
+                    config,
+                    &board,
+                    reply,
+                    media,
+                    Some(thread_id),
+                    &mut quotes,
+                )
+                .await?;
+                posts += 1;
+            }
+            posts += 1;
+        }
+        insert_quotes(&mut tx, &board.code, &quotes).await?;
+        relink::unlink_missing(&mut tx, &board.code).await?;
+        tx.commit().await?;
+        println!("imported /{}/, {posts} posts", board.code);
+    }
+    Ok(())
+}
+
+pub async fn insert_board(tx: &mut SqliteConnection, board: &Board, post_count: i64) -> Res<()> {
+    check_board_code(&board.code).map_err(|e| format!("/{}/: {e}", board.code))?;
+    sqlx::query(
+        r#"
+        INSERT INTO boards (code, name, desc, max_threads, max_replies, max_img_replies, max_sub_len, max_com_len, max_file_size, is_nsfw, markup, proxy_policy, category, position, on_overboard, thumb_dimension, catalog_thumb_dimension, thumb_quality, thumb_format, scan_uploads, reencode_images, forced_anon, default_name, hold_new_posters, allow_documents, allow_audio, pow_difficulty, pow_step, created_at, post_count)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+        "#,
+    )
+    .bind(&board.code)
+    .bind(&board.name)
+    .bind(&board.desc)
+    .bind(board.max_threads)
+    .bind(board.max_replies)
+    .bind(board.max_img_replies)
+    .bind(board.max_sub_len)
+    .bind(board.max_com_len)
+    .bind(board.max_file_size)
+    .bind(board.is_nsfw)
+    .bind(board.markup)
+    .bind(board.proxy_policy)
+    .bind(&board.category)
+    .bind(board.position)
+    .bind(board.on_overboard)
+    .bind(board.thumb_dimension)
+    .bind(board.catalog_thumb_dimension)
+    .bind(board.thumb_quality)
+    .bind(board.thumb_format)
+    .bind(board.scan_uploads)
+    .bind(board.reencode_images)
+    .bind(board.forced_anon)
+    .bind(&board.default_name)
+    .bind(board.hold_new_posters)
+    .bind(board.allow_documents)
+    .bind(board.allow_audio)
+    .bind(board.pow_difficulty)
+    .bind(board.pow_step)
+    .bind(board.created_at)
+    .bind(post_count)
+    .execute(&mut *tx)
+    .await?;
+    Ok(())
+}
+/// Links the replies to the posts they quote, given as pairs of reply id and board post number
+pub async fn insert_quotes(
+    tx: &mut SqliteConnection,
+    board: &str,
+    quotes: &[(i64, i64)],
+) -> Res<()> {
+    for &(reply_id, post_no) in quotes {
+        sqlx::query(
+            r#"
+            INSERT OR IGNORE INTO post_replies (post_id, reply_id)
+            SELECT c.id, ? FROM comments c
+            LEFT JOIN comments t ON t.id = c.op
+            WHERE COALESCE(t.board, c.board) = ? AND c.board_post_no = ? AND c.id != ?
+            "#,
+        )
+        .bind(reply_id)
+        .bind(board)
+        .bind(post_no)
+        .bind(reply_id)
+        .execute(&mut *tx)
+        .await?;
+    }
+    Ok(())
+}
+/// Inserts a post keeping its board post number, with the media thumbnailed again
+pub async fn insert_post(
+    tx: &mut SqliteConnection,
+    config: &Config,
+    board: &Board,
+    post: ArchivedPost,
+    media: Option<Vec<u8>>,
+    op: Option<i64>,
+    quotes: &mut Vec<(i64, i64)>,
+) -> Res<i64> {
+    let media = match media {
+        Some(data) => {
+            let upload = Upload::from_bytes(&data).await?;
+            Some(
+                save_media(
+                    upload,
+                    config.thumbs_for(board),
+                    None,
+                    config.reencode_for(board),
+                    board.accepts(),
+                )
+                .await?,
+            )
+        }
+        None => None,
+    };
+    let id: i64 = sqlx::query_scalar(
+        r#"
+        INSERT INTO comments (file_name, media_name, media_hash, thumb_name, media_size, thumb_size, media_ext, media_type, thumb_type, media_w, media_h, media_pages, media_duration, media_bitrate, media_orig_size, thumb_w, thumb_h, catalog_thumb_name, catalog_thumb_w, catalog_thumb_h, thumb_failed, media_desc, alias, sub, com, sub_raw, com_raw, board, op, is_sticky, is_locked, is_held, created_at, edited_at, board_post_no, post_extras, capcode)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+        RETURNING id
+        "#,
+    )
+    .bind(media.as_ref().and(post.file_name))
+    .bind(media.as_ref().map(|m| &m.media_name))
+    .bind(media.as_ref().map(|m| &m.media_hash))
+    .bind(media.as_ref().map(|m| &m.thumbs.thumb_name))
+    .bind(media.as_ref().map(|m| m.media_size))
+    .bind(media.as_ref().map(|m| m.thumbs.thumb_size))
+    .bind(media.as_ref().map(|m| &m.media_ext))
+    .bind(media.as_ref().map(|m| &m.media_type))
+    .bind(media.as_ref().map(|m| &m.thumbs.thumb_type))
+    .bind(media.as_ref().and_then(|m| m.media_w))
+    .bind(media.as_ref().and_then(|m| m.media_h))
+    .bind(media.as_ref().and_then(|m| m.media_pages))
+    .bind(media.as_ref().and_then(|m| m.media_duration))
+    .bind(media.as_ref().and_then(|m| m.media_bitrate))
+    .bind(media.as_ref().and_then(|m| m.media_orig_size))
+    .bind(media.as_ref().map(|m| m.thumbs.thumb_w))
+    .bind(media.as_ref().map(|m| m.thumbs.thumb_h))
+    .bind(media.as_ref().and_then(|m| m.thumbs.catalog_thumb_name.as_ref()))
+    .bind(media.as_ref().and_then(|m| m.thumbs.catalog_thumb_w))
+    .bind(media.as_ref().and_then(|m| m.thumbs.catalog_thumb_h))
+    .bind(media.as_ref().is_some_and(|m| m.thumbs.thumb_failed))
+    .bind(media.as_ref().and(post.media_desc))
+    .bind(post.alias)
+    .bind(post.sub)
+    .bind(post.com)
+    .bind(post.sub_raw)
+    .bind(post.com_raw)
+    .bind(&board.code)
+    .bind(op)
+    .bind(post.is_sticky)
+    .bind(post.is_locked)
+    .bind(post.is_held)
+    .bind(post.created_at)
+    .bind(post.edited_at)
+    .bind(post.board_post_no)
+    .bind(post.post_extras)
+    .bind(post.capcode)
+    .fetch_one(&mut *tx)
+    .await?;
+    quotes.extend(post.quotes.iter().map(|&post_no| (id, post_no)));
+    Ok(id)
+}
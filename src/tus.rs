@@ -0,0 +1,292 @@
+//! Resumable uploads with the tus protocol (https://tus.io/protocols/resumable-upload), the
+//! files are staged in `uploads/` chunk by chunk and attached to a post by their id once
+//! complete, instead of being sent in the body of the post
+
+use std::error::Error;
+use std::io::SeekFrom;
+
+use axum::body::Body;
+use axum::http::{HeaderMap, StatusCode};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use sqlx::prelude::FromRow;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use uuid::Uuid;
+
+use crate::media::Upload;
+use crate::{Res, StatusError};
+
+pub const VERSION: &str = "1.0.0";
+pub const EXTENSIONS: &str = "creation,termination";
+const SAVE_EVERY: u64 = 1024 * 1024;
+
+/// How many uploads an ip can have pending at once and how large they can be together, set
+/// with MAX_PENDING_UPLOADS and MAX_PENDING_UPLOAD_BYTES
+pub struct UploadQuota {
+    pub uploads: i64,
+    pub bytes: u64,
+}
+impl UploadQuota {
+    pub fn from_env() -> Self {
+        Self {
+            uploads: std::env::var("MAX_PENDING_UPLOADS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(5),
+            bytes: std::env::var("MAX_PENDING_UPLOAD_BYTES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(20 * 1024 * 1024),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, FromRow)]
+pub struct StagedUpload {
+    pub id: String,
+    pub length: i64,
+    pub offset: i64,
+    pub file_name: Option<String>,
+    pub created_at: i64,
+    pub expires_at: i64,
+    #[serde(skip)]
+    pub ip_hash: Option<String>,
+}
+
+/// Rejects the requests of clients speaking another version of the protocol
+pub fn check_version(headers: &HeaderMap) -> Res<()> {
+    match headers.get("tus-resumable").map(|v| v.as_bytes()) {
+        Some(v) if v == VERSION.as_bytes() => Ok(()),
+        _ => {
+            let message = format!("expected Tus-Resumable: {VERSION}");
+            Err(StatusError(StatusCode::PRECONDITION_FAILED, message).into())
+        }
+    }
+}
+
+/// A numeric header of the protocol, like Upload-Length or Upload-Offset
+pub fn header_number(headers: &HeaderMap, name: &str) -> Res<u64> {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+        .ok_or_else(|| format!("a valid {name} header is required").into())
+}
+
+/// The filename of the Upload-Metadata header, a list of keys with base64 values
+pub fn file_name(headers: &HeaderMap) -> Option<String> {
+    let metadata = headers.get("upload-metadata")?.to_str().ok()?;
+    metadata.split(',').find_map(|pair| {
+        let (key, value) = pair.trim().split_once(' ')?;
+        let name = String::from_utf8(STANDARD.decode(value.trim()).ok()?).ok()?;
+        (key == "filename" && !name.is_empty()).then_some(name)
+    })
+}
+
+/// Stages an upload of the ip, within the quota of its pending uploads
+pub async fn create(
+    pool: &SqlitePool,
+    ip_hash: &str,
+    length: u64,
+    file_name: Option<String>,
+    max_size: u64,
+    expiry: i64,
+    quota: &UploadQuota,
+) -> Res<StagedUpload> {
+    if length > max_size {
+        let message = "media is too large".to_string();
+        return Err(StatusError(StatusCode::PAYLOAD_TOO_LARGE, message).into());
+    }
+    let (pending, bytes): (i64, i64) = sqlx::query_as(
+        r#"
+        SELECT COUNT(*), COALESCE(SUM(length), 0) FROM staged_uploads
+        WHERE ip_hash = ? AND expires_at > strftime('%s', 'now')
+        "#,
+    )
+    .bind(ip_hash)
+    .fetch_one(pool)
+    .await?;
+    if pending >= quota.uploads || bytes as u64 + length > quota.bytes {
+        let message = "too many pending uploads, finish or delete one first".to_string();
+        return Err(StatusError(StatusCode::TOO_MANY_REQUESTS, message).into());
+    }
+    let id = Uuid::new_v4().simple().to_string();
+    tokio::fs::create_dir_all("uploads").await?;
+    tokio::fs::File::create(path(&id)).await?;
+    sqlx::query_as(
+        r#"
+        INSERT INTO staged_uploads (id, length, file_name, expires_at, ip_hash)
+        VALUES (?, ?, ?, strftime('%s', 'now') + ?, ?) RETURNING *
+        "#,
+    )
+    .bind(&id)
+    .bind(length as i64)
+    .bind(file_name)
+    .bind(expiry)
+    .bind(ip_hash)
+    .fetch_one(pool)
+    .await
+    .map_err(|e| e.into())
+}
+
+pub async fn status(pool: &SqlitePool, id: &str) -> Res<StagedUpload> {
+    let upload = sqlx::query_as(
+        r#"SELECT * FROM staged_uploads WHERE id = ? AND expires_at > strftime('%s', 'now')"#,
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await?;
+    upload.ok_or_else(|| StatusError(StatusCode::NOT_FOUND, "upload not found".to_string()).into())
+}
+
+/// Writes the chunk at the offset, which has to be where the upload stopped. What was received
+/// before the connection dropped is kept so the client can resume from there, and the offset
+/// is saved every [`SAVE_EVERY`] bytes in case the request is cut short
+pub async fn append(pool: &SqlitePool, id: &str, offset: u64, chunk: Body) -> Res<StagedUpload> {
+    let upload = status(pool, id).await?;
+    if upload.offset as u64 != offset {
+        let message = format!("the upload is at offset {}", upload.offset);
+        return Err(StatusError(StatusCode::CONFLICT, message).into());
+    }
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .open(path(id))
+        .await?;
+    file.set_len(offset).await?;
+    file.seek(SeekFrom::Start(offset)).await?;
+
+    let (mut written, mut saved) = (offset, offset);
+    let mut stream = chunk.into_data_stream();
+    // the error is kept across the saving of the offset, so it has to be Send
+    let received: Result<(), Box<dyn Error + Send + Sync>> = loop {
+        match stream.next().await {
+            None => break Ok(()),
+            Some(Err(e)) => break Err(e.into()),
+            Some(Ok(data)) if written + data.len() as u64 > upload.length as u64 => {
+                let message = "the chunk is past the length of the upload".to_string();
+                break Err(StatusError(StatusCode::PAYLOAD_TOO_LARGE, message).into());
+            }
+            Some(Ok(data)) => {
+                file.write_all(&data).await?;
+                written += data.len() as u64;
+                if written - saved >= SAVE_EVERY {
+                    save_offset(pool, &file, id, saved, written).await?;
+                    saved = written;
+                }
+            }
+        }
+    };
+    save_offset(pool, &file, id, saved, written).await?;
+    received.map_err(|e| e as Box<dyn Error>)?;
+    Ok(StagedUpload {
+        offset: written as i64,
+        ..upload
+    })
+}
+
+pub async fn remove(pool: &SqlitePool, id: &str) -> Res<bool> {
+    let deleted = sqlx::query(r#"DELETE FROM staged_uploads WHERE id = ?"#)
+        .bind(id)
+        .execute(pool)
+        .await?;
+    if deleted.rows_affected() == 0 {
+        return Ok(false);
+    }
+    remove_file(id).await?;
+    Ok(true)
+}
+
+/// The file of the post, sent in the body or staged with the upload id by the same ip. The
+/// staged upload is copied, since the upload may be reencoded in place, so it can be attached
+/// again if the post is rejected, see [`remove`]
+pub async fn attach(
+    pool: &SqlitePool,
+    file: Option<Upload>,
+    upload_id: Option<&str>,
+    ip_hash: &str,
+) -> Res<Option<Upload>> {
+    let Some(id) = upload_id else {
+        return Ok(file);
+    };
+    if file.is_some() {
+        return Err("send either media or upload_id".into());
+    }
+    let staged = status(pool, id).await?;
+    if staged.ip_hash.as_deref() != Some(ip_hash) {
+        return Err(StatusError(StatusCode::NOT_FOUND, "upload not found".to_string()).into());
+    }
+    if staged.offset != staged.length {
+        let message = "the upload isn't complete".to_string();
+        return Err(StatusError(StatusCode::CONFLICT, message).into());
+    }
+    let upload = Upload {
+        path: Upload::temp_path(),
+        size: staged.length as u64,
+        name: staged.file_name,
+    };
+    tokio::fs::copy(path(id), &upload.path).await?;
+    Ok(Some(upload))
+}
+
+/// Deletes the uploads that weren't completed or attached in time
+pub async fn purge_expired(pool: &SqlitePool) -> Res<u64> {
+    let purged: Vec<String> = sqlx::query_scalar(
+        r#"DELETE FROM staged_uploads WHERE expires_at <= strftime('%s', 'now') RETURNING id"#,
+    )
+    .fetch_all(pool)
+    .await?;
+    for id in &purged {
+        remove_file(id).await?;
+    }
+    Ok(purged.len() as u64)
+}
+
+async fn save_offset(
+    pool: &SqlitePool,
+    file: &tokio::fs::File,
+    id: &str,
+    saved: u64,
+    offset: u64,
+) -> Res<()> {
+    file.sync_data().await?;
+    let updated =
+        sqlx::query(r#"UPDATE staged_uploads SET offset = ? WHERE id = ? AND offset = ?"#)
+            .bind(offset as i64)
+            .bind(id)
+            .bind(saved as i64)
+            .execute(pool)
+            .await?;
+    if updated.rows_affected() == 0 {
+        let message = "the upload was resumed concurrently".to_string();
+        return Err(StatusError(StatusCode::CONFLICT, message).into());
+    }
+    Ok(())
+}
+
+fn path(id: &str) -> String {
+    format!("uploads/{id}")
+}
+
+async fn remove_file(id: &str) -> Res<()> {
+    match tokio::fs::remove_file(path(id)).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+#[test]
+fn test_file_name() {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        "upload-metadata",
+        "filetype dmlkZW8vd2VibQ==, filename Y2F0LndlYm0=, is_confidential"
+            .parse()
+            .unwrap(),
+    );
+    assert_eq!(file_name(&headers).as_deref(), Some("cat.webm"));
+    headers.insert("upload-metadata", "filename".parse().unwrap());
+    assert_eq!(file_name(&headers), None);
+}
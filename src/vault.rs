@@ -0,0 +1,62 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use ring::aead::{Aad, CHACHA20_POLY1305, LessSafeKey, NONCE_LEN, Nonce, UnboundKey};
+use ring::rand::{SecureRandom, SystemRandom};
+
+use crate::Res;
+
+/// A ChaCha20-Poly1305 key, read from an env var holding its 32 bytes in base64
+pub struct SealingKey(LessSafeKey);
+
+impl SealingKey {
+    pub fn from_env(name: &str) -> Res<Option<Self>> {
+        let Some(key) = std::env::var(name).ok().filter(|k| !k.is_empty()) else {
+            return Ok(None);
+        };
+        let key = UnboundKey::new(&CHACHA20_POLY1305, &STANDARD.decode(key)?)
+            .map_err(|_| format!("{name} must be 32 bytes"))?;
+        Ok(Some(Self(LessSafeKey::new(key))))
+    }
+
+    /// The nonce followed by the encrypted data and its tag
+    pub fn seal(&self, mut data: Vec<u8>) -> Res<Vec<u8>> {
+        let mut nonce = [0; NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| "failed to generate a nonce")?;
+        self.0
+            .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut data)
+            .map_err(|_| "failed to encrypt")?;
+        Ok([nonce.as_slice(), &data].concat())
+    }
+
+    pub fn open(&self, sealed: &[u8]) -> Res<Vec<u8>> {
+        if sealed.len() < NONCE_LEN {
+            return Err("not an encrypted copy".into());
+        }
+        let (nonce, data) = sealed.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| "invalid nonce")?;
+        let mut data = data.to_vec();
+        let len = self
+            .0
+            .open_in_place(nonce, Aad::empty(), &mut data)
+            .map_err(|_| "failed to decrypt, wrong key or corrupted copy")?
+            .len();
+        data.truncate(len);
+        Ok(data)
+    }
+}
+
+#[test]
+fn test_seal() {
+    let key = SealingKey(LessSafeKey::new(
+        UnboundKey::new(&CHACHA20_POLY1305, &[7; 32]).unwrap(),
+    ));
+    let sealed = key.seal(b"evidence".to_vec()).unwrap();
+    assert_ne!(&sealed[NONCE_LEN..NONCE_LEN + 8], b"evidence");
+    assert_eq!(key.open(&sealed).unwrap(), b"evidence");
+
+    let mut tampered = sealed.clone();
+    tampered[NONCE_LEN] ^= 1;
+    assert!(key.open(&tampered).is_err());
+}
@@ -0,0 +1,225 @@
+//! Imports a board from a running vichan or Tinyboard instance through its JSON api,
+//! `/{board}/threads.json` for the thread list and `/{board}/res/{no}.json` for the posts,
+//! keeping the post numbers and downloading the media from `/{board}/src/`
+
+use std::sync::LazyLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use html_escape::decode_html_entities;
+use regex::Regex;
+use serde::Deserialize;
+use sqlx::SqlitePool;
+
+use crate::proxy::ProxyPolicy;
+use crate::transfer::{ArchivedPost, insert_board, insert_post, insert_quotes};
+use crate::{Board, Config, PostFormat, Res, parse_quotes, relink};
+
+static RE_BREAK: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)<br\s*/?>").unwrap());
+static RE_TAG: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<[^>]*>").unwrap());
+
+#[derive(Deserialize)]
+struct Page {
+    threads: Vec<ThreadRef>,
+}
+#[derive(Deserialize)]
+struct ThreadRef {
+    no: i64,
+}
+#[derive(Deserialize)]
+struct Thread {
+    posts: Vec<Post>,
+}
+#[derive(Deserialize)]
+struct Post {
+    no: i64,
+    #[serde(default)]
+    sub: Option<String>,
+    #[serde(default)]
+    com: Option<String>,
+    #[serde(default)]
+    name: Option<String>,
+    time: i64,
+    #[serde(default)]
+    sticky: i64,
+    #[serde(default)]
+    locked: i64,
+    #[serde(default)]
+    filename: Option<String>,
+    #[serde(default)]
+    ext: Option<String>,
+    /// a string in vichan, a number in a few forks
+    #[serde(default)]
+    tim: Option<serde_json::Value>,
+}
+
+/// Creates the board `code` from the board `remote` of the instance at `url`,
+/// only the first file of each post is kept
+pub async fn import(
+    pool: &SqlitePool,
+    config: &Config,
+    url: &str,
+    remote: &str,
+    code: &str,
+    with_media: bool,
+) -> Res<()> {
+    let url = url.trim_end_matches('/');
+    let pages: Vec<Page> = fetch_json(&format!("{url}/{remote}/threads.json")).await?;
+    let mut thread_nos: Vec<i64> = pages
+        .into_iter()
+        .flat_map(|p| p.threads)
+        .map(|t| t.no)
+        .collect();
+    thread_nos.sort();
+
+    let board = Board {
+        code: code.to_string(),
+        name: code.to_string(),
+        desc: format!("imported from {url}/{remote}/"),
+        max_threads: 150,
+        max_replies: 500,
+        max_img_replies: 250,
+        max_sub_len: 100,
+        max_com_len: 20000,
+        max_file_size: 5 * 1024 * 1024,
+        is_nsfw: false,
+        markup: false,
+        proxy_policy: ProxyPolicy::default(),
+        category: None,
+        position: 0,
+        on_overboard: true,
+        thumb_dimension: None,
+        catalog_thumb_dimension: None,
+        thumb_quality: None,
+        thumb_format: None,
+        scan_uploads: false,
+        reencode_images: false,
+        forced_anon: false,
+        default_name: "Anonymous".to_string(),
+        hold_new_posters: false,
+        allow_documents: false,
+        allow_audio: false,
+        pow_difficulty: 0,
+        pow_step: 1,
+        created_at: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64,
+    };
+    let mut tx = pool.begin().await?;
+    insert_board(&mut tx, &board, 0).await?;
+    tx.commit().await?;
+
+    let format = PostFormat::load(pool, Some(&board)).await?;
+    let mut quotes = Vec::new();
+    let mut post_count = 0;
+    for thread_no in thread_nos {
+        let thread: Thread = match fetch_json(&format!("{url}/{remote}/res/{thread_no}.json")).await
+        {
+            Ok(thread) => thread,
+            Err(e) => {
+                eprintln!("skipping thread {thread_no}: {e}");
+                continue;
+            }
+        };
+        let mut tx = pool.begin().await?;
+        let mut thread_id = None;
+        for post in thread.posts {
+            let tim = post
+                .tim
+                .as_ref()
+                .map(|tim| tim.as_str().map_or(tim.to_string(), str::to_string));
+            let media = match (tim, &post.ext) {
+                (Some(tim), Some(ext)) if with_media => {
+                    match download(&format!("{url}/{remote}/src/{tim}{ext}")).await {
+                        Ok(media) => Some(media),
+                        Err(e) => {
+                            eprintln!("skipping the media of post {}: {e}", post.no);
+                            None
+                        }
+                    }
+                }
+                _ => None,
+            };
+            let com = post.com.as_deref().map(html_to_text);
+            let sub = post.sub.map(|s| decode_html_entities(&s).into_owned());
+            let archived = ArchivedPost {
+                id: 0,
+                op: None,
+                media_name: None,
+                board_post_no: post.no,
+                alias: post.name.filter(|n| n != "Anonymous"),
+                sub: sub.as_deref().map(|s| format.encode_subject(s)),
+                com: com.as_deref().map(|c| format.encode_comment(c)),
+                sub_raw: sub,
+                com_raw: com.clone(),
+                file_name: post.filename.zip(post.ext).map(|(f, e)| f + &e),
+                media_ext: None,
+                media_size: None,
+                media_w: None,
+                media_h: None,
+                media_desc: None,
+                is_sticky: post.sticky != 0,
+                is_locked: post.locked != 0,
+                is_held: false,
+                created_at: post.time,
+                edited_at: None,
+                post_extras: None,
+                capcode: None,
+                quotes: com.map(|c| parse_quotes(&c)).unwrap_or_default(),
+                media: None,
+            };
+            let id = insert_post(
+                &mut tx,
+                config,
+                &board,
+                archived,
+                media,
+                thread_id,
+                &mut quotes,
+            )
+            .await?;
+            thread_id.get_or_insert(id);
+            post_count = post_count.max(post.no);
+        }
+        tx.commit().await?;
+        println!("imported thread {thread_no}");
+    }
+
+    let mut tx = pool.begin().await?;
+    insert_quotes(&mut tx, code, &quotes).await?;
+    relink::unlink_missing(&mut tx, code).await?;
+    sqlx::query(r#"UPDATE boards SET post_count = ? WHERE code = ?"#)
+        .bind(post_count)
+        .bind(code)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+async fn fetch_json<T: serde::de::DeserializeOwned>(url: &str) -> Res<T> {
+    let text = reqwest::get(url).await?.error_for_status()?.text().await?;
+    Ok(serde_json::from_str(&text)?)
+}
+async fn download(url: &str) -> Res<Vec<u8>> {
+    Ok(reqwest::get(url)
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?
+        .to_vec())
+}
+/// vichan stores comments rendered, they are turned back into text to be rendered again
+fn html_to_text(html: &str) -> String {
+    let text = RE_BREAK.replace_all(html, "\n");
+    let text = RE_TAG.replace_all(&text, "");
+    decode_html_entities(&text).into_owned()
+}
+
+#[test]
+fn test_html_to_text() {
+    assert_eq!(
+        html_to_text(
+            r#"<a onclick="highlightReply('2')" href="/g/res/1.html#2">&gt;&gt;2</a><br/><span class="quote">&gt;implying</span><br>a &amp; b"#
+        ),
+        ">>2\n>implying\na & b"
+    );
+    assert_eq!(html_to_text("<script>x</script>"), "x");
+}
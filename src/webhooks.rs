@@ -0,0 +1,130 @@
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use sqlx::prelude::FromRow;
+use sqlx::types::Json;
+
+use crate::Res;
+use crate::events::{Event, EventKind, Subscriber};
+
+const MAX_ATTEMPTS: u32 = 5;
+const FIRST_RETRY: Duration = Duration::from_secs(2);
+
+static CLIENT: LazyLock<reqwest::Client> = LazyLock::new(|| {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .user_agent(concat!("blu/", env!("CARGO_PKG_VERSION")))
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .expect("failed to build the webhook client")
+});
+
+/// An url the events are posted to as JSON, signed with the secret in `X-Blu-Signature` as the
+/// hex HMAC-SHA256 of the body. Webhooks without a board get the events of every board
+#[derive(Serialize, Deserialize, FromRow)]
+pub struct Webhook {
+    pub id: i64,
+    pub url: String,
+    #[serde(skip_serializing)]
+    pub secret: String,
+    pub events: Json<Vec<EventKind>>,
+    pub board: Option<String>,
+    pub is_enabled: bool,
+    pub created_at: i64,
+}
+
+/// Posts the events of the bus to the webhooks subscribed to them
+pub struct WebhookSubscriber {
+    pub pool: Arc<SqlitePool>,
+}
+
+impl Subscriber for WebhookSubscriber {
+    async fn on_event(&self, event: Event) {
+        if let Err(e) = notify(&self.pool, &event).await {
+            tracing::warn!(
+                "failed to notify the webhooks of {}: {e}",
+                event.kind.name()
+            );
+        }
+    }
+}
+
+/// Each delivery runs in the background so a slow receiver doesn't hold back the others
+async fn notify(pool: &SqlitePool, event: &Event) -> Res<()> {
+    let hooks: Vec<Webhook> = sqlx::query_as(
+        r#"
+        SELECT * FROM webhooks
+        WHERE is_enabled AND (board IS NULL OR board = ?)
+        AND EXISTS (SELECT 1 FROM json_each(events) WHERE value = ?)
+        "#,
+    )
+    .bind(&event.board)
+    .bind(event.kind.name())
+    .fetch_all(pool)
+    .await?;
+    if hooks.is_empty() {
+        return Ok(());
+    }
+    let body = serde_json::to_vec(event)?;
+    for hook in hooks {
+        let body = body.clone();
+        let kind = event.kind;
+        tokio::spawn(async move { deliver(hook, kind, body).await });
+    }
+    Ok(())
+}
+
+/// Retries server errors and unreachable hosts with an exponential backoff
+async fn deliver(hook: Webhook, event: EventKind, body: Vec<u8>) {
+    let signature = sign(&hook.secret, &body);
+    let mut delay = FIRST_RETRY;
+    for attempt in 1..=MAX_ATTEMPTS {
+        let res = CLIENT
+            .post(&hook.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header("x-blu-event", event.name())
+            .header("x-blu-signature", format!("sha256={signature}"))
+            .body(body.clone())
+            .send()
+            .await;
+        let retry = match res {
+            Ok(res) if res.status().is_success() => return,
+            Ok(res) => {
+                let status = res.status();
+                tracing::warn!("webhook {} answered {status}", hook.id);
+                status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+            }
+            Err(e) => {
+                tracing::warn!("webhook {} failed: {e}", hook.id);
+                true
+            }
+        };
+        if !retry || attempt == MAX_ATTEMPTS {
+            break;
+        }
+        tokio::time::sleep(delay).await;
+        delay *= 4;
+    }
+    tracing::warn!("gave up delivering {} to webhook {}", event.name(), hook.id);
+}
+
+fn sign(secret: &str, body: &[u8]) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    hmac::sign(&key, body)
+        .as_ref()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+#[test]
+fn test_sign() {
+    // from RFC 4231
+    assert_eq!(
+        sign("Jefe", b"what do ya want for nothing?"),
+        "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+    );
+}
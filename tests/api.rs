@@ -0,0 +1,785 @@
+use std::io::Cursor;
+use std::sync::{Arc, Once};
+
+use axum::Router;
+use axum::body::{Body, to_bytes};
+use axum::http::{Method, Request, StatusCode, header};
+use blu::Services;
+use blu::db::ReadPool;
+use serde_json::{Value, json};
+use sqlx::sqlite::SqlitePoolOptions;
+use tower::ServiceExt;
+
+const BOUNDARY: &str = "blu-test-boundary";
+const MOD_TOKEN: &str = "mod-token";
+const ADMIN_TOKEN: &str = "admin-token";
+
+/// The media is stored relative to the working directory, so the tests run in a scratch one.
+/// The staff tokens and the quarantine are set before any config is loaded
+fn scratch_dir() {
+    static SCRATCH: Once = Once::new();
+    SCRATCH.call_once(|| {
+        let dir = std::env::temp_dir().join(format!("blu-tests-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("media")).unwrap();
+        std::env::set_current_dir(dir).unwrap();
+        // SAFETY: no other thread reads the environment before the first app is built
+        unsafe {
+            std::env::set_var("MOD_TOKEN", MOD_TOKEN);
+            std::env::set_var("ADMIN_TOKEN", ADMIN_TOKEN);
+            std::env::set_var("QUARANTINE_KEY", "A".repeat(43) + "=");
+        }
+    });
+}
+
+/// The router over a migrated in memory database, the single connection is kept open since
+/// the database goes away with it
+async fn test_app() -> Router {
+    scratch_dir();
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+    blu::MIGRATOR.run(&pool).await.unwrap();
+    let pool = Arc::new(pool);
+    let services = Services::load(pool.clone(), ReadPool(pool)).await.unwrap();
+    blu::app(&services).unwrap()
+}
+
+async fn send(app: &Router, req: Request<Body>) -> (StatusCode, Value) {
+    let res = app.clone().oneshot(req).await.unwrap();
+    let status = res.status();
+    let body = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+}
+
+async fn get(app: &Router, uri: &str) -> (StatusCode, Value) {
+    send(app, request(Method::GET, uri).body(Body::empty()).unwrap()).await
+}
+
+fn request(method: Method, uri: &str) -> axum::http::request::Builder {
+    Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("x-forwarded-for", "203.0.113.7")
+}
+
+fn from_ip(mut req: Request<Body>, ip: &str) -> Request<Body> {
+    req.headers_mut()
+        .insert("x-forwarded-for", ip.parse().unwrap());
+    req
+}
+
+fn staff(mut req: Request<Body>, token: &str) -> Request<Body> {
+    let bearer = format!("Bearer {token}").parse().unwrap();
+    req.headers_mut().insert(header::AUTHORIZATION, bearer);
+    req
+}
+
+fn json_request(method: Method, uri: &str, body: Value) -> Request<Body> {
+    request(method, uri)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+fn multipart_request(uri: &str, data: Value, media: Option<&[u8]>) -> Request<Body> {
+    fields_request(uri, &[("data", &data.to_string())], media)
+}
+
+fn fields_request(uri: &str, fields: &[(&str, &str)], media: Option<&[u8]>) -> Request<Body> {
+    let mut body = Vec::new();
+    for (name, value) in fields {
+        body.extend(
+            format!(
+                "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"{name}\"\r\n\r\n{value}\r\n"
+            )
+            .as_bytes(),
+        );
+    }
+    if let Some(media) = media {
+        body.extend(
+            format!(
+                "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"media\"; filename=\"a.png\"\r\nContent-Type: image/png\r\n\r\n"
+            )
+            .as_bytes(),
+        );
+        body.extend(media);
+        body.extend(b"\r\n");
+    }
+    body.extend(format!("--{BOUNDARY}--\r\n").as_bytes());
+    request(Method::POST, uri)
+        .header(
+            header::CONTENT_TYPE,
+            format!("multipart/form-data; boundary={BOUNDARY}"),
+        )
+        .body(Body::from(body))
+        .unwrap()
+}
+
+fn png() -> Vec<u8> {
+    let mut data = Cursor::new(Vec::new());
+    image::RgbImage::from_pixel(64, 48, image::Rgb([30, 60, 90]))
+        .write_to(&mut data, image::ImageOutputFormat::Png)
+        .unwrap();
+    data.into_inner()
+}
+
+async fn create_board(app: &Router, code: &str, settings: Value) {
+    let mut board = json!({
+        "code": code,
+        "name": "Technology",
+        "desc": "technology",
+        "max_threads": 10,
+        "max_replies": 100,
+        "max_img_replies": 50,
+        "max_sub_len": 100,
+        "max_com_len": 2000,
+        "max_file_size": 5000000,
+        "is_nsfw": false,
+    });
+    board
+        .as_object_mut()
+        .unwrap()
+        .extend(settings.as_object().unwrap().clone());
+    let (status, _) = send(
+        app,
+        json_request(Method::POST, "/api/v1/create_board", board),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+}
+
+#[tokio::test]
+async fn test_post_read_delete() {
+    let app = test_app().await;
+    create_board(&app, "g", json!({})).await;
+    let board = json!({"code": "g", "name": "Again", "desc": "again", "max_threads": 1, "max_replies": 1, "max_img_replies": 1, "max_sub_len": 1, "max_com_len": 1, "max_file_size": 1, "is_nsfw": false});
+    let req = json_request(Method::POST, "/api/v1/create_board", board);
+    let (status, res) = send(&app, req).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(res["Err"], "board /g/ already exists");
+    let (_, res) = get(&app, "/api/v1/boards").await;
+    assert_eq!(res["Ok"]["boards"][0]["code"], "g");
+    assert_eq!(res["Ok"]["boards"][0]["name"], "Technology");
+    assert_eq!(res["Ok"]["boards"][0]["on_overboard"], true);
+
+    let thread = json!({"sub": "hello", "com": "first >>9", "board": "g", "password": "hunter22"});
+    let req = multipart_request("/api/v1/create_thread", thread, Some(&png()));
+    let (status, res) = send(&app, req).await;
+    assert_eq!(status, StatusCode::CREATED, "{res}");
+    let op = res["Ok"]["id"].as_i64().unwrap();
+    assert_eq!(res["Ok"]["board_post_no"], 1);
+
+    let reply = json!({"com": ">>1 agreed", "op": op, "password": "hunter22"});
+    let (status, res) = send(
+        &app,
+        multipart_request("/api/v1/create_comment", reply, None),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{res}");
+    let reply = res["Ok"]["id"].as_i64().unwrap();
+    assert_eq!(res["Ok"]["replying_to"], json!([op]));
+    let again = json!({"com": ">>1 agreed", "op": op});
+    let (status, res) = send(
+        &app,
+        multipart_request("/api/v1/create_comment", again, None),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(res["Err"], "duplicate post");
+    let replies = [
+        (
+            json!({"com": "to a reply", "op": reply}),
+            StatusCode::CONFLICT,
+        ),
+        (
+            json!({"com": "to nothing", "op": 999}),
+            StatusCode::NOT_FOUND,
+        ),
+        (
+            json!({"com": "elsewhere", "op": op, "board": "b"}),
+            StatusCode::CONFLICT,
+        ),
+        (
+            json!({"com": ">>2 here", "op": op, "board": "g"}),
+            StatusCode::OK,
+        ),
+    ];
+    for (reply, expected) in replies {
+        let req = json_request(Method::POST, "/api/v1/create_comment", reply);
+        let (status, res) = send(&app, req).await;
+        assert_eq!(status, expected, "{res}");
+    }
+
+    let (status, res) = get(&app, "/api/v1/g").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(res["Ok"]["threads"][0]["id"], op);
+    assert!(res["Ok"]["threads"][0]["created_at"].is_i64());
+    assert_eq!(res["Ok"]["threads"][0]["replies"], 2);
+    let thread = format!("/api/v1/g/thread/{op}");
+    let (status, res) = get(&app, &thread).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(res["Ok"][0]["sub"], "<b>hello</b>");
+    assert_eq!(
+        res["Ok"][0]["com"],
+        "first <a href=\"#p9\" class=\"dead\">&gt;&gt;9</a>"
+    );
+    assert_eq!(res["Ok"][0]["com_raw"], "first >>9");
+    assert_eq!(res["Ok"][0]["replied_by"], json!([reply]));
+    assert_eq!(res["Ok"][1]["id"], reply);
+    assert_eq!(res["Ok"][1]["board"], "g");
+    let last = res["Ok"].as_array().unwrap().last().unwrap()["id"].clone();
+    let (status, _) = get(&app, &format!("{thread}?since_id={last}")).await;
+    assert_eq!(status, StatusCode::NOT_MODIFIED);
+    for (board, id) in [("b", op), ("g", 999)] {
+        let missing = format!("/api/v1/{board}/thread/{id}?since_id={last}");
+        let (status, _) = get(&app, &missing).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+    let (_, res) = get(&app, &format!("{thread}?view=tree")).await;
+    assert_eq!(res["Ok"].as_array().unwrap().len(), 1);
+    let root = &res["Ok"][0];
+    assert_eq!(root["id"], op);
+    assert_eq!(root["children"][0]["id"], reply);
+    assert_eq!(root["children"][0]["children"][0]["com_raw"], ">>2 here");
+    let (status, res) = get(&app, &format!("{thread}/last/1")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(res["Ok"]["posts"][0]["id"], op);
+    assert_eq!(res["Ok"]["posts"][1]["com_raw"], ">>2 here");
+    assert_eq!(res["Ok"]["posts"][1]["replying_to"], json!([reply]));
+    assert_eq!(res["Ok"]["omitted_posts"], 1);
+    let (status, _) = get(&app, &format!("/api/v1/g/thread/{reply}/last/1")).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let post = format!("/api/v1/post/{reply}");
+    let wrong = json!({"password": "wrong"});
+    let (status, _) = send(&app, json_request(Method::DELETE, &post, wrong)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let right = json!({"password": "hunter22"});
+    let (status, _) = send(&app, json_request(Method::DELETE, &post, right.clone())).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send(&app, json_request(Method::DELETE, &post, right)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (_, res) = get(&app, &thread).await;
+    assert_eq!(res["Ok"][0]["replied_by"], json!([]));
+
+    let req = request(Method::GET, "/g").body(Body::empty()).unwrap();
+    let res = app.clone().oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["deprecation"], "true");
+
+    // edits go through the duplicate check of the replies
+    let mut created = Vec::new();
+    for com in ["once", "twice"] {
+        let reply = json!({"com": com, "op": op});
+        let req = multipart_request("/api/v1/create_comment", reply, None);
+        let (status, res) = send(&app, req).await;
+        assert_eq!(status, StatusCode::OK, "{res}");
+        created.push(res["Ok"].clone());
+    }
+    let (edited, edit_token) = (&created[1]["id"], &created[1]["edit_token"]);
+    let edit = |com| json!({"edit_token": edit_token, "com": com});
+    let uri = format!("/api/v1/post/{edited}");
+    let (status, res) = send(&app, json_request(Method::PATCH, &uri, edit("once"))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(res["Err"], "duplicate post");
+    let req = json_request(Method::PATCH, &uri, edit("twice again"));
+    assert_eq!(send(&app, req).await.0, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_upload() {
+    let app = test_app().await;
+    create_board(&app, "h", json!({"hold_new_posters": true})).await;
+    create_board(&app, "p", json!({})).await;
+    create_board(&app, "d", json!({"allow_documents": true})).await;
+
+    let thread = json!({"com": "held picture", "board": "h"});
+    let req = multipart_request("/api/v1/create_thread", thread, Some(&png()));
+    let (status, res) = send(&app, req).await;
+    assert!(status.is_success(), "{res}");
+    let hashed = format!("/media/{}.png", res["Ok"]["media_hash"].as_str().unwrap());
+    assert_eq!(get(&app, &hashed).await.0, StatusCode::NOT_FOUND);
+    create_board(&app, "mu", json!({"allow_audio": true})).await;
+
+    let thread = json!({"com": "a picture", "board": "p", "password": "hunter22"});
+    let req = multipart_request("/api/v1/create_thread", thread, Some(&png()));
+    let (status, res) = send(&app, req).await;
+    assert_eq!(status, StatusCode::CREATED, "{res}");
+    assert_eq!(res["Ok"]["media_ext"], "png");
+    assert_eq!(res["Ok"]["media_w"], 64);
+    assert_eq!(res["Ok"]["media_h"], 48);
+
+    let hashed = format!("/media/{}.png", res["Ok"]["media_hash"].as_str().unwrap());
+    let legacy = format!("/media/{}", res["Ok"]["media_name"].as_str().unwrap());
+    let req = request(Method::GET, &legacy).body(Body::empty()).unwrap();
+    let redirect = app.clone().oneshot(req).await.unwrap();
+    assert_eq!(redirect.status(), StatusCode::PERMANENT_REDIRECT);
+    assert_eq!(redirect.headers()[header::LOCATION], hashed.as_str());
+    let media = [
+        hashed,
+        format!("/media/{}", res["Ok"]["thumb_name"].as_str().unwrap()),
+    ];
+    assert_eq!(res["Ok"]["file_name"], "a.png");
+    assert_eq!(res["Ok"]["media_type"], "image/png");
+    let headers = [
+        (
+            "image/png",
+            "inline; filename=\"a.png\"; filename*=UTF-8''a.png",
+        ),
+        (
+            "image/jpeg",
+            &format!("inline; filename=\"{}.jpg\"", &media[1][7..]),
+        ),
+    ];
+    for (uri, (content_type, disposition)) in media.iter().zip(headers) {
+        let req = request(Method::GET, uri).body(Body::empty()).unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[header::CONTENT_TYPE], content_type);
+        let sent = res.headers()[header::CONTENT_DISPOSITION].to_str().unwrap();
+        assert!(sent.starts_with(disposition), "{sent}");
+    }
+    let req = request(Method::GET, &media[0]).body(Body::empty()).unwrap();
+    let cached = app.clone().oneshot(req).await.unwrap();
+    assert_eq!(cached.headers()[header::CACHE_CONTROL], "max-age=86400");
+
+    let post = format!("/api/v1/post/{}", res["Ok"]["id"]);
+    let (status, _) = send(
+        &app,
+        json_request(Method::DELETE, &post, json!({"password": "hunter22"})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    for uri in &media {
+        assert_eq!(get(&app, uri).await.0, StatusCode::NOT_FOUND);
+    }
+
+    let thread = json!({"com": "no picture", "board": "p"});
+    let (status, res) = send(
+        &app,
+        multipart_request("/api/v1/create_thread", thread, None),
+    )
+    .await;
+    assert_ne!(status, StatusCode::CREATED, "{res}");
+    let mut corrupt = png();
+    corrupt.truncate(60);
+    let thread = json!({"com": "a broken picture", "board": "p"});
+    let req = multipart_request("/api/v1/create_thread", thread, Some(&corrupt));
+    let (status, res) = send(&app, req).await;
+    assert_eq!(status, StatusCode::CREATED, "{res}");
+    assert_eq!(res["Ok"]["thumb_failed"], true);
+    let thumb = format!("/media/{}", res["Ok"]["thumb_name"].as_str().unwrap());
+    assert_eq!(get(&app, &thumb).await.0, StatusCode::OK);
+
+    let pdf = b"%PDF-1.4\n1 0 obj << /Type /Page >> endobj\n2 0 obj << /Type /Page >> endobj\n";
+    let thread = json!({"com": "a document", "board": "p"});
+    let req = multipart_request("/api/v1/create_thread", thread, Some(pdf));
+    assert_eq!(send(&app, req).await.0, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    let thread = json!({"com": "a document", "board": "d"});
+    let req = multipart_request("/api/v1/create_thread", thread, Some(pdf));
+    let (status, res) = send(&app, req).await;
+    assert_eq!(status, StatusCode::CREATED, "{res}");
+    assert_eq!(res["Ok"]["media_ext"], "pdf");
+    assert_eq!(res["Ok"]["media_pages"], 2);
+
+    let mut mp3 = b"ID3\x03\x00\x00\x00\x00\x00\x00".to_vec();
+    mp3.extend([0xff, 0xfb, 0x90, 0x00].iter().cycle().take(32000));
+    let thread = json!({"com": "a song", "board": "p"});
+    let req = multipart_request("/api/v1/create_thread", thread, Some(&mp3));
+    assert_eq!(send(&app, req).await.0, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    let thread = json!({"com": "a song", "board": "mu"});
+    let req = multipart_request("/api/v1/create_thread", thread, Some(&mp3));
+    let (status, res) = send(&app, req).await;
+    assert_eq!(status, StatusCode::CREATED, "{res}");
+    assert_eq!(res["Ok"]["media_duration"], 2.0);
+    assert_eq!(res["Ok"]["media_bitrate"], 128);
+    assert_eq!(res["Ok"]["thumb_failed"], false);
+
+    let thread = json!({"com": "not a picture", "board": "p"});
+    let req = multipart_request("/api/v1/create_thread", thread, Some(b"plain text"));
+    let (status, res) = send(&app, req).await;
+    assert_ne!(status, StatusCode::CREATED, "{res}");
+}
+
+#[tokio::test]
+async fn test_staff_audit() {
+    let app = test_app().await;
+    create_board(&app, "s", json!({})).await;
+    let thread = json!({"com": "kept", "board": "s"});
+    let req = multipart_request("/api/v1/create_thread", thread, Some(&png()));
+    let (status, res) = send(&app, req).await;
+    assert_eq!(status, StatusCode::CREATED, "{res}");
+    let op = &res["Ok"]["id"];
+    let reply = json!({"com": "deleted by mistake", "op": op});
+    let req = multipart_request("/api/v1/create_comment", reply, None);
+    let (status, res) = send(&app, req).await;
+    assert_eq!(status, StatusCode::OK, "{res}");
+    let reply = &res["Ok"]["id"];
+
+    let delete = json_request(Method::DELETE, &format!("/api/v1/post/{reply}"), json!({}));
+    let (status, res) = send(&app, staff(delete, MOD_TOKEN)).await;
+    assert_eq!(status, StatusCode::OK, "{res}");
+    let thread = format!("/api/v1/s/thread/{op}");
+    let (_, res) = get(&app, &thread).await;
+    assert_eq!(res["Ok"].as_array().unwrap().len(), 1);
+    let restore = || {
+        let restore = request(Method::POST, &format!("/api/v1/mod/restore/{reply}"));
+        staff(restore.body(Body::empty()).unwrap(), MOD_TOKEN)
+    };
+    let (status, res) = send(&app, restore()).await;
+    assert_eq!(status, StatusCode::OK, "{res}");
+    assert_eq!(res["Ok"]["com_raw"], "deleted by mistake");
+    let (_, res) = get(&app, &thread).await;
+    assert_eq!(res["Ok"][1]["id"], *reply);
+    let (status, res) = send(&app, restore()).await;
+    assert_eq!(status, StatusCode::NOT_FOUND, "{res}");
+
+    let audit = request(Method::GET, "/api/v1/admin/audit").body(Body::empty());
+    let (status, res) = send(&app, audit.unwrap()).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(res["Err"], "unauthorized");
+    let audit = request(Method::GET, "/api/v1/admin/audit").body(Body::empty());
+    let (_, res) = send(&app, staff(audit.unwrap(), ADMIN_TOKEN)).await;
+    let entry = &res["Ok"][0];
+    assert_eq!(entry["action"], "restore_post");
+    assert_eq!(entry["actor"], "mod");
+}
+
+#[tokio::test]
+async fn test_ban_and_purge() {
+    let app = test_app().await;
+    create_board(&app, "x", json!({})).await;
+    let thread = json!({"com": "welcome", "board": "x"});
+    let req = multipart_request("/api/v1/create_thread", thread, Some(&png()));
+    let (_, res) = send(&app, req).await;
+    let op = res["Ok"]["id"].as_i64().unwrap();
+    let spammer = "198.51.100.9";
+    let mut spam = Vec::new();
+    for com in ["buy now", "buy again"] {
+        let reply = json!({"com": com, "op": op});
+        let req = multipart_request("/api/v1/create_comment", reply, None);
+        let (status, res) = send(&app, from_ip(req, spammer)).await;
+        assert_eq!(status, StatusCode::OK, "{res}");
+        spam.push(res["Ok"]["id"].as_i64().unwrap());
+    }
+    let reply = json!({"com": "not spam", "op": op});
+    let req = multipart_request("/api/v1/create_comment", reply, None);
+    let (_, res) = send(&app, req).await;
+    let kept = res["Ok"]["id"].clone();
+
+    let ban = json!({"post_id": spam[0], "reason": "spam"});
+    let req = json_request(Method::POST, "/api/v1/mod/ban", ban.clone());
+    assert_eq!(send(&app, req).await.0, StatusCode::UNAUTHORIZED);
+    let req = json_request(Method::POST, "/api/v1/mod/ban", ban);
+    let (status, res) = send(&app, staff(req, MOD_TOKEN)).await;
+    assert_eq!(status, StatusCode::CREATED, "{res}");
+    let mut deleted: Vec<i64> = serde_json::from_value(res["Ok"]["deleted"].clone()).unwrap();
+    deleted.sort();
+    assert_eq!(deleted, spam);
+    assert_eq!(res["Ok"]["boards"], json!(["x"]));
+
+    let (_, res) = get(&app, &format!("/api/v1/x/thread/{op}")).await;
+    let ids: Vec<&Value> = res["Ok"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|p| &p["id"])
+        .collect();
+    assert_eq!(ids, [&json!(op), &kept]);
+    let reply = json!({"com": "buy once more", "op": op});
+    let req = multipart_request("/api/v1/create_comment", reply, None);
+    let (status, res) = send(&app, from_ip(req, spammer)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(res["Err"], "you are banned: spam");
+}
+
+#[tokio::test]
+async fn test_bulk_delete() {
+    let app = test_app().await;
+    create_board(&app, "y", json!({})).await;
+    let mut threads = Vec::new();
+    for com in ["first", "second"] {
+        let thread = json!({"com": com, "board": "y"});
+        let req = multipart_request("/api/v1/create_thread", thread, Some(&png()));
+        let (_, res) = send(&app, req).await;
+        threads.push(res["Ok"]["id"].as_i64().unwrap());
+    }
+    let reply = json!({"com": "reply", "op": threads[0]});
+    let req = multipart_request("/api/v1/create_comment", reply, None);
+    let (_, res) = send(&app, req).await;
+    let reply = res["Ok"]["id"].as_i64().unwrap();
+
+    let req = json_request(Method::POST, "/api/v1/mod/bulk/delete", json!({"ids": []}));
+    assert_eq!(
+        send(&app, staff(req, MOD_TOKEN)).await.0,
+        StatusCode::BAD_REQUEST
+    );
+    // the reply is gone with its thread by the time its own id comes up
+    let ids = json!({"ids": [threads[1], 999, threads[0], reply]});
+    let req = json_request(Method::POST, "/api/v1/mod/bulk/delete", ids.clone());
+    assert_eq!(send(&app, req).await.0, StatusCode::UNAUTHORIZED);
+    let req = json_request(Method::POST, "/api/v1/mod/bulk/delete", ids);
+    let (status, res) = send(&app, staff(req, MOD_TOKEN)).await;
+    assert_eq!(status, StatusCode::OK, "{res}");
+    let done: Vec<&Value> = res["Ok"]["done"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|p| &p["id"])
+        .collect();
+    assert_eq!(done, [&json!(threads[1]), &json!(threads[0])]);
+    assert_eq!(
+        res["Ok"]["failed"],
+        json!([
+            {"id": 999, "error": "post not found"},
+            {"id": reply, "error": "post not found"},
+        ])
+    );
+    let (_, res) = get(&app, "/api/v1/y").await;
+    assert_eq!(res["Ok"]["threads"], json!([]));
+    // the deleted threads are kept with their reply, the failures aren't
+    let req = request(Method::GET, "/api/v1/mod/quarantine").body(Body::empty());
+    let (_, res) = send(&app, staff(req.unwrap(), MOD_TOKEN)).await;
+    let mut kept: Vec<(i64, i64)> = res["Ok"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|q| (q["post_id"].as_i64().unwrap(), q["posts"].as_i64().unwrap()))
+        .collect();
+    kept.sort();
+    assert_eq!(kept, [(threads[0], 2), (threads[1], 1)]);
+}
+
+#[tokio::test]
+async fn test_cooldown() {
+    let app = test_app().await;
+    create_board(&app, "z", json!({})).await;
+    let flood = json!({"thread": 0, "reply": 60, "image": 0});
+    let req = json_request(Method::PUT, "/api/v1/admin/flood", flood);
+    let (status, res) = send(&app, staff(req, ADMIN_TOKEN)).await;
+    assert_eq!(status, StatusCode::OK, "{res}");
+    let thread = json!({"com": "slow", "board": "z"});
+    let req = multipart_request("/api/v1/create_thread", thread, Some(&png()));
+    let (_, res) = send(&app, req).await;
+    let op = &res["Ok"]["id"];
+
+    let reply = |com| {
+        multipart_request(
+            "/api/v1/create_comment",
+            json!({"com": com, "op": op}),
+            None,
+        )
+    };
+    assert_eq!(send(&app, reply("one")).await.0, StatusCode::OK);
+    let res = app.clone().oneshot(reply("two")).await.unwrap();
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    let retry_after: i64 = res.headers()[header::RETRY_AFTER]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!((1..=60).contains(&retry_after));
+    let body = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["reason"], "reply_cooldown");
+    assert_eq!(body["retry_after"], retry_after);
+    assert_eq!(
+        body["Err"],
+        format!("wait {retry_after} seconds before posting another reply")
+    );
+    assert_eq!(body["code"], "reply_cooldown");
+
+    let req = staff(reply("three"), MOD_TOKEN);
+    assert_eq!(send(&app, req).await.0, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_board_rules() {
+    let app = test_app().await;
+    create_board(&app, "q", json!({})).await;
+    let rules = json!({"rules": ["be nice", " no spam "], "require_acceptance": true});
+    let req = json_request(Method::PUT, "/api/v1/boards/q/rules", rules.clone());
+    assert_eq!(send(&app, req).await.0, StatusCode::UNAUTHORIZED);
+    let req = json_request(Method::PUT, "/api/v1/boards/q/rules", rules);
+    let (status, res) = send(&app, staff(req, ADMIN_TOKEN)).await;
+    assert_eq!(status, StatusCode::OK, "{res}");
+    let (_, res) = get(&app, "/api/v1/q/rules").await;
+    assert_eq!(res["Ok"]["rules"], json!(["be nice", "no spam"]));
+    assert_eq!(res["Ok"]["require_acceptance"], true);
+
+    let thread = json!({"com": "hi", "board": "q"});
+    let req = multipart_request("/api/v1/create_thread", thread.clone(), Some(&png()));
+    let (status, res) = send(&app, req).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(
+        res["Err"],
+        "accept the rules in /q/rules first, with accepted_rules"
+    );
+    assert_eq!(res["code"], "rules_not_accepted");
+    let mut accepted = thread.clone();
+    accepted["accepted_rules"] = true.into();
+    let req = multipart_request("/api/v1/create_thread", accepted, Some(&png()));
+    let (status, res) = send(&app, req).await;
+    assert_eq!(status, StatusCode::CREATED, "{res}");
+    // only the first post of the ip has to accept them
+    let reply = json!({"com": "again", "op": res["Ok"]["id"]});
+    let req = multipart_request("/api/v1/create_comment", reply, None);
+    assert_eq!(send(&app, req).await.0, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_resumable_upload() {
+    let app = test_app().await;
+    create_board(&app, "r", json!({})).await;
+    let tus = |method, uri: &str| request(method, uri).header("tus-resumable", "1.0.0");
+    let create = |length: usize| {
+        tus(Method::POST, "/api/v1/uploads")
+            .header("upload-length", length)
+            .body(Body::empty())
+            .unwrap()
+    };
+    let chunk = |uri: &str, offset: usize, data: &[u8]| {
+        tus(Method::PATCH, uri)
+            .header(header::CONTENT_TYPE, "application/offset+octet-stream")
+            .header("upload-offset", offset)
+            .body(Body::from(data.to_vec()))
+            .unwrap()
+    };
+    let media = png();
+    let res = app.clone().oneshot(create(media.len())).await.unwrap();
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let uri = res.headers()[header::LOCATION]
+        .to_str()
+        .unwrap()
+        .to_string();
+    let upload_id = uri.rsplit('/').next().unwrap().to_string();
+
+    let (half, rest) = media.split_at(media.len() / 2);
+    let res = app.clone().oneshot(chunk(&uri, 0, half)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::NO_CONTENT);
+    assert_eq!(res.headers()["upload-offset"], half.len().to_string());
+    let (status, res) = send(&app, chunk(&uri, 0, rest)).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(
+        res["Err"],
+        format!("the upload is at offset {}", half.len())
+    );
+
+    let thread = json!({"com": "resumed", "board": "r", "upload_id": upload_id});
+    let req = multipart_request("/api/v1/create_thread", thread.clone(), None);
+    let (status, res) = send(&app, req).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(res["Err"], "the upload isn't complete");
+
+    let mut past = rest.to_vec();
+    past.push(0);
+    let (status, _) = send(&app, chunk(&uri, half.len(), &past)).await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    let res = app
+        .clone()
+        .oneshot(chunk(&uri, half.len(), rest))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::NO_CONTENT);
+    let req = multipart_request("/api/v1/create_thread", thread.clone(), None);
+    let (status, res) = send(&app, from_ip(req, "198.51.100.9")).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(res["Err"], "upload not found");
+    let req = multipart_request("/api/v1/create_thread", thread, None);
+    let (status, res) = send(&app, req).await;
+    assert_eq!(status, StatusCode::CREATED, "{res}");
+
+    // an ip can stage a few uploads at once, and only so many bytes
+    let full = 5 * 1024 * 1024;
+    for length in [full, full, full, 1] {
+        assert_eq!(send(&app, create(length)).await.0, StatusCode::CREATED);
+    }
+    let (status, res) = send(&app, create(full)).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(
+        res["Err"],
+        "too many pending uploads, finish or delete one first"
+    );
+    assert_eq!(send(&app, create(1)).await.0, StatusCode::CREATED);
+    assert_eq!(send(&app, create(1)).await.0, StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[tokio::test]
+async fn test_post_bodies() {
+    let app = test_app().await;
+    create_board(&app, "b", json!({})).await;
+
+    let fields = [("board", "b"), ("sub", ""), ("com", "plain fields")];
+    let req = fields_request("/api/v1/create_thread", &fields, Some(&png()));
+    let (status, res) = send(&app, req).await;
+    assert_eq!(status, StatusCode::CREATED, "{res}");
+    assert_eq!(res["Ok"]["sub"], Value::Null);
+    let op = res["Ok"]["id"].as_i64().unwrap();
+
+    let reply = json!({"com": "as json", "op": op});
+    let req = json_request(Method::POST, "/api/v1/create_comment", reply);
+    let (status, res) = send(&app, req).await;
+    assert_eq!(status, StatusCode::OK, "{res}");
+    assert_eq!(res["Ok"]["com_raw"], "as json");
+
+    let req = request(Method::POST, "/api/v1/create_comment")
+        .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .body(Body::from(format!("com=as+a+form%21&op={op}&alias=")))
+        .unwrap();
+    let (status, res) = send(&app, req).await;
+    assert_eq!(status, StatusCode::OK, "{res}");
+    assert_eq!(res["Ok"]["com_raw"], "as a form!");
+
+    let req = request(Method::POST, "/api/v1/create_comment")
+        .header(header::CONTENT_TYPE, "text/plain")
+        .body(Body::from("hello"))
+        .unwrap();
+    let (status, _) = send(&app, req).await;
+    assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+    let reply = json!({"com": "mine", "op": op});
+    let req = json_request(Method::POST, "/api/v1/create_comment", reply);
+    let res = app.clone().oneshot(req).await.unwrap();
+    let cookie = res.headers()[header::SET_COOKIE].to_str().unwrap();
+    let cookie = cookie.split(';').next().unwrap().to_string();
+    let req = request(Method::GET, &format!("/api/v1/b/thread/{op}"))
+        .header(header::COOKIE, cookie)
+        .body(Body::empty())
+        .unwrap();
+    let (_, res) = send(&app, req).await;
+    let yours: Vec<_> = res["Ok"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|p| &p["yours"])
+        .collect();
+    assert_eq!(yours, [false, false, false, true]);
+}
+
+#[tokio::test]
+async fn test_media_traversal() {
+    let app = test_app().await;
+    std::fs::write("secret.txt", "secret").unwrap();
+    let link = std::path::Path::new("media/link.txt");
+    if !link.exists() {
+        std::os::unix::fs::symlink(std::fs::canonicalize("secret.txt").unwrap(), link).unwrap();
+    }
+    for uri in [
+        "/media/..%2Fsecret.txt",
+        "/media/%2E%2E%2Fsecret.txt",
+        "/media/..%5Csecret.txt",
+        "/media/%2Fetc%2Fpasswd",
+        "/media/..",
+        "/media/link.txt",
+        "/media/../secret.txt",
+    ] {
+        let req = request(Method::GET, uri).body(Body::empty()).unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND, "{uri}");
+    }
+}